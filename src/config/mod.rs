@@ -1,7 +1,16 @@
-use crate::util::expand_tilde;
+use crate::util::{expand_tilde, find_executable, parse_log_level};
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
+
+mod schedule;
+
+pub use schedule::{ParsedCommand, ScheduleSpec};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GeneralConfig {
@@ -13,10 +22,79 @@ pub struct GeneralConfig {
     pub state_path: PathBuf,
     #[serde(default = "default_max_immediate_executions")]
     pub max_immediate_executions: usize,
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    #[serde(default = "default_history_retention_days")]
+    pub history_retention_days: u32,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Directory commands without a `log_file` write their output to, as `<name>.log`
+    #[serde(default)]
+    pub log_dir: Option<PathBuf>,
+    #[serde(default = "default_max_log_size_mb")]
+    pub max_log_size_mb: u64,
+    #[serde(default = "default_log_files_kept")]
+    pub log_files_kept: usize,
+    /// Most output kept in memory from each of a run's streams
+    #[serde(default = "default_max_output_bytes")]
+    pub max_output_bytes: usize,
+    /// Default `on_failure_webhook` for commands that don't set their own
+    #[serde(default)]
+    pub on_failure_webhook: Option<String>,
+    /// Default `shell` for commands that don't set their own
+    #[serde(default)]
+    pub shell: Option<Shell>,
+    /// Seconds running commands get to finish on shutdown before they are killed
+    #[serde(default = "default_shutdown_timeout_seconds")]
+    pub shutdown_timeout_seconds: u64,
+    /// Unix socket `zephyr ctl` talks to the running scheduler through
+    #[serde(default = "default_control_socket")]
+    pub control_socket: PathBuf,
+    /// Default `unset_env_vars` for commands that don't set their own
+    #[serde(default)]
+    pub unset_env_vars: UnsetEnvVars,
+    /// Default `notify_on_failure` for commands that don't set their own
+    #[serde(default)]
+    pub notify_on_failure: bool,
+    /// Default `jitter_seconds` for commands that don't set their own
+    #[serde(default)]
+    pub jitter_seconds: Option<u64>,
+    /// Upper bound of a random delay before the first dispatch after startup
+    #[serde(default)]
+    pub startup_splay_seconds: u64,
+    /// How the scheduler notices that the system slept through scheduled runs
+    #[serde(default)]
+    pub sleep_detection: SleepDetection,
+    /// How much longer than the scheduler's own clock the wall clock has to have moved on
+    /// before `sleep_detection = "heuristic"` treats it as a sleep
+    #[serde(default = "default_sleep_detection_threshold_minutes")]
+    pub sleep_detection_threshold_minutes: u64,
+    /// Glob patterns of further files whose `commands` are added to this file's, relative to
+    /// the directory of this file
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Directory of further files that each define one command, such as `commands.d`,
+    /// relative to the directory of this file
+    #[serde(default)]
+    pub commands_dir: Option<PathBuf>,
+    /// Fail startup when a command can't be scheduled, instead of reporting and skipping it
+    #[serde(default)]
+    pub strict: bool,
+    /// Keep the saved state of commands that are no longer configured, instead of deleting it
+    /// on startup
+    #[serde(default)]
+    pub keep_orphaned_state: bool,
 }
 
 impl GeneralConfig {
     pub fn validate(&self) -> anyhow::Result<()> {
+        if parse_log_level(&self.log_level).is_none() {
+            return Err(anyhow::anyhow!(
+                "Invalid log_level '{}', expected one of trace, debug, info, warn, error",
+                self.log_level
+            ));
+        }
+
         if self.min_interval_seconds < 1 {
             return Err(anyhow::anyhow!(
                 "min_interval_seconds must be at least 1 second"
@@ -41,6 +119,16 @@ impl GeneralConfig {
             ));
         }
 
+        if self.max_concurrent == Some(0) {
+            return Err(anyhow::anyhow!("max_concurrent must be at least 1"));
+        }
+
+        if self.sleep_detection_threshold_minutes < 1 {
+            return Err(anyhow::anyhow!(
+                "sleep_detection_threshold_minutes must be at least 1"
+            ));
+        }
+
         let expanded_state_path = expand_tilde(&self.state_path);
         if let Some(parent) = expanded_state_path.parent() {
             if !parent.exists() {
@@ -61,6 +149,27 @@ impl Default for GeneralConfig {
             min_interval_seconds: default_min_interval_seconds(),
             state_path: default_state_path(),
             max_immediate_executions: default_max_immediate_executions(),
+            max_concurrent: None,
+            history_retention_days: default_history_retention_days(),
+            log_format: LogFormat::default(),
+            log_dir: None,
+            max_log_size_mb: default_max_log_size_mb(),
+            log_files_kept: default_log_files_kept(),
+            max_output_bytes: default_max_output_bytes(),
+            on_failure_webhook: None,
+            shell: None,
+            shutdown_timeout_seconds: default_shutdown_timeout_seconds(),
+            control_socket: default_control_socket(),
+            unset_env_vars: UnsetEnvVars::default(),
+            notify_on_failure: false,
+            jitter_seconds: None,
+            startup_splay_seconds: 0,
+            sleep_detection: SleepDetection::default(),
+            sleep_detection_threshold_minutes: default_sleep_detection_threshold_minutes(),
+            include: Vec::new(),
+            commands_dir: None,
+            strict: false,
+            keep_orphaned_state: false,
         }
     }
 }
@@ -73,6 +182,7 @@ fn default_min_interval_seconds() -> u64 {
     30
 }
 
+#[cfg(not(windows))]
 fn default_state_path() -> PathBuf {
     let mut path = dirs::home_dir().unwrap_or_else(|| {
         std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."))
@@ -81,18 +191,439 @@ fn default_state_path() -> PathBuf {
     path
 }
 
+#[cfg(windows)]
+fn default_state_path() -> PathBuf {
+    let mut path = dirs::data_local_dir().unwrap_or_else(|| {
+        std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."))
+    });
+    path.push("zephyr");
+    path.push("state.db");
+    path
+}
+
+/// `~/.local/state/zephyr/control.sock`, next to the default state database
+pub fn default_control_socket() -> PathBuf {
+    let mut path = dirs::home_dir().unwrap_or_else(|| {
+        std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."))
+    });
+    path.push(".local/state/zephyr/control.sock");
+    path
+}
+
 fn default_max_immediate_executions() -> usize {
     10
 }
 
+fn default_sleep_detection_threshold_minutes() -> u64 {
+    5
+}
+
+fn default_history_retention_days() -> u32 {
+    30
+}
+
+fn default_shutdown_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_max_output_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
+fn default_max_log_size_mb() -> u64 {
+    10
+}
+
+fn default_log_files_kept() -> usize {
+    5
+}
+
+/// Quotes an argument for display as part of a shell command line, if it needs it
+fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// When a command's log file is rotated and how many old files are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    /// Size in bytes past which the file is rotated before the next write; 0 never rotates
+    pub max_bytes: u64,
+    /// Number of rotated files (`<file>.1`, `<file>.2`, ...) to keep
+    pub keep: usize,
+}
+
+/// How the daemon formats its log output
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per event, with structured fields such as `command` and `event`
+    Json,
+}
+
+/// What an environment variable reference expands to when the variable isn't set
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnsetEnvVars {
+    /// Leave the reference as written, so a shell can still expand it
+    #[default]
+    Keep,
+    /// Replace the reference with nothing
+    Empty,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow::anyhow!(
+                "Invalid log format '{}', expected text or json",
+                s
+            )),
+        }
+    }
+}
+
+/// The shell a command line is passed to
+///
+/// Either a program, which is run as `<program> -c <command>` (`/C` for `cmd` and `-Command`
+/// for PowerShell), or a program with the arguments that come before the command, such as
+/// `["pwsh", "-NoProfile", "-Command"]`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Shell {
+    Program(String),
+    Argv(Vec<String>),
+}
+
+impl Shell {
+    /// Returns the program and the arguments to put before the command line
+    pub fn argv(&self) -> Vec<String> {
+        match self {
+            Self::Program(program) => vec![program.clone(), shell_flag(program).to_string()],
+            Self::Argv(argv) => argv.clone(),
+        }
+    }
+
+    /// Returns the shell commands run with when none is configured: `sh -c`, or `cmd /C`
+    /// on Windows
+    pub fn platform_default() -> Self {
+        if cfg!(windows) {
+            Self::Argv(vec!["cmd".to_string(), "/C".to_string()])
+        } else {
+            Self::Argv(vec!["sh".to_string(), "-c".to_string()])
+        }
+    }
+}
+
+/// Returns the flag a shell takes its command line after
+fn shell_flag(program: &str) -> &'static str {
+    let name = program
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(program)
+        .to_lowercase();
+    match name.strip_suffix(".exe").unwrap_or(&name) {
+        "cmd" => "/C",
+        "powershell" | "pwsh" => "-Command",
+        _ => "-c",
+    }
+}
+
+/// Parses a cron expression, accepting standard 5-field crontab expressions and `@` macros
+/// besides the 6- and 7-field expressions (with seconds, and optionally years) of the `cron`
+/// crate
+pub fn parse_cron(expression: &str) -> Result<cron::Schedule, cron::error::Error> {
+    cron::Schedule::from_str(&normalize_cron(expression))
+}
+
+/// Rewrites a cron expression into the `cron` crate's syntax
+///
+/// `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are expanded, and a 5-field
+/// crontab expression gets a seconds field of `0`. Crontab numbers days of the week from
+/// Sunday as 0 (or 7) while the `cron` crate starts at Sunday as 1, so numeric days in a
+/// 5-field expression are shifted as well.
+pub fn normalize_cron(expression: &str) -> String {
+    let expression = expression.trim();
+    let expanded = match expression {
+        "@hourly" => "0 0 * * * *",
+        "@daily" | "@midnight" => "0 0 0 * * *",
+        "@weekly" => "0 0 0 * * Sun",
+        "@monthly" => "0 0 0 1 * *",
+        "@yearly" | "@annually" => "0 0 0 1 1 *",
+        _ => expression,
+    };
+    let fields = expanded.split_whitespace().collect::<Vec<_>>();
+    if fields.len() != 5 {
+        return expanded.to_string();
+    }
+    format!(
+        "0 {} {}",
+        fields[..4].join(" "),
+        crontab_days_of_week(fields[4])
+    )
+}
+
+/// Converts a crontab day-of-week field to the `cron` crate's numbering, leaving names
+/// such as `MON-FRI` as they are
+fn crontab_days_of_week(field: &str) -> String {
+    if field == "*" || field == "?" {
+        return field.to_string();
+    }
+    field
+        .split(',')
+        .map(|item| match crontab_days(item) {
+            Some(days) => days
+                .iter()
+                .map(|day| (day % 7 + 1).to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            None => item.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Expands a numeric crontab day-of-week item such as `5`, `1-5`, `*/2` or `0-6/2` into
+/// the days (0-7) it matches
+fn crontab_days(item: &str) -> Option<Vec<u32>> {
+    let (range, step) = match item.split_once('/') {
+        Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)?),
+        None => (item, 1),
+    };
+    let (start, end) = match range.split_once('-') {
+        _ if range == "*" => (0, 6),
+        Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+        None if item.contains('/') => (range.parse().ok()?, 7),
+        None => {
+            let day = range.parse().ok()?;
+            (day, day)
+        }
+    };
+    if start > end || end > 7 {
+        return None;
+    }
+    let mut days = (start..=end)
+        .step_by(step as usize)
+        .map(|day| day % 7)
+        .collect::<Vec<_>>();
+    days.sort_unstable();
+    days.dedup();
+    Some(days)
+}
+
+/// What to do when a command comes due while its previous run is still active
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlapPolicy {
+    /// Skip the run and wait for the next scheduled one
+    #[default]
+    Skip,
+    /// Run again as soon as the active run finishes
+    Queue,
+    /// Start another run alongside the active one
+    Allow,
+}
+
+/// How the scheduler notices that the system slept
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SleepDetection {
+    /// Assume a sleep when wall-clock time got ahead of monotonic time by at least
+    /// `sleep_detection_threshold_minutes`
+    #[default]
+    Heuristic,
+    /// Use the operating system's resume notifications, falling back to the heuristic where
+    /// there are none
+    System,
+    /// Don't look for sleeps; missed runs execute once each when the scheduler next looks
+    Off,
+}
+
+/// What to do with the runs a command missed while the system was asleep or the scheduler
+/// wasn't running
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUpPolicy {
+    /// Execute every missed run, one after another
+    RunAll,
+    /// Execute a single run no matter how many were missed
+    #[default]
+    RunOnce,
+    /// Don't execute missed runs, just schedule the next one
+    Skip,
+}
+
+/// What the next run of an `interval_minutes` command is counted from
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IntervalAnchor {
+    /// The start of the previous run
+    Start,
+    /// The end of the previous run, so the time a run takes pushes back the ones after it
+    #[default]
+    Finish,
+    /// A fixed grid counted from the first run, skipping slots that passed during a long run
+    Fixed,
+}
+
+/// A daily time window, which wraps past midnight when it ends before it starts
+///
+/// Written as `HH:MM-HH:MM`, optionally after the days of the week it applies to, such as
+/// `Mon-Fri 09:00-17:00` or `Sat,Sun 22:00-06:00`. A window that wraps past midnight
+/// belongs to the day it starts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    /// Days the window opens on, indexed from Monday
+    pub days: [bool; 7],
+}
+
+impl RunWindow {
+    /// Returns whether `time` falls inside the window on any day; the start is inclusive
+    /// and the end exclusive
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Returns whether a local date and time falls inside the window, on one of its days
+    pub fn contains_datetime(&self, time: NaiveDateTime) -> bool {
+        self.opened_on(time)
+            .is_some_and(|date| self.days[date.weekday().num_days_from_monday() as usize])
+    }
+
+    /// Returns the local time the window was last opened at if `time` falls inside it,
+    /// ignoring its days
+    fn opened_on(&self, time: NaiveDateTime) -> Option<NaiveDate> {
+        if !self.contains(time.time()) {
+            return None;
+        }
+        if self.start < self.end || time.time() >= self.start {
+            Some(time.date())
+        } else {
+            time.date().pred_opt()
+        }
+    }
+
+    /// Returns the first local time after `time` that the window opens, if it opens on any
+    /// day
+    pub fn next_opening(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        (0..=7)
+            .filter_map(|days| time.date().checked_add_days(Days::new(days)))
+            .filter(|date| self.days[date.weekday().num_days_from_monday() as usize])
+            .map(|date| date.and_time(self.start))
+            .find(|start| *start > time)
+    }
+
+    /// Returns the local time the window closes if `time` falls inside it on one of its
+    /// days
+    pub fn closing(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        if !self.contains_datetime(time) {
+            return None;
+        }
+        let opened = self.opened_on(time)?;
+        let closes = if self.start < self.end {
+            opened
+        } else {
+            opened.succ_opt()?
+        };
+        Some(closes.and_time(self.end))
+    }
+}
+
+impl FromStr for RunWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let (days, times) = match trimmed.split_once(char::is_whitespace) {
+            Some((days, times)) if trimmed.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                (parse_weekdays(days)?, times)
+            }
+            _ => ([true; 7], trimmed),
+        };
+        let (start, end) = times
+            .split_once('-')
+            .ok_or_else(|| format!("expected HH:MM-HH:MM, got '{}'", s))?;
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| format!("invalid time '{}', expected HH:MM", time.trim()))
+        };
+        let window = RunWindow {
+            start: parse(start)?,
+            end: parse(end)?,
+            days,
+        };
+        if window.start == window.end {
+            return Err(format!("window '{}' is empty", s));
+        }
+        Ok(window)
+    }
+}
+
+/// Parses days of the week such as `Mon-Fri` or `Sat,Sun` into flags indexed from Monday;
+/// ranges may wrap past Sunday, as in `Fri-Mon`
+fn parse_weekdays(days: &str) -> Result<[bool; 7], String> {
+    let parse = |day: &str| {
+        day.trim()
+            .parse::<Weekday>()
+            .map(|day| day.num_days_from_monday() as usize)
+            .map_err(|_| format!("invalid day of the week '{}'", day.trim()))
+    };
+    let mut flags = [false; 7];
+    for part in days.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                let mut day = first;
+                loop {
+                    flags[day] = true;
+                    if day == last {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            }
+            None => flags[parse(part)?] = true,
+        }
+    }
+    Ok(flags)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CommandConfig {
     pub name: String,
+    /// Shell command line, run with `shell` (`sh -c`, or `cmd /C` on Windows, by default)
+    #[serde(default)]
     pub command: String,
+    /// Program and arguments to execute directly, without a shell, instead of `command`
+    #[serde(default)]
+    pub args: Option<Vec<String>>,
     #[serde(default)]
     pub interval_minutes: Option<f64>,
     #[serde(default)]
     pub cron: Option<String>,
+    /// RFC 3339 time of the command's only run, in place of `interval_minutes` or `cron`
+    #[serde(default, alias = "run_at")]
+    pub at: Option<String>,
     pub max_runtime_minutes: Option<u32>,
     #[serde(default = "default_enabled")]
     pub enabled: bool,
@@ -100,6 +631,137 @@ pub struct CommandConfig {
     pub environment: Option<Vec<(String, String)>>,
     #[serde(default)]
     pub immediate: bool,
+    #[serde(default)]
+    pub overlap_policy: OverlapPolicy,
+    pub max_retries: Option<u32>,
+    pub retry_delay_seconds: Option<u64>,
+    pub retry_backoff_multiplier: Option<f64>,
+    /// IANA timezone cron expressions are evaluated in (default: UTC)
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub catch_up: CatchUpPolicy,
+    pub disable_after_failures: Option<u32>,
+    /// File each run's stdout and stderr are appended to, instead of the scheduler log
+    pub log_file: Option<PathBuf>,
+    /// Overrides `max_log_size_mb` from the general settings for this command's log file
+    pub max_log_size_mb: Option<u64>,
+    /// Overrides `log_files_kept` from the general settings for this command's log file
+    pub log_files_kept: Option<usize>,
+    /// Overrides `max_output_bytes` from the general settings for this command
+    pub max_output_bytes: Option<usize>,
+    /// URL a JSON notification is posted to when a run fails
+    pub on_failure_webhook: Option<String>,
+    /// Shell `command` is run with instead of the platform default
+    pub shell: Option<Shell>,
+    /// Exit statuses that count as a successful run (default: `[0]`)
+    pub success_exit_codes: Option<Vec<i32>>,
+    /// Seconds to wait before the first run after startup
+    pub start_delay_seconds: Option<u64>,
+    /// Upper bound of a random extra delay added to every run, so commands sharing an
+    /// interval don't all start at once
+    pub jitter_seconds: Option<u64>,
+    /// Runs an `interval_minutes` command on multiples of its interval since the Unix epoch
+    /// (e.g. :00/:15/:30/:45 UTC for 15 minutes) instead of counting from startup
+    #[serde(default)]
+    pub align_to_interval: bool,
+    /// Time of day the command may run in, as `HH:MM-HH:MM` (e.g. `22:00-06:00`) with
+    /// optional days of the week, in `timezone` if set and local time otherwise
+    #[serde(alias = "allowed_window")]
+    pub run_window: Option<String>,
+    /// What `$VAR` in `command` and `environment` becomes when `VAR` isn't set
+    pub unset_env_vars: Option<UnsetEnvVars>,
+    /// Shows a desktop notification when a run fails
+    pub notify_on_failure: Option<bool>,
+    /// Minutes the command may go without running before it is reported as overdue
+    pub max_staleness_minutes: Option<u64>,
+    /// Commands that must finish successfully before this one runs; without a schedule of
+    /// its own, the command runs as soon as they have
+    pub depends_on: Option<Vec<String>>,
+    /// Shell command run after a successful run, with `ZEPHYR_*` variables describing it
+    pub on_success: Option<String>,
+    /// Shell command run after a failed run, with `ZEPHYR_*` variables describing it
+    pub on_failure: Option<String>,
+    /// Whether the schedule backs off while runs keep failing, until the next success
+    #[serde(default)]
+    pub failure_backoff: bool,
+    /// Longest delay between runs while backing off (default: 1440)
+    pub failure_backoff_max_minutes: Option<u64>,
+    /// Time of day the command must not run in, written like `run_window`
+    pub blocked_window: Option<String>,
+    /// Starts the command as soon as it is due even if `min_interval_seconds` hasn't passed
+    /// since the last command started, without delaying the commands after it either
+    #[serde(default)]
+    pub ignore_min_interval: bool,
+    /// What the next run of an `interval_minutes` command is counted from: the `start` or
+    /// `finish` of the previous run, or a `fixed` grid from the first run
+    #[serde(default)]
+    pub interval_anchor: IntervalAnchor,
+    /// Counts an `interval_minutes` command's runs from the time each was scheduled for
+    /// rather than from when the previous one finished; shorthand for
+    /// `interval_anchor = "fixed"`
+    #[serde(default)]
+    pub fixed_rate: bool,
+    /// Names of the groups the command belongs to, such as `backups`, for running or listing
+    /// them together
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// Image the command is run in a new container of, with `docker run`, instead of on
+    /// the host
+    pub container_image: Option<String>,
+    /// Name of the user the command runs as; the scheduler must run as root to switch to
+    /// another user (Unix only)
+    pub run_as_user: Option<String>,
+}
+
+impl Default for CommandConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            command: String::new(),
+            args: None,
+            interval_minutes: None,
+            cron: None,
+            at: None,
+            max_runtime_minutes: None,
+            enabled: default_enabled(),
+            working_dir: None,
+            environment: None,
+            immediate: false,
+            overlap_policy: OverlapPolicy::default(),
+            max_retries: None,
+            retry_delay_seconds: None,
+            retry_backoff_multiplier: None,
+            timezone: None,
+            catch_up: CatchUpPolicy::default(),
+            disable_after_failures: None,
+            log_file: None,
+            max_log_size_mb: None,
+            log_files_kept: None,
+            max_output_bytes: None,
+            on_failure_webhook: None,
+            shell: None,
+            success_exit_codes: None,
+            start_delay_seconds: None,
+            jitter_seconds: None,
+            align_to_interval: false,
+            run_window: None,
+            unset_env_vars: None,
+            notify_on_failure: None,
+            max_staleness_minutes: None,
+            depends_on: None,
+            on_success: None,
+            on_failure: None,
+            failure_backoff: false,
+            failure_backoff_max_minutes: None,
+            blocked_window: None,
+            ignore_min_interval: false,
+            interval_anchor: IntervalAnchor::default(),
+            fixed_rate: false,
+            tags: None,
+            container_image: None,
+            run_as_user: None,
+        }
+    }
 }
 
 fn default_enabled() -> bool {
@@ -107,28 +769,249 @@ fn default_enabled() -> bool {
 }
 
 impl CommandConfig {
+    /// Returns a short human-readable description of the command's schedule
+    pub fn schedule_description(&self) -> String {
+        let schedule = if let Some(interval) = self.interval_minutes {
+            format!("every {} minutes", interval)
+        } else if let Some(cron) = &self.cron {
+            match &self.timezone {
+                Some(timezone) => format!("cron: {} ({})", cron, timezone),
+                None => format!("cron: {}", cron),
+            }
+        } else if let Some(at) = &self.at {
+            format!("once at {}", at)
+        } else if let Some(depends_on) = &self.depends_on {
+            return format!("after {}", depends_on.join(", "));
+        } else {
+            return "unscheduled".to_string();
+        };
+        match &self.depends_on {
+            Some(depends_on) => format!("{}, after {}", schedule, depends_on.join(", ")),
+            None => schedule,
+        }
+    }
+
+    /// Returns whether the command has no schedule of its own and runs whenever its
+    /// `depends_on` commands have succeeded
+    pub fn runs_after_dependencies(&self) -> bool {
+        self.depends_on.is_some()
+            && self.interval_minutes.is_none()
+            && self.cron.is_none()
+            && self.at.is_none()
+    }
+
+    /// Parses the schedule the command runs on by itself, or returns `None` for commands
+    /// that only run after their `depends_on` commands
+    pub fn schedule(&self) -> anyhow::Result<Option<ScheduleSpec>> {
+        ScheduleSpec::from_command(self)
+    }
+
+    /// Returns how many bytes of each output stream are kept from a run
+    pub fn output_limit(&self) -> usize {
+        self.max_output_bytes
+            .unwrap_or_else(default_max_output_bytes)
+    }
+
+    /// Returns the rotation settings for the command's log file
+    pub fn log_rotation(&self) -> LogRotation {
+        LogRotation {
+            max_bytes: self.max_log_size_mb.unwrap_or_else(default_max_log_size_mb) * 1024 * 1024,
+            keep: self.log_files_kept.unwrap_or_else(default_log_files_kept),
+        }
+    }
+
+    /// Returns what the command runs, quoting `args` the way a shell would need them
+    pub fn command_line(&self) -> String {
+        match &self.args {
+            Some(args) => args
+                .iter()
+                .map(|arg| shell_quote(arg))
+                .collect::<Vec<_>>()
+                .join(" "),
+            None => self.command.clone(),
+        }
+    }
+
+    /// Returns whether the command is tagged with `tag`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().flatten().any(|own| own == tag)
+    }
+
+    /// Returns whether two configurations describe the same schedule
+    pub fn same_schedule(&self, other: &CommandConfig) -> bool {
+        self.depends_on == other.depends_on
+            && self.interval_minutes == other.interval_minutes
+            && self.align_to_interval == other.align_to_interval
+            && self.interval_anchor() == other.interval_anchor()
+            && self.cron == other.cron
+            && self.at == other.at
+            && self.timezone == other.timezone
+            && self.run_window == other.run_window
+            && self.blocked_window == other.blocked_window
+    }
+
+    /// Returns what the command's interval is counted from, taking `fixed_rate` into account
+    pub fn interval_anchor(&self) -> IntervalAnchor {
+        if self.fixed_rate {
+            IntervalAnchor::Fixed
+        } else {
+            self.interval_anchor
+        }
+    }
+
+    /// Returns the timezone cron expressions are evaluated in
+    pub fn timezone(&self) -> anyhow::Result<Tz> {
+        match &self.timezone {
+            Some(name) => name.parse::<Tz>().map_err(|e| {
+                anyhow::anyhow!("Invalid timezone for command '{}': {}", self.name, e)
+            }),
+            None => Ok(Tz::UTC),
+        }
+    }
+
+    /// Returns the time window the command is restricted to, if it has one
+    pub fn run_window(&self) -> anyhow::Result<Option<RunWindow>> {
+        self.parse_window("run_window", self.run_window.as_deref())
+    }
+
+    /// Returns the time window the command must not run in, if it has one
+    pub fn blocked_window(&self) -> anyhow::Result<Option<RunWindow>> {
+        self.parse_window("blocked_window", self.blocked_window.as_deref())
+    }
+
+    fn parse_window(
+        &self,
+        setting: &str,
+        window: Option<&str>,
+    ) -> anyhow::Result<Option<RunWindow>> {
+        window
+            .map(|window| {
+                window.parse().map_err(|e| {
+                    anyhow::anyhow!("Invalid {} for command '{}': {}", setting, self.name, e)
+                })
+            })
+            .transpose()
+    }
+
+    /// Returns whether a run that exited with `status` succeeded
+    pub fn is_success(&self, status: i32) -> bool {
+        match &self.success_exit_codes {
+            Some(codes) => codes.contains(&status),
+            None => status == 0,
+        }
+    }
+
+    /// Returns whether a command has failed often enough in a row to be disabled
+    pub fn exceeds_failure_limit(&self, consecutive_failures: u32) -> bool {
+        self.disable_after_failures
+            .is_some_and(|limit| consecutive_failures >= limit)
+    }
+
+    /// Returns how long to wait before the next run after `consecutive_failures` failed
+    /// runs in a row, or `None` when the command isn't backing off
+    ///
+    /// The delay doubles `period`, the time between two scheduled runs, with each failure,
+    /// up to `failure_backoff_max_minutes`; it never drops below `period` itself.
+    pub fn failure_backoff_delay(
+        &self,
+        period: chrono::Duration,
+        consecutive_failures: u32,
+    ) -> Option<chrono::Duration> {
+        if !self.failure_backoff || consecutive_failures == 0 {
+            return None;
+        }
+        let max_ms = self.failure_backoff_max_minutes.unwrap_or(1440) as f64 * 60_000.0;
+        let delay_ms =
+            period.num_milliseconds() as f64 * 2f64.powi(consecutive_failures.min(32) as i32);
+        Some(chrono::Duration::milliseconds(delay_ms.min(max_ms) as i64).max(period))
+    }
+
+    /// Returns how long a single run may take before it is killed (default: 5 minutes)
+    pub fn max_runtime(&self) -> Duration {
+        Duration::from_secs(self.max_runtime_minutes.unwrap_or(5) as u64 * 60)
+    }
+
     pub fn validate(&self) -> anyhow::Result<()> {
-        if self.interval_minutes.is_none() && self.cron.is_none() {
-            return Err(anyhow::anyhow!(
-                "Command '{}' must specify either interval_minutes or cron",
-                self.name
-            ));
+        match (&self.args, self.command.is_empty()) {
+            (None, true) => {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' must specify either command or args",
+                    self.name
+                ))
+            }
+            (Some(_), false) => {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' cannot specify both command and args",
+                    self.name
+                ))
+            }
+            (Some(args), true) if args.is_empty() => {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' args must include the program to run",
+                    self.name
+                ))
+            }
+            _ => {}
+        }
+        if self.tags.iter().flatten().any(|tag| tag.trim().is_empty()) {
+            return Err(anyhow::anyhow!("Command '{}' has an empty tag", self.name));
         }
-        if self.interval_minutes.is_some() && self.cron.is_some() {
+        if matches!(&self.container_image, Some(image) if image.trim().is_empty()) {
             return Err(anyhow::anyhow!(
-                "Command '{}' cannot specify both interval_minutes and cron",
+                "Command '{}' has an empty container_image",
                 self.name
             ));
         }
-        if let Some(interval) = self.interval_minutes {
-            if interval <= 0.0 {
+        match &self.run_as_user {
+            Some(user) if user.trim().is_empty() => {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' has an empty run_as_user",
+                    self.name
+                ))
+            }
+            Some(_) if self.container_image.is_some() => {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' cannot use run_as_user with container_image",
+                    self.name
+                ))
+            }
+            _ => {}
+        }
+        if let Some(shell) = &self.shell {
+            if self.args.is_some() {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' cannot specify a shell when it uses args",
+                    self.name
+                ));
+            }
+            let argv = shell.argv();
+            let Some(program) = argv.first() else {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' shell must include the program to run",
+                    self.name
+                ));
+            };
+            // A container's shell is looked up in its image rather than on the host
+            if self.container_image.is_none() && find_executable(program).is_none() {
                 return Err(anyhow::anyhow!(
-                    "Command '{}' interval_minutes must be positive, got {}",
+                    "Command '{}' shell '{}' was not found or is not executable",
                     self.name,
-                    interval
+                    program
                 ));
             }
         }
+        if self.depends_on.as_ref().is_some_and(Vec::is_empty) {
+            return Err(anyhow::anyhow!(
+                "Command '{}' depends_on must name at least one command",
+                self.name
+            ));
+        }
+        if self.schedule()?.is_none() && self.depends_on.is_none() {
+            return Err(anyhow::anyhow!(
+                "Command '{}' must specify either interval_minutes, cron, at or depends_on",
+                self.name
+            ));
+        }
         if let Some(max) = self.max_runtime_minutes {
             if max == 0 {
                 return Err(anyhow::anyhow!(
@@ -137,10 +1020,62 @@ impl CommandConfig {
                 ));
             }
         }
-        if let Some(cron) = &self.cron {
-            cron::Schedule::from_str(cron).map_err(|e| {
-                anyhow::anyhow!("Invalid cron expression for command '{}': {}", self.name, e)
-            })?;
+        self.timezone()?;
+        self.run_window()?;
+        self.blocked_window()?;
+        if let Some(retries) = self.max_retries {
+            if retries > 10 {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' max_retries cannot be greater than 10, got {}",
+                    self.name,
+                    retries
+                ));
+            }
+        }
+        if self.success_exit_codes.as_ref().is_some_and(Vec::is_empty) {
+            return Err(anyhow::anyhow!(
+                "Command '{}' success_exit_codes must not be empty",
+                self.name
+            ));
+        }
+        if self.disable_after_failures == Some(0) {
+            return Err(anyhow::anyhow!(
+                "Command '{}' disable_after_failures must be at least 1",
+                self.name
+            ));
+        }
+        if self.failure_backoff_max_minutes == Some(0) {
+            return Err(anyhow::anyhow!(
+                "Command '{}' failure_backoff_max_minutes must be at least 1",
+                self.name
+            ));
+        }
+        if self.max_staleness_minutes == Some(0) {
+            return Err(anyhow::anyhow!(
+                "Command '{}' max_staleness_minutes must be at least 1",
+                self.name
+            ));
+        }
+        if let Some(multiplier) = self.retry_backoff_multiplier {
+            if !(1.0..=10.0).contains(&multiplier) {
+                return Err(anyhow::anyhow!(
+                    "Command '{}' retry_backoff_multiplier must be between 1 and 10, got {}",
+                    self.name,
+                    multiplier
+                ));
+            }
+        }
+        if let Some(url) = &self.on_failure_webhook {
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Command '{}' on_failure_webhook must be an http or https URL, got '{}'",
+                        self.name,
+                        url
+                    ))
+                }
+            }
         }
         Ok(())
     }
@@ -150,37 +1085,285 @@ impl CommandConfig {
 pub struct Config {
     #[serde(default)]
     pub general: GeneralConfig,
+    /// Settings for the HTTP status server, which is only started when this section (or its
+    /// alias `[api]`) is present
+    #[serde(default, alias = "api")]
+    pub http: Option<ServerConfig>,
+    /// Settings for the Prometheus metrics server, which is only started when this section is
+    /// present
+    #[serde(default)]
+    pub metrics: Option<ServerConfig>,
     pub commands: Vec<CommandConfig>,
 }
 
+/// Settings for an HTTP server started by the scheduler
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServerConfig {
+    /// Address the server listens on, e.g. "127.0.0.1:9191"
+    #[serde(alias = "listen")]
+    pub listen_addr: String,
+}
+
+impl ServerConfig {
+    /// Parses the listen address
+    pub fn socket_addr(&self) -> anyhow::Result<SocketAddr> {
+        self.listen_addr
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid listen_addr '{}': {}", self.listen_addr, e))
+    }
+}
+
 impl Config {
     pub fn load(path: &Path) -> anyhow::Result<Self> {
-        let config = config::Config::builder()
-            .add_source(config::File::from(path))
+        let config = Self::parse(path)?;
+        if let Some(error) = config.validate().into_iter().next() {
+            return Err(error);
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the commands tagged with `tag`, in the order they are configured
+    pub fn commands_tagged(&self, tag: &str) -> Vec<&CommandConfig> {
+        self.commands
+            .iter()
+            .filter(|command| command.has_tag(tag))
+            .collect()
+    }
+
+    /// Reads and deserializes a configuration file without validating it
+    pub fn parse(path: &Path) -> anyhow::Result<Self> {
+        let config = config::Config::builder()
+            .add_source(config::File::from(path))
             .build()?;
 
-        let config: Config = config.try_deserialize()?;
-        config.general.validate()?;
+        let mut config: Self = config.try_deserialize()?;
+        for included in config.included_files(path)? {
+            config.commands.extend(Self::parse_included(&included)?);
+        }
+        for file in config.command_files(path)? {
+            config.commands.push(Self::parse_command_file(&file)?);
+        }
+        config.apply_general_defaults();
+        Ok(config)
+    }
+
+    /// Expands the `include` patterns of the configuration file at `path`, in the order they
+    /// are listed and alphabetically within each pattern
+    fn included_files(&self, path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut files = Vec::new();
+        for pattern in &self.general.include {
+            let pattern = expand_tilde(Path::new(pattern));
+            let pattern = if pattern.is_absolute() {
+                pattern
+            } else {
+                PathBuf::from(glob::Pattern::escape(&base.to_string_lossy())).join(pattern)
+            };
+            let matches = glob::glob(&pattern.to_string_lossy())
+                .map_err(|e| anyhow::anyhow!("Invalid include pattern {:?}: {}", pattern, e))?;
+            for file in matches {
+                files.push(file.map_err(|e| anyhow::anyhow!("Failed to read include: {}", e))?);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Reads the commands from an included file, which may not have sections of its own
+    fn parse_included(path: &Path) -> anyhow::Result<Vec<CommandConfig>> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Included {
+            #[serde(default)]
+            commands: Vec<CommandConfig>,
+        }
+        let included: Included = config::Config::builder()
+            .add_source(config::File::from(path))
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .map_err(|e| anyhow::anyhow!("Failed to load included file {:?}: {}", path, e))?;
+        Ok(included.commands)
+    }
+
+    /// Lists the `.toml` files in the `commands_dir` of the configuration file at `path`,
+    /// sorted by name
+    fn command_files(&self, path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let Some(dir) = &self.general.commands_dir else {
+            return Ok(Vec::new());
+        };
+        let dir = path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(expand_tilde(dir));
+        let entries = std::fs::read_dir(&dir)
+            .map_err(|e| anyhow::anyhow!("Failed to read commands_dir {:?}: {}", dir, e))?;
+        let mut files = Vec::new();
+        for entry in entries {
+            let file = entry
+                .map_err(|e| anyhow::anyhow!("Failed to read commands_dir {:?}: {}", dir, e))?
+                .path();
+            if file.is_file()
+                && file
+                    .extension()
+                    .is_some_and(|extension| extension == "toml")
+            {
+                files.push(file);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Reads a file of `commands_dir`, which holds the settings of a single command
+    fn parse_command_file(path: &Path) -> anyhow::Result<CommandConfig> {
+        config::Config::builder()
+            .add_source(config::File::from(path))
+            .build()
+            .and_then(|config| config.try_deserialize())
+            .map_err(|e| anyhow::anyhow!("Failed to load command file {:?}: {}", path, e))
+    }
+
+    /// Fills in per-command settings that fall back to the general section
+    fn apply_general_defaults(&mut self) {
+        for command in &mut self.commands {
+            if command.log_file.is_none() {
+                command.log_file = self
+                    .general
+                    .log_dir
+                    .as_ref()
+                    .map(|dir| dir.join(format!("{}.log", command.name)));
+            }
+            command
+                .max_log_size_mb
+                .get_or_insert(self.general.max_log_size_mb);
+            command
+                .log_files_kept
+                .get_or_insert(self.general.log_files_kept);
+            command
+                .max_output_bytes
+                .get_or_insert(self.general.max_output_bytes);
+            if command.shell.is_none()
+                && command.args.is_none()
+                && command.container_image.is_none()
+            {
+                command.shell = self.general.shell.clone();
+            }
+            if command.on_failure_webhook.is_none() {
+                command.on_failure_webhook = self.general.on_failure_webhook.clone();
+            }
+            command
+                .unset_env_vars
+                .get_or_insert(self.general.unset_env_vars);
+            command
+                .notify_on_failure
+                .get_or_insert(self.general.notify_on_failure);
+            if command.jitter_seconds.is_none() {
+                command.jitter_seconds = self.general.jitter_seconds;
+            }
+        }
+    }
+
+    /// Validates the general settings and every command, collecting all problems found
+    pub fn validate(&self) -> Vec<anyhow::Error> {
+        let mut errors = Vec::new();
+        if let Err(e) = self.general.validate() {
+            errors.push(e);
+        }
+        for (section, server) in [("http", &self.http), ("metrics", &self.metrics)] {
+            if let Some(Err(e)) = server.as_ref().map(ServerConfig::socket_addr) {
+                errors.push(anyhow::anyhow!("[{}] {}", section, e));
+            }
+        }
         let mut seen = std::collections::HashSet::new();
-        for cmd in &config.commands {
+        for cmd in &self.commands {
             if !seen.insert(cmd.name.as_str()) {
-                return Err(anyhow::anyhow!(
+                errors.push(anyhow::anyhow!(
                     "Duplicate command name '{}' - command names must be unique",
                     cmd.name
                 ));
             }
         }
-        for command in &config.commands {
-            command.validate()?;
+        for command in &self.commands {
+            if let Err(e) = command.validate() {
+                errors.push(e);
+            }
         }
+        errors.extend(self.dependency_errors());
+        errors
+    }
 
-        Ok(config)
+    /// Checks that every `depends_on` names a configured command and that no command
+    /// depends on itself, directly or through others
+    fn dependency_errors(&self) -> Vec<anyhow::Error> {
+        let dependencies = self
+            .commands
+            .iter()
+            .map(|command| {
+                let depends_on = command.depends_on.as_deref().unwrap_or_default();
+                (command.name.as_str(), depends_on)
+            })
+            .collect::<HashMap<_, _>>();
+        let mut errors = Vec::new();
+        for command in &self.commands {
+            for dependency in command.depends_on.iter().flatten() {
+                if !dependencies.contains_key(dependency.as_str()) {
+                    errors.push(anyhow::anyhow!(
+                        "Command '{}' depends on unknown command '{}'",
+                        command.name,
+                        dependency
+                    ));
+                }
+            }
+        }
+
+        // Depth-first search; a command reached again while still on the path closes a cycle
+        fn visit<'a>(
+            name: &'a str,
+            dependencies: &HashMap<&'a str, &'a [String]>,
+            path: &mut Vec<&'a str>,
+            done: &mut HashSet<&'a str>,
+            cycles: &mut Vec<String>,
+        ) {
+            if done.contains(name) {
+                return;
+            }
+            if let Some(start) = path.iter().position(|visited| *visited == name) {
+                let mut cycle = path[start..].to_vec();
+                cycle.push(name);
+                cycles.push(cycle.join(" -> "));
+                return;
+            }
+            path.push(name);
+            for dependency in dependencies.get(name).copied().unwrap_or_default() {
+                visit(dependency, dependencies, path, done, cycles);
+            }
+            path.pop();
+            done.insert(name);
+        }
+        let mut done = HashSet::new();
+        let mut cycles = Vec::new();
+        for command in &self.commands {
+            visit(
+                &command.name,
+                &dependencies,
+                &mut Vec::new(),
+                &mut done,
+                &mut cycles,
+            );
+        }
+        errors.extend(
+            cycles.into_iter().map(|cycle| {
+                anyhow::anyhow!("Commands depend on each other in a cycle: {}", cycle)
+            }),
+        );
+        errors
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::{TimeZone, Utc};
 
     fn create_temp_config(content: &str) -> tempfile::TempDir {
         let dir = tempfile::tempdir().unwrap();
@@ -189,6 +1372,182 @@ mod tests {
         dir
     }
 
+    #[test]
+    fn test_config_include() {
+        let dir = create_temp_config(
+            r#"
+[general]
+include = ["commands/*.toml"]
+
+[[commands]]
+name = "base"
+command = "echo base"
+interval_minutes = 5.0
+"#,
+        );
+        let commands = dir.path().join("commands");
+        std::fs::create_dir(&commands).unwrap();
+        std::fs::write(
+            commands.join("backups.toml"),
+            r#"
+[[commands]]
+name = "backup"
+command = "backup.sh"
+cron = "@daily"
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            commands.join("reports.toml"),
+            r#"
+[[commands]]
+name = "report"
+command = "report.sh"
+interval_minutes = 60.0
+
+[[commands]]
+name = "cleanup"
+command = "cleanup.sh"
+interval_minutes = 60.0
+"#,
+        )
+        .unwrap();
+        std::fs::write(commands.join("notes.txt"), "not a configuration file").unwrap();
+
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        let names = config
+            .commands
+            .iter()
+            .map(|command| command.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["base", "backup", "report", "cleanup"]);
+
+        // A name used in two files is rejected
+        std::fs::write(
+            commands.join("more.toml"),
+            "[[commands]]\nname = \"backup\"\ncommand = \"other.sh\"\ninterval_minutes = 5.0\n",
+        )
+        .unwrap();
+        assert!(Config::load(&dir.path().join("scheduler.toml"))
+            .unwrap_err()
+            .to_string()
+            .contains("Duplicate command name 'backup'"));
+
+        // Included files only hold commands
+        std::fs::write(
+            commands.join("more.toml"),
+            "[general]\nmin_interval_seconds = 5\n",
+        )
+        .unwrap();
+        assert!(Config::load(&dir.path().join("scheduler.toml"))
+            .unwrap_err()
+            .to_string()
+            .contains("more.toml"));
+    }
+
+    #[test]
+    fn test_commands_tagged() {
+        let dir = create_temp_config(
+            r#"
+[[commands]]
+name = "db-backup"
+command = "backup.sh db"
+interval_minutes = 60.0
+tags = ["backups", "nightly"]
+
+[[commands]]
+name = "report"
+command = "report.sh"
+interval_minutes = 60.0
+tags = ["reports"]
+
+[[commands]]
+name = "home-backup"
+command = "backup.sh home"
+interval_minutes = 60.0
+enabled = false
+tags = ["backups"]
+
+[[commands]]
+name = "cleanup"
+command = "cleanup.sh"
+interval_minutes = 60.0
+"#,
+        );
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        let tagged = |tag| {
+            config
+                .commands_tagged(tag)
+                .iter()
+                .map(|command| command.name.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tagged("backups"), ["db-backup", "home-backup"]);
+        assert_eq!(tagged("reports"), ["report"]);
+        assert!(tagged("missing").is_empty());
+        assert!(tagged("backup").is_empty());
+
+        let command = CommandConfig {
+            name: "blank".to_string(),
+            command: "true".to_string(),
+            interval_minutes: Some(1.0),
+            tags: Some(vec![" ".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            command.validate().unwrap_err().to_string(),
+            "Command 'blank' has an empty tag"
+        );
+    }
+
+    #[test]
+    fn test_config_commands_dir() {
+        let dir = create_temp_config(
+            r#"
+[general]
+commands_dir = "commands.d"
+
+[[commands]]
+name = "base"
+command = "echo base"
+interval_minutes = 5.0
+"#,
+        );
+        let commands = dir.path().join("commands.d");
+        std::fs::create_dir(&commands).unwrap();
+        std::fs::write(
+            commands.join("20-report.toml"),
+            "name = \"report\"\ncommand = \"report.sh\"\ninterval_minutes = 60.0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            commands.join("10-backup.toml"),
+            "name = \"backup\"\ncommand = \"backup.sh\"\ncron = \"@daily\"\n",
+        )
+        .unwrap();
+        std::fs::write(commands.join("README"), "not a command").unwrap();
+
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        let names = config
+            .commands
+            .iter()
+            .map(|command| command.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["base", "backup", "report"]);
+        assert_eq!(config.commands[1].cron.as_deref(), Some("@daily"));
+
+        // A malformed file is reported by name
+        std::fs::write(
+            commands.join("30-broken.toml"),
+            "name = \"broken\"\ncommand =\n",
+        )
+        .unwrap();
+        let error = Config::load(&dir.path().join("scheduler.toml"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("30-broken.toml"), "{}", error);
+    }
+
     #[test]
     fn test_load_valid_config() {
         let config_content = r#"
@@ -325,4 +1684,1076 @@ interval_minutes = 10.0
             .to_string()
             .contains("Duplicate command name"));
     }
+
+    #[test]
+    fn test_config_max_concurrent() {
+        let config_content = r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+max_concurrent = 2
+
+[[commands]]
+name = "test_cmd"
+command = "echo hello"
+interval_minutes = 5.0
+"#;
+        let dir = create_temp_config(config_content);
+        let config_path = dir.path().join("scheduler.toml");
+        let config = Config::load(&config_path).unwrap();
+        assert_eq!(config.general.max_concurrent, Some(2));
+
+        let dir = create_temp_config(&config_content.replace("= 2", "= 0"));
+        let config_path = dir.path().join("scheduler.toml");
+        let result = Config::load(&config_path);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("max_concurrent must be at least 1"));
+    }
+
+    #[test]
+    fn test_config_log_level_validation() {
+        let config_content = r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+log_level = "Debug"
+
+[[commands]]
+name = "test_cmd"
+command = "echo hello"
+interval_minutes = 5.0
+"#;
+        let dir = create_temp_config(config_content);
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(config.general.log_level, "Debug");
+
+        let dir = create_temp_config(&config_content.replace("\"Debug\"", "\"loud\""));
+        let error = Config::load(&dir.path().join("scheduler.toml")).unwrap_err();
+        assert!(error.to_string().contains("Invalid log_level 'loud'"));
+    }
+
+    #[test]
+    fn test_unset_env_vars_falls_back_to_general() {
+        let dir = create_temp_config(
+            r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+unset_env_vars = "empty"
+
+[[commands]]
+name = "inherits"
+command = "echo $NAME"
+interval_minutes = 5.0
+
+[[commands]]
+name = "overrides"
+command = "echo $NAME"
+interval_minutes = 5.0
+unset_env_vars = "keep"
+"#,
+        );
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(config.commands[0].unset_env_vars, Some(UnsetEnvVars::Empty));
+        assert_eq!(config.commands[1].unset_env_vars, Some(UnsetEnvVars::Keep));
+        assert_eq!(
+            CommandConfig::default().unset_env_vars.unwrap_or_default(),
+            UnsetEnvVars::Keep
+        );
+    }
+
+    #[test]
+    fn test_config_sleep_detection() {
+        let dir = create_temp_config(
+            r#"
+[general]
+sleep_detection = "system"
+sleep_detection_threshold_minutes = 15
+
+[[commands]]
+name = "backup"
+command = "backup.sh"
+interval_minutes = 60.0
+"#,
+        );
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(config.general.sleep_detection, SleepDetection::System);
+        assert_eq!(config.general.sleep_detection_threshold_minutes, 15);
+        let general = GeneralConfig {
+            sleep_detection_threshold_minutes: 0,
+            ..Default::default()
+        };
+        assert!(general
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("sleep_detection_threshold_minutes must be at least 1"));
+        assert_eq!(
+            GeneralConfig::default().sleep_detection,
+            SleepDetection::Heuristic
+        );
+
+        let dir = create_temp_config(
+            r#"
+[general]
+sleep_detection = "sometimes"
+
+[[commands]]
+name = "backup"
+command = "backup.sh"
+interval_minutes = 60.0
+"#,
+        );
+        assert!(Config::load(&dir.path().join("scheduler.toml"))
+            .unwrap_err()
+            .to_string()
+            .contains("sometimes"));
+    }
+
+    #[test]
+    fn test_jitter_falls_back_to_general() {
+        let dir = create_temp_config(
+            r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+jitter_seconds = 300
+startup_splay_seconds = 60
+
+[[commands]]
+name = "inherits"
+command = "true"
+interval_minutes = 60.0
+
+[[commands]]
+name = "overrides"
+command = "true"
+interval_minutes = 60.0
+jitter_seconds = 0
+"#,
+        );
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(config.general.startup_splay_seconds, 60);
+        assert_eq!(config.commands[0].jitter_seconds, Some(300));
+        assert_eq!(config.commands[1].jitter_seconds, Some(0));
+    }
+
+    #[test]
+    fn test_config_log_format() {
+        let config_content = r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+
+[[commands]]
+name = "test_cmd"
+command = "echo hello"
+interval_minutes = 5.0
+"#;
+        let dir = create_temp_config(config_content);
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(config.general.log_format, LogFormat::Text);
+
+        let dir = create_temp_config(
+            &config_content.replace("[general]\n", "[general]\nlog_format = \"json\"\n"),
+        );
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(config.general.log_format, LogFormat::Json);
+
+        assert_eq!("JSON".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn test_config_log_dir_and_rotation_defaults() {
+        let config_content = r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+log_dir = "~/zephyr-logs"
+max_log_size_mb = 2
+max_output_bytes = 1024
+
+[[commands]]
+name = "from_dir"
+command = "echo hello"
+interval_minutes = 5.0
+
+[[commands]]
+name = "own_file"
+command = "echo hello"
+interval_minutes = 5.0
+log_file = "/var/log/own.log"
+log_files_kept = 1
+max_output_bytes = 65536
+"#;
+        let dir = create_temp_config(config_content);
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(
+            config.commands[0].log_file,
+            Some(PathBuf::from("~/zephyr-logs/from_dir.log"))
+        );
+        assert_eq!(
+            config.commands[0].log_rotation(),
+            LogRotation {
+                max_bytes: 2 * 1024 * 1024,
+                keep: 5
+            }
+        );
+        assert_eq!(
+            config.commands[1].log_file,
+            Some(PathBuf::from("/var/log/own.log"))
+        );
+        assert_eq!(config.commands[1].log_rotation().keep, 1);
+        assert_eq!(config.commands[0].output_limit(), 1024);
+        assert_eq!(config.commands[1].output_limit(), 65536);
+
+        let dir = create_temp_config(&config_content.replace("log_dir = \"~/zephyr-logs\"\n", ""));
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(config.commands[0].log_file, None);
+    }
+
+    #[test]
+    fn test_config_server_sections() {
+        let config_content = r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+
+[http]
+listen_addr = "127.0.0.1:9191"
+
+[[commands]]
+name = "test_cmd"
+command = "echo hello"
+interval_minutes = 5.0
+"#;
+        let dir = create_temp_config(config_content);
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        let http = config.http.unwrap();
+        assert_eq!(
+            http.socket_addr().unwrap(),
+            "127.0.0.1:9191".parse().unwrap()
+        );
+
+        let dir = create_temp_config(&config_content.replace("127.0.0.1:9191", "localhost"));
+        let err = Config::load(&dir.path().join("scheduler.toml")).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("[http] Invalid listen_addr 'localhost'"));
+
+        let dir = create_temp_config(
+            &config_content.replace("[http]\nlisten_addr = \"127.0.0.1:9191\"\n", ""),
+        );
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert!(config.http.is_none());
+        assert!(config.metrics.is_none());
+
+        let dir = create_temp_config(&config_content.replace("[http]", "[metrics]"));
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert!(config.http.is_none());
+        assert_eq!(config.metrics.unwrap().listen_addr, "127.0.0.1:9191");
+        let dir = create_temp_config(&config_content.replace(
+            "[http]\nlisten_addr = \"127.0.0.1:9191\"",
+            "[api]\nlisten = \"127.0.0.1:8775\"",
+        ));
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(
+            config.http.unwrap().socket_addr().unwrap(),
+            "127.0.0.1:8775".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_config_history_retention_days() {
+        let config_content = r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+
+[[commands]]
+name = "test_cmd"
+command = "echo hello"
+interval_minutes = 5.0
+"#;
+        let dir = create_temp_config(config_content);
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(config.general.history_retention_days, 30);
+        assert_eq!(config.general.shutdown_timeout_seconds, 30);
+
+        let dir = create_temp_config(
+            &config_content.replace("[general]\n", "[general]\nhistory_retention_days = 0\n"),
+        );
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(config.general.history_retention_days, 0);
+    }
+
+    #[test]
+    fn test_schedule_description() {
+        let interval = CommandConfig {
+            name: "interval".to_string(),
+            interval_minutes: Some(1.5),
+            ..Default::default()
+        };
+        assert_eq!(interval.schedule_description(), "every 1.5 minutes");
+
+        let cron = CommandConfig {
+            name: "cron".to_string(),
+            cron: Some("0 0 * * * *".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(cron.schedule_description(), "cron: 0 0 * * * *");
+
+        let zoned = CommandConfig {
+            timezone: Some("Europe/Berlin".to_string()),
+            ..cron
+        };
+        assert_eq!(
+            zoned.schedule_description(),
+            "cron: 0 0 * * * * (Europe/Berlin)"
+        );
+    }
+
+    #[test]
+    fn test_config_validation_timezone() {
+        let mut command = CommandConfig {
+            name: "zoned".to_string(),
+            command: "true".to_string(),
+            cron: Some("0 0 8 * * *".to_string()),
+            timezone: Some("America/New_York".to_string()),
+            ..Default::default()
+        };
+        assert!(command.validate().is_ok());
+        assert_eq!(command.timezone().unwrap(), Tz::America__New_York);
+
+        command.timezone = None;
+        assert_eq!(command.timezone().unwrap(), Tz::UTC);
+
+        command.timezone = Some("Mars/Olympus_Mons".to_string());
+        let error = command.validate().unwrap_err().to_string();
+        assert!(error.contains("Invalid timezone for command 'zoned'"));
+    }
+
+    #[test]
+    fn test_config_validation_retry_limits() {
+        let command = CommandConfig {
+            name: "flaky".to_string(),
+            command: "true".to_string(),
+            interval_minutes: Some(5.0),
+            max_retries: Some(3),
+            retry_backoff_multiplier: Some(2.0),
+            ..Default::default()
+        };
+        assert!(command.validate().is_ok());
+
+        let too_many = CommandConfig {
+            max_retries: Some(11),
+            ..command.clone()
+        };
+        assert!(too_many
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("max_retries cannot be greater than 10"));
+
+        let shrinking = CommandConfig {
+            retry_backoff_multiplier: Some(0.5),
+            ..command
+        };
+        assert!(shrinking
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("retry_backoff_multiplier must be between 1 and 10"));
+    }
+
+    #[test]
+    fn test_config_args() {
+        let config_content = r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+
+[[commands]]
+name = "sync"
+args = ["rsync", "-a", "/src dir/", "/dst/", "it's"]
+interval_minutes = 5.0
+"#;
+        let dir = create_temp_config(config_content);
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        let command = &config.commands[0];
+        assert_eq!(command.command, "");
+        assert_eq!(command.args.as_ref().unwrap().len(), 5);
+        assert_eq!(
+            command.command_line(),
+            r#"rsync -a '/src dir/' /dst/ 'it'\''s'"#
+        );
+
+        let dir = create_temp_config(
+            &config_content.replace("interval_minutes", "command = \"rsync\"\ninterval_minutes"),
+        );
+        let err = Config::load(&dir.path().join("scheduler.toml")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("cannot specify both command and args"));
+
+        let dir = create_temp_config(
+            &config_content.replace(r#"["rsync", "-a", "/src dir/", "/dst/", "it's"]"#, "[]"),
+        );
+        let err = Config::load(&dir.path().join("scheduler.toml")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("args must include the program to run"));
+
+        let dir = create_temp_config(&config_content.replace(
+            "args = [\"rsync\", \"-a\", \"/src dir/\", \"/dst/\", \"it's\"]\n",
+            "",
+        ));
+        let err = Config::load(&dir.path().join("scheduler.toml")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("must specify either command or args"));
+    }
+
+    #[test]
+    fn test_shell_program_flag() {
+        let argv = |program: &str| Shell::Program(program.to_string()).argv();
+        assert_eq!(argv("/bin/bash"), ["/bin/bash", "-c"]);
+        assert_eq!(argv("cmd"), ["cmd", "/C"]);
+        assert_eq!(argv("C:\\Windows\\System32\\cmd.exe")[1], "/C");
+        assert_eq!(argv("powershell.exe"), ["powershell.exe", "-Command"]);
+        assert_eq!(argv("pwsh"), ["pwsh", "-Command"]);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_defaults() {
+        assert_eq!(Shell::platform_default().argv(), ["cmd", "/C"]);
+        let state_path = default_state_path();
+        assert!(state_path.starts_with(dirs::data_local_dir().unwrap()));
+        assert!(state_path.ends_with("zephyr\\state.db"));
+    }
+
+    #[test]
+    fn test_config_shell() {
+        let config_content = r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+shell = "sh"
+
+[[commands]]
+name = "default_shell"
+command = "echo hello"
+interval_minutes = 5.0
+
+[[commands]]
+name = "own_shell"
+command = "echo hello"
+interval_minutes = 5.0
+shell = ["sh", "-e", "-c"]
+
+[[commands]]
+name = "no_shell"
+args = ["echo", "hello"]
+interval_minutes = 5.0
+
+[[commands]]
+name = "in_container"
+command = "echo hello"
+interval_minutes = 5.0
+container_image = "alpine"
+"#;
+        let dir = create_temp_config(config_content);
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(
+            config.commands[0].shell.as_ref().unwrap().argv(),
+            ["sh", "-c"]
+        );
+        assert_eq!(
+            config.commands[1].shell,
+            Some(Shell::Argv(vec![
+                "sh".to_string(),
+                "-e".to_string(),
+                "-c".to_string()
+            ]))
+        );
+        assert_eq!(config.commands[2].shell, None);
+        assert_eq!(config.commands[3].shell, None);
+
+        let dir = create_temp_config(&config_content.replace("\"alpine\"", "\" \""));
+        let err = Config::load(&dir.path().join("scheduler.toml")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Command 'in_container' has an empty container_image"
+        );
+
+        let dir = create_temp_config(&config_content.replace(
+            "container_image = \"alpine\"",
+            "container_image = \"alpine\"\nrun_as_user = \"nobody\"",
+        ));
+        let err = Config::load(&dir.path().join("scheduler.toml")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Command 'in_container' cannot use run_as_user with container_image"
+        );
+
+        let dir = create_temp_config(
+            &config_content.replace("[\"sh\", \"-e\", \"-c\"]", "\"/nonexistent/fish\""),
+        );
+        let err = Config::load(&dir.path().join("scheduler.toml")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Command 'own_shell' shell '/nonexistent/fish' was not found or is not executable"
+        );
+
+        let dir = create_temp_config(&config_content.replace(
+            "shell = [\"sh\", \"-e\", \"-c\"]",
+            "shell = \"/nonexistent/fish\"\ncontainer_image = \"alpine\"",
+        ));
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(
+            config.commands[1].shell,
+            Some(Shell::Program("/nonexistent/fish".to_string()))
+        );
+
+        let dir = create_temp_config(&config_content.replace("[\"sh\", \"-e\", \"-c\"]", "[]"));
+        let err = Config::load(&dir.path().join("scheduler.toml")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("shell must include the program to run"));
+    }
+
+    #[test]
+    fn test_normalize_cron() {
+        assert_eq!(normalize_cron("0 30 9 * * Mon-Fri"), "0 30 9 * * Mon-Fri");
+        assert_eq!(normalize_cron("0 0 12 1 1 * 2030"), "0 0 12 1 1 * 2030");
+        assert_eq!(normalize_cron(" 0 8 * * * "), "0 0 8 * * *");
+        assert_eq!(normalize_cron("*/15 * * * MON-FRI"), "0 */15 * * * MON-FRI");
+        // Crontab counts days from Sunday = 0, the cron crate from Sunday = 1
+        assert_eq!(normalize_cron("0 8 * * 1-5"), "0 0 8 * * 2,3,4,5,6");
+        assert_eq!(normalize_cron("0 8 * * 0,7"), "0 0 8 * * 1,1");
+        assert_eq!(normalize_cron("0 8 * * 5-7"), "0 0 8 * * 1,6,7");
+        assert_eq!(normalize_cron("0 8 * * */2"), "0 0 8 * * 1,3,5,7");
+        assert_eq!(normalize_cron("@hourly"), "0 0 * * * *");
+        assert_eq!(normalize_cron("@daily"), "0 0 0 * * *");
+        assert_eq!(normalize_cron("@weekly"), "0 0 0 * * Sun");
+        assert_eq!(normalize_cron("@monthly"), "0 0 0 1 * *");
+
+        for expression in ["0 8 * * *", "0 8 * * 0,7", "@daily", "0 0 8 * * *"] {
+            assert!(parse_cron(expression).is_ok(), "{}", expression);
+        }
+        assert!(parse_cron("0 8 * *").is_err());
+    }
+
+    #[test]
+    fn test_parse_run_window() {
+        let window = "09:00-17:00".parse::<RunWindow>().unwrap();
+        let time = |s: &str| NaiveTime::parse_from_str(s, "%H:%M").unwrap();
+        assert!(window.contains(time("09:00")));
+        assert!(window.contains(time("16:59")));
+        assert!(!window.contains(time("17:00")));
+        assert!(!window.contains(time("08:59")));
+
+        let overnight = " 22:00 - 06:00 ".parse::<RunWindow>().unwrap();
+        assert!(overnight.contains(time("23:00")));
+        assert!(overnight.contains(time("00:00")));
+        assert!(overnight.contains(time("05:59")));
+        assert!(!overnight.contains(time("06:00")));
+        assert!(!overnight.contains(time("12:00")));
+
+        for invalid in ["09:00", "9am-5pm", "25:00-06:00", "10:00-10:00"] {
+            assert!(invalid.parse::<RunWindow>().is_err(), "{}", invalid);
+        }
+
+        let command = CommandConfig {
+            name: "windowed".to_string(),
+            command: "echo".to_string(),
+            interval_minutes: Some(60.0),
+            run_window: Some("22:00-6".to_string()),
+            ..Default::default()
+        };
+        assert!(command
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid run_window for command 'windowed'"));
+    }
+
+    #[test]
+    fn test_parse_run_window_days() {
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        // 2026-10-16 is a Friday
+        let weekdays = "Mon-Fri 09:00-17:00".parse::<RunWindow>().unwrap();
+        assert!(weekdays.contains_datetime(at("2026-10-16 10:00")));
+        assert!(!weekdays.contains_datetime(at("2026-10-17 10:00")));
+        assert_eq!(
+            weekdays.next_opening(at("2026-10-16 18:00")),
+            Some(at("2026-10-19 09:00"))
+        );
+        assert_eq!(
+            weekdays.closing(at("2026-10-16 10:00")),
+            Some(at("2026-10-16 17:00"))
+        );
+        assert_eq!(weekdays.closing(at("2026-10-17 10:00")), None);
+
+        // An overnight window belongs to the day it opens on
+        let friday_night = "fri 22:00-06:00".parse::<RunWindow>().unwrap();
+        assert!(friday_night.contains_datetime(at("2026-10-17 03:00")));
+        assert!(!friday_night.contains_datetime(at("2026-10-16 03:00")));
+        assert_eq!(
+            friday_night.closing(at("2026-10-17 03:00")),
+            Some(at("2026-10-17 06:00"))
+        );
+
+        let weekend = "Sat,Sun 10:00-12:00".parse::<RunWindow>().unwrap();
+        assert_eq!(
+            weekend.days,
+            [false, false, false, false, false, true, true]
+        );
+        let wrapping = "Fri-Mon 10:00-12:00".parse::<RunWindow>().unwrap();
+        assert_eq!(wrapping.days, [true, false, false, false, true, true, true]);
+
+        for invalid in ["Funday 09:00-17:00", "Mon- 09:00-17:00", "Mon,"] {
+            assert!(invalid.parse::<RunWindow>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_allowed_and_blocked_windows() {
+        let dir = create_temp_config(
+            r#"
+[[commands]]
+name = "nightly"
+command = "true"
+interval_minutes = 60.0
+allowed_window = "22:00-06:00"
+blocked_window = "Sat 00:00-06:00"
+timezone = "Europe/Berlin"
+"#,
+        );
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        let command = &config.commands[0];
+        assert_eq!(command.run_window.as_deref(), Some("22:00-06:00"));
+        assert!(command.blocked_window().unwrap().is_some());
+
+        let mut command = command.clone();
+        command.blocked_window = Some("never".to_string());
+        assert!(command
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("Invalid blocked_window for command 'nightly'"));
+    }
+
+    #[test]
+    fn test_align_to_interval_requires_interval() {
+        let command = CommandConfig {
+            name: "aligned".to_string(),
+            command: "echo".to_string(),
+            cron: Some("0 0 * * * *".to_string()),
+            align_to_interval: true,
+            ..Default::default()
+        };
+        assert!(command
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("align_to_interval"));
+
+        let command = CommandConfig {
+            cron: None,
+            interval_minutes: Some(15.0),
+            ..command
+        };
+        assert!(command.validate().is_ok());
+    }
+
+    #[test]
+    fn test_success_exit_codes() {
+        let mut command = CommandConfig {
+            name: "sync".to_string(),
+            command: "rsync".to_string(),
+            interval_minutes: Some(5.0),
+            ..Default::default()
+        };
+        assert!(command.is_success(0));
+        assert!(!command.is_success(24));
+
+        command.success_exit_codes = Some(vec![0, 24]);
+        assert!(command.validate().is_ok());
+        assert!(command.is_success(24));
+        assert!(!command.is_success(1));
+
+        command.success_exit_codes = Some(vec![]);
+        assert!(command
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("success_exit_codes must not be empty"));
+    }
+
+    #[test]
+    fn test_disable_after_failures() {
+        let mut command = CommandConfig {
+            name: "flaky".to_string(),
+            command: "true".to_string(),
+            interval_minutes: Some(5.0),
+            ..Default::default()
+        };
+        assert!(!command.exceeds_failure_limit(100));
+
+        command.disable_after_failures = Some(3);
+        assert!(command.validate().is_ok());
+        assert!(!command.exceeds_failure_limit(2));
+        assert!(command.exceeds_failure_limit(3));
+
+        command.disable_after_failures = Some(0);
+        assert!(command
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("disable_after_failures must be at least 1"));
+    }
+
+    #[test]
+    fn test_failure_backoff_delay() {
+        let mut command = CommandConfig {
+            name: "flaky".to_string(),
+            command: "true".to_string(),
+            interval_minutes: Some(5.0),
+            ..Default::default()
+        };
+        let period = chrono::Duration::minutes(5);
+        assert_eq!(command.failure_backoff_delay(period, 3), None);
+
+        command.failure_backoff = true;
+        command.failure_backoff_max_minutes = Some(60);
+        assert!(command.validate().is_ok());
+        assert_eq!(command.failure_backoff_delay(period, 0), None);
+        assert_eq!(
+            command.failure_backoff_delay(period, 1),
+            Some(chrono::Duration::minutes(10))
+        );
+        assert_eq!(
+            command.failure_backoff_delay(period, 3),
+            Some(chrono::Duration::minutes(40))
+        );
+        assert_eq!(
+            command.failure_backoff_delay(period, 100),
+            Some(chrono::Duration::minutes(60))
+        );
+        // A cap below the schedule's own period never makes runs more frequent
+        assert_eq!(
+            command.failure_backoff_delay(chrono::Duration::hours(2), 1),
+            Some(chrono::Duration::hours(2))
+        );
+
+        command.failure_backoff_max_minutes = None;
+        assert_eq!(
+            command.failure_backoff_delay(period, 100),
+            Some(chrono::Duration::days(1))
+        );
+
+        command.failure_backoff_max_minutes = Some(0);
+        assert!(command
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("failure_backoff_max_minutes must be at least 1"));
+    }
+
+    #[test]
+    fn test_max_staleness_minutes() {
+        let mut command = CommandConfig {
+            name: "backup".to_string(),
+            command: "true".to_string(),
+            interval_minutes: Some(60.0),
+            max_staleness_minutes: Some(90),
+            ..Default::default()
+        };
+        assert!(command.validate().is_ok());
+
+        command.max_staleness_minutes = Some(0);
+        assert!(command
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("max_staleness_minutes must be at least 1"));
+    }
+
+    #[test]
+    fn test_at_schedule() {
+        let mut command = CommandConfig {
+            name: "migrate".to_string(),
+            command: "migrate.sh".to_string(),
+            at: Some("2030-06-01T03:00:00+02:00".to_string()),
+            ..Default::default()
+        };
+        assert!(command.validate().is_ok());
+        assert_eq!(
+            command.schedule().unwrap(),
+            Some(ScheduleSpec::At(
+                Utc.with_ymd_and_hms(2030, 6, 1, 1, 0, 0).unwrap()
+            ))
+        );
+        assert_eq!(
+            command.schedule_description(),
+            "once at 2030-06-01T03:00:00+02:00"
+        );
+
+        command.cron = Some("@daily".to_string());
+        assert_eq!(
+            command.validate().unwrap_err().to_string(),
+            "Command 'migrate' cannot specify both cron and at"
+        );
+
+        command.cron = None;
+        command.at = Some("tomorrow".to_string());
+        assert!(command
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("invalid at time 'tomorrow'"));
+
+        let dir = create_temp_config(
+            r#"
+[[commands]]
+name = "migrate"
+command = "migrate.sh"
+run_at = "2030-06-01T03:00:00Z"
+"#,
+        );
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(
+            config.commands[0].at.as_deref(),
+            Some("2030-06-01T03:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_config_depends_on() {
+        let config_content = r#"
+[[commands]]
+name = "fetch-data"
+command = "echo fetch"
+interval_minutes = 60.0
+
+[[commands]]
+name = "process"
+command = "echo process"
+depends_on = ["fetch-data"]
+"#;
+        let dir = create_temp_config(config_content);
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(
+            config.commands[1].depends_on,
+            Some(vec!["fetch-data".to_string()])
+        );
+        assert_eq!(
+            config.commands[1].schedule_description(),
+            "after fetch-data"
+        );
+        assert!(config.commands[1].runs_after_dependencies());
+
+        let dir = create_temp_config(&config_content.replace(
+            r#"depends_on = ["fetch-data"]"#,
+            r#"depends_on = ["fetch"]
+interval_minutes = 5.0"#,
+        ));
+        let config = Config::parse(&dir.path().join("scheduler.toml")).unwrap();
+        // A command with a schedule of its own runs on it, once its dependencies succeeded
+        assert_eq!(
+            config.commands[1].schedule_description(),
+            "every 5 minutes, after fetch"
+        );
+        assert!(!config.commands[1].runs_after_dependencies());
+        let errors = config
+            .validate()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            ["Command 'process' depends on unknown command 'fetch'"]
+        );
+    }
+
+    #[test]
+    fn test_config_rejects_dependency_cycles() {
+        let config_content = r#"
+[[commands]]
+name = "a"
+command = "echo a"
+depends_on = ["c"]
+
+[[commands]]
+name = "b"
+command = "echo b"
+interval_minutes = 5.0
+depends_on = ["a"]
+
+[[commands]]
+name = "c"
+command = "echo c"
+depends_on = ["b"]
+
+[[commands]]
+name = "self"
+command = "echo self"
+depends_on = ["self"]
+"#;
+        let dir = create_temp_config(config_content);
+        let config = Config::parse(&dir.path().join("scheduler.toml")).unwrap();
+        let errors = config
+            .validate()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "Commands depend on each other in a cycle: a -> c -> b -> a",
+                "Commands depend on each other in a cycle: self -> self",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_on_failure_webhook() {
+        let config_content = r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+on_failure_webhook = "https://alerts.example.com/zephyr"
+
+[[commands]]
+name = "default_hook"
+command = "echo hello"
+interval_minutes = 5.0
+
+[[commands]]
+name = "own_hook"
+command = "echo hello"
+interval_minutes = 5.0
+on_failure_webhook = "http://localhost:8080/hook"
+"#;
+        let dir = create_temp_config(config_content);
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(
+            config.commands[0].on_failure_webhook.as_deref(),
+            Some("https://alerts.example.com/zephyr")
+        );
+        assert_eq!(
+            config.commands[1].on_failure_webhook.as_deref(),
+            Some("http://localhost:8080/hook")
+        );
+
+        let dir =
+            create_temp_config(&config_content.replace("http://localhost", "ftp://localhost"));
+        let err = Config::load(&dir.path().join("scheduler.toml")).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("on_failure_webhook must be an http or https URL"));
+    }
+
+    #[test]
+    fn test_config_notify_on_failure() {
+        let dir = create_temp_config(
+            r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+notify_on_failure = true
+
+[[commands]]
+name = "inherits"
+command = "echo hello"
+interval_minutes = 5.0
+
+[[commands]]
+name = "quiet"
+command = "echo hello"
+interval_minutes = 5.0
+notify_on_failure = false
+"#,
+        );
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(config.commands[0].notify_on_failure, Some(true));
+        assert_eq!(config.commands[1].notify_on_failure, Some(false));
+    }
+
+    #[test]
+    fn test_config_overlap_policy() {
+        let config_content = r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+
+[[commands]]
+name = "default_cmd"
+command = "echo default"
+interval_minutes = 5.0
+
+[[commands]]
+name = "queued_cmd"
+command = "echo queued"
+interval_minutes = 5.0
+overlap_policy = "queue"
+"#;
+        let dir = create_temp_config(config_content);
+        let config_path = dir.path().join("scheduler.toml");
+        let config = Config::load(&config_path).unwrap();
+        assert_eq!(config.commands[0].overlap_policy, OverlapPolicy::Skip);
+        assert_eq!(config.commands[1].overlap_policy, OverlapPolicy::Queue);
+
+        let dir = create_temp_config(&config_content.replace("\"queue\"", "\"sometimes\""));
+        let config_path = dir.path().join("scheduler.toml");
+        assert!(Config::load(&config_path).is_err());
+    }
+
+    #[test]
+    fn test_config_catch_up_policy() {
+        let config_content = r#"
+[[commands]]
+name = "default_cmd"
+command = "echo default"
+interval_minutes = 5.0
+
+[[commands]]
+name = "all_cmd"
+command = "echo all"
+interval_minutes = 5.0
+catch_up = "run_all"
+
+[[commands]]
+name = "skip_cmd"
+command = "echo skip"
+interval_minutes = 5.0
+catch_up = "skip"
+"#;
+        let dir = create_temp_config(config_content);
+        let config = Config::load(&dir.path().join("scheduler.toml")).unwrap();
+        assert_eq!(config.commands[0].catch_up, CatchUpPolicy::RunOnce);
+        assert_eq!(config.commands[1].catch_up, CatchUpPolicy::RunAll);
+        assert_eq!(config.commands[2].catch_up, CatchUpPolicy::Skip);
+    }
+
+    #[test]
+    fn test_config_validate_collects_all_errors() {
+        let config_content = r#"
+[general]
+state_path = "/tmp/zephyr/state.db"
+
+[[commands]]
+name = "good_cmd"
+command = "echo good"
+interval_minutes = 5.0
+
+[[commands]]
+name = "no_schedule"
+command = "echo test"
+
+[[commands]]
+name = "bad_cron"
+command = "echo test"
+cron = "not a cron"
+"#;
+        let dir = create_temp_config(config_content);
+        let config_path = dir.path().join("scheduler.toml");
+        let config = Config::parse(&config_path).unwrap();
+        let errors = config.validate();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].to_string().contains("'no_schedule'"));
+        assert!(errors[1].to_string().contains("'bad_cron'"));
+    }
 }
@@ -0,0 +1,430 @@
+//! Schedules of commands, parsed from their `interval_minutes`, `cron` or `at` settings
+
+use super::{parse_cron, CommandConfig, IntervalAnchor};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use std::ops::Deref;
+
+/// When a command runs on its own, as opposed to after its `depends_on` commands
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScheduleSpec {
+    /// Runs `every` so often, counted from the previous run, or on multiples of `every`
+    /// since the Unix epoch when `aligned`
+    Interval { every: Duration, aligned: bool },
+    /// Runs whenever `schedule` matches wall-clock time in `timezone`
+    Cron {
+        /// The expression as written in the configuration
+        expression: String,
+        schedule: Box<cron::Schedule>,
+        timezone: Tz,
+    },
+    /// Runs once, at the given time
+    At(DateTime<Utc>),
+}
+
+impl ScheduleSpec {
+    /// Parses the schedule of a command, or returns `None` if it only has `depends_on`
+    pub fn from_command(command: &CommandConfig) -> anyhow::Result<Option<Self>> {
+        let set = [
+            ("interval_minutes", command.interval_minutes.is_some()),
+            ("cron", command.cron.is_some()),
+            ("at", command.at.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect::<Vec<_>>();
+        if let [first, second, ..] = set[..] {
+            anyhow::bail!(
+                "Command '{}' cannot specify both {} and {}",
+                command.name,
+                first,
+                second
+            );
+        }
+
+        let anchor_setting = if command.fixed_rate {
+            if command.interval_anchor == IntervalAnchor::Start {
+                anyhow::bail!(
+                    "Command '{}' cannot specify both fixed_rate and interval_anchor = \"start\"",
+                    command.name
+                );
+            }
+            "fixed_rate"
+        } else {
+            "interval_anchor"
+        };
+        let anchored = command.interval_anchor() != IntervalAnchor::Finish;
+
+        if let Some(interval) = command.interval_minutes {
+            if interval <= 0.0 {
+                anyhow::bail!(
+                    "Command '{}' interval_minutes must be positive, got {}",
+                    command.name,
+                    interval
+                );
+            }
+            let every = Duration::milliseconds(((interval * 60_000.0).round() as i64).max(1));
+            if command.align_to_interval && anchored {
+                anyhow::bail!(
+                    "Command '{}' cannot specify both align_to_interval and {}",
+                    command.name,
+                    anchor_setting
+                );
+            }
+            return Ok(Some(Self::Interval {
+                every,
+                aligned: command.align_to_interval,
+            }));
+        }
+        if anchored {
+            anyhow::bail!(
+                "Command '{}' {} only applies to interval_minutes schedules",
+                command.name,
+                anchor_setting
+            );
+        }
+        if command.align_to_interval {
+            anyhow::bail!(
+                "Command '{}' align_to_interval only applies to interval_minutes schedules",
+                command.name
+            );
+        }
+        if let Some(expression) = &command.cron {
+            let schedule = parse_cron(expression).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid cron expression for command '{}': {}",
+                    command.name,
+                    e
+                )
+            })?;
+            return Ok(Some(Self::Cron {
+                expression: expression.clone(),
+                schedule: Box::new(schedule),
+                timezone: command.timezone()?,
+            }));
+        }
+        if let Some(at) = &command.at {
+            let at = DateTime::parse_from_rfc3339(at).map_err(|e| {
+                anyhow::anyhow!(
+                    "Command '{}' has an invalid at time '{}': {}; expected an RFC 3339 time \
+                     such as 2024-06-01T03:00:00Z",
+                    command.name,
+                    at,
+                    e
+                )
+            })?;
+            return Ok(Some(Self::At(at.with_timezone(&Utc))));
+        }
+        Ok(None)
+    }
+
+    /// Returns the first run strictly after `now`, or `None` if there is none, as for a
+    /// one-shot command whose time has come
+    pub fn next_after(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Interval {
+                every,
+                aligned: false,
+            } => Some(now + *every),
+            Self::Interval {
+                every,
+                aligned: true,
+            } => Some(Self::next_on_grid(DateTime::UNIX_EPOCH, *every, now)),
+            Self::Cron {
+                schedule, timezone, ..
+            } => schedule
+                .after(&now.with_timezone(timezone))
+                .next()
+                .map(|next| next.with_timezone(&Utc)),
+            Self::At(at) => (*at > now).then_some(*at),
+        }
+    }
+
+    /// Returns up to `count` runs after `now`, each following the one before it, as
+    /// [`cron::Schedule::after`] does for cron expressions
+    ///
+    /// Interval runs are counted from `now` as if every run started on time.
+    pub fn upcoming(&self, now: DateTime<Utc>, count: usize) -> Vec<DateTime<Utc>> {
+        std::iter::successors(self.next_after(now), |run| self.next_after(*run))
+            .take(count)
+            .collect()
+    }
+
+    /// Returns the first of `origin`, `origin + every`, `origin + 2 * every`, ... that is
+    /// strictly after `now`
+    pub fn next_on_grid(
+        origin: DateTime<Utc>,
+        every: Duration,
+        now: DateTime<Utc>,
+    ) -> DateTime<Utc> {
+        if origin > now {
+            return origin;
+        }
+        let every_ms = every.num_milliseconds().max(1);
+        let elapsed_ms = (now - origin).num_milliseconds();
+        origin + Duration::milliseconds((elapsed_ms / every_ms + 1) * every_ms)
+    }
+}
+
+/// A command along with its parsed schedule, so the schedule is parsed once when the command
+/// is scheduled rather than on every calculation
+#[derive(Debug, Clone)]
+pub struct ParsedCommand {
+    pub config: CommandConfig,
+    /// The schedule the command runs on by itself; `None` if it only has `depends_on`
+    pub schedule: Option<ScheduleSpec>,
+}
+
+impl ParsedCommand {
+    /// Parses the schedule of `config`
+    pub fn new(config: CommandConfig) -> anyhow::Result<Self> {
+        let schedule = ScheduleSpec::from_command(&config)?;
+        Ok(Self { config, schedule })
+    }
+}
+
+impl Deref for ParsedCommand {
+    type Target = CommandConfig;
+
+    fn deref(&self) -> &CommandConfig {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn schedule(command: CommandConfig) -> ScheduleSpec {
+        ScheduleSpec::from_command(&command).unwrap().unwrap()
+    }
+
+    fn interval(minutes: f64, aligned: bool) -> ScheduleSpec {
+        schedule(CommandConfig {
+            interval_minutes: Some(minutes),
+            align_to_interval: aligned,
+            ..Default::default()
+        })
+    }
+
+    fn cron(expression: &str, timezone: Option<&str>) -> ScheduleSpec {
+        schedule(CommandConfig {
+            cron: Some(expression.to_string()),
+            timezone: timezone.map(str::to_string),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_interval_next_after() {
+        let after = at("2026-10-15T10:07:31Z");
+        assert_eq!(
+            interval(60.0, false).next_after(after),
+            Some(at("2026-10-15T11:07:31Z"))
+        );
+        assert_eq!(
+            interval(0.5, false).next_after(after),
+            Some(at("2026-10-15T10:08:01Z"))
+        );
+        // 4.35 * 60000 is just under 261000 in floating point
+        assert_eq!(
+            interval(4.35, false).next_after(after),
+            Some(at("2026-10-15T10:11:52Z"))
+        );
+    }
+
+    #[test]
+    fn test_aligned_interval_next_after() {
+        let after = at("2026-10-15T10:07:31Z");
+        let next = |minutes, after| interval(minutes, true).next_after(after).unwrap();
+        assert_eq!(next(60.0, after), at("2026-10-15T11:00:00Z"));
+        assert_eq!(next(15.0, after), at("2026-10-15T10:15:00Z"));
+        assert_eq!(next(1440.0, after), at("2026-10-16T00:00:00Z"));
+        assert_eq!(next(0.5, after), at("2026-10-15T10:08:00Z"));
+        // A time exactly on the boundary moves on to the next one
+        assert_eq!(
+            next(15.0, at("2026-10-15T10:15:00Z")),
+            at("2026-10-15T10:30:00Z")
+        );
+    }
+
+    #[test]
+    fn test_next_on_grid() {
+        let origin = at("2026-10-15T10:00:00Z");
+        let every = Duration::minutes(10);
+        let next = |now| ScheduleSpec::next_on_grid(origin, every, at(now));
+        assert_eq!(next("2026-10-15T09:00:00Z"), origin);
+        assert_eq!(next("2026-10-15T10:00:00Z"), at("2026-10-15T10:10:00Z"));
+        assert_eq!(next("2026-10-15T10:34:59Z"), at("2026-10-15T10:40:00Z"));
+    }
+
+    #[test]
+    fn test_interval_anchor_requires_plain_interval() {
+        let error = |command: CommandConfig| {
+            ScheduleSpec::from_command(&CommandConfig {
+                name: "anchored".to_string(),
+                interval_anchor: IntervalAnchor::Fixed,
+                ..command
+            })
+            .unwrap_err()
+            .to_string()
+        };
+        assert_eq!(
+            error(CommandConfig {
+                cron: Some("@daily".to_string()),
+                ..Default::default()
+            }),
+            "Command 'anchored' interval_anchor only applies to interval_minutes schedules"
+        );
+        assert_eq!(
+            error(CommandConfig {
+                interval_minutes: Some(15.0),
+                align_to_interval: true,
+                ..Default::default()
+            }),
+            "Command 'anchored' cannot specify both align_to_interval and interval_anchor"
+        );
+
+        let fixed_rate = |command| {
+            ScheduleSpec::from_command(&CommandConfig {
+                name: "anchored".to_string(),
+                fixed_rate: true,
+                ..command
+            })
+        };
+        assert!(fixed_rate(CommandConfig {
+            interval_minutes: Some(15.0),
+            interval_anchor: IntervalAnchor::Fixed,
+            ..Default::default()
+        })
+        .is_ok());
+        assert_eq!(
+            fixed_rate(CommandConfig {
+                interval_minutes: Some(15.0),
+                interval_anchor: IntervalAnchor::Start,
+                ..Default::default()
+            })
+            .unwrap_err()
+            .to_string(),
+            "Command 'anchored' cannot specify both fixed_rate and interval_anchor = \"start\""
+        );
+        assert_eq!(
+            fixed_rate(CommandConfig {
+                cron: Some("@daily".to_string()),
+                ..Default::default()
+            })
+            .unwrap_err()
+            .to_string(),
+            "Command 'anchored' fixed_rate only applies to interval_minutes schedules"
+        );
+    }
+
+    #[test]
+    fn test_crontab_expressions_and_macros() {
+        // Thursday
+        let after = at("2026-10-15T10:07:31Z");
+        let next = |expression| cron(expression, None).next_after(after).unwrap();
+
+        assert_eq!(next("0 8 * * *"), at("2026-10-16T08:00:00Z"));
+        assert_eq!(next("30 * * * *"), at("2026-10-15T10:30:00Z"));
+        // Monday to Friday in crontab numbering, so Friday comes next
+        assert_eq!(next("0 8 * * 1-5"), at("2026-10-16T08:00:00Z"));
+        assert_eq!(next("0 8 * * 0"), at("2026-10-18T08:00:00Z"));
+        assert_eq!(next("@hourly"), at("2026-10-15T11:00:00Z"));
+        assert_eq!(next("@daily"), at("2026-10-16T00:00:00Z"));
+        assert_eq!(next("@weekly"), at("2026-10-18T00:00:00Z"));
+        assert_eq!(next("@monthly"), at("2026-11-01T00:00:00Z"));
+        assert_eq!(next("0 0 9 * * *"), at("2026-10-16T09:00:00Z"));
+    }
+
+    #[test]
+    fn test_cron_next_after_in_timezone_across_dst() {
+        let new_york = cron("0 0 8 * * *", Some("America/New_York"));
+
+        // 08:00 EST on the day before clocks spring forward is 13:00 UTC
+        let before_dst = new_york.next_after(at("2024-03-09T12:00:00Z")).unwrap();
+        assert_eq!(before_dst, at("2024-03-09T13:00:00Z"));
+
+        // The following morning 08:00 EDT is 12:00 UTC
+        let after_dst = new_york.next_after(before_dst).unwrap();
+        assert_eq!(after_dst, at("2024-03-10T12:00:00Z"));
+        assert_eq!(
+            after_dst
+                .with_timezone(&Tz::America__New_York)
+                .format("%H:%M")
+                .to_string(),
+            "08:00"
+        );
+
+        // Without a timezone the expression is evaluated in UTC
+        let utc = cron("0 0 8 * * *", None).next_after(before_dst).unwrap();
+        assert_eq!(utc, at("2024-03-10T08:00:00Z"));
+    }
+
+    #[test]
+    fn test_at_next_after() {
+        let one_shot = schedule(CommandConfig {
+            at: Some("2030-06-01T03:00:00+02:00".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(one_shot, ScheduleSpec::At(at("2030-06-01T01:00:00Z")));
+        assert_eq!(
+            one_shot.next_after(at("2030-05-31T00:00:00Z")),
+            Some(at("2030-06-01T01:00:00Z"))
+        );
+        assert_eq!(one_shot.next_after(at("2030-06-01T01:00:00Z")), None);
+    }
+
+    #[test]
+    fn test_upcoming() {
+        let now = at("2026-10-15T10:07:31Z");
+        assert_eq!(
+            interval(15.0, true).upcoming(now, 3),
+            [
+                at("2026-10-15T10:15:00Z"),
+                at("2026-10-15T10:30:00Z"),
+                at("2026-10-15T10:45:00Z")
+            ]
+        );
+        assert_eq!(
+            cron("0 30 9 * * Mon-Fri", None).upcoming(now, 3),
+            [
+                at("2026-10-16T09:30:00Z"),
+                at("2026-10-19T09:30:00Z"),
+                at("2026-10-20T09:30:00Z")
+            ]
+        );
+        let one_shot = ScheduleSpec::At(at("2030-06-01T01:00:00Z"));
+        assert_eq!(one_shot.upcoming(now, 5), [at("2030-06-01T01:00:00Z")]);
+        assert!(interval(5.0, false).upcoming(now, 0).is_empty());
+    }
+
+    #[test]
+    fn test_from_command_without_schedule() {
+        let command = CommandConfig {
+            name: "process".to_string(),
+            depends_on: Some(vec!["fetch".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(ScheduleSpec::from_command(&command).unwrap(), None);
+
+        let command = CommandConfig {
+            name: "both".to_string(),
+            interval_minutes: Some(5.0),
+            cron: Some("@daily".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            ScheduleSpec::from_command(&command)
+                .unwrap_err()
+                .to_string(),
+            "Command 'both' cannot specify both interval_minutes and cron"
+        );
+    }
+}
@@ -0,0 +1,355 @@
+//! Unix domain socket for controlling a running scheduler, as used by `zephyr ctl`
+//!
+//! Clients send one JSON request per line, such as `{"command": "trigger", "name": "backup"}`,
+//! and get one JSON response per line back. Plain text lines such as `run backup` or `list`
+//! are accepted too, for typing requests by hand with `socat` or `nc -U`.
+
+use crate::core::control::{ControlHandle, ControlRequest, ControlResponse};
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, info, warn};
+
+/// Binds the control socket, readable and writable by the current user only
+///
+/// Missing directories above the socket are created for the current user only, and the
+/// socket is bound under a umask of `077`, so other users can't connect to it before its
+/// permissions are set. A socket left behind by a daemon that didn't shut down cleanly is
+/// removed first; one that another daemon is still listening on is an error.
+pub async fn bind(path: &Path) -> Result<UnixListener> {
+    if let Some(parent) = path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)
+            .with_context(|| format!("Failed to create control socket directory {:?}", parent))?;
+    }
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!("{:?} already exists and is not a socket", path);
+        }
+        if UnixStream::connect(path).await.is_ok() {
+            anyhow::bail!(
+                "Another zephyr is already listening on the control socket {:?}",
+                path
+            );
+        }
+        fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale control socket {:?}", path))?;
+        info!("Removed stale control socket {:?}", path);
+    }
+    let listener = with_umask(0o077, || UnixListener::bind(path))
+        .with_context(|| format!("Failed to bind control socket {:?}", path))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict permissions of {:?}", path))?;
+    info!("Control socket listening on {:?}", path);
+    Ok(listener)
+}
+
+/// Runs `f` with the process umask set to `mask`, restoring the previous one afterwards
+///
+/// The umask is shared by the whole process, so files other threads create meanwhile get
+/// `mask` too; the scheduler binds its socket before it starts running commands.
+fn with_umask<T>(mask: libc::mode_t, f: impl FnOnce() -> T) -> T {
+    // SAFETY: umask has no preconditions and only swaps the file mode creation mask
+    let previous = unsafe { libc::umask(mask) };
+    let result = f();
+    // SAFETY: as above
+    unsafe { libc::umask(previous) };
+    result
+}
+
+/// Answers requests on the control socket by passing them to the scheduler
+///
+/// Runs until the task is dropped; errors are logged rather than returned.
+pub async fn serve(listener: UnixListener, handle: ControlHandle) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(stream, handle.clone()));
+            }
+            Err(e) => warn!("Failed to accept control connection: {}", e),
+        }
+    }
+}
+
+async fn handle_connection(stream: UnixStream, handle: ControlHandle) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match parse_request(&line) {
+            Ok(request) => handle.request(request).await,
+            Err(e) => ControlResponse::error(format!("Invalid request: {}", e)),
+        };
+        let mut reply = match serde_json::to_vec(&response) {
+            Ok(reply) => reply,
+            Err(e) => {
+                warn!("Failed to encode control response: {}", e);
+                return;
+            }
+        };
+        reply.push(b'\n');
+        if let Err(e) = writer.write_all(&reply).await {
+            debug!("Control client went away: {}", e);
+            return;
+        }
+    }
+}
+
+/// The plain text requests, for error messages
+const REQUESTS: &str = "status, list, trigger, run, pause, resume or reload";
+
+/// Parses a request line, either a JSON object or a plain text command and its argument
+///
+/// `list` is an alias of `status` and `run` of `trigger`, and `pause` or `resume` without a
+/// name applies to every command.
+fn parse_request(line: &str) -> std::result::Result<ControlRequest, String> {
+    let line = line.trim();
+    if line.starts_with('{') {
+        return serde_json::from_str(line).map_err(|e| e.to_string());
+    }
+
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let argument = words.next();
+    if words.next().is_some() {
+        return Err(format!("too many arguments to '{}'", command));
+    }
+    let name = || {
+        argument
+            .map(str::to_string)
+            .ok_or_else(|| format!("'{}' needs a command name", command))
+    };
+    let request = match command {
+        "status" | "list" => ControlRequest::Status,
+        "trigger" | "run" => ControlRequest::Trigger { name: name()? },
+        "pause" if argument.is_none() => ControlRequest::PauseAll,
+        "resume" if argument.is_none() => ControlRequest::ResumeAll,
+        "pause" => ControlRequest::Pause { name: name()? },
+        "resume" => ControlRequest::Resume { name: name()? },
+        "reload" => ControlRequest::Reload,
+        _ => {
+            return Err(format!(
+                "unknown command '{}'; expected {}",
+                command, REQUESTS
+            ))
+        }
+    };
+    if argument.is_some() && matches!(request, ControlRequest::Status | ControlRequest::Reload) {
+        return Err(format!("'{}' takes no arguments", command));
+    }
+    Ok(request)
+}
+
+/// Sends a request to the scheduler listening on `path` and returns its response
+pub async fn send(path: &Path, request: &ControlRequest) -> Result<ControlResponse> {
+    let stream = UnixStream::connect(path).await.with_context(|| {
+        format!(
+            "Failed to connect to the control socket {:?}; is zephyr running?",
+            path
+        )
+    })?;
+    let (reader, mut writer) = stream.into_split();
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    writer
+        .write_all(&line)
+        .await
+        .context("Failed to send the request")?;
+    let response = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await
+        .context("Failed to read the response")?
+        .context("zephyr closed the connection without answering")?;
+    serde_json::from_str(&response).context("Failed to parse the response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CommandConfig;
+    use crate::core::testing::RecordingExecutor;
+    use crate::SchedulerBuilder;
+    use std::time::Duration;
+
+    fn command(name: &str) -> CommandConfig {
+        CommandConfig {
+            name: name.to_string(),
+            command: "true".to_string(),
+            interval_minutes: Some(60.0),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_control_socket_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("run/control.sock");
+        let executor = RecordingExecutor::new();
+        let mut scheduler = SchedulerBuilder::new(vec![command("backup"), command("report")])
+            .with_executor(Box::new(executor.clone()))
+            .with_state_path(dir.path().join("state.db"))
+            .build()
+            .unwrap();
+
+        let listener = bind(&socket_path).await.unwrap();
+        let mode = fs::metadata(&socket_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let mode = fs::metadata(dir.path().join("run"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+        tokio::spawn(serve(listener, scheduler.control_handle()));
+
+        let client_path = socket_path.clone();
+        let client = tokio::spawn(async move {
+            let send = |request| {
+                let path = client_path.clone();
+                async move { send(&path, &request).await.unwrap() }
+            };
+            let paused = send(ControlRequest::Pause {
+                name: "report".to_string(),
+            })
+            .await;
+            let triggered = send(ControlRequest::Trigger {
+                name: "backup".to_string(),
+            })
+            .await;
+            let unknown = send(ControlRequest::Trigger {
+                name: "missing".to_string(),
+            })
+            .await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let status = send(ControlRequest::Status).await;
+            (paused, triggered, unknown, status)
+        });
+        scheduler
+            .run_until(tokio::time::sleep(Duration::from_millis(400)))
+            .await;
+        let (paused, triggered, unknown, status) = client.await.unwrap();
+
+        assert!(paused.ok);
+        assert_eq!(paused.message.as_deref(), Some("Paused command 'report'"));
+        assert!(triggered.ok);
+        assert!(!unknown.ok);
+        assert_eq!(unknown.error.as_deref(), Some("No command named 'missing'"));
+        assert_eq!(executor.invocations(), vec!["backup".to_string()]);
+
+        let commands = status.commands.unwrap();
+        let report = commands.iter().find(|c| c.name == "report").unwrap();
+        assert!(report.paused);
+        let backup = commands.iter().find(|c| c.name == "backup").unwrap();
+        assert!(!backup.paused);
+        assert!(backup.last_execution.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_bind_replaces_stale_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("control.sock");
+        // A socket file nobody listens on, as left behind by a crash
+        drop(std::os::unix::net::UnixListener::bind(&socket_path).unwrap());
+        assert!(socket_path.exists());
+
+        let listener = bind(&socket_path).await.unwrap();
+        assert!(bind(&socket_path)
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("Another zephyr is already listening"));
+        drop(listener);
+
+        let file_path = dir.path().join("not-a-socket");
+        fs::write(&file_path, "data").unwrap();
+        assert!(bind(&file_path).await.is_err());
+        assert_eq!(fs::read_to_string(&file_path).unwrap(), "data");
+    }
+
+    #[test]
+    fn test_parse_plain_text_requests() {
+        assert_eq!(parse_request("list"), Ok(ControlRequest::Status));
+        assert_eq!(parse_request(" status \r"), Ok(ControlRequest::Status));
+        assert_eq!(
+            parse_request("run backup"),
+            Ok(ControlRequest::Trigger {
+                name: "backup".to_string()
+            })
+        );
+        assert_eq!(
+            parse_request("pause report"),
+            Ok(ControlRequest::Pause {
+                name: "report".to_string()
+            })
+        );
+        assert_eq!(
+            parse_request(r#"{"command": "resume", "name": "report"}"#),
+            Ok(ControlRequest::Resume {
+                name: "report".to_string()
+            })
+        );
+        assert_eq!(parse_request("pause"), Ok(ControlRequest::PauseAll));
+        assert_eq!(
+            parse_request(r#"{"command": "resume_all"}"#),
+            Ok(ControlRequest::ResumeAll)
+        );
+        assert_eq!(parse_request("reload"), Ok(ControlRequest::Reload));
+        assert_eq!(
+            parse_request("run"),
+            Err("'run' needs a command name".to_string())
+        );
+        assert!(parse_request("reload now").is_err());
+        assert!(parse_request("pause a b").is_err());
+        assert!(parse_request("restart").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_plain_text_list_over_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("control.sock");
+        let mut scheduler = SchedulerBuilder::new(vec![command("backup"), command("report")])
+            .with_executor(Box::new(RecordingExecutor::new()))
+            .with_state_path(dir.path().join("state.db"))
+            .build()
+            .unwrap();
+        let listener = bind(&socket_path).await.unwrap();
+        tokio::spawn(serve(listener, scheduler.control_handle()));
+
+        let client = tokio::spawn(async move {
+            let stream = UnixStream::connect(&socket_path).await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            writer.write_all(b"list\nfrobnicate\n").await.unwrap();
+            let mut lines = BufReader::new(reader).lines();
+            let list = lines.next_line().await.unwrap().unwrap();
+            let invalid = lines.next_line().await.unwrap().unwrap();
+            (list, invalid)
+        });
+        scheduler
+            .run_until(tokio::time::sleep(Duration::from_millis(200)))
+            .await;
+        let (list, invalid) = client.await.unwrap();
+
+        let list: ControlResponse = serde_json::from_str(&list).unwrap();
+        assert!(list.ok);
+        let names = list
+            .commands
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["backup", "report"]);
+        let invalid: ControlResponse = serde_json::from_str(&invalid).unwrap();
+        assert!(!invalid.ok);
+        assert!(invalid
+            .error
+            .unwrap()
+            .starts_with("Invalid request: unknown command 'frobnicate'"));
+    }
+}
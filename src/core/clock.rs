@@ -0,0 +1,32 @@
+//! The scheduler's view of time, which tests can replace to control it
+
+use chrono::{DateTime, Utc};
+use tokio::time::Instant;
+
+/// Source of wall-clock time and timers for the scheduler
+///
+/// Waits end at monotonic [`Instant`]s, so a wall clock that jumps, as it does across a
+/// system sleep or when it is set, doesn't stretch or cut short a wait.
+#[async_trait::async_trait]
+pub trait Clock: Send + Sync {
+    /// Returns the current wall-clock time
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Waits until the monotonic clock reaches `deadline`
+    async fn sleep_until(&self, deadline: Instant);
+}
+
+/// The system's clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+#[async_trait::async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        tokio::time::sleep_until(deadline).await;
+    }
+}
@@ -0,0 +1,117 @@
+//! Requests that control a running scheduler, such as triggering or pausing a command
+
+use crate::core::status::CommandStatus;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+
+/// A request to a running scheduler
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Report the status of every configured command
+    Status,
+    /// Run a command now; its schedule continues from when the run finishes
+    Trigger { name: String },
+    /// Skip the scheduled runs of a command until it is resumed
+    Pause { name: String },
+    /// Undo a pause
+    Resume { name: String },
+    /// Skip the scheduled runs of every command until the scheduler is resumed, as on SIGUSR1
+    PauseAll,
+    /// Undo [`ControlRequest::PauseAll`], as on SIGUSR2
+    ResumeAll,
+    /// Reload the configuration file, as on SIGHUP
+    Reload,
+}
+
+/// The scheduler's answer to a [`ControlRequest`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Every configured command, in answer to [`ControlRequest::Status`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commands: Option<Vec<CommandStatus>>,
+}
+
+impl ControlResponse {
+    pub fn message(message: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            message: Some(message.into()),
+            error: None,
+            commands: None,
+        }
+    }
+
+    pub fn error(error: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            message: None,
+            error: Some(error.into()),
+            commands: None,
+        }
+    }
+
+    pub fn commands(commands: Vec<CommandStatus>) -> Self {
+        Self {
+            ok: true,
+            message: None,
+            error: None,
+            commands: Some(commands),
+        }
+    }
+}
+
+/// A request waiting for the scheduler loop, with where to send the answer
+pub(crate) struct ControlMessage {
+    pub(crate) request: ControlRequest,
+    pub(crate) reply: oneshot::Sender<ControlResponse>,
+}
+
+/// Sends requests to a running scheduler, which handles them between scheduling decisions
+#[derive(Clone)]
+pub struct ControlHandle {
+    pub(crate) tx: mpsc::UnboundedSender<ControlMessage>,
+}
+
+impl ControlHandle {
+    /// Sends a request and waits for the scheduler to answer it
+    pub async fn request(&self, request: ControlRequest) -> ControlResponse {
+        let (reply, response) = oneshot::channel();
+        if self.tx.send(ControlMessage { request, reply }).is_err() {
+            return ControlResponse::error("The scheduler has stopped");
+        }
+        response
+            .await
+            .unwrap_or_else(|_| ControlResponse::error("The scheduler has stopped"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_wire_format() {
+        let request: ControlRequest =
+            serde_json::from_str(r#"{"command": "trigger", "name": "backup"}"#).unwrap();
+        assert_eq!(
+            request,
+            ControlRequest::Trigger {
+                name: "backup".to_string()
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&ControlRequest::Status).unwrap(),
+            r#"{"command":"status"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&ControlResponse::error("No command named 'x'")).unwrap(),
+            r#"{"ok":false,"error":"No command named 'x'"}"#
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Desktop notifications for failed and overdue runs, for commands with `notify_on_failure`
+//!
+//! Notifications are shown with `notify-send` on Linux and `osascript` on macOS. Where
+//! neither works, such as on a headless server, the failure is only logged at debug level.
+//! A [`Notifier`] passed to `SchedulerBuilder::with_notifier` shows them some other way.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Least time between two notifications about the same command
+pub const NOTIFICATION_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Shows a notification to the user
+///
+/// Called from the scheduler loop, so it must return without waiting for the notification
+/// to be shown, and handle its own failures.
+pub trait Notifier: Send + Sync {
+    fn show(&self, title: &str, body: &str);
+}
+
+/// Shows notifications on the desktop of the user running the scheduler
+pub struct SystemNotifier;
+
+impl Notifier for SystemNotifier {
+    /// Shows the notification on its own task; must be called from within a tokio runtime
+    fn show(&self, title: &str, body: &str) {
+        let (title, body) = (title.to_string(), body.to_string());
+        tokio::spawn(async move { show(&title, &body).await });
+    }
+}
+
+/// Sends desktop notifications, at most one per command every [`NOTIFICATION_INTERVAL`]
+pub(crate) struct DesktopNotifier {
+    notifier: Arc<dyn Notifier>,
+    last_sent: HashMap<String, Instant>,
+}
+
+impl Default for DesktopNotifier {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemNotifier))
+    }
+}
+
+impl DesktopNotifier {
+    pub(crate) fn new(notifier: Arc<dyn Notifier>) -> Self {
+        Self {
+            notifier,
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// Notifies that a run of `name` failed, unless a notification about it was shown
+    /// recently
+    pub(crate) fn notify_failure(&mut self, name: &str, reason: &str) {
+        self.notify(name, format!("Zephyr: {} failed", name), reason.to_string());
+    }
+
+    /// Notifies that `name` hasn't run for longer than it should have, unless a
+    /// notification about it was shown recently
+    pub(crate) fn notify_overdue(&mut self, name: &str, reason: &str) {
+        self.notify(
+            name,
+            format!("Zephyr: {} is overdue", name),
+            reason.to_string(),
+        );
+    }
+
+    fn notify(&mut self, name: &str, title: String, body: String) {
+        if !self.should_notify(name, Instant::now()) {
+            debug!(
+                "Not showing a desktop notification for command '{}'; one was shown recently",
+                name
+            );
+            return;
+        }
+        self.notifier.show(&title, &body);
+    }
+
+    /// Records a notification about `name` at `now` if the last one is long enough ago
+    fn should_notify(&mut self, name: &str, now: Instant) -> bool {
+        match self.last_sent.get(name) {
+            Some(last) if now.duration_since(*last) < NOTIFICATION_INTERVAL => false,
+            _ => {
+                self.last_sent.insert(name.to_string(), now);
+                true
+            }
+        }
+    }
+}
+
+async fn show(title: &str, body: &str) {
+    let Some(mut command) = notification_command(title, body) else {
+        debug!("Desktop notifications are not supported on this platform");
+        return;
+    };
+    match command.output().await {
+        Ok(output) if output.status.success() => debug!("Showed desktop notification '{}'", title),
+        Ok(output) => debug!(
+            "Failed to show desktop notification '{}': {}",
+            title,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => debug!("Failed to show desktop notification '{}': {}", title, e),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn notification_command(title: &str, body: &str) -> Option<tokio::process::Command> {
+    let mut command = tokio::process::Command::new("osascript");
+    command.arg("-e").arg(format!(
+        "display notification {} with title {}",
+        applescript_string(body),
+        applescript_string(title)
+    ));
+    Some(command)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn notification_command(title: &str, body: &str) -> Option<tokio::process::Command> {
+    let mut command = tokio::process::Command::new("notify-send");
+    command.args(["--app-name", "Zephyr", "--urgency", "critical", title, body]);
+    Some(command)
+}
+
+#[cfg(not(unix))]
+fn notification_command(_title: &str, _body: &str) -> Option<tokio::process::Command> {
+    None
+}
+
+/// Quotes text as an AppleScript string literal
+#[cfg(any(target_os = "macos", test))]
+fn applescript_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifications_are_rate_limited_per_command() {
+        let mut notifier = DesktopNotifier::default();
+        let start = Instant::now();
+        assert!(notifier.should_notify("backup", start));
+        assert!(!notifier.should_notify("backup", start + Duration::from_secs(60)));
+        assert!(notifier.should_notify("report", start + Duration::from_secs(60)));
+        assert!(notifier.should_notify("backup", start + NOTIFICATION_INTERVAL));
+        assert!(!notifier.should_notify("backup", start + NOTIFICATION_INTERVAL * 3 / 2));
+    }
+
+    #[test]
+    fn test_applescript_string() {
+        assert_eq!(
+            applescript_string(r#"say "hi" \ bye"#),
+            r#""say \"hi\" \\ bye""#
+        );
+    }
+}
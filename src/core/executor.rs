@@ -1,11 +1,43 @@
-use crate::config::CommandConfig;
-use crate::util::expand_tilde;
+use crate::config::{CommandConfig, Shell, UnsetEnvVars};
+use crate::core::output_log::{OutputLog, OutputStream};
+use crate::util::{expand_env_vars, expand_tilde};
+use chrono::{DateTime, Utc};
 use std::io;
 use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::process::Command;
+use tracing::{error, info, warn};
+
+/// Appends the command line as the shell's last argument
+#[cfg(not(windows))]
+fn push_command_line(cmd: &mut Command, _shell: &[String], command_line: &str) {
+    cmd.arg(command_line);
+}
+
+/// Appends the command line as the shell's last argument
+///
+/// `cmd` doesn't unquote its arguments the way other Windows programs do, so the command
+/// line is passed to it verbatim.
+#[cfg(windows)]
+fn push_command_line(cmd: &mut Command, shell: &[String], command_line: &str) {
+    let program = Path::new(&shell[0])
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase());
+    if program.as_deref() == Some("cmd") {
+        cmd.raw_arg(command_line);
+    } else {
+        cmd.arg(command_line);
+    }
+}
+
+/// Longest line passed on from a command's output before it is split
+const MAX_LINE_BYTES: usize = 8192;
 
 /// Represents the output of a command execution
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct CommandOutput {
     /// The standard output of the command
     pub stdout: Vec<u8>,
@@ -13,6 +45,36 @@ pub struct CommandOutput {
     pub stderr: Vec<u8>,
     /// The exit status of the command
     pub status: i32,
+    /// Bytes of stdout past the command's `max_output_bytes` that weren't kept; `stdout`
+    /// then ends with a marker saying how many
+    pub stdout_truncated: usize,
+    /// Bytes of stderr past the command's `max_output_bytes` that weren't kept; `stderr`
+    /// then ends with a marker saying how many
+    pub stderr_truncated: usize,
+}
+
+impl CommandOutput {
+    /// Returns how many bytes the command wrote to stdout, including any that weren't kept
+    pub fn stdout_len(&self) -> usize {
+        written_len(&self.stdout, self.stdout_truncated)
+    }
+
+    /// Returns how many bytes the command wrote to stderr, including any that weren't kept
+    pub fn stderr_len(&self) -> usize {
+        written_len(&self.stderr, self.stderr_truncated)
+    }
+}
+
+/// Returns the line appended to output that was cut off after `truncated` more bytes
+fn truncation_marker(truncated: usize) -> String {
+    format!("\n...(truncated {} bytes)\n", truncated)
+}
+
+fn written_len(output: &[u8], truncated: usize) -> usize {
+    match truncated {
+        0 => output.len(),
+        _ => output.len() - truncation_marker(truncated).len() + truncated,
+    }
 }
 
 /// Trait for executing commands with different implementations
@@ -31,51 +93,558 @@ pub trait CommandExecutor: Send + Sync {
     async fn execute(&self, command: &CommandConfig) -> io::Result<CommandOutput>;
 }
 
+/// Executes a command once, killing it if it exceeds its maximum runtime
+///
+/// A run that times out is reported as an error of kind `io::ErrorKind::TimedOut`.
+pub async fn execute_with_timeout(
+    executor: &dyn CommandExecutor,
+    command: &CommandConfig,
+) -> io::Result<CommandOutput> {
+    let max_runtime = command.max_runtime();
+    tokio::time::timeout(max_runtime, executor.execute(command))
+        .await
+        .unwrap_or_else(|_| Err(timed_out(command, max_runtime)))
+}
+
+fn timed_out(command: &CommandConfig, max_runtime: Duration) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!(
+            "command '{}' timed out after {:?}",
+            command.name, max_runtime
+        ),
+    )
+}
+
 /// Default implementation of CommandExecutor that uses the system shell
 pub struct DefaultExecutor;
 
-#[async_trait::async_trait]
-impl CommandExecutor for DefaultExecutor {
-    async fn execute(&self, command: &CommandConfig) -> io::Result<CommandOutput> {
-        let mut cmd = Command::new("sh");
+impl DefaultExecutor {
+    /// Runs a command with its output going straight to the current terminal, killing it if
+    /// it exceeds its maximum runtime
+    ///
+    /// Returns the exit status of the command.
+    pub async fn run_attached(&self, command: &CommandConfig) -> io::Result<i32> {
+        let (mut cmd, container) = match &command.container_image {
+            Some(image) => {
+                let container = Container::new(command);
+                (
+                    DockerExecutor::build(command, image, &container.name),
+                    Some(container),
+                )
+            }
+            None => (Self::build(command)?, None),
+        };
+        let mut child = cmd.spawn()?;
+        let max_runtime = command.max_runtime();
+        match tokio::time::timeout(max_runtime, child.wait()).await {
+            Ok(status) => {
+                if let Some(container) = container {
+                    container.exited();
+                }
+                Ok(status?.code().unwrap_or(-1))
+            }
+            Err(_) => {
+                child.kill().await?;
+                Err(timed_out(command, max_runtime))
+            }
+        }
+    }
+
+    /// Builds the process for a command with its working directory and environment
+    ///
+    /// Commands given as `args` run the program directly; otherwise `command` is passed to
+    /// the command's shell, `sh -c` by default. Environment variables in `command` and in
+    /// `environment` values are expanded first; see [`Self::environment`]. Fails if the
+    /// process can't be switched to the command's `run_as_user`.
+    fn build(command: &CommandConfig) -> io::Result<Command> {
+        let environment = Self::environment(command);
+        let mut cmd = match command.args.as_deref() {
+            Some([program, args @ ..]) => {
+                let mut cmd = Command::new(program);
+                cmd.args(args);
+                cmd
+            }
+            _ => {
+                let shell = command
+                    .shell
+                    .clone()
+                    .unwrap_or_else(Shell::platform_default)
+                    .argv();
+                let command_line = expand_env_vars(
+                    &command.command,
+                    |name| lookup_env(&environment, name),
+                    keep_unset(command),
+                );
+                let mut cmd = Command::new(expand_tilde(Path::new(&shell[0])));
+                cmd.args(&shell[1..]);
+                push_command_line(&mut cmd, &shell, &command_line);
+                cmd
+            }
+        };
         cmd.kill_on_drop(true);
-        cmd.arg("-c").arg(&command.command);
 
         if let Some(dir) = &command.working_dir {
             let expanded_dir = expand_tilde(dir);
             cmd.current_dir(&expanded_dir);
         }
 
-        if let Some(env) = &command.environment {
-            for (key, value) in env {
-                let expanded_value =
-                    if value.starts_with("${") && value.ends_with('}') && value.len() > 3 {
-                        let var_name = &value[2..value.len() - 1];
-                        std::env::var(var_name).unwrap_or_else(|_| value.clone())
-                    } else if value.starts_with('$') {
-                        let var_name = value.trim_start_matches('$');
-                        std::env::var(var_name).unwrap_or_else(|_| value.clone())
-                    } else if value.starts_with('~') {
-                        expand_tilde(Path::new(value)).to_string_lossy().to_string()
-                    } else {
-                        value.clone()
-                    };
-                cmd.env(key, expanded_value);
+        cmd.envs(environment);
+        switch_user(&mut cmd, command)?;
+        Ok(cmd)
+    }
+
+    /// Returns the command's `environment` with `~` and `$VAR` references expanded
+    ///
+    /// Each value sees the process environment overridden by the entries before it, so
+    /// `PATH = "$PATH:/opt/bin"` extends the scheduler's own `PATH`.
+    fn environment(command: &CommandConfig) -> Vec<(String, String)> {
+        let mut environment: Vec<(String, String)> = Vec::new();
+        for (key, value) in command.environment.iter().flatten() {
+            let value = if value.starts_with('~') {
+                expand_tilde(Path::new(value)).to_string_lossy().to_string()
+            } else {
+                value.clone()
+            };
+            let value = expand_env_vars(
+                &value,
+                |name| lookup_env(&environment, name),
+                keep_unset(command),
+            );
+            environment.push((key.clone(), value));
+        }
+        environment
+    }
+}
+
+/// Looks a variable up in a command's environment, then in the scheduler's
+fn lookup_env(environment: &[(String, String)], name: &str) -> Option<String> {
+    environment
+        .iter()
+        .rev()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.clone())
+        .or_else(|| std::env::var(name).ok())
+}
+
+fn keep_unset(command: &CommandConfig) -> bool {
+    command.unset_env_vars.unwrap_or_default() == UnsetEnvVars::Keep
+}
+
+/// Makes the process run as the command's `run_as_user`, which needs the scheduler to run
+/// as root unless it already is that user
+#[cfg(unix)]
+fn switch_user(cmd: &mut Command, command: &CommandConfig) -> io::Result<()> {
+    let Some(user) = &command.run_as_user else {
+        return Ok(());
+    };
+    let (uid, gid) = resolve_user(user, users::get_user_by_name)?;
+    let current_uid = users::get_effective_uid();
+    if uid == current_uid {
+        return Ok(());
+    }
+    if current_uid != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "cannot run command '{}' as user '{}': zephyr runs as uid {} and needs to \
+                run as root to change user",
+                command.name, user, current_uid
+            ),
+        ));
+    }
+    cmd.uid(uid);
+    cmd.gid(gid);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn switch_user(_cmd: &mut Command, command: &CommandConfig) -> io::Result<()> {
+    match &command.run_as_user {
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "cannot run command '{}': run_as_user is only supported on Unix",
+                command.name
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Returns the uid and primary gid of the user called `name`
+#[cfg(unix)]
+fn resolve_user(
+    name: &str,
+    lookup: impl FnOnce(&str) -> Option<users::User>,
+) -> io::Result<(u32, u32)> {
+    lookup(name)
+        .map(|user| (user.uid(), user.primary_group_id()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no user named '{}'", name)))
+}
+
+#[async_trait::async_trait]
+impl CommandExecutor for DefaultExecutor {
+    /// Runs the command, passing each line of its output to its log file, or to the
+    /// scheduler's log if it has none, as soon as it is written
+    ///
+    /// Commands with a `container_image` are run by [`DockerExecutor`].
+    async fn execute(&self, command: &CommandConfig) -> io::Result<CommandOutput> {
+        if command.container_image.is_some() {
+            return DockerExecutor.execute(command).await;
+        }
+        capture(Self::build(command)?, command).await
+    }
+}
+
+/// Runs a process with its output piped, passing each line on as it is written
+async fn capture(mut cmd: Command, command: &CommandConfig) -> io::Result<CommandOutput> {
+    let started = Utc::now();
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdout = LineReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut stderr = LineReader::new(child.stderr.take().expect("stderr is piped"));
+    let mut sink = OutputSink::new(command, started);
+
+    loop {
+        tokio::select! {
+            line = stdout.next_line(), if !stdout.is_finished() => {
+                if let Some(line) = line? {
+                    sink.write_line(OutputStream::Stdout, line);
+                }
             }
+            line = stderr.next_line(), if !stderr.is_finished() => {
+                if let Some(line) = line? {
+                    sink.write_line(OutputStream::Stderr, line);
+                }
+            }
+            else => break,
         }
+    }
+
+    let status = child.wait().await?.code().unwrap_or(-1);
+    Ok(sink.finish(status))
+}
 
-        let output = cmd.output().await?;
+/// Executor that runs each command in a new container of its `container_image`, with
+/// `docker run`
+///
+/// The container gets the command's `environment`, and its `working_dir` is mounted at the
+/// same path and used as the working directory. Output is captured the same way as by
+/// [`DefaultExecutor`].
+pub struct DockerExecutor;
+
+impl DockerExecutor {
+    /// Builds the `docker run` process for a command, in a container called `container`
+    ///
+    /// The container is removed once the command exits. `command` is run with the
+    /// command's `shell` if it sets one and `sh -c` otherwise, looked up in the image;
+    /// `args` are run directly. Environment variables are expanded on the host, the same
+    /// as for [`DefaultExecutor`], and passed to the container by name so their values
+    /// don't show up in the process list.
+    fn build(command: &CommandConfig, image: &str, container: &str) -> Command {
+        let environment = DefaultExecutor::environment(command);
+        let mut cmd = Command::new("docker");
+        cmd.args(["run", "--rm", "--name", container]);
+        for (key, _) in &environment {
+            cmd.args(["--env", key]);
+        }
+        if let Some(dir) = &command.working_dir {
+            let dir = expand_tilde(dir);
+            let mut volume = dir.clone().into_os_string();
+            volume.push(":");
+            volume.push(&dir);
+            cmd.arg("--volume").arg(volume);
+            cmd.arg("--workdir").arg(&dir);
+        }
+        cmd.arg(image);
+        match &command.args {
+            Some(args) => {
+                cmd.args(args);
+            }
+            None => {
+                let shell = match &command.shell {
+                    Some(shell) => shell.argv(),
+                    None => vec!["sh".to_string(), "-c".to_string()],
+                };
+                let command_line = expand_env_vars(
+                    &command.command,
+                    |name| lookup_env(&environment, name),
+                    keep_unset(command),
+                );
+                cmd.args(&shell).arg(command_line);
+            }
+        }
+        cmd.kill_on_drop(true);
+        cmd.envs(environment);
+        cmd
+    }
+}
+
+#[async_trait::async_trait]
+impl CommandExecutor for DockerExecutor {
+    async fn execute(&self, command: &CommandConfig) -> io::Result<CommandOutput> {
+        let Some(image) = &command.container_image else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("command '{}' has no container_image", command.name),
+            ));
+        };
+        let container = Container::new(command);
+        let output = capture(Self::build(command, image, &container.name), command).await;
+        container.exited();
+        output
+    }
+}
+
+/// A container started for a run, which is killed if the run is dropped before it exits
+///
+/// Killing `docker run` itself, as happens when a run exceeds `max_runtime_minutes`, leaves
+/// the container running.
+struct Container {
+    name: String,
+    running: bool,
+}
+
+impl Container {
+    /// Picks a name for a new container for `command` that no other run uses
+    fn new(command: &CommandConfig) -> Self {
+        static STARTED: AtomicU64 = AtomicU64::new(0);
+        let command_name: String = command
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        Self {
+            name: format!(
+                "zephyr-{}-{}-{}",
+                command_name,
+                std::process::id(),
+                STARTED.fetch_add(1, Ordering::Relaxed)
+            ),
+            running: true,
+        }
+    }
+
+    /// Marks the container as gone, because `docker run` has returned
+    fn exited(mut self) {
+        self.running = false;
+    }
+}
+
+impl Drop for Container {
+    fn drop(&mut self) {
+        if !self.running {
+            return;
+        }
+        warn!(container = %self.name, "killing container of a run that was stopped");
+        let kill = std::process::Command::new("docker")
+            .args(["kill", &self.name])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match kill {
+            // Reaped on a thread of its own, since this may run on the scheduler's
+            Ok(mut child) => {
+                std::thread::spawn(move || child.wait());
+            }
+            Err(e) => error!(container = %self.name, "failed to kill container: {}", e),
+        }
+    }
+}
+
+/// Executor for dry runs, which logs each command instead of running it
+///
+/// Every run succeeds straight away with no output, so the scheduler carries on as if the
+/// command had run.
+pub struct DryRunExecutor;
+
+#[async_trait::async_trait]
+impl CommandExecutor for DryRunExecutor {
+    async fn execute(&self, command: &CommandConfig) -> io::Result<CommandOutput> {
+        info!(
+            command = %command.name,
+            event = "dry_run",
+            "would execute: {}",
+            command.command_line()
+        );
+        let status = match command.success_exit_codes.as_deref() {
+            Some([status, ..]) => *status,
+            _ => 0,
+        };
         Ok(CommandOutput {
-            stdout: output.stdout,
-            stderr: output.stderr,
-            status: output.status.code().unwrap_or(-1),
+            status,
+            ..Default::default()
         })
     }
 }
 
+/// Splits a stream of output into lines as it arrives
+///
+/// Lines keep their trailing newline. A line longer than `MAX_LINE_BYTES` is split so a
+/// process that never writes a newline is still passed on in pieces.
+struct LineReader<R> {
+    reader: R,
+    pending: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> LineReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pending: Vec::new(),
+            eof: false,
+        }
+    }
+
+    /// Returns whether every line has been read
+    fn is_finished(&self) -> bool {
+        self.eof && self.pending.is_empty()
+    }
+
+    /// Reads the next line, or `None` at the end of the stream
+    ///
+    /// Cancel safe: bytes that have been read are kept until their line is complete.
+    async fn next_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let searched = self.pending.len().min(MAX_LINE_BYTES);
+            if let Some(newline) = self.pending[..searched].iter().position(|b| *b == b'\n') {
+                return Ok(Some(self.pending.drain(..=newline).collect()));
+            }
+            if self.pending.len() >= MAX_LINE_BYTES {
+                return Ok(Some(self.pending.drain(..MAX_LINE_BYTES).collect()));
+            }
+            if self.eof {
+                return Ok((!self.pending.is_empty()).then(|| std::mem::take(&mut self.pending)));
+            }
+            let mut chunk = [0; 8192];
+            let read = self.reader.read(&mut chunk).await?;
+            if read == 0 {
+                self.eof = true;
+            } else {
+                self.pending.extend_from_slice(&chunk[..read]);
+            }
+        }
+    }
+}
+
+/// Collects a run's output while passing each line on to the command's log file or the
+/// scheduler's log
+struct OutputSink<'a> {
+    command: &'a CommandConfig,
+    log: Option<OutputLog>,
+    output: CommandOutput,
+}
+
+impl<'a> OutputSink<'a> {
+    fn new(command: &'a CommandConfig, started: DateTime<Utc>) -> Self {
+        let log = command.log_file.as_ref().and_then(|log_file| {
+            OutputLog::open(log_file, &command.name, started, command.log_rotation())
+                .map_err(|e| Self::warn_log_failed(command, e))
+                .ok()
+        });
+        Self {
+            command,
+            log,
+            output: CommandOutput::default(),
+        }
+    }
+
+    fn write_line(&mut self, stream: OutputStream, line: Vec<u8>) {
+        if let Some(log) = &mut self.log {
+            if let Err(e) = log.write_line(stream, &line) {
+                Self::warn_log_failed(self.command, e);
+                self.log = None;
+            }
+        }
+        if self.log.is_none() {
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\n', '\r']);
+            let name = &self.command.name;
+            match stream {
+                OutputStream::Stdout => info!(command = %name, "[{}] {}", name, text),
+                OutputStream::Stderr => error!(command = %name, "[{}] {}", name, text),
+            }
+        }
+
+        let (buffer, truncated) = match stream {
+            OutputStream::Stdout => (&mut self.output.stdout, &mut self.output.stdout_truncated),
+            OutputStream::Stderr => (&mut self.output.stderr, &mut self.output.stderr_truncated),
+        };
+        if *truncated > 0 {
+            *truncated += line.len();
+            return;
+        }
+        let limit = self.command.output_limit();
+        let room = limit.saturating_sub(buffer.len());
+        if line.len() > room {
+            buffer.extend_from_slice(&line[..room]);
+            buffer.shrink_to_fit();
+            *truncated = line.len() - room;
+            warn!(
+                "Output of command '{}' on {} exceeded {} bytes, not keeping the rest",
+                self.command.name, stream, limit
+            );
+        } else {
+            buffer.extend_from_slice(&line);
+        }
+    }
+
+    fn finish(self, status: i32) -> CommandOutput {
+        if let Some(log) = self.log {
+            if let Err(e) = log.finish(status) {
+                Self::warn_log_failed(self.command, e);
+            }
+        }
+        let mut output = CommandOutput {
+            status,
+            ..self.output
+        };
+        for (buffer, truncated) in [
+            (&mut output.stdout, output.stdout_truncated),
+            (&mut output.stderr, output.stderr_truncated),
+        ] {
+            if truncated > 0 {
+                let marker = truncation_marker(truncated);
+                buffer.reserve_exact(marker.len());
+                buffer.extend_from_slice(marker.as_bytes());
+            }
+        }
+        output
+    }
+
+    fn warn_log_failed(command: &CommandConfig, e: io::Error) {
+        warn!(
+            "Failed to write output of command '{}' to {}: {}",
+            command.name,
+            command
+                .log_file
+                .as_deref()
+                .unwrap_or(Path::new(""))
+                .display(),
+            e
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::state::StateManager;
+    use crate::SchedulerBuilder;
+    use std::ffi::OsStr;
+    use std::path::PathBuf;
     use tempfile::tempdir;
 
     fn create_test_command(command: &str) -> CommandConfig {
@@ -83,12 +652,8 @@ mod tests {
             name: "test".to_string(),
             command: command.to_string(),
             interval_minutes: Some(1.0),
-            cron: None,
             max_runtime_minutes: Some(5),
-            enabled: true,
-            working_dir: None,
-            environment: None,
-            immediate: false,
+            ..Default::default()
         }
     }
 
@@ -114,12 +679,9 @@ mod tests {
             name: "test".to_string(),
             command: "pwd".to_string(),
             interval_minutes: Some(1.0),
-            cron: None,
             max_runtime_minutes: Some(5),
-            enabled: true,
             working_dir: Some(temp_dir.path().to_path_buf()),
-            environment: None,
-            immediate: false,
+            ..Default::default()
         };
 
         let output = executor.execute(&command).await.unwrap();
@@ -141,12 +703,9 @@ mod tests {
             name: "test".to_string(),
             command: "echo $TEST_VAR".to_string(),
             interval_minutes: Some(1.0),
-            cron: None,
             max_runtime_minutes: Some(5),
-            enabled: true,
-            working_dir: None,
             environment: Some(vec![("TEST_VAR".to_string(), "test_value".to_string())]),
-            immediate: false,
+            ..Default::default()
         };
 
         let output = executor.execute(&command).await.unwrap();
@@ -162,12 +721,9 @@ mod tests {
             name: "test".to_string(),
             command: "echo $EXPANDED_HOME".to_string(),
             interval_minutes: Some(1.0),
-            cron: None,
             max_runtime_minutes: Some(5),
-            enabled: true,
-            working_dir: None,
             environment: Some(vec![("EXPANDED_HOME".to_string(), "${HOME}".to_string())]),
-            immediate: false,
+            ..Default::default()
         };
 
         let output = executor.execute(&command).await.unwrap();
@@ -175,6 +731,204 @@ mod tests {
         assert_eq!(output.status, 0);
     }
 
+    #[tokio::test]
+    async fn test_execute_expands_env_vars_in_command() {
+        // Single quotes stop the shell from expanding the variables itself
+        let command = CommandConfig {
+            environment: Some(vec![
+                ("TEST_VAR".to_string(), "from_environment".to_string()),
+                ("HOME".to_string(), "/overridden".to_string()),
+                ("DATA_DIR".to_string(), "$HOME/data".to_string()),
+            ]),
+            ..create_test_command("echo '${TEST_VAR} $DATA_DIR [$ZEPHYR_UNSET_VAR]'")
+        };
+        let output = DefaultExecutor.execute(&command).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            "from_environment /overridden/data [$ZEPHYR_UNSET_VAR]"
+        );
+
+        let command = CommandConfig {
+            unset_env_vars: Some(UnsetEnvVars::Empty),
+            ..command
+        };
+        let output = DefaultExecutor.execute(&command).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            "from_environment /overridden/data []"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_appends_output_to_log_file() {
+        let temp_dir = tempdir().unwrap();
+        let log_file = temp_dir.path().join("logs").join("test.log");
+        let command = CommandConfig {
+            log_file: Some(log_file.clone()),
+            ..create_test_command("echo 'logged output'; echo oops >&2")
+        };
+
+        let output = DefaultExecutor.execute(&command).await.unwrap();
+        assert_eq!(output.status, 0);
+        DefaultExecutor.execute(&command).await.unwrap();
+
+        let contents = std::fs::read_to_string(&log_file).unwrap();
+        assert_eq!(contents.matches(" test ===\n").count(), 2);
+        assert_eq!(contents.matches("logged output\n").count(), 2);
+        assert_eq!(contents.matches("[stderr] oops\n").count(), 2);
+        assert_eq!(contents.matches("=== exit status 0 ===\n").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_execute_keeps_output_without_trailing_newline() {
+        let command = create_test_command("printf 'a\\nb'; printf 'long%.0s' $(seq 3000) >&2");
+
+        let output = DefaultExecutor.execute(&command).await.unwrap();
+        assert_eq!(output.stdout, b"a\nb");
+        assert_eq!(output.stderr, "long".repeat(3000).into_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_execute_caps_captured_output() {
+        let command = CommandConfig {
+            max_output_bytes: Some(4096),
+            ..create_test_command("yes | head -c 1M; echo done >&2")
+        };
+
+        let output = DefaultExecutor.execute(&command).await.unwrap();
+        assert_eq!(output.status, 0);
+        let marker = b"\n...(truncated 1044480 bytes)\n";
+        assert_eq!(output.stdout.len(), 4096 + marker.len());
+        assert!(output.stdout.capacity() <= 4096 + marker.len());
+        assert!(output.stdout.ends_with(marker));
+        assert!(output.stdout.starts_with(b"y\ny\n"));
+        assert_eq!(output.stdout_truncated, 1024 * 1024 - 4096);
+        assert_eq!(output.stdout_len(), 1024 * 1024);
+        assert_eq!(output.stderr, b"done\n");
+        assert_eq!(output.stderr_truncated, 0);
+        assert_eq!(output.stderr_len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_line_reader_splits_lines() {
+        let input = [
+            b"one\ntwo\n".as_slice(),
+            &[b'x'; MAX_LINE_BYTES + 5],
+            b"\nend",
+        ]
+        .concat();
+        let mut reader = LineReader::new(input.as_slice());
+
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), b"one\n");
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), b"two\n");
+        assert_eq!(
+            reader.next_line().await.unwrap().unwrap().len(),
+            MAX_LINE_BYTES
+        );
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), b"xxxxx\n");
+        assert_eq!(reader.next_line().await.unwrap().unwrap(), b"end");
+        assert_eq!(reader.next_line().await.unwrap(), None);
+        assert!(reader.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_execute_args_without_shell() {
+        let command = CommandConfig {
+            command: String::new(),
+            args: Some(vec![
+                "printf".to_string(),
+                "%s|".to_string(),
+                "two words".to_string(),
+                "$HOME".to_string(),
+                "; echo injected".to_string(),
+            ]),
+            ..create_test_command("")
+        };
+
+        let output = DefaultExecutor.execute(&command).await.unwrap();
+        assert_eq!(output.status, 0);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "two words|$HOME|; echo injected|"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_shell() {
+        let command = CommandConfig {
+            shell: Some(Shell::Argv(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "echo \"$0: $1\"".to_string(),
+            ])),
+            ..create_test_command("passed-to-shell")
+        };
+
+        let output = DefaultExecutor.execute(&command).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            "passed-to-shell:"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_with_bash_program() {
+        let command = CommandConfig {
+            shell: Some(Shell::Program("bash".to_string())),
+            ..create_test_command("words=(one two); [[ ${#words[@]} == 2 ]] && echo ${words[1]}")
+        };
+
+        let output = DefaultExecutor.execute(&command).await.unwrap();
+        assert_eq!(output.status, 0);
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "two");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_defaults_to_sh() {
+        let output = DefaultExecutor
+            .execute(&create_test_command("echo $0"))
+            .await
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "sh");
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_spawns_nothing_but_advances_state() {
+        let dir = tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        let state_path = dir.path().join("state.db");
+        let command = CommandConfig {
+            name: "touch".to_string(),
+            command: format!("touch {}", marker.display()),
+            interval_minutes: Some(60.0),
+            immediate: true,
+            success_exit_codes: Some(vec![3]),
+            ..Default::default()
+        };
+        let started = Utc::now();
+        let mut scheduler = SchedulerBuilder::new(vec![command])
+            .with_executor(Box::new(DryRunExecutor))
+            .with_state_path(&state_path)
+            .build()
+            .unwrap();
+        scheduler
+            .run_until(tokio::time::sleep(Duration::from_millis(300)))
+            .await;
+
+        assert!(!marker.exists());
+        let state = StateManager::new(&state_path)
+            .unwrap()
+            .get_command_state("touch")
+            .unwrap()
+            .unwrap();
+        assert!(state.last_execution.unwrap() >= started);
+        assert_eq!(state.last_status, Some(3));
+        assert_eq!(state.consecutive_failures, 0);
+        assert!(state.next_scheduled > started + chrono::Duration::minutes(59));
+    }
+
     #[tokio::test]
     async fn test_execute_invalid_command() {
         let executor = DefaultExecutor;
@@ -184,4 +938,171 @@ mod tests {
         let output = executor.execute(&command).await.unwrap();
         assert_eq!(output.status, 1); // false command exits with status 1
     }
+
+    #[tokio::test]
+    async fn test_execute_with_timeout_completes() {
+        let command = create_test_command("echo done");
+
+        let output = execute_with_timeout(&DefaultExecutor, &command)
+            .await
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "done");
+    }
+
+    #[tokio::test]
+    async fn test_run_attached_returns_exit_status() {
+        let status = DefaultExecutor
+            .run_attached(&create_test_command("exit 3"))
+            .await
+            .unwrap();
+        assert_eq!(status, 3);
+
+        let status = DefaultExecutor
+            .run_attached(&create_test_command("true"))
+            .await
+            .unwrap();
+        assert_eq!(status, 0);
+    }
+
+    fn docker_args(command: &CommandConfig) -> Vec<String> {
+        let image = command.container_image.as_deref().unwrap();
+        DockerExecutor::build(command, image, "zephyr-test-1")
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_docker_run_args() {
+        let dir = tempdir().unwrap();
+        let dir = dir.path().to_string_lossy().to_string();
+        let command = CommandConfig {
+            command: "echo $GREETING $USER_NAME".to_string(),
+            container_image: Some("alpine:3".to_string()),
+            working_dir: Some(PathBuf::from(&dir)),
+            environment: Some(vec![
+                ("GREETING".to_string(), "hello".to_string()),
+                ("USER_NAME".to_string(), "${GREETING}-user".to_string()),
+            ]),
+            ..create_test_command("")
+        };
+        assert_eq!(
+            docker_args(&command),
+            [
+                "run",
+                "--rm",
+                "--name",
+                "zephyr-test-1",
+                "--env",
+                "GREETING",
+                "--env",
+                "USER_NAME",
+                "--volume",
+                &format!("{dir}:{dir}"),
+                "--workdir",
+                &dir,
+                "alpine:3",
+                "sh",
+                "-c",
+                "echo hello hello-user",
+            ]
+        );
+        let cmd = DockerExecutor::build(&command, "alpine:3", "zephyr-test-1");
+        let envs: Vec<_> = cmd.as_std().get_envs().collect();
+        assert!(envs.contains(&(OsStr::new("USER_NAME"), Some(OsStr::new("hello-user")))));
+
+        let command = CommandConfig {
+            args: Some(vec!["echo".to_string(), "a b".to_string()]),
+            container_image: Some("alpine".to_string()),
+            ..create_test_command("")
+        };
+        assert_eq!(
+            docker_args(&command),
+            [
+                "run",
+                "--rm",
+                "--name",
+                "zephyr-test-1",
+                "alpine",
+                "echo",
+                "a b"
+            ]
+        );
+
+        let command = CommandConfig {
+            shell: Some(Shell::Program("bash".to_string())),
+            container_image: Some("debian".to_string()),
+            ..create_test_command("echo hi")
+        };
+        assert_eq!(
+            docker_args(&command)[4..],
+            ["debian", "bash", "-c", "echo hi"]
+        );
+    }
+
+    #[test]
+    fn test_container_names() {
+        let command = CommandConfig {
+            name: "nightly backup/db".to_string(),
+            ..create_test_command("true")
+        };
+        let first = Container::new(&command);
+        let second = Container::new(&command);
+        assert!(first
+            .name
+            .starts_with(&format!("zephyr-nightly-backup-db-{}-", std::process::id())));
+        assert_ne!(first.name, second.name);
+        first.exited();
+        second.exited();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_user() {
+        let lookup = |name: &str| (name == "backup").then(|| users::User::new(34, "backup", 40));
+        assert_eq!(resolve_user("backup", lookup).unwrap(), (34, 40));
+        let err = resolve_user("nobody-here", lookup).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "no user named 'nobody-here'");
+
+        assert_eq!(
+            resolve_user("root", users::get_user_by_name).unwrap(),
+            (0, 0)
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_as_user() {
+        let current = users::get_current_username()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let command = CommandConfig {
+            run_as_user: Some(current),
+            ..create_test_command("id -u")
+        };
+        let output = DefaultExecutor.execute(&command).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            users::get_current_uid().to_string()
+        );
+
+        let command = CommandConfig {
+            run_as_user: Some("nobody-here".to_string()),
+            ..command
+        };
+        let err = DefaultExecutor.execute(&command).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_docker_executor_requires_an_image() {
+        let err = DockerExecutor
+            .execute(&create_test_command("true"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
 }
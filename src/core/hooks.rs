@@ -0,0 +1,132 @@
+//! `on_success` and `on_failure` hook commands, run after a command's run finishes
+//!
+//! Hooks go through the scheduler's executor with the command's working directory and
+//! environment. They are never recorded in the state database, retried or reported; a hook
+//! that fails or runs past [`HOOK_TIMEOUT`] is only logged.
+
+use crate::config::CommandConfig;
+use crate::core::executor::CommandExecutor;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::{info, warn};
+
+/// How long a hook may run before it is killed
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Which hook of a command to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HookKind {
+    OnSuccess,
+    OnFailure,
+}
+
+impl HookKind {
+    fn name(self) -> &'static str {
+        match self {
+            HookKind::OnSuccess => "on_success",
+            HookKind::OnFailure => "on_failure",
+        }
+    }
+}
+
+/// Returns the hook of `command` to run as a command of its own, with `ZEPHYR_COMMAND`,
+/// `ZEPHYR_EXIT_CODE` and `ZEPHYR_DURATION_MS` describing the run that finished
+///
+/// `ZEPHYR_EXIT_CODE` is empty when the run timed out or couldn't be started.
+pub(crate) fn hook_command(
+    command: &CommandConfig,
+    kind: HookKind,
+    exit_code: Option<i32>,
+    duration_ms: i64,
+) -> Option<CommandConfig> {
+    let hook = match kind {
+        HookKind::OnSuccess => command.on_success.as_ref()?,
+        HookKind::OnFailure => command.on_failure.as_ref()?,
+    };
+    let mut environment = command.environment.clone().unwrap_or_default();
+    environment.extend([
+        ("ZEPHYR_COMMAND".to_string(), command.name.clone()),
+        (
+            "ZEPHYR_EXIT_CODE".to_string(),
+            exit_code.map(|code| code.to_string()).unwrap_or_default(),
+        ),
+        ("ZEPHYR_DURATION_MS".to_string(), duration_ms.to_string()),
+    ]);
+    Some(CommandConfig {
+        name: format!("{} ({})", command.name, kind.name()),
+        command: hook.clone(),
+        args: None,
+        environment: Some(environment),
+        on_success: None,
+        on_failure: None,
+        ..command.clone()
+    })
+}
+
+/// Runs a hook on its own task so it never holds up the scheduler
+pub(crate) fn spawn(executor: Arc<dyn CommandExecutor>, hook: CommandConfig) {
+    tokio::spawn(async move { run(executor.as_ref(), &hook).await });
+}
+
+/// Runs a hook to completion or until [`HOOK_TIMEOUT`], logging how it went
+pub(crate) async fn run(executor: &dyn CommandExecutor, hook: &CommandConfig) {
+    info!("Running hook '{}'", hook.name);
+    match timeout(HOOK_TIMEOUT, executor.execute(hook)).await {
+        Ok(Ok(output)) if output.status == 0 => info!("Hook '{}' completed", hook.name),
+        Ok(Ok(output)) => warn!(
+            "Hook '{}' failed with exit status {}: {}",
+            hook.name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Ok(Err(e)) => warn!("Failed to run hook '{}': {}", hook.name, e),
+        Err(_) => warn!(
+            "Hook '{}' was killed after running for {:?}",
+            hook.name, HOOK_TIMEOUT
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command() -> CommandConfig {
+        CommandConfig {
+            name: "backup".to_string(),
+            command: "backup.sh".to_string(),
+            interval_minutes: Some(60.0),
+            working_dir: Some("/srv".into()),
+            environment: Some(vec![("TARGET".to_string(), "/mnt".to_string())]),
+            on_failure: Some("notify.sh backup".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hook_command_inherits_command_settings() {
+        let hook = hook_command(&command(), HookKind::OnFailure, Some(3), 1500).unwrap();
+        assert_eq!(hook.name, "backup (on_failure)");
+        assert_eq!(hook.command, "notify.sh backup");
+        assert_eq!(hook.working_dir, Some("/srv".into()));
+        assert_eq!(hook.on_failure, None);
+        assert_eq!(
+            hook.environment.unwrap(),
+            [
+                ("TARGET", "/mnt"),
+                ("ZEPHYR_COMMAND", "backup"),
+                ("ZEPHYR_EXIT_CODE", "3"),
+                ("ZEPHYR_DURATION_MS", "1500"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+
+        let hook = hook_command(&command(), HookKind::OnFailure, None, 0).unwrap();
+        assert!(hook
+            .environment
+            .unwrap()
+            .contains(&("ZEPHYR_EXIT_CODE".to_string(), String::new())));
+        assert!(hook_command(&command(), HookKind::OnSuccess, Some(0), 0).is_none());
+    }
+}
@@ -1,2 +1,15 @@
+pub mod clock;
+pub mod control;
+pub mod desktop;
 pub mod executor;
+mod hooks;
+pub mod output_log;
+mod power;
 pub mod scheduler;
+mod sd_notify;
+mod signals;
+mod staleness;
+pub mod status;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod webhook;
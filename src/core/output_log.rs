@@ -0,0 +1,165 @@
+use crate::config::LogRotation;
+use crate::util::expand_tilde;
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Which of a command's output streams a line came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for OutputStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdout => write!(f, "stdout"),
+            Self::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// A command's log file, open for appending the output of one run as it arrives
+///
+/// Each run is written as a block that starts with a timestamped header and ends with the
+/// exit status; lines from stderr are prefixed with `[stderr]`.
+pub struct OutputLog {
+    file: File,
+}
+
+impl OutputLog {
+    /// Opens the log file and writes the header for a run started at `started`
+    ///
+    /// The path may start with `~`, and missing parent directories are created. A file that
+    /// has grown past the rotation size is rotated first.
+    pub fn open(
+        path: &Path,
+        command_name: &str,
+        started: DateTime<Utc>,
+        rotation: LogRotation,
+    ) -> io::Result<Self> {
+        let path = expand_tilde(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let size = fs::metadata(&path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        if rotation.max_bytes > 0 && size >= rotation.max_bytes {
+            rotate(&path, rotation.keep)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        writeln!(file, "=== {} {} ===", started.to_rfc3339(), command_name)?;
+        Ok(Self { file })
+    }
+
+    /// Appends one line of output, adding a newline if it doesn't end with one
+    pub fn write_line(&mut self, stream: OutputStream, line: &[u8]) -> io::Result<()> {
+        let mut entry = Vec::with_capacity(line.len() + 10);
+        if stream == OutputStream::Stderr {
+            entry.extend_from_slice(b"[stderr] ");
+        }
+        entry.extend_from_slice(line);
+        if !line.ends_with(b"\n") {
+            entry.push(b'\n');
+        }
+        self.file.write_all(&entry)
+    }
+
+    /// Closes the run's block with its exit status
+    pub fn finish(mut self, status: i32) -> io::Result<()> {
+        writeln!(self.file, "=== exit status {} ===", status)
+    }
+}
+
+/// Shifts `<file>.1` .. `<file>.N-1` up by one and moves the file itself to `<file>.1`,
+/// dropping whatever was in `<file>.N`
+fn rotate(path: &Path, keep: usize) -> io::Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    for index in (1..keep).rev() {
+        let from = rotated_path(path, index);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, index + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const NO_ROTATION: LogRotation = LogRotation {
+        max_bytes: 0,
+        keep: 0,
+    };
+
+    fn write_run(path: &Path, started: DateTime<Utc>, lines: &[(OutputStream, &str)], status: i32) {
+        let mut log = OutputLog::open(path, "backup", started, NO_ROTATION).unwrap();
+        for (stream, line) in lines {
+            log.write_line(*stream, line.as_bytes()).unwrap();
+        }
+        log.finish(status).unwrap();
+    }
+
+    #[test]
+    fn test_output_log_creates_file_and_appends() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("logs").join("backup.log");
+        let started = Utc::now();
+        write_run(
+            &path,
+            started,
+            &[
+                (OutputStream::Stdout, "first"),
+                (OutputStream::Stderr, "warning\n"),
+                (OutputStream::Stdout, "done\n"),
+            ],
+            0,
+        );
+        write_run(&path, started, &[(OutputStream::Stdout, "second\n")], 2);
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let expected = format!(
+            "=== {0} backup ===\nfirst\n[stderr] warning\ndone\n=== exit status 0 ===\n\
+             === {0} backup ===\nsecond\n=== exit status 2 ===\n",
+            started.to_rfc3339()
+        );
+        assert_eq!(contents, expected);
+    }
+
+    #[test]
+    fn test_output_log_rotates_full_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("backup.log");
+        let rotation = LogRotation {
+            max_bytes: 1,
+            keep: 2,
+        };
+        for run in 1..=4 {
+            let mut log = OutputLog::open(&path, "backup", Utc::now(), rotation).unwrap();
+            log.write_line(OutputStream::Stdout, format!("run {}\n", run).as_bytes())
+                .unwrap();
+            log.finish(0).unwrap();
+        }
+
+        let read = |path: &Path| fs::read_to_string(path).unwrap();
+        assert!(read(&path).contains("run 4"));
+        assert!(read(&rotated_path(&path, 1)).contains("run 3"));
+        assert!(read(&rotated_path(&path, 2)).contains("run 2"));
+        assert!(!rotated_path(&path, 3).exists());
+    }
+}
@@ -0,0 +1,166 @@
+//! Notifications from the operating system that it resumed from sleep, for
+//! `sleep_detection = "system"`
+//!
+//! On Linux the `PrepareForSleep` signal of systemd-logind is followed with `gdbus monitor`.
+//! On macOS the kernel's last wake time, `sysctl kern.waketime`, is polled. Elsewhere, or
+//! when the tool isn't available, no events are delivered and the scheduler falls back to
+//! noticing gaps in wall-clock time.
+
+use tokio::sync::mpsc;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use tracing::debug;
+#[cfg(target_os = "linux")]
+use tracing::warn;
+
+/// Receives an event each time the system resumes from sleep
+pub(crate) struct PowerEvents {
+    resumed: mpsc::UnboundedReceiver<()>,
+}
+
+impl PowerEvents {
+    /// Starts watching for resume events, or returns `None` if the platform doesn't offer a
+    /// way to; must be called from within a tokio runtime
+    pub(crate) fn start() -> Option<Self> {
+        let (tx, resumed) = mpsc::unbounded_channel();
+        watch(tx)?;
+        Some(Self { resumed })
+    }
+
+    /// Events sent through the returned channel, for tests
+    #[cfg(test)]
+    pub(crate) fn channel() -> (mpsc::UnboundedSender<()>, Self) {
+        let (tx, resumed) = mpsc::unbounded_channel();
+        (tx, Self { resumed })
+    }
+
+    /// Waits until the system resumes, returning `false` instead if the watcher stopped
+    pub(crate) async fn recv(&mut self) -> bool {
+        self.resumed.recv().await.is_some()
+    }
+}
+
+/// Whether the system is about to sleep or has just woken, from a line of `gdbus monitor`
+/// output such as `/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep
+/// (false,)`
+///
+/// Returns `Some(true)` when going to sleep and `Some(false)` on resume.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_prepare_for_sleep(line: &str) -> Option<bool> {
+    let (_, arguments) = line.split_once("PrepareForSleep")?;
+    match arguments.trim().trim_start_matches('(').split(',').next()? {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// The seconds of `sysctl -n kern.waketime` output such as
+/// `{ sec = 1760000000, usec = 123456 } Thu Oct  9 08:53:20 2025`
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_waketime(output: &str) -> Option<i64> {
+    let (_, rest) = output.split_once("sec =")?;
+    rest.split(',').next()?.trim().parse().ok()
+}
+
+#[cfg(target_os = "linux")]
+fn watch(tx: mpsc::UnboundedSender<()>) -> Option<()> {
+    use std::process::Stdio;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    let mut child = tokio::process::Command::new("gdbus")
+        .args([
+            "monitor",
+            "--system",
+            "--dest",
+            "org.freedesktop.login1",
+            "--object-path",
+            "/org/freedesktop/login1",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| warn!("Failed to watch for logind sleep signals with gdbus: {}", e))
+        .ok()?;
+    let stdout = child.stdout.take()?;
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            match parse_prepare_for_sleep(&line) {
+                Some(true) => debug!("System is going to sleep"),
+                Some(false) if tx.send(()).is_err() => break,
+                Some(false) | None => {}
+            }
+        }
+        warn!("Stopped receiving sleep signals from logind");
+        let _ = child.kill().await;
+    });
+    Some(())
+}
+
+#[cfg(target_os = "macos")]
+fn watch(tx: mpsc::UnboundedSender<()>) -> Option<()> {
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+    async fn waketime() -> Option<i64> {
+        let output = tokio::process::Command::new("sysctl")
+            .args(["-n", "kern.waketime"])
+            .output()
+            .await
+            .map_err(|e| debug!("Failed to read kern.waketime: {}", e))
+            .ok()?;
+        parse_waketime(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    tokio::spawn(async move {
+        let mut last = waketime().await;
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let current = waketime().await;
+            if current.is_some() && current != last {
+                last = current;
+                if tx.send(()).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    Some(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn watch(_tx: mpsc::UnboundedSender<()>) -> Option<()> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prepare_for_sleep() {
+        let signal = |argument| {
+            format!(
+                "/org/freedesktop/login1: org.freedesktop.login1.Manager.PrepareForSleep ({},)",
+                argument
+            )
+        };
+        assert_eq!(parse_prepare_for_sleep(&signal("true")), Some(true));
+        assert_eq!(parse_prepare_for_sleep(&signal("false")), Some(false));
+        assert_eq!(
+            parse_prepare_for_sleep(
+                "/org/freedesktop/login1: org.freedesktop.login1.Manager.SessionNew ('3', objectpath '/org/freedesktop/login1/session/_33')"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_waketime() {
+        assert_eq!(
+            parse_waketime("{ sec = 1760000000, usec = 123456 } Thu Oct  9 08:53:20 2025\n"),
+            Some(1760000000)
+        );
+        assert_eq!(parse_waketime(""), None);
+    }
+}
@@ -1,24 +1,66 @@
-use crate::config::CommandConfig;
-use crate::core::executor::{CommandExecutor, DefaultExecutor};
-use crate::state::StateManager;
+use crate::config::{
+    CatchUpPolicy, CommandConfig, Config, GeneralConfig, IntervalAnchor, OverlapPolicy,
+    ParsedCommand, RunWindow, ScheduleSpec, SleepDetection,
+};
+use crate::core::clock::{Clock, SystemClock};
+use crate::core::control::{ControlHandle, ControlMessage, ControlRequest, ControlResponse};
+use crate::core::desktop::{DesktopNotifier, Notifier};
+use crate::core::executor::{CommandExecutor, CommandOutput, DefaultExecutor};
+use crate::core::hooks::{self, HookKind};
+use crate::core::power::PowerEvents;
+use crate::core::sd_notify::SdNotify;
+use crate::core::signals::{SignalEvent, Signals};
+use crate::core::staleness::{self, StalenessCheck};
+use crate::core::status::{statuses_from_states, CommandStatus};
+use crate::core::webhook::{self, FailureEvent, FailurePayload};
+use crate::metrics::Metrics;
+use crate::state::{CommandState, ExecutionRecord, StateManager};
+use crate::util::expand_tilde;
 use anyhow::Result;
-use chrono::{DateTime, Duration, Utc};
-use cron::Schedule;
+use chrono::{DateTime, Duration, Local, LocalResult, NaiveDateTime, TimeZone, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::cmp::Ordering;
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::future::Future;
 use std::path::PathBuf;
-use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration as StdDuration;
-use tokio::time::{sleep, timeout};
+use tokio::sync::{mpsc, watch, Semaphore};
+use tokio::time::{timeout, Instant};
 use tracing::{error, info, warn};
 
+/// Longest single wait for the next command, after which the queue is looked at again so a
+/// change to the system clock is noticed
+const MAX_SLEEP: StdDuration = StdDuration::from_secs(3600);
+
+/// How far the wall clock may fall behind the monotonic clock before next runs are
+/// recalculated for a clock that was set back
+const CLOCK_STEP_TOLERANCE: Duration = Duration::minutes(1);
+
+/// How the wall clock moved between two looks, compared with the monotonic clock
+#[derive(Debug, PartialEq)]
+enum ClockJump {
+    /// Both clocks moved together
+    None,
+    /// The wall clock got ahead by this much, from a sleep or being set forward
+    Ahead(Duration),
+    /// The wall clock was set back by this much
+    Back(Duration),
+}
+
+/// How often execution history older than `history_retention_days` is pruned while the
+/// scheduler runs
+const HISTORY_PRUNE_INTERVAL: Duration = Duration::days(1);
+
 /// Represents a command that is scheduled to run at a specific time
 ///
-/// This struct combines a command configuration with its next scheduled execution time.
-/// It implements ordering traits to allow commands to be sorted by their next run time.
+/// This struct combines a command configuration and its parsed schedule with its next
+/// scheduled execution time. It implements ordering traits to allow commands to be sorted by
+/// their next run time.
 #[derive(Debug)]
 struct ScheduledCommand {
-    command: CommandConfig,
+    command: ParsedCommand,
     next_run: DateTime<Utc>,
 }
 
@@ -42,6 +84,35 @@ impl Ord for ScheduledCommand {
     }
 }
 
+/// How a dispatched command execution ended
+#[derive(Debug)]
+enum RunOutcome {
+    /// The process ran to completion (with any exit status)
+    Finished(CommandOutput),
+    /// The process could not be spawned or waited on
+    Failed(std::io::Error),
+    /// The process exceeded its maximum runtime and was killed
+    TimedOut(StdDuration),
+}
+
+/// A finished execution reported back from its task to the scheduler loop
+#[derive(Debug)]
+struct CompletedRun {
+    command: ParsedCommand,
+    /// When the run was due, which a cron command's next run is counted from
+    scheduled_for: DateTime<Utc>,
+    started: DateTime<Utc>,
+    finished: DateTime<Utc>,
+    outcome: RunOutcome,
+}
+
+/// The run a command's next run is calculated after
+#[derive(Debug, Clone, Copy)]
+struct PreviousRun {
+    scheduled_for: DateTime<Utc>,
+    started: DateTime<Utc>,
+}
+
 /// Manages the scheduling and execution of commands
 ///
 /// The scheduler maintains a priority queue of commands sorted by their next execution time.
@@ -49,12 +120,71 @@ impl Ord for ScheduledCommand {
 /// and manages system sleep events to ensure commands are executed as expected.
 pub struct Scheduler {
     commands: BinaryHeap<ScheduledCommand>,
-    executor: Box<dyn CommandExecutor + Send + Sync>,
+    executor: Arc<dyn CommandExecutor>,
+    clock: Arc<dyn Clock>,
     min_interval_seconds: u64,
     last_execution_time: Option<DateTime<Utc>>,
+    /// Start time of the last wait for `min_interval_seconds` that was logged, so each wait
+    /// is only logged once however often the loop wakes during it
+    throttle_logged_until: Option<DateTime<Utc>>,
     last_wake_time: Option<DateTime<Utc>>,
+    /// Monotonic time of `last_wake_time`
+    last_wake_instant: Instant,
     state_manager: StateManager,
     max_immediate_executions: usize,
+    running: HashMap<String, usize>,
+    queued: HashMap<String, ParsedCommand>,
+    retry_attempts: HashMap<String, u32>,
+    /// Missed runs still to execute after a sleep, for commands with `CatchUpPolicy::RunAll`
+    catch_up_runs: HashMap<String, usize>,
+    /// First scheduled run of each `interval_anchor = "fixed"` command, which its later runs
+    /// are counted from
+    interval_origins: HashMap<String, DateTime<Utc>>,
+    /// For each command that runs after its `depends_on` commands, the dependencies that
+    /// have succeeded since it last ran or was skipped
+    dependency_progress: HashMap<String, HashSet<String>>,
+    concurrency_limit: Option<Arc<Semaphore>>,
+    completions_tx: mpsc::UnboundedSender<CompletedRun>,
+    completions_rx: mpsc::UnboundedReceiver<CompletedRun>,
+    config_path: Option<PathBuf>,
+    /// Configuration changes from a reload for commands that were running at the time,
+    /// applied when they finish (`None` if the command was removed or disabled)
+    reloaded: HashMap<String, Option<ParsedCommand>>,
+    signals: Option<Signals>,
+    control_tx: mpsc::UnboundedSender<ControlMessage>,
+    control_rx: mpsc::UnboundedReceiver<ControlMessage>,
+    /// Commands whose scheduled runs are skipped, paused through a [`ControlHandle`]
+    paused: HashSet<String>,
+    /// Whether the scheduled runs of every command are skipped, as after SIGUSR1
+    paused_all: bool,
+    /// Readiness, status and watchdog notifications for systemd
+    sd_notify: SdNotify,
+    desktop: DesktopNotifier,
+    /// Commands with `max_staleness_minutes`, for the task that reports overdue commands
+    staleness_tx: watch::Sender<Vec<StalenessCheck>>,
+    /// Set when a shutdown signal arrives; the loop stops at its next iteration
+    shutdown_requested: bool,
+    /// Set once the scheduler is stopping, after which due commands are no longer started
+    shutting_down: bool,
+    /// How long running commands get to finish when the scheduler stops
+    shutdown_timeout: StdDuration,
+    /// Every command from the configuration, including disabled ones, for status reports
+    configured: Vec<CommandConfig>,
+    status_tx: watch::Sender<Vec<CommandStatus>>,
+    metrics: Option<Arc<Metrics>>,
+    /// Source of jitter and startup splay; seeded through the builder for repeatable runs
+    rng: StdRng,
+    /// Upper bound of the random delay before the first dispatch after startup
+    startup_splay: StdDuration,
+    /// Days of execution history to keep; 0 keeps everything
+    history_retention_days: u32,
+    /// When execution history was last pruned
+    last_history_prune: Option<DateTime<Utc>>,
+    sleep_detection: SleepDetection,
+    /// How far the wall clock has to get ahead of the monotonic clock to count as a sleep
+    sleep_threshold: Duration,
+    /// Resume notifications from the operating system, with `SleepDetection::System`
+    power_events: Option<PowerEvents>,
 }
 
 impl Scheduler {
@@ -69,84 +199,558 @@ impl Scheduler {
     /// * `commands` - A vector of command configurations to be scheduled
     #[allow(dead_code)]
     pub fn new(commands: Vec<CommandConfig>, state_path: PathBuf) -> Result<Self> {
-        Self::new_with_config(commands, state_path, 10, 30)
+        let general = GeneralConfig::default();
+        Self::new_with_config(
+            commands,
+            state_path,
+            general.max_immediate_executions,
+            general.min_interval_seconds,
+            None,
+        )
+    }
+
+    /// Creates a new scheduler that runs commands through the given executor instead of the
+    /// system shell
+    pub fn new_with_executor(
+        commands: Vec<CommandConfig>,
+        state_path: PathBuf,
+        executor: Box<dyn CommandExecutor>,
+    ) -> Result<Self> {
+        let mut scheduler = Self::new(commands, state_path)?;
+        scheduler.executor = Arc::from(executor);
+        Ok(scheduler)
     }
 
+    /// Creates a new scheduler using the limits from the general configuration
+    ///
+    /// `max_concurrent` caps how many commands may execute at the same time; `None`
+    /// lets every due command run in parallel.
     pub fn new_with_config(
         commands: Vec<CommandConfig>,
         state_path: PathBuf,
         max_immediate_executions: usize,
         min_interval_seconds: u64,
+        max_concurrent: Option<usize>,
+    ) -> Result<Self> {
+        let mut scheduler = Self::create(
+            commands,
+            state_path,
+            max_immediate_executions,
+            min_interval_seconds,
+            max_concurrent,
+            StdRng::from_entropy(),
+            Arc::new(SystemClock),
+        )?;
+        scheduler.prune_orphaned_state();
+        scheduler.schedule_configured(false)?;
+        Ok(scheduler)
+    }
+
+    /// Sets up a scheduler for `commands` without scheduling them yet
+    fn create(
+        commands: Vec<CommandConfig>,
+        state_path: PathBuf,
+        max_immediate_executions: usize,
+        min_interval_seconds: u64,
+        max_concurrent: Option<usize>,
+        rng: StdRng,
+        clock: Arc<dyn Clock>,
     ) -> Result<Self> {
         let state_path_for_manager = state_path.clone();
 
         let state_manager = StateManager::new(state_path_for_manager)?;
 
-        let existing_states = state_manager.load_command_states().unwrap_or_else(|e| {
-            warn!("Failed to load command states (using empty): {}", e);
-            Vec::new()
-        });
-        let mut state_map = existing_states
-            .into_iter()
-            .map(|state| (state.name.clone(), state))
-            .collect::<std::collections::HashMap<_, _>>();
-
-        let mut scheduler = Scheduler {
+        let (completions_tx, completions_rx) = mpsc::unbounded_channel();
+        let (control_tx, control_rx) = mpsc::unbounded_channel();
+        let now = clock.now();
+        Ok(Scheduler {
             commands: BinaryHeap::new(),
-            executor: Box::new(DefaultExecutor),
+            executor: Arc::new(DefaultExecutor),
+            clock,
             min_interval_seconds,
             last_execution_time: None,
-            last_wake_time: Some(Utc::now()),
+            throttle_logged_until: None,
+            last_wake_time: Some(now),
+            last_wake_instant: Instant::now(),
             state_manager,
             max_immediate_executions,
+            running: HashMap::new(),
+            queued: HashMap::new(),
+            retry_attempts: HashMap::new(),
+            catch_up_runs: HashMap::new(),
+            interval_origins: HashMap::new(),
+            dependency_progress: HashMap::new(),
+            concurrency_limit: max_concurrent.map(|limit| Arc::new(Semaphore::new(limit))),
+            completions_tx,
+            completions_rx,
+            config_path: None,
+            reloaded: HashMap::new(),
+            signals: None,
+            control_tx,
+            control_rx,
+            paused: HashSet::new(),
+            paused_all: false,
+            sd_notify: SdNotify::from_env(),
+            desktop: DesktopNotifier::default(),
+            staleness_tx: watch::channel(Vec::new()).0,
+            shutdown_requested: false,
+            shutting_down: false,
+            shutdown_timeout: StdDuration::from_secs(
+                GeneralConfig::default().shutdown_timeout_seconds,
+            ),
+            configured: commands,
+            status_tx: watch::channel(Vec::new()).0,
+            metrics: None,
+            rng,
+            startup_splay: StdDuration::ZERO,
+            history_retention_days: 0,
+            last_history_prune: None,
+            sleep_detection: SleepDetection::default(),
+            sleep_threshold: Duration::minutes(
+                GeneralConfig::default().sleep_detection_threshold_minutes as i64,
+            ),
+            power_events: None,
+        })
+    }
+
+    /// Deletes the saved state of commands that are no longer in the configuration
+    fn prune_orphaned_state(&self) {
+        let names = self
+            .configured
+            .iter()
+            .map(|command| command.name.clone())
+            .collect::<Vec<_>>();
+        match self.state_manager.prune(&names) {
+            Ok(removed) => {
+                for name in removed {
+                    info!(
+                        "Removed the saved state of command '{}', which is no longer configured",
+                        name
+                    );
+                }
+            }
+            Err(e) => warn!("Failed to remove the state of unconfigured commands: {}", e),
+        }
+    }
+
+    /// Schedules the enabled commands from the configuration, continuing from their saved
+    /// state
+    ///
+    /// A command that can't be scheduled is reported and left out, or fails startup if
+    /// `strict`.
+    fn schedule_configured(&mut self, strict: bool) -> Result<()> {
+        let existing_states = self
+            .state_manager
+            .load_command_states()
+            .unwrap_or_else(|e| {
+                warn!("Failed to load command states (using empty): {}", e);
+                Vec::new()
+            });
+        let mut state_map = existing_states
+            .into_iter()
+            .map(|state| (state.name.clone(), state))
+            .collect::<HashMap<_, _>>();
+
+        info!("Scheduling {} commands", self.configured.len());
+        for command in self
+            .configured
+            .clone()
+            .into_iter()
+            .filter(|command| command.enabled)
+        {
+            let state = state_map.remove(&command.name);
+            let initial = ParsedCommand::new(command.clone()).and_then(|command| {
+                let next_run = self.initial_run(&command, state)?;
+                Ok(next_run.map(|next_run| (command, next_run)))
+            });
+            match initial {
+                Ok(Some((command, next_run))) => {
+                    // Saved straight away, so a restart before the first run keeps it. The
+                    // loop isn't running yet, so this doesn't hold anything up
+                    if let Err(e) = self.state_manager.save_next_scheduled(&command, next_run) {
+                        error!("Failed to save state for command '{}': {}", command.name, e);
+                    }
+                    self.commands.push(ScheduledCommand { command, next_run });
+                }
+                Ok(None) => {}
+                Err(e) if strict => {
+                    return Err(e.context(format!("Failed to schedule command '{}'", command.name)))
+                }
+                Err(e) => Self::report_unschedulable(&command, &e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Decides when an enabled command first runs after startup, from its saved state if it
+    /// has one, or returns `None` if it shouldn't be scheduled
+    fn initial_run(
+        &mut self,
+        command: &ParsedCommand,
+        state: Option<CommandState>,
+    ) -> Result<Option<DateTime<Utc>>> {
+        command.validate()?;
+        if command.runs_after_dependencies() {
+            info!(
+                "Command '{}' runs {}",
+                command.name,
+                command.schedule_description()
+            );
+            return Ok(None);
+        }
+        info!("Scheduling command: {}", command.name);
+        let next_run = if let Some(state) = state {
+            info!("Found existing state for command '{}'", command.name);
+            if command.exceeds_failure_limit(state.consecutive_failures) {
+                Self::warn_disabled(command, state.consecutive_failures);
+                return Ok(None);
+            }
+            if state.is_complete(command.schedule.as_ref()) {
+                info!(
+                    "Not scheduling command '{}': it already had its run at {}",
+                    command.name,
+                    command.schedule_description()
+                );
+                return Ok(None);
+            }
+            if command.at.is_some() {
+                self.first_run(command)?
+            } else if let Some((schedule_type, schedule_data)) = state.changed_schedule(command) {
+                info!(
+                    "Schedule of command '{}' changed from {} '{}' to {}, recalculating its next run",
+                    command.name,
+                    schedule_type,
+                    schedule_data,
+                    command.schedule_description()
+                );
+                self.first_run(command)?
+            } else {
+                self.catch_up_on_startup(command, state.next_scheduled)?
+            }
+        } else {
+            self.first_run(command)?
         };
+        if self.misses_one_shot(command) {
+            if let Err(e) = self.state_manager.mark_completed(command, self.clock.now()) {
+                error!("Failed to save state for command '{}': {}", command.name, e);
+            }
+            return Ok(None);
+        }
+        Ok(Some(next_run))
+    }
 
-        info!("Scheduling {} commands", commands.len());
-        for command in commands {
-            if command.enabled {
-                info!("Scheduling command: {}", command.name);
-                command.validate()?;
-                let next_run = if let Some(state) = state_map.remove(&command.name) {
-                    info!("Found existing state for command '{}'", command.name);
-                    state.next_scheduled
-                } else {
-                    Self::calculate_next_run(&command)?
-                };
+    /// Calculates the next run time after `now` for a command based on its schedule type,
+    /// moved into its run window if it has one
+    pub fn calculate_next_run(
+        command: &ParsedCommand,
+        now: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>> {
+        Self::within_run_window(command, Self::scheduled_run(command, now)?)
+    }
 
-                scheduler
-                    .commands
-                    .push(ScheduledCommand { command, next_run });
+    /// Calculates the first run of a command that has no saved schedule, pushing it back by
+    /// the command's start delay plus a random amount up to its jitter
+    ///
+    /// One-shot commands run at their `at` time as given, or right away if it has passed.
+    fn first_run(&mut self, command: &ParsedCommand) -> Result<DateTime<Utc>> {
+        if command.at.is_some() {
+            return Self::calculate_next_run(command, self.clock.now());
+        }
+        let delay = Duration::seconds(command.start_delay_seconds.unwrap_or(0) as i64);
+        let first_run = Self::scheduled_run(command, self.clock.now())? + delay;
+        let first_run = Self::jitter(command, first_run, &mut self.rng);
+        Self::within_run_window(command, first_run)
+    }
+
+    /// Applies a command's `catch_up` policy to a saved run that came due while the
+    /// scheduler wasn't running, returning when the command should run first
+    fn catch_up_on_startup(
+        &mut self,
+        command: &ParsedCommand,
+        next_scheduled: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>> {
+        let now = self.clock.now();
+        if next_scheduled >= now {
+            return Ok(next_scheduled);
+        }
+        match command.catch_up {
+            CatchUpPolicy::Skip => {
+                info!(
+                    "Skipping runs of command '{}' missed while the scheduler was stopped (was scheduled for {})",
+                    command.name, next_scheduled
+                );
+                self.note_interval_origin(command, next_scheduled);
+                self.jittered_next_run(command)
+            }
+            // Due now rather than at the missed run, so a cron command continues from here
+            CatchUpPolicy::RunOnce => Ok(now),
+            CatchUpPolicy::RunAll => {
+                let missed_runs = Self::missed_runs(
+                    command,
+                    next_scheduled,
+                    now,
+                    self.max_immediate_executions.max(1),
+                );
+                if missed_runs > 1 {
+                    info!(
+                        "Command '{}' missed {} runs while the scheduler was stopped",
+                        command.name, missed_runs
+                    );
+                    self.catch_up_runs
+                        .insert(command.name.clone(), missed_runs - 1);
+                }
+                Ok(next_scheduled)
             }
         }
+    }
 
-        Ok(scheduler)
+    /// Calculates the next run of a command, pushed back by a random amount up to its
+    /// jitter and moved into its run window if it has one
+    fn jittered_next_run(&mut self, command: &ParsedCommand) -> Result<DateTime<Utc>> {
+        self.jittered_run_after(command, None)
     }
 
-    /// Calculates the next run time for a command based on its schedule type
-    fn calculate_next_run(command: &CommandConfig) -> Result<DateTime<Utc>> {
-        let now = Utc::now();
-        if let Some(interval) = command.interval_minutes {
-            Ok(now + Duration::minutes(interval as i64))
-        } else if let Some(cron) = &command.cron {
-            let schedule = Schedule::from_str(cron)
-                .map_err(|e| anyhow::anyhow!("Invalid cron expression: {}", e))?;
-            schedule
-                .upcoming(Utc)
-                .next()
-                .ok_or_else(|| anyhow::anyhow!("Failed to calculate next cron run"))
-        } else {
-            Err(anyhow::anyhow!(
+    /// Like [`Self::jittered_next_run`], counting from the run that just finished if the
+    /// command's schedule is anchored to it
+    fn jittered_run_after(
+        &mut self,
+        command: &ParsedCommand,
+        previous: Option<PreviousRun>,
+    ) -> Result<DateTime<Utc>> {
+        let origin = self.interval_origins.get(&command.name).copied();
+        let next_run = Self::anchored_run(command, previous, origin, self.clock.now())?;
+        let next_run = Self::jitter(command, next_run, &mut self.rng);
+        Self::within_run_window(command, next_run)
+    }
+
+    /// Remembers `slot` as the first scheduled run of a command with
+    /// `interval_anchor = "fixed"`, unless it already has one
+    fn note_interval_origin(&mut self, command: &CommandConfig, slot: DateTime<Utc>) {
+        if command.interval_anchor() == IntervalAnchor::Fixed {
+            self.interval_origins
+                .entry(command.name.clone())
+                .or_insert(slot);
+        }
+    }
+
+    /// Calculates the next run after `now` from the command's schedule
+    ///
+    /// `previous` is the run that just finished, if any, and `origin` the first scheduled
+    /// run of an `interval_anchor = "fixed"` command. Intervals follow their
+    /// `interval_anchor`: a `start` anchored run that took longer than the interval is
+    /// followed by the next one straight away, and a `fixed` one by the first slot still
+    /// ahead. Cron schedules continue from the time the previous run was due, see
+    /// [`Self::cron_run_after`].
+    fn anchored_run(
+        command: &ParsedCommand,
+        previous: Option<PreviousRun>,
+        origin: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>> {
+        match (&command.schedule, previous) {
+            (
+                Some(ScheduleSpec::Interval {
+                    every,
+                    aligned: false,
+                }),
+                _,
+            ) => match (command.interval_anchor(), previous, origin) {
+                (IntervalAnchor::Start, Some(previous), _) => {
+                    return Ok((previous.started + *every).max(now))
+                }
+                (IntervalAnchor::Fixed, _, Some(origin)) => {
+                    return Ok(ScheduleSpec::next_on_grid(origin, *every, now))
+                }
+                _ => {}
+            },
+            (Some(schedule @ ScheduleSpec::Cron { .. }), Some(previous)) => {
+                return Self::cron_run_after(command, schedule, previous.scheduled_for, now)
+            }
+            _ => {}
+        }
+        Self::scheduled_run(command, now)
+    }
+
+    /// Calculates the next run of a cron command from the time its previous run was due
+    ///
+    /// Every occurrence is accounted for: the one after `scheduled_for` is next, unless it
+    /// already passed while the previous run went on or waited, in which case the
+    /// command's `catch_up` policy decides. `run_all` runs each missed occurrence in turn,
+    /// `run_once` runs one right away, and `skip` waits for the first occurrence after
+    /// `now`.
+    fn cron_run_after(
+        command: &CommandConfig,
+        schedule: &ScheduleSpec,
+        scheduled_for: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>> {
+        let no_runs = || anyhow::anyhow!("Command '{}' has no upcoming runs", command.name);
+        let next = schedule.next_after(scheduled_for).ok_or_else(no_runs)?;
+        if next > now {
+            return Ok(next);
+        }
+        match command.catch_up {
+            CatchUpPolicy::RunAll => Ok(next),
+            CatchUpPolicy::RunOnce => Ok(now),
+            CatchUpPolicy::Skip => schedule.next_after(now).ok_or_else(no_runs),
+        }
+    }
+
+    /// Pushes `run` back by a random amount up to the command's `jitter_seconds`
+    ///
+    /// The run always stays before the one that follows it, so a short interval or a busy
+    /// cron expression never has a run overtake the next. One-shot commands keep their time.
+    fn jitter(command: &ParsedCommand, run: DateTime<Utc>, rng: &mut impl Rng) -> DateTime<Utc> {
+        let jitter_ms = command.jitter_seconds.unwrap_or(0) as i64 * 1000;
+        let schedule = match &command.schedule {
+            Some(ScheduleSpec::At(_)) | None => return run,
+            Some(schedule) => schedule,
+        };
+        let max_ms = match schedule.next_after(run) {
+            Some(following) => jitter_ms.min((following - run).num_milliseconds() - 1),
+            None => jitter_ms,
+        };
+        if max_ms <= 0 {
+            return run;
+        }
+        run + Duration::milliseconds(rng.gen_range(0..=max_ms))
+    }
+
+    /// Calculates the next run after `now` once the failure backoff is applied
+    ///
+    /// Interval schedules wait out the whole delay; cron and aligned schedules keep to
+    /// their slots and take the first one the delay reaches.
+    fn backoff_run(
+        command: &ParsedCommand,
+        now: DateTime<Utc>,
+        consecutive_failures: u32,
+    ) -> Result<DateTime<Utc>> {
+        let next = Self::scheduled_run(command, now)?;
+        let period = Self::scheduled_run(command, next)? - next;
+        let Some(delay) = command.failure_backoff_delay(period, consecutive_failures) else {
+            return Ok(next);
+        };
+        let backed_off = now + delay;
+        if backed_off <= next {
+            return Ok(next);
+        }
+        match command.schedule {
+            Some(ScheduleSpec::Interval { aligned: false, .. }) => Ok(backed_off),
+            _ => Self::scheduled_run(command, backed_off - Duration::milliseconds(1)),
+        }
+    }
+
+    /// Calculates the next run after `now` from the command's schedule
+    ///
+    /// A one-shot command whose time has passed is due at `now`.
+    fn scheduled_run(command: &ParsedCommand, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        let Some(schedule) = &command.schedule else {
+            return Err(anyhow::anyhow!(
                 "Command '{}' has no schedule type",
                 command.name
-            ))
+            ));
+        };
+        match schedule.next_after(now) {
+            Some(next) => Ok(next),
+            None if matches!(schedule, ScheduleSpec::At(_)) => Ok(now),
+            None => Err(anyhow::anyhow!(
+                "Command '{}' has no upcoming runs",
+                command.name
+            )),
+        }
+    }
+
+    /// Moves `time` to the next time the command may run when it falls outside its run
+    /// window or inside its blocked window; both are in the command's `timezone` if set and
+    /// local time otherwise
+    fn within_run_window(command: &CommandConfig, time: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        let allowed = command.run_window()?;
+        let blocked = command.blocked_window()?;
+        if allowed.is_none() && blocked.is_none() {
+            return Ok(time);
+        }
+        let next = if command.timezone.is_some() {
+            Self::next_permitted(
+                allowed.as_ref(),
+                blocked.as_ref(),
+                time,
+                &command.timezone()?,
+            )
+        } else {
+            Self::next_permitted(allowed.as_ref(), blocked.as_ref(), time, &Local)
+        };
+        next.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Command '{}' has no time inside its run_window that is outside its \
+                 blocked_window",
+                command.name
+            )
+        })
+    }
+
+    /// Returns `time` if it falls inside `allowed` and outside `blocked`, and otherwise the
+    /// next time that does, or `None` if there is no such time
+    fn next_permitted<Z: TimeZone>(
+        allowed: Option<&RunWindow>,
+        blocked: Option<&RunWindow>,
+        time: DateTime<Utc>,
+        timezone: &Z,
+    ) -> Option<DateTime<Utc>> {
+        let mut time = time;
+        // Every step moves to where one of the windows opens or closes, which happens at
+        // most a few times a day, so a few weeks' worth of steps means there is no answer
+        for _ in 0..64 {
+            let local = time.with_timezone(timezone).naive_local();
+            let next = match allowed {
+                Some(window) if !window.contains_datetime(local) => window.next_opening(local)?,
+                _ => match blocked.and_then(|window| window.closing(local)) {
+                    Some(end) => end,
+                    None => return Some(time),
+                },
+            };
+            time = Self::from_local_after(timezone, next, time);
         }
+        None
+    }
+
+    /// Converts a local time to UTC, taking the first reading of it after `after` when the
+    /// clocks go back and the time an hour later when it falls into a gap as they go forward
+    fn from_local_after<Z: TimeZone>(
+        timezone: &Z,
+        local: NaiveDateTime,
+        after: DateTime<Utc>,
+    ) -> DateTime<Utc> {
+        let readings = |local: NaiveDateTime| match timezone.from_local_datetime(&local) {
+            LocalResult::Single(time) => vec![time.with_timezone(&Utc)],
+            LocalResult::Ambiguous(first, second) => {
+                vec![first.with_timezone(&Utc), second.with_timezone(&Utc)]
+            }
+            LocalResult::None => Vec::new(),
+        };
+        readings(local)
+            .into_iter()
+            .chain(readings(local + Duration::hours(1)))
+            .find(|time| *time > after)
+            .unwrap_or(after + Duration::minutes(1))
     }
 
     /// Schedules the next run of a command based on its schedule type
-    fn schedule_next_run(&mut self, command: CommandConfig) -> Result<DateTime<Utc>> {
-        let next_run = Self::calculate_next_run(&command)?;
+    fn schedule_next_run(&mut self, command: ParsedCommand) -> Result<DateTime<Utc>> {
+        self.schedule_run_after(command, None)
+    }
+
+    /// Schedules the next run of a command after the run `previous`, if any, for schedules
+    /// that are counted from it
+    fn schedule_run_after(
+        &mut self,
+        command: ParsedCommand,
+        previous: Option<PreviousRun>,
+    ) -> Result<DateTime<Utc>> {
+        let next_run = self.jittered_run_after(&command, previous)?;
 
-        let interval_display = if let Some(interval) = command.interval_minutes {
+        let interval_display = if let Some(ScheduleSpec::Interval { every, .. }) = command.schedule
+        {
+            let interval = every.num_milliseconds() as f64 / 60_000.0;
             if interval < 1.0 {
                 format!("{:.1} seconds", interval * 60.0)
             } else if interval < 60.0 {
@@ -154,106 +758,337 @@ impl Scheduler {
             } else {
                 format!("{:.1} hours", interval / 60.0)
             }
-        } else if let Some(cron) = &command.cron {
-            format!("cron: {}", cron)
         } else {
-            "unknown".to_string()
+            command.schedule_description()
         };
 
         info!(
+            command = %command.name,
+            event = "scheduled",
+            next_run = %next_run,
             "Command '{}' next scheduled for {} (in {})",
-            command.name, next_run, interval_display
+            command.name,
+            next_run,
+            interval_display
         );
 
         self.commands.push(ScheduledCommand { command, next_run });
         Ok(next_run)
     }
 
+    /// Schedules the next run of a command whose run just finished, backing off while it
+    /// keeps failing if it has `failure_backoff`
+    fn schedule_after_run(
+        &mut self,
+        command: ParsedCommand,
+        previous: PreviousRun,
+        consecutive_failures: u32,
+    ) -> Result<DateTime<Utc>> {
+        if !command.failure_backoff || consecutive_failures == 0 {
+            return self.schedule_run_after(command, Some(previous));
+        }
+        let next_run = Self::backoff_run(&command, self.clock.now(), consecutive_failures)?;
+        let next_run = Self::jitter(&command, next_run, &mut self.rng);
+        let next_run = Self::within_run_window(&command, next_run)?;
+        warn!(
+            command = %command.name,
+            event = "backoff",
+            next_run = %next_run,
+            "Command '{}' failed {} times in a row, backing off until {}",
+            command.name,
+            consecutive_failures,
+            next_run
+        );
+        self.commands.push(ScheduledCommand { command, next_run });
+        Ok(next_run)
+    }
+
+    /// Queues a failed command for another attempt if its retry policy allows one
+    ///
+    /// Returns the time of the retry, or `None` once the retries for the current cycle are
+    /// exhausted, in which case the command falls back to its normal schedule.
+    fn schedule_retry(&mut self, command: &ParsedCommand) -> Option<DateTime<Utc>> {
+        let max_retries = command.max_retries.unwrap_or(0);
+        let attempt = self.retry_attempts.get(&command.name).copied().unwrap_or(0) + 1;
+        if attempt > max_retries {
+            if self.retry_attempts.remove(&command.name).is_some() {
+                warn!(
+                    "Command '{}' still failing after {} retries, resuming normal schedule",
+                    command.name, max_retries
+                );
+            }
+            return None;
+        }
+
+        self.retry_attempts.insert(command.name.clone(), attempt);
+        let delay = Self::retry_delay(command, attempt);
+        let next_run = self.clock.now() + Duration::milliseconds(delay.as_millis() as i64);
+        info!(
+            "Retrying command '{}' in {:.1} seconds (retry {}/{})",
+            command.name,
+            delay.as_secs_f64(),
+            attempt,
+            max_retries
+        );
+
+        self.commands.push(ScheduledCommand {
+            command: command.clone(),
+            next_run,
+        });
+        Some(next_run)
+    }
+
+    /// Calculates the delay before the given retry attempt, applying any backoff multiplier
+    fn retry_delay(command: &CommandConfig, attempt: u32) -> StdDuration {
+        let base = command.retry_delay_seconds.unwrap_or(60) as f64;
+        let multiplier = command.retry_backoff_multiplier.unwrap_or(1.0);
+        StdDuration::from_secs_f64(base * multiplier.powi(attempt as i32 - 1))
+    }
+
     /// Detects and handles system sleep events
     ///
-    /// This method checks if the system has been asleep for an extended period (more than 5 minutes)
-    /// and executes any commands that were scheduled to run during that time. It maintains the
-    /// regular schedule for future executions.
+    /// This method checks if the system has been asleep for an extended period (at least
+    /// `sleep_detection_threshold_minutes`, 5 by default) and executes any commands that were
+    /// scheduled to run during that time. It maintains the regular schedule for future
+    /// executions. Sleeps aren't looked for when they are reported by the operating system
+    /// instead, or `sleep_detection` is `off`. A system clock that was set back has every
+    /// next run recalculated.
     ///
     /// # Examples
     ///
     /// ```ignore
-    /// let mut scheduler = Scheduler::new_with_config(commands, state_path, 10, 30)?;
+    /// let mut scheduler = Scheduler::new_with_config(commands, state_path, 10, 30, None)?;
     /// scheduler.handle_sleep_resume().await;
     /// ```
     pub async fn handle_sleep_resume(&mut self) {
-        let now = Utc::now();
+        let now = self.clock.now();
+        let now_instant = Instant::now();
 
         if let Some(last_wake) = self.last_wake_time {
-            let time_since_last_wake = now.signed_duration_since(last_wake);
-
-            let was_sleeping = time_since_last_wake.num_minutes() > 5
-                && (match self.last_execution_time {
-                    None => true,
-                    Some(last_exec) => now.signed_duration_since(last_exec).num_minutes() > 5,
-                });
+            match Self::clock_jump(
+                last_wake,
+                now,
+                self.last_wake_instant,
+                now_instant,
+                self.sleep_threshold,
+            ) {
+                ClockJump::Ahead(slept) if self.detects_sleep_by_time_gap() => {
+                    info!("Detected system sleep of {} minutes", slept.num_minutes());
+                    self.catch_up_after_sleep(now).await;
+                }
+                ClockJump::Back(by) => {
+                    warn!(
+                        "System clock was set back by {} seconds, recalculating next runs",
+                        by.num_seconds()
+                    );
+                    self.refresh_next_runs(|_| true).await;
+                }
+                _ => {}
+            }
+        }
 
-            if was_sleeping {
-                info!(
-                    "Detected system sleep of {} minutes",
-                    time_since_last_wake.num_minutes()
-                );
+        self.last_wake_time = Some(now);
+        self.last_wake_instant = now_instant;
+    }
 
-                let current_commands = std::mem::take(&mut self.commands);
-                let command_list: Vec<_> = current_commands.into_iter().collect();
+    /// Compares how far the wall clock moved between two looks with how far the monotonic
+    /// clock did, which doesn't count time the system spent asleep
+    ///
+    /// A wall clock that got ahead by at least `threshold` means a sleep, or the clock being
+    /// set forward, which leaves the same runs missed.
+    fn clock_jump(
+        last_wall: DateTime<Utc>,
+        now_wall: DateTime<Utc>,
+        last_instant: Instant,
+        now_instant: Instant,
+        threshold: Duration,
+    ) -> ClockJump {
+        let monotonic_elapsed =
+            Duration::from_std(now_instant.duration_since(last_instant)).unwrap_or_default();
+        let drift = (now_wall - last_wall) - monotonic_elapsed;
+        if drift >= threshold {
+            ClockJump::Ahead(drift)
+        } else if drift <= -CLOCK_STEP_TOLERANCE {
+            ClockJump::Back(-drift)
+        } else {
+            ClockJump::None
+        }
+    }
 
-                let (missed_commands, future_commands): (Vec<_>, Vec<_>) = command_list
-                    .into_iter()
-                    .partition(|scheduled| scheduled.next_run < now);
+    /// Whether sleeps are noticed by wall-clock time jumping ahead, rather than reported by
+    /// the operating system
+    fn detects_sleep_by_time_gap(&self) -> bool {
+        match self.sleep_detection {
+            SleepDetection::Heuristic => true,
+            SleepDetection::System => self.power_events.is_none(),
+            SleepDetection::Off => false,
+        }
+    }
 
-                for scheduled in future_commands {
-                    self.commands.push(scheduled);
+    /// Moves up the next runs of the commands `applies` picks that were calculated before the
+    /// clock changed and now lie later than their schedule gives
+    ///
+    /// Commands waiting to retry a failed run keep their retry time.
+    async fn refresh_next_runs(&mut self, applies: impl Fn(&ParsedCommand) -> bool) {
+        let now = self.clock.now();
+        for mut scheduled in std::mem::take(&mut self.commands).into_vec() {
+            if applies(&scheduled.command)
+                && !self.retry_attempts.contains_key(&scheduled.command.name)
+            {
+                if let Ok(next_run) = Self::calculate_next_run(&scheduled.command, now) {
+                    if next_run < scheduled.next_run {
+                        info!(
+                            "Moved next run of command '{}' from {} to {}",
+                            scheduled.command.name, scheduled.next_run, next_run
+                        );
+                        scheduled.next_run = next_run;
+                        self.save_next_run(&scheduled.command, next_run).await;
+                    }
                 }
+            }
+            self.commands.push(scheduled);
+        }
+    }
 
-                let missed_count = missed_commands.len();
-                if missed_count > 0 {
-                    info!(
-                        "Found {} commands that should have run during sleep",
-                        missed_count
-                    );
+    /// Executes the runs missed during a sleep according to each command's `catch_up`
+    /// policy, and recalculates cron runs that may have gone stale while the system slept
+    async fn catch_up_after_sleep(&mut self, now: DateTime<Utc>) {
+        let current_commands = std::mem::take(&mut self.commands);
+        let command_list: Vec<_> = current_commands.into_iter().collect();
 
-                    let (immediate_executions, reschedule_rest) =
-                        if missed_commands.len() > self.max_immediate_executions {
-                            missed_commands.split_at(self.max_immediate_executions)
-                        } else {
-                            (missed_commands.as_slice(), &[][..])
-                        };
+        let (missed_commands, future_commands): (Vec<_>, Vec<_>) = command_list
+            .into_iter()
+            .partition(|scheduled| scheduled.next_run < now);
+        for scheduled in &missed_commands {
+            self.note_interval_origin(&scheduled.command, scheduled.next_run);
+        }
 
-                    for scheduled in immediate_executions {
-                        info!(
-                            "Executing missed command: {} (originally scheduled for {})",
-                            scheduled.command.name, scheduled.next_run
-                        );
-                        self.execute_command(scheduled.command.clone()).await;
-                    }
+        for scheduled in future_commands {
+            self.commands.push(scheduled);
+        }
+        self.refresh_next_runs(|command| {
+            matches!(command.schedule, Some(ScheduleSpec::Cron { .. }))
+        })
+        .await;
 
-                    for scheduled in reschedule_rest {
-                        info!(
-                            "Rescheduling missed command without execution: {} (was scheduled for {})",
-                            scheduled.command.name, scheduled.next_run
-                        );
-                        if let Err(e) = self.schedule_next_run(scheduled.command.clone()) {
-                            error!(
-                                "Failed to reschedule command '{}': {}",
-                                scheduled.command.name, e
-                            );
-                        }
-                    }
+        let (skipped_commands, missed_commands): (Vec<_>, Vec<_>) =
+            missed_commands.into_iter().partition(|scheduled| {
+                scheduled.command.catch_up == CatchUpPolicy::Skip
+                    || self.is_paused(&scheduled.command.name)
+            });
+
+        for scheduled in skipped_commands {
+            if self.is_paused(&scheduled.command.name) {
+                self.log_paused_skip(&scheduled.command.name);
+            } else {
+                info!(
+                    "Skipping missed runs of command '{}' per its catch-up policy (was scheduled for {})",
+                    scheduled.command.name, scheduled.next_run
+                );
+            }
+            match self.schedule_next_run(scheduled.command.clone()) {
+                Ok(next_run) => self.save_next_run(&scheduled.command, next_run).await,
+                Err(e) => Self::report_unschedulable(&scheduled.command, &e),
+            }
+        }
+
+        let missed_count = missed_commands.len();
+        if missed_count > 0 {
+            info!(
+                "Found {} commands that should have run during sleep",
+                missed_count
+            );
+
+            let (immediate_executions, reschedule_rest) =
+                if missed_commands.len() > self.max_immediate_executions {
+                    missed_commands.split_at(self.max_immediate_executions)
+                } else {
+                    (missed_commands.as_slice(), &[][..])
+                };
+
+            for scheduled in immediate_executions {
+                let missed_runs = match scheduled.command.catch_up {
+                    CatchUpPolicy::RunAll => Self::missed_runs(
+                        &scheduled.command,
+                        scheduled.next_run,
+                        now,
+                        self.max_immediate_executions.max(1),
+                    ),
+                    _ => 1,
+                };
+                info!(
+                    "Executing missed command: {} (originally scheduled for {}, {} missed runs)",
+                    scheduled.command.name, scheduled.next_run, missed_runs
+                );
+                if missed_runs > 1 {
+                    self.catch_up_runs
+                        .insert(scheduled.command.name.clone(), missed_runs - 1);
+                }
+                // Due now rather than at the missed run, so a cron command continues from here
+                self.dispatch_command(ScheduledCommand {
+                    command: scheduled.command.clone(),
+                    next_run: now,
+                })
+                .await;
+            }
+
+            for scheduled in reschedule_rest {
+                info!(
+                    "Rescheduling missed command without execution: {} (was scheduled for {})",
+                    scheduled.command.name, scheduled.next_run
+                );
+                match self.schedule_next_run(scheduled.command.clone()) {
+                    Ok(next_run) => self.save_next_run(&scheduled.command, next_run).await,
+                    Err(e) => Self::report_unschedulable(&scheduled.command, &e),
                 }
             }
         }
+    }
 
-        self.last_wake_time = Some(now);
+    /// Counts the scheduled runs of a command from `next_run` up to `now`, at most `limit`
+    fn missed_runs(
+        command: &ParsedCommand,
+        next_run: DateTime<Utc>,
+        now: DateTime<Utc>,
+        limit: usize,
+    ) -> usize {
+        if next_run > now {
+            return 0;
+        }
+        let Some(schedule) = &command.schedule else {
+            return 1;
+        };
+        let mut count = 1;
+        let mut run = next_run;
+        while count < limit {
+            match schedule.next_after(run) {
+                Some(next) if next <= now => {
+                    count += 1;
+                    run = next;
+                }
+                _ => break,
+            }
+        }
+        count.min(limit)
     }
 
     /// Runs the scheduler loop, executing commands at their scheduled times
+    ///
+    /// Due commands are dispatched onto their own tasks so a slow command never holds up
+    /// the others; their results are collected here as they finish.
     pub async fn run(&mut self) {
         info!("Starting scheduler loop");
+        if self.signals.is_none() {
+            self.signals = Some(Signals::new());
+        }
+        if self.sleep_detection == SleepDetection::System && self.power_events.is_none() {
+            self.power_events = PowerEvents::start();
+            if self.power_events.is_none() {
+                warn!("System sleep notifications are unavailable, detecting sleep from gaps in time instead");
+            }
+        }
+        self.sd_notify.ready();
+        self.splay_startup().await;
 
         let mut immediate_commands = Vec::new();
         let mut other_commands = Vec::new();
@@ -268,321 +1103,4489 @@ impl Scheduler {
 
         let immediate_count =
             std::cmp::min(immediate_commands.len(), self.max_immediate_executions);
-        for scheduled in immediate_commands.into_iter().take(immediate_count) {
+        let mut immediate_commands = immediate_commands.into_iter();
+        for scheduled in immediate_commands.by_ref().take(immediate_count) {
             info!("Executing immediate command: {}", scheduled.command.name);
-            self.execute_command(scheduled.command).await;
+            self.dispatch_command(scheduled).await;
         }
 
-        for scheduled in other_commands {
+        for scheduled in immediate_commands.chain(other_commands) {
             self.commands.push(scheduled);
         }
+        tokio::spawn(staleness::watch(
+            self.staleness_tx.subscribe(),
+            staleness::CHECK_INTERVAL,
+        ));
 
-        loop {
+        while !self.shutdown_requested {
+            self.publish_status().await;
+            self.notify_status();
+            self.update_scheduled_metric();
             self.handle_sleep_resume().await;
+            self.prune_history_if_due().await;
 
-            if self.commands.is_empty() {
+            let Some(next_run) = self.commands.peek().map(|scheduled| scheduled.next_run) else {
                 info!("No commands scheduled, sleeping for 60 seconds");
-                sleep(StdDuration::from_secs(60)).await;
+                self.wait_for(StdDuration::from_secs(60)).await;
                 continue;
-            }
-
-            let now = Utc::now();
+            };
 
-            if let Some(last_time) = self.last_execution_time {
-                let time_since_last = now.signed_duration_since(last_time);
-                let min_interval_millis = (self.min_interval_seconds * 1000) as i64;
+            let now = self.clock.now();
+            let throttled_until = self
+                .min_interval_elapsed_at()
+                .filter(|allowed| *allowed > now);
+            let mut start_at = next_run.max(throttled_until.unwrap_or(next_run));
+            if throttled_until.is_some() {
+                // Commands that ignore the minimum interval don't wait for it
+                let exempt = self
+                    .commands
+                    .iter()
+                    .filter(|scheduled| scheduled.command.ignore_min_interval)
+                    .map(|scheduled| scheduled.next_run)
+                    .min();
+                start_at = exempt.map_or(start_at, |exempt| start_at.min(exempt));
+            }
+            if start_at <= now {
+                let command_to_run = match throttled_until {
+                    Some(_) => self.take_due_exempt(now),
+                    None => self.commands.pop(),
+                };
+                if let Some(command_to_run) = command_to_run {
+                    self.start_due(command_to_run).await;
+                }
+                continue;
+            }
 
-                if time_since_last.num_milliseconds() < min_interval_millis {
-                    let wait_millis = min_interval_millis - time_since_last.num_milliseconds();
-                    let wait_duration = StdDuration::from_millis(wait_millis as u64);
+            // Any finished run, control request or signal ends the wait early, and those
+            // are the only things that change the queue while it lasts
+            let wait = (start_at - now).to_std().unwrap_or_default().min(MAX_SLEEP);
+            if next_run <= now {
+                if self.throttle_logged_until != Some(start_at) {
+                    self.throttle_logged_until = Some(start_at);
                     info!(
                         "Enforcing minimum interval: waiting for {} milliseconds before next execution",
-                        wait_millis
+                        wait.as_millis()
                     );
-                    sleep(wait_duration).await;
-                    continue;
                 }
+            } else {
+                info!("Sleeping for {:?} until next command", wait);
             }
+            self.wait_for(wait).await;
+        }
 
-            if let Some(scheduled) = self.commands.peek() {
-                let time_until_next = scheduled.next_run.signed_duration_since(now);
-
-                if time_until_next.num_milliseconds() <= 0 {
-                    if let Some(command_to_run) = self.commands.pop() {
-                        let cmd_name = command_to_run.command.name.clone();
-                        info!("Executing command: {}", cmd_name);
-                        self.last_execution_time = Some(Utc::now());
+        self.shutdown().await;
+    }
 
-                        let execution_start = Utc::now();
-                        let execution_timeout = StdDuration::from_secs(
-                            (command_to_run.command.max_runtime_minutes.unwrap_or(5) as u64) * 60,
-                        );
-                        match timeout(
-                            execution_timeout,
-                            self.execute_command(command_to_run.command.clone()),
-                        )
-                        .await
-                        {
-                            Ok(_) => {
-                                info!("Command '{}' execution completed within timeout", cmd_name);
-                            }
-                            Err(_) => {
-                                warn!(
-                                    "Command '{}' execution timed out after {:?}",
-                                    cmd_name, execution_timeout
-                                );
-                                match self.schedule_next_run(command_to_run.command.clone()) {
-                                    Ok(next_run) => {
-                                        if let Err(e) = self.state_manager.save_command_state(
-                                            &command_to_run.command,
-                                            Some(execution_start),
-                                            next_run,
-                                        ) {
-                                            error!(
-                                                "Failed to save state for command '{}': {}",
-                                                cmd_name, e
-                                            );
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!(
-                                            "Failed to calculate next run for timed-out command '{}': {}",
-                                            cmd_name, e
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                    }
-                } else {
-                    let sleep_time_secs = std::cmp::max(time_until_next.num_seconds(), 1) as u64;
-                    let sleep_time_secs = std::cmp::min(sleep_time_secs, 3600);
-                    info!(
-                        "Sleeping for {} seconds until next command",
-                        sleep_time_secs
-                    );
-                    sleep(StdDuration::from_secs(sleep_time_secs)).await;
+    /// Deletes execution history older than `history_retention_days` if it wasn't done in
+    /// the last day, then compacts the state database when anything was removed
+    ///
+    /// Both run on a blocking thread, since `VACUUM` rewrites the whole database file.
+    async fn prune_history_if_due(&mut self) {
+        let now = self.clock.now();
+        if self.history_retention_days == 0
+            || self
+                .last_history_prune
+                .is_some_and(|last| now - last < HISTORY_PRUNE_INTERVAL)
+        {
+            return;
+        }
+        self.last_history_prune = Some(now);
+        let cutoff = now - Duration::days(self.history_retention_days.into());
+        match self.state_manager.prune_history_async(cutoff).await {
+            Ok(0) => {}
+            Ok(removed) => {
+                info!("Pruned {} old executions from history", removed);
+                if let Err(e) = self.state_manager.vacuum_async().await {
+                    warn!("Failed to compact the state database: {}", e);
                 }
-            } else {
-                warn!("Command queue unexpectedly empty, sleeping for 1 second");
-                sleep(StdDuration::from_secs(1)).await;
             }
+            Err(e) => warn!("Failed to prune execution history: {}", e),
         }
     }
 
-    /// Executes a command and handles its output
-    async fn execute_command(&mut self, command: CommandConfig) {
-        let execution_start = Utc::now();
+    /// Waits a random time up to `startup_splay_seconds` before anything is dispatched, so
+    /// machines sharing a configuration don't all start their commands at once
+    ///
+    /// Control requests and signals are still handled while waiting.
+    async fn splay_startup(&mut self) {
+        let max_ms = self.startup_splay.as_millis() as u64;
+        if max_ms == 0 {
+            return;
+        }
+        let splay = StdDuration::from_millis(self.rng.gen_range(0..=max_ms));
+        info!("Delaying the first dispatch by {:?} (startup splay)", splay);
+        let deadline = Instant::now() + splay;
+        while !self.shutdown_requested && Instant::now() < deadline {
+            self.wait_for(deadline - Instant::now()).await;
+        }
+    }
 
-        match self.executor.execute(&command).await {
-            Ok(output) => {
-                if output.status == 0 {
-                    info!("Command '{}' completed successfully", command.name);
-                } else {
+    /// Removes and returns the earliest due command that ignores `min_interval_seconds`
+    fn take_due_exempt(&mut self, now: DateTime<Utc>) -> Option<ScheduledCommand> {
+        let mut commands = std::mem::take(&mut self.commands).into_vec();
+        let index = commands
+            .iter()
+            .enumerate()
+            .filter(|(_, scheduled)| {
+                scheduled.command.ignore_min_interval && scheduled.next_run <= now
+            })
+            .min_by_key(|(_, scheduled)| scheduled.next_run)
+            .map(|(index, _)| index);
+        let taken = index.map(|index| commands.swap_remove(index));
+        self.commands = commands.into();
+        taken
+    }
+
+    /// Returns when `min_interval_seconds` will have passed since the last execution
+    /// started, or `None` if nothing has run yet
+    fn min_interval_elapsed_at(&self) -> Option<DateTime<Utc>> {
+        self.last_execution_time
+            .map(|last| last + Duration::seconds(self.min_interval_seconds as i64))
+    }
+
+    /// Starts a command that came due on its schedule, unless it is paused or waiting for
+    /// its dependencies, in which case this run is skipped
+    async fn start_due(&mut self, scheduled: ScheduledCommand) {
+        self.note_interval_origin(&scheduled.command, scheduled.next_run);
+        if self.is_paused(&scheduled.command.name) {
+            self.log_paused_skip(&scheduled.command.name);
+            self.reschedule(scheduled.command).await;
+        } else if let Some(dependency) = self.unmet_dependency(&scheduled.command).await {
+            let reason = format!(
+                "Skipped because dependency '{}' has not succeeded since the last run",
+                dependency
+            );
+            self.record_skip(&scheduled.command, &reason).await;
+            self.finish_cycle(&scheduled.command.name, false).await;
+            self.reschedule(scheduled.command).await;
+        } else {
+            self.dispatch_command(scheduled).await;
+        }
+    }
+
+    /// Returns the status of every configured command as the scheduler currently sees it
+    ///
+    /// The stored states are read on a blocking thread.
+    pub async fn statuses(&self) -> Result<Vec<CommandStatus>> {
+        let states = self.state_manager.load_command_states_async().await?;
+        let next_runs = self
+            .commands
+            .iter()
+            .map(|scheduled| (scheduled.command.name.as_str(), scheduled.next_run))
+            .collect::<HashMap<_, _>>();
+        let mut statuses = statuses_from_states(&self.configured, states)?;
+        for status in &mut statuses {
+            if status.next_run.is_some() {
+                status.next_run = next_runs.get(status.name.as_str()).copied();
+            }
+            status.running = self.running.contains_key(&status.name);
+            status.paused = self.is_paused(&status.name);
+        }
+        Ok(statuses)
+    }
+
+    /// Returns a receiver that is updated with [`Scheduler::statuses`] while the scheduler runs
+    pub fn subscribe_status(&self) -> watch::Receiver<Vec<CommandStatus>> {
+        self.status_tx.subscribe()
+    }
+
+    /// Returns a handle for controlling the scheduler while it runs
+    pub fn control_handle(&self) -> ControlHandle {
+        ControlHandle {
+            tx: self.control_tx.clone(),
+        }
+    }
+
+    /// Answers a control request
+    async fn handle_control(&mut self, request: ControlRequest) -> ControlResponse {
+        match request {
+            ControlRequest::Status => match self.statuses().await {
+                Ok(statuses) => ControlResponse::commands(statuses),
+                Err(e) => ControlResponse::error(format!("Failed to collect statuses: {}", e)),
+            },
+            ControlRequest::Trigger { name } => self.trigger(&name).await,
+            ControlRequest::Pause { name } => {
+                if !self.is_configured(&name) {
+                    return ControlResponse::error(format!("No command named '{}'", name));
+                }
+                info!("Pausing command '{}'", name);
+                self.paused.insert(name.clone());
+                ControlResponse::message(format!("Paused command '{}'", name))
+            }
+            ControlRequest::Resume { name } => {
+                if !self.is_configured(&name) {
+                    return ControlResponse::error(format!("No command named '{}'", name));
+                }
+                if !self.paused.remove(&name) {
+                    return ControlResponse::error(format!("Command '{}' is not paused", name));
+                }
+                info!("Resuming command '{}'", name);
+                ControlResponse::message(format!("Resumed command '{}'", name))
+            }
+            ControlRequest::PauseAll => {
+                self.pause_all();
+                ControlResponse::message("Paused all commands")
+            }
+            ControlRequest::ResumeAll => {
+                if !self.paused_all {
+                    return ControlResponse::error("The scheduler is not paused");
+                }
+                self.resume_all();
+                ControlResponse::message("Resumed all commands")
+            }
+            ControlRequest::Reload => match self.try_reload_config().await {
+                Ok(()) => ControlResponse::message("Reloaded the configuration"),
+                Err(e) => ControlResponse::error(format!("{:#}", e)),
+            },
+        }
+    }
+
+    /// Skips the scheduled runs of every command until [`Scheduler::resume_all`]; commands
+    /// keep being rescheduled, and running ones finish
+    fn pause_all(&mut self) {
+        if !self.paused_all {
+            info!("Pausing all commands");
+        }
+        self.paused_all = true;
+    }
+
+    fn resume_all(&mut self) {
+        if self.paused_all {
+            info!("Resuming all commands");
+        }
+        self.paused_all = false;
+    }
+
+    /// Whether the scheduled runs of a command are skipped, on its own or with every command
+    fn is_paused(&self, name: &str) -> bool {
+        self.paused_all || self.paused.contains(name)
+    }
+
+    fn log_paused_skip(&self, name: &str) {
+        if self.paused.contains(name) {
+            info!("Skipping run of paused command '{}'", name);
+        } else {
+            info!("Scheduler is paused, skipping run of command '{}'", name);
+        }
+    }
+
+    fn is_configured(&self, name: &str) -> bool {
+        self.configured.iter().any(|command| command.name == name)
+    }
+
+    /// Returns the enabled command named `name` if it runs after its `depends_on` commands
+    fn dependent(&self, name: &str) -> Option<ParsedCommand> {
+        self.configured
+            .iter()
+            .find(|command| {
+                command.name == name && command.enabled && command.runs_after_dependencies()
+            })
+            .map(|command| ParsedCommand {
+                config: command.clone(),
+                schedule: None,
+            })
+    }
+
+    /// Starts a run of a scheduled command now, taking the place of its next scheduled run
+    async fn trigger(&mut self, name: &str) -> ControlResponse {
+        if !self.is_configured(name) {
+            return ControlResponse::error(format!("No command named '{}'", name));
+        }
+        if self.running.contains_key(name) {
+            return ControlResponse::error(format!("Command '{}' is already running", name));
+        }
+        if self.shutting_down {
+            return ControlResponse::error("The scheduler is shutting down");
+        }
+        let mut remaining = std::mem::take(&mut self.commands).into_vec();
+        let Some(index) = remaining
+            .iter()
+            .position(|scheduled| scheduled.command.name == name)
+        else {
+            self.commands = remaining.into();
+            return match self.dependent(name) {
+                Some(command) => {
+                    info!("Triggering command '{}'", name);
+                    self.dispatch_command(ScheduledCommand {
+                        command,
+                        next_run: self.clock.now(),
+                    })
+                    .await;
+                    ControlResponse::message(format!("Triggered command '{}'", name))
+                }
+                None => ControlResponse::error(format!("Command '{}' is not scheduled", name)),
+            };
+        };
+        let mut scheduled = remaining.swap_remove(index);
+        self.commands = remaining.into();
+        info!("Triggering command '{}'", name);
+        scheduled.next_run = self.clock.now();
+        self.dispatch_command(scheduled).await;
+        ControlResponse::message(format!("Triggered command '{}'", name))
+    }
+
+    /// Sends the current statuses to status subscribers, if there are any, and to the
+    /// staleness check if any command has `max_staleness_minutes`
+    async fn publish_status(&self) {
+        let watched = self
+            .configured
+            .iter()
+            .any(|command| command.max_staleness_minutes.is_some());
+        if !watched && !self.staleness_tx.borrow().is_empty() {
+            self.staleness_tx.send_replace(Vec::new());
+        }
+        if self.status_tx.receiver_count() == 0 && !watched {
+            return;
+        }
+        match self.statuses().await {
+            Ok(statuses) => {
+                if watched {
+                    self.staleness_tx
+                        .send_replace(StalenessCheck::from_statuses(&self.configured, &statuses));
+                }
+                self.status_tx.send_replace(statuses);
+            }
+            Err(e) => warn!("Failed to collect command statuses: {}", e),
+        }
+    }
+
+    /// Shows the next scheduled command in `systemctl status`
+    fn notify_status(&mut self) {
+        let status = match self.commands.peek() {
+            Some(next) => format!(
+                "Next: {} at {}",
+                next.command.name,
+                next.next_run.format("%Y-%m-%d %H:%M:%S UTC")
+            ),
+            None => "No commands scheduled".to_string(),
+        };
+        self.sd_notify.status(&status);
+    }
+
+    /// Sets the scheduled commands gauge to the number of distinct commands that are waiting
+    /// to run or running
+    fn update_scheduled_metric(&self) {
+        if let Some(metrics) = &self.metrics {
+            let mut names = self
+                .commands
+                .iter()
+                .map(|scheduled| scheduled.command.name.as_str())
+                .collect::<std::collections::HashSet<_>>();
+            names.extend(self.running.keys().map(String::as_str));
+            metrics.set_scheduled(names.len());
+        }
+    }
+
+    /// Runs the scheduler loop until `shutdown` completes
+    ///
+    /// This is the entry point for embedding the scheduler in another tokio application:
+    /// pass any future (a signal, a channel receiver, a cancellation token) to stop the
+    /// loop. The scheduler then shuts down the same way it does on SIGTERM.
+    pub async fn run_until<F: Future<Output = ()>>(&mut self, shutdown: F) {
+        tokio::select! {
+            _ = self.run() => return,
+            _ = shutdown => {
+                info!("Shutdown requested, stopping scheduler loop");
+            }
+        }
+        self.shutdown().await;
+    }
+
+    /// Stops the scheduler after its loop has ended
+    ///
+    /// No new runs are started, running commands get up to the shutdown timeout to finish
+    /// and the next run of every scheduled command is saved to the state database.
+    async fn shutdown(&mut self) {
+        self.shutting_down = true;
+        self.sd_notify.stopping();
+        let running = self.running.values().sum::<usize>();
+        if running > 0 {
+            info!(
+                "Waiting up to {} seconds for {} running commands to finish",
+                self.shutdown_timeout.as_secs(),
+                running
+            );
+            let deadline = tokio::time::Instant::now() + self.shutdown_timeout;
+            while !self.running.is_empty() {
+                match tokio::time::timeout_at(deadline, self.completions_rx.recv()).await {
+                    Ok(Some(completed)) => self.handle_completion(completed).await,
+                    _ => break,
+                }
+            }
+            if !self.running.is_empty() {
+                let names = self.running.keys().cloned().collect::<Vec<_>>();
+                warn!(
+                    "Stopping while commands are still running: {}",
+                    names.join(", ")
+                );
+            }
+        }
+
+        for scheduled in self.commands.iter() {
+            self.save_next_run(&scheduled.command, scheduled.next_run)
+                .await;
+        }
+        info!(
+            "Scheduler stopped, saved the schedule of {} commands",
+            self.commands.len()
+        );
+    }
+
+    /// Sleeps for up to `duration`, waking early to process a finished execution or a
+    /// signal
+    ///
+    /// The systemd watchdog is pinged on entry and, during long sleeps, as often as it
+    /// asks for.
+    async fn wait_for(&mut self, duration: StdDuration) {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.sd_notify.watchdog();
+            let wake = match self.sd_notify.watchdog_interval() {
+                Some(interval) => deadline.min(Instant::now() + interval),
+                None => deadline,
+            };
+            let signal = async {
+                match self.signals.as_mut() {
+                    Some(signals) => signals.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let resumed = async {
+                match self.power_events.as_mut() {
+                    Some(power_events) => power_events.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                Some(completed) = self.completions_rx.recv() => {
+                    self.handle_completion(completed).await;
+                }
+                Some(message) = self.control_rx.recv() => {
+                    let response = self.handle_control(message.request).await;
+                    // The requester may have given up waiting
+                    let _ = message.reply.send(response);
+                }
+                event = signal => match event {
+                    SignalEvent::Reload => {
+                        info!("Received SIGHUP, reloading configuration");
+                        self.reload_config().await;
+                    }
+                    SignalEvent::Shutdown(name) => {
+                        info!("Received {}, shutting down", name);
+                        self.shutdown_requested = true;
+                    }
+                    SignalEvent::Pause => {
+                        info!("Received SIGUSR1");
+                        self.pause_all();
+                    }
+                    SignalEvent::Resume => {
+                        info!("Received SIGUSR2");
+                        self.resume_all();
+                    }
+                },
+                resumed = resumed => {
+                    if resumed {
+                        info!("System resumed from sleep");
+                        let now = self.clock.now();
+                        self.catch_up_after_sleep(now).await;
+                        self.last_wake_time = Some(now);
+                        self.last_wake_instant = Instant::now();
+                    } else {
+                        warn!("Lost system sleep notifications, detecting sleep from gaps in time instead");
+                        self.power_events = None;
+                    }
+                }
+                _ = self.clock.sleep_until(wake) => {
+                    if wake < deadline {
+                        continue;
+                    }
+                }
+            }
+            return;
+        }
+    }
+
+    /// Reloads the configuration file the scheduler was built from
+    ///
+    /// If the file can't be loaded the error is logged and the current commands are kept.
+    pub async fn reload_config(&mut self) {
+        if let Err(e) = self.try_reload_config().await {
+            error!("{:#}", e);
+        }
+    }
+
+    async fn try_reload_config(&mut self) -> Result<()> {
+        let Some(config_path) = self.config_path.clone() else {
+            anyhow::bail!("No configuration file to reload from");
+        };
+        let config = Config::load(&config_path).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to reload configuration from {:?}, keeping current commands: {}",
+                config_path,
+                e
+            )
+        })?;
+        if config.general.min_interval_seconds != self.min_interval_seconds {
+            info!(
+                "Minimum interval between executions changed from {} to {} seconds",
+                self.min_interval_seconds, config.general.min_interval_seconds
+            );
+            self.min_interval_seconds = config.general.min_interval_seconds;
+        }
+        if config.general.history_retention_days != self.history_retention_days {
+            info!(
+                "History retention changed from {} to {} days",
+                self.history_retention_days, config.general.history_retention_days
+            );
+            self.history_retention_days = config.general.history_retention_days;
+            self.last_history_prune = None;
+        }
+        self.sleep_detection = config.general.sleep_detection;
+        self.sleep_threshold =
+            Duration::minutes(config.general.sleep_detection_threshold_minutes as i64);
+        self.reload_commands(config.commands).await;
+        Ok(())
+    }
+
+    /// Replaces the scheduled commands with a new set, preserving existing timing
+    ///
+    /// Commands that are unchanged keep their next run time, commands whose schedule
+    /// changed are rescheduled, removed or disabled commands are dropped and new commands
+    /// are added. Commands that are currently running pick up their new configuration when
+    /// they finish.
+    pub async fn reload_commands(&mut self, commands: Vec<CommandConfig>) {
+        self.configured = commands.clone();
+        self.paused
+            .retain(|name| commands.iter().any(|command| &command.name == name));
+        self.interval_origins
+            .retain(|name, _| commands.iter().any(|command| &command.name == name));
+        let mut new_commands = commands
+            .into_iter()
+            .filter(|command| command.enabled)
+            .map(|command| (command.name.clone(), command))
+            .collect::<HashMap<_, _>>();
+
+        for scheduled in std::mem::take(&mut self.commands).into_vec() {
+            let name = scheduled.command.name.clone();
+            match new_commands.remove(&name) {
+                None => info!("Removing command '{}' after reload", name),
+                Some(command) if command.same_schedule(&scheduled.command) => {
+                    self.commands.push(ScheduledCommand {
+                        command: ParsedCommand {
+                            config: command,
+                            schedule: scheduled.command.schedule,
+                        },
+                        next_run: scheduled.next_run,
+                    });
+                }
+                Some(command) => {
+                    info!(
+                        "Schedule for command '{}' changed from '{}' to '{}'",
+                        name,
+                        scheduled.command.schedule_description(),
+                        command.schedule_description()
+                    );
+                    self.add_reloaded_command(command).await;
+                }
+            }
+        }
+
+        for name in self.running.keys() {
+            let command = new_commands.remove(name);
+            if command.is_none() {
+                info!(
+                    "Command '{}' was removed and will not be rescheduled after its current run",
+                    name
+                );
+            }
+            let command = match command.map(ParsedCommand::new).transpose() {
+                Ok(command) => command,
+                Err(e) => {
                     error!(
-                        "Command '{}' failed with exit status {}",
-                        command.name, output.status
+                        "Keeping the current configuration of command '{}': {}",
+                        name, e
+                    );
+                    continue;
+                }
+            };
+            self.reloaded.insert(name.clone(), command);
+        }
+
+        for (name, command) in new_commands {
+            if !command.runs_after_dependencies() {
+                info!("Adding command '{}' after reload", name);
+            }
+            self.add_reloaded_command(command).await;
+        }
+        let configured = &self.configured;
+        self.dependency_progress.retain(|name, _| {
+            configured
+                .iter()
+                .any(|command| &command.name == name && command.runs_after_dependencies())
+        });
+    }
+
+    /// Schedules a command that is new or changed after a reload
+    async fn add_reloaded_command(&mut self, command: CommandConfig) {
+        let command = match ParsedCommand::new(command.clone()) {
+            Ok(command) => command,
+            Err(e) => return Self::report_unschedulable(&command, &e),
+        };
+        self.interval_origins.remove(&command.name);
+        let state = self
+            .state_manager
+            .get_command_state_async(&command.name)
+            .await
+            .ok()
+            .flatten();
+        if let Some(state) = &state {
+            if command.exceeds_failure_limit(state.consecutive_failures) {
+                Self::warn_disabled(&command, state.consecutive_failures);
+                return;
+            }
+        }
+        if command.runs_after_dependencies() {
+            return;
+        }
+        if state.is_some_and(|state| state.is_complete(command.schedule.as_ref())) {
+            return;
+        }
+        if self.misses_one_shot(&command) {
+            self.mark_completed(&command).await;
+            return;
+        }
+        match self.first_run(&command) {
+            Ok(next_run) => {
+                self.save_next_run(&command, next_run).await;
+                self.commands.push(ScheduledCommand { command, next_run });
+            }
+            Err(e) => Self::report_unschedulable(&command, &e),
+        }
+    }
+
+    /// Starts a command that has come due on its own task, enforcing its overlap policy
+    ///
+    /// When the previous execution of the command is still in flight, its `overlap_policy`
+    /// decides whether the run is skipped (and rescheduled), queued until the active run
+    /// finishes, or started alongside it. The result of the execution is delivered back to
+    /// the loop through the completion channel.
+    async fn dispatch_command(&mut self, scheduled: ScheduledCommand) {
+        if self.shutting_down {
+            // Left in the queue so the run is saved as due and happens after a restart
+            self.commands.push(scheduled);
+            return;
+        }
+        let cmd_name = scheduled.command.name.clone();
+
+        if self.running.contains_key(&cmd_name) {
+            match scheduled.command.overlap_policy {
+                OverlapPolicy::Skip => {
+                    warn!("Skipping command '{}', previous run still active", cmd_name);
+                    self.reschedule(scheduled.command).await;
+                    return;
+                }
+                OverlapPolicy::Queue => {
+                    info!(
+                        "Queueing command '{}' until its previous run finishes",
+                        cmd_name
                     );
+                    self.queued.insert(cmd_name, scheduled.command);
+                    return;
                 }
-                if !output.stdout.is_empty() {
-                    info!("Output: {}", String::from_utf8_lossy(&output.stdout));
+                OverlapPolicy::Allow => {
+                    info!("Starting command '{}' alongside its previous run", cmd_name);
                 }
-                if !output.stderr.is_empty() {
-                    error!("Error output: {}", String::from_utf8_lossy(&output.stderr));
+            }
+        }
+
+        match self.retry_attempts.get(&cmd_name) {
+            Some(attempt) => info!(
+                command = %cmd_name,
+                event = "started",
+                retry = attempt,
+                "Executing command: {} (retry {}/{})",
+                cmd_name,
+                attempt,
+                scheduled.command.max_retries.unwrap_or(0)
+            ),
+            None => info!(
+                command = %cmd_name,
+                event = "started",
+                "Executing command: {}",
+                cmd_name
+            ),
+        }
+        if !scheduled.command.ignore_min_interval {
+            self.last_execution_time = Some(self.clock.now());
+        }
+        *self.running.entry(cmd_name).or_insert(0) += 1;
+
+        let executor = Arc::clone(&self.executor);
+        let clock = Arc::clone(&self.clock);
+        let concurrency_limit = self.concurrency_limit.clone();
+        let completions_tx = self.completions_tx.clone();
+        tokio::spawn(async move {
+            let _permit = match concurrency_limit {
+                Some(semaphore) => semaphore.acquire_owned().await.ok(),
+                None => None,
+            };
+            let completed =
+                Self::execute_command(executor.as_ref(), clock.as_ref(), scheduled).await;
+            // The receiver only goes away when the scheduler itself is dropped
+            let _ = completions_tx.send(completed);
+        });
+    }
+
+    /// Schedules the next run of a command whose current run is skipped and saves it
+    ///
+    /// Commands with `depends_on` and no schedule of their own wait for their dependencies
+    /// instead.
+    ///
+    /// One-shot commands aren't rescheduled: a skipped run counts as their only one.
+    async fn reschedule(&mut self, command: ParsedCommand) {
+        if command.runs_after_dependencies() {
+            return;
+        }
+        if command.at.is_some() {
+            self.complete_one_shot(&command).await;
+            return;
+        }
+        match self.schedule_next_run(command.clone()) {
+            Ok(next_run) => {
+                self.save_next_run(&command, next_run).await;
+            }
+            Err(e) => Self::report_unschedulable(&command, &e),
+        }
+    }
+
+    /// Executes a command, killing it if it exceeds its maximum runtime
+    async fn execute_command(
+        executor: &dyn CommandExecutor,
+        clock: &dyn Clock,
+        scheduled: ScheduledCommand,
+    ) -> CompletedRun {
+        let ScheduledCommand { command, next_run } = scheduled;
+        let started = clock.now();
+        let execution_timeout = command.max_runtime();
+
+        let outcome = match timeout(execution_timeout, executor.execute(&command)).await {
+            Ok(Ok(output)) => RunOutcome::Finished(output),
+            Ok(Err(e)) => RunOutcome::Failed(e),
+            Err(_) => RunOutcome::TimedOut(execution_timeout),
+        };
+
+        CompletedRun {
+            command,
+            scheduled_for: next_run,
+            started,
+            finished: clock.now(),
+            outcome,
+        }
+    }
+
+    /// Marks a one-shot command as done in the state database so it isn't scheduled again
+    async fn complete_one_shot(&self, command: &CommandConfig) {
+        info!(
+            "Command '{}' had its only run and will not be scheduled again",
+            command.name
+        );
+        self.mark_completed(command).await;
+    }
+
+    /// Records in the state database that a one-shot command is done as of now, logging
+    /// rather than returning a failure
+    async fn mark_completed(&self, command: &CommandConfig) {
+        let marked = self
+            .state_manager
+            .mark_completed_async(command, self.clock.now());
+        if let Err(e) = marked.await {
+            error!("Failed to save state for command '{}': {}", command.name, e);
+        }
+    }
+
+    /// Returns whether a one-shot command's time passed while the scheduler wasn't running
+    /// and its `catch_up` policy is `skip`, so it is done without running
+    ///
+    /// The caller marks the command completed.
+    fn misses_one_shot(&self, command: &ParsedCommand) -> bool {
+        if command.catch_up != CatchUpPolicy::Skip {
+            return false;
+        }
+        let now = self.clock.now();
+        let Some(ScheduleSpec::At(at)) = command.schedule else {
+            return false;
+        };
+        if at > now {
+            return false;
+        }
+        warn!(
+            "Not running command '{}': its run at {} was missed and its catch_up policy is skip",
+            command.name,
+            at.to_rfc3339()
+        );
+        true
+    }
+
+    /// Saves the next run of a command to the state database, logging rather than returning
+    /// a failure
+    async fn save_next_run(&self, command: &CommandConfig, next_run: DateTime<Utc>) {
+        let saved = self
+            .state_manager
+            .save_next_scheduled_async(command, next_run);
+        if let Err(e) = saved.await {
+            error!("Failed to save state for command '{}': {}", command.name, e);
+        }
+    }
+
+    /// Logs that a command's next run couldn't be calculated; it stays out of the schedule,
+    /// while the other commands carry on, until the configuration is reloaded
+    fn report_unschedulable(command: &CommandConfig, error: &anyhow::Error) {
+        error!(
+            command = %command.name,
+            event = "unschedulable",
+            "Not scheduling command '{}': {:#}. It won't run until its schedule is fixed and \
+             the configuration reloaded",
+            command.name,
+            error
+        );
+    }
+
+    fn warn_disabled(command: &CommandConfig, consecutive_failures: u32) {
+        warn!(
+            "Not scheduling command '{}': it was disabled after {} consecutive failures. \
+             A successful `zephyr run-now {}` or `zephyr state reset` enables it again",
+            command.name, consecutive_failures, command.name
+        );
+    }
+
+    /// Adds a finished run to the execution history and stores its result with the
+    /// command's state
+    ///
+    /// Returns how many runs of the command in a row have now failed.
+    async fn record_execution(
+        &self,
+        command: &CommandConfig,
+        started: DateTime<Utc>,
+        finished: DateTime<Utc>,
+        outcome: &RunOutcome,
+    ) -> u32 {
+        let (exit_code, stdout, stderr) = match outcome {
+            RunOutcome::Finished(output) => (
+                Some(output.status),
+                String::from_utf8_lossy(&output.stdout).into_owned(),
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ),
+            RunOutcome::Failed(e) => (None, String::new(), e.to_string()),
+            RunOutcome::TimedOut(_) => (None, String::new(), String::new()),
+        };
+        let (stdout_len, stderr_len) = match outcome {
+            RunOutcome::Finished(output) => (output.stdout_len(), output.stderr_len()),
+            _ => (stdout.len(), stderr.len()),
+        };
+        let record = ExecutionRecord {
+            name: command.name.clone(),
+            started_at: started,
+            finished_at: finished,
+            exit_code,
+            timed_out: matches!(outcome, RunOutcome::TimedOut(_)),
+            succeeded: exit_code.is_some_and(|status| command.is_success(status)),
+            skipped: false,
+            stdout,
+            stderr,
+            stdout_len,
+            stderr_len,
+        };
+        let duration_ms = record.duration().num_milliseconds();
+        if let Err(e) = self.state_manager.record_execution_async(record).await {
+            error!(
+                "Failed to record execution of command '{}': {}",
+                command.name, e
+            );
+        }
+        self.state_manager
+            .save_last_run_async(command, started, exit_code, duration_ms)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to save state for command '{}': {}", command.name, e);
+                0
+            })
+    }
+
+    /// Logs the result of a finished execution, then reschedules the command and saves its state
+    ///
+    /// The state database is written on a blocking thread, so a slow disk doesn't hold up
+    /// the async runtime.
+    async fn handle_completion(&mut self, completed: CompletedRun) {
+        let CompletedRun {
+            mut command,
+            scheduled_for,
+            started,
+            finished,
+            outcome,
+        } = completed;
+        let duration_ms = (finished - started).num_milliseconds();
+        let consecutive_failures = self
+            .record_execution(&command, started, finished, &outcome)
+            .await;
+        let succeeded =
+            matches!(&outcome, RunOutcome::Finished(output) if command.is_success(output.status));
+        if let Some(metrics) = &self.metrics {
+            let duration = (finished - started).to_std().unwrap_or_default();
+            metrics.record_execution(&command.name, succeeded, duration);
+        }
+        let failure = match &outcome {
+            _ if succeeded => None,
+            RunOutcome::Finished(output) => Some((
+                Some(output.status),
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            )),
+            RunOutcome::Failed(e) => Some((None, e.to_string())),
+            RunOutcome::TimedOut(max_runtime) => {
+                Some((None, format!("timed out after {:?}", max_runtime)))
+            }
+        };
+        let hook = if failure.is_some() {
+            HookKind::OnFailure
+        } else {
+            HookKind::OnSuccess
+        };
+        let exit_code = match &outcome {
+            RunOutcome::Finished(output) => Some(output.status),
+            _ => None,
+        };
+        if let Some(hook) = hooks::hook_command(&command, hook, exit_code, duration_ms) {
+            hooks::spawn(Arc::clone(&self.executor), hook);
+        }
+        if let Some((exit_status, stderr)) = failure {
+            if command.notify_on_failure == Some(true) {
+                let reason = match exit_status {
+                    Some(status) => format!("Exited with status {}", status),
+                    None => stderr.clone(),
+                };
+                self.desktop.notify_failure(&command.name, &reason);
+            }
+            if let Some(url) = &command.on_failure_webhook {
+                webhook::notify_failure(
+                    url.clone(),
+                    FailurePayload {
+                        name: command.name.clone(),
+                        event: FailureEvent::Failed,
+                        exit_status,
+                        stderr,
+                        timestamp: finished,
+                    },
+                );
+            }
+        }
+        if let Some(active) = self.running.get_mut(&command.name) {
+            *active -= 1;
+            if *active == 0 {
+                self.running.remove(&command.name);
+            }
+        }
+        let removed = match self.reloaded.remove(&command.name) {
+            Some(Some(reloaded)) => {
+                command = reloaded;
+                false
+            }
+            Some(None) => true,
+            None => false,
+        };
+
+        let should_retry = match &outcome {
+            RunOutcome::Finished(_) => !succeeded,
+            RunOutcome::Failed(_) => true,
+            RunOutcome::TimedOut(_) => false,
+        };
+
+        match outcome {
+            RunOutcome::Finished(output) => {
+                if succeeded {
+                    match self.retry_attempts.remove(&command.name) {
+                        Some(attempt) => info!(
+                            command = %command.name,
+                            event = "completed",
+                            exit_code = output.status,
+                            duration_ms,
+                            "Command '{}' completed successfully on retry {}",
+                            command.name,
+                            attempt
+                        ),
+                        None => info!(
+                            command = %command.name,
+                            event = "completed",
+                            exit_code = output.status,
+                            duration_ms,
+                            "Command '{}' completed successfully",
+                            command.name
+                        ),
+                    }
+                } else {
+                    error!(
+                        command = %command.name,
+                        event = "failed",
+                        exit_code = output.status,
+                        duration_ms,
+                        "Command '{}' failed with exit status {}",
+                        command.name,
+                        output.status
+                    );
                 }
+                info!(
+                    command = %command.name,
+                    stdout_bytes = output.stdout_len(),
+                    stderr_bytes = output.stderr_len(),
+                    "Command '{}' wrote {} bytes of output and {} bytes of error output",
+                    command.name,
+                    output.stdout_len(),
+                    output.stderr_len()
+                );
+            }
+            RunOutcome::Failed(e) => {
+                error!(
+                    command = %command.name,
+                    event = "failed",
+                    error = %e,
+                    duration_ms,
+                    "Failed to execute command '{}': {}",
+                    command.name,
+                    e
+                );
+            }
+            RunOutcome::TimedOut(execution_timeout) => {
+                warn!(
+                    command = %command.name,
+                    event = "timed_out",
+                    duration_ms,
+                    "Command '{}' execution timed out after {:?}",
+                    command.name,
+                    execution_timeout
+                );
+            }
+        }
+
+        info!(
+            "Command '{}' execution took {} milliseconds",
+            command.name, duration_ms
+        );
+
+        if removed {
+            self.queued.remove(&command.name);
+            self.retry_attempts.remove(&command.name);
+            self.catch_up_runs.remove(&command.name);
+            let deleted = self.state_manager.delete_command_state_async(&command.name);
+            if let Err(e) = deleted.await {
+                error!(
+                    "Failed to delete state for command '{}': {}",
+                    command.name, e
+                );
+            }
+            return;
+        }
+
+        if command.exceeds_failure_limit(consecutive_failures) {
+            error!(
+                "Command '{}' failed {} times in a row and has been disabled",
+                command.name, consecutive_failures
+            );
+            self.queued.remove(&command.name);
+            self.retry_attempts.remove(&command.name);
+            self.catch_up_runs.remove(&command.name);
+            self.finish_cycle(&command.name, false).await;
+            return;
+        }
+
+        if let Some(remaining) = self.catch_up_runs.remove(&command.name) {
+            if remaining > 1 {
+                self.catch_up_runs
+                    .insert(command.name.clone(), remaining - 1);
+            }
+            info!(
+                "Catching up on missed run of command '{}' ({} remaining)",
+                command.name,
+                remaining - 1
+            );
+            let saved = self.state_manager.save_command_state_async(
+                &command,
+                Some(started),
+                self.clock.now(),
+            );
+            if let Err(e) = saved.await {
+                error!("Failed to save state for command '{}': {}", command.name, e);
+            }
+            self.finish_cycle(&command.name, succeeded).await;
+            self.dispatch_command(ScheduledCommand {
+                command,
+                next_run: self.clock.now(),
+            })
+            .await;
+            return;
+        }
+
+        if let Some(queued) = self.queued.remove(&command.name) {
+            // The queued run takes the place of this cycle's retry or reschedule
+            info!("Starting queued run of command '{}'", command.name);
+            let saved = self.state_manager.save_command_state_async(
+                &command,
+                Some(started),
+                self.clock.now(),
+            );
+            if let Err(e) = saved.await {
+                error!("Failed to save state for command '{}': {}", command.name, e);
+            }
+            self.finish_cycle(&command.name, succeeded).await;
+            self.dispatch_command(ScheduledCommand {
+                command: queued,
+                next_run: self.clock.now(),
+            })
+            .await;
+            return;
+        }
+
+        if should_retry {
+            if let Some(retry_at) = self.schedule_retry(&command) {
+                let saved =
+                    self.state_manager
+                        .save_command_state_async(&command, Some(started), retry_at);
+                if let Err(e) = saved.await {
+                    error!("Failed to save state for command '{}': {}", command.name, e);
+                }
+                return;
+            }
+        } else {
+            self.retry_attempts.remove(&command.name);
+        }
+        self.finish_cycle(&command.name, succeeded).await;
+        if command.runs_after_dependencies() {
+            return;
+        }
+        if command.at.is_some() {
+            self.complete_one_shot(&command).await;
+            return;
+        }
+
+        // Save state after execution
+        match self.schedule_after_run(
+            command.clone(),
+            PreviousRun {
+                scheduled_for,
+                started,
+            },
+            consecutive_failures,
+        ) {
+            Ok(next_run) => {
+                let saved =
+                    self.state_manager
+                        .save_command_state_async(&command, Some(started), next_run);
+                if let Err(e) = saved.await {
+                    error!("Failed to save state for command '{}': {}", command.name, e);
+                }
+            }
+            Err(e) => Self::report_unschedulable(&command, &e),
+        }
+    }
+
+    /// Starts or skips the commands that depend on `name` once its run, including any
+    /// retries, is over
+    ///
+    /// A dependent starts when every command in its `depends_on` has succeeded since it
+    /// last ran or was skipped. When a dependency fails, the dependent is skipped, which
+    /// is recorded in its history, and so are the commands that depend on it in turn.
+    async fn finish_cycle(&mut self, name: &str, succeeded: bool) {
+        let mut finished = vec![(name.to_string(), succeeded)];
+        while let Some((name, succeeded)) = finished.pop() {
+            let dependents = self
+                .configured
+                .iter()
+                .filter(|command| {
+                    command.enabled
+                        && command.runs_after_dependencies()
+                        && command
+                            .depends_on
+                            .as_ref()
+                            .is_some_and(|depends_on| depends_on.contains(&name))
+                })
+                .map(|command| ParsedCommand {
+                    config: command.clone(),
+                    schedule: None,
+                })
+                .collect::<Vec<_>>();
+            for dependent in dependents {
+                if !succeeded {
+                    self.dependency_progress.remove(&dependent.name);
+                    let reason = format!("Skipped because dependency '{}' failed", name);
+                    self.record_skip(&dependent, &reason).await;
+                    finished.push((dependent.config.name, false));
+                    continue;
+                }
+                let done = self
+                    .dependency_progress
+                    .entry(dependent.name.clone())
+                    .or_default();
+                done.insert(name.clone());
+                let depends_on = dependent.depends_on.as_deref().unwrap_or_default();
+                if !depends_on.iter().all(|dep| done.contains(dep)) {
+                    continue;
+                }
+                self.dependency_progress.remove(&dependent.name);
+                if self.is_paused(&dependent.name) {
+                    self.log_paused_skip(&dependent.name);
+                    continue;
+                }
+                let state = self.state_manager.get_command_state_async(&dependent.name);
+                if let Ok(Some(state)) = state.await {
+                    if dependent.exceeds_failure_limit(state.consecutive_failures) {
+                        Self::warn_disabled(&dependent, state.consecutive_failures);
+                        continue;
+                    }
+                }
+                info!(
+                    "Starting command '{}' now that its dependencies succeeded",
+                    dependent.name
+                );
+                self.dispatch_command(ScheduledCommand {
+                    command: dependent,
+                    next_run: self.clock.now(),
+                })
+                .await;
+            }
+        }
+    }
+
+    /// Returns the first dependency of a scheduled command that hasn't succeeded since the
+    /// command last ran, judged by the last run of each stored in the state database
+    ///
+    /// Retries aren't held back, since the command ran after its dependencies already.
+    async fn unmet_dependency(&self, command: &CommandConfig) -> Option<String> {
+        let depends_on = command.depends_on.as_ref()?;
+        if self.retry_attempts.contains_key(&command.name) {
+            return None;
+        }
+        let states = self
+            .state_manager
+            .load_command_states_async()
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to load command states: {}", e);
+                Vec::new()
+            });
+        let state_of = |name: &str| states.iter().find(|state| state.name == name);
+        let own_last_run = state_of(&command.name).and_then(|state| state.last_execution);
+        depends_on
+            .iter()
+            .find(|dependency| {
+                let Some(state) = state_of(dependency) else {
+                    return true;
+                };
+                let succeeded = match (
+                    state.last_status,
+                    self.configured.iter().find(|c| &c.name == *dependency),
+                ) {
+                    (Some(status), Some(config)) => config.is_success(status),
+                    (Some(status), None) => status == 0,
+                    (None, _) => false,
+                };
+                let newer = match (state.last_execution, own_last_run) {
+                    (Some(dependency_run), Some(own_run)) => dependency_run > own_run,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                !(succeeded && newer)
+            })
+            .cloned()
+    }
+
+    /// Records in its history that a run of a dependent was skipped for `reason`
+    ///
+    /// The caller skips the commands that run after it with [`Self::finish_cycle`].
+    async fn record_skip(&self, command: &CommandConfig, reason: &str) {
+        warn!(
+            command = %command.name,
+            event = "skipped",
+            "Command '{}': {}",
+            command.name,
+            reason
+        );
+        let now = self.clock.now();
+        let stderr = reason.to_string();
+        let record = ExecutionRecord {
+            name: command.name.clone(),
+            started_at: now,
+            finished_at: now,
+            exit_code: None,
+            timed_out: false,
+            succeeded: false,
+            skipped: true,
+            stdout: String::new(),
+            stdout_len: 0,
+            stderr_len: stderr.len(),
+            stderr,
+        };
+        if let Err(e) = self.state_manager.record_execution_async(record).await {
+            error!(
+                "Failed to record skipped run of command '{}': {}",
+                command.name, e
+            );
+        }
+    }
+}
+
+/// Builds a [`Scheduler`] with optional overrides for its executor, state path and limits
+///
+/// Settings that aren't overridden take the same defaults as the `[general]` section of the
+/// configuration file.
+///
+/// # Examples
+///
+/// ```
+/// use zephyr_scheduler::{CommandConfig, SchedulerBuilder};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// # let state_dir = tempfile::tempdir()?;
+/// let command = CommandConfig {
+///     name: "hello".to_string(),
+///     command: "echo hello".to_string(),
+///     interval_minutes: Some(5.0),
+///     ..Default::default()
+/// };
+/// let scheduler = SchedulerBuilder::new(vec![command])
+///     .with_state_path(state_dir.path().join("state.db"))
+///     .with_min_interval(10)
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SchedulerBuilder {
+    commands: Vec<CommandConfig>,
+    config_path: Option<PathBuf>,
+    state_path: PathBuf,
+    executor: Option<Box<dyn CommandExecutor>>,
+    clock: Option<Box<dyn Clock>>,
+    notifier: Option<Box<dyn Notifier>>,
+    min_interval_seconds: u64,
+    max_immediate_executions: usize,
+    max_concurrent: Option<usize>,
+    history_retention_days: u32,
+    shutdown_timeout_seconds: u64,
+    startup_splay_seconds: u64,
+    sleep_detection: SleepDetection,
+    sleep_detection_threshold_minutes: u64,
+    rng_seed: Option<u64>,
+    metrics: Option<Arc<Metrics>>,
+    strict: bool,
+    keep_orphaned_state: bool,
+}
+
+impl SchedulerBuilder {
+    /// Starts a builder for the given commands using the default general settings
+    pub fn new(commands: Vec<CommandConfig>) -> Self {
+        let general = GeneralConfig::default();
+        Self {
+            commands,
+            config_path: None,
+            state_path: general.state_path,
+            executor: None,
+            clock: None,
+            notifier: None,
+            min_interval_seconds: general.min_interval_seconds,
+            max_immediate_executions: general.max_immediate_executions,
+            max_concurrent: general.max_concurrent,
+            history_retention_days: general.history_retention_days,
+            shutdown_timeout_seconds: general.shutdown_timeout_seconds,
+            startup_splay_seconds: general.startup_splay_seconds,
+            sleep_detection: general.sleep_detection,
+            sleep_detection_threshold_minutes: general.sleep_detection_threshold_minutes,
+            rng_seed: None,
+            metrics: None,
+            strict: general.strict,
+            keep_orphaned_state: general.keep_orphaned_state,
+        }
+    }
+
+    /// Starts a builder from a loaded configuration file, including its general settings
+    pub fn from_config(config: Config) -> Self {
+        Self {
+            commands: config.commands,
+            config_path: None,
+            state_path: expand_tilde(&config.general.state_path),
+            executor: None,
+            clock: None,
+            notifier: None,
+            min_interval_seconds: config.general.min_interval_seconds,
+            max_immediate_executions: config.general.max_immediate_executions,
+            max_concurrent: config.general.max_concurrent,
+            history_retention_days: config.general.history_retention_days,
+            shutdown_timeout_seconds: config.general.shutdown_timeout_seconds,
+            startup_splay_seconds: config.general.startup_splay_seconds,
+            sleep_detection: config.general.sleep_detection,
+            sleep_detection_threshold_minutes: config.general.sleep_detection_threshold_minutes,
+            rng_seed: None,
+            metrics: None,
+            strict: config.general.strict,
+            keep_orphaned_state: config.general.keep_orphaned_state,
+        }
+    }
+
+    /// Replaces the default shell executor, e.g. to run commands remotely or in tests
+    pub fn with_executor(mut self, executor: Box<dyn CommandExecutor>) -> Self {
+        self.executor = Some(executor);
+        self
+    }
+
+    /// Replaces how `notify_on_failure` notifications are shown, e.g. with a
+    /// [`RecordingNotifier`](crate::core::testing::RecordingNotifier) in tests
+    pub fn with_notifier(mut self, notifier: Box<dyn Notifier>) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// Replaces the system clock, e.g. with a [`MockClock`](crate::core::testing::MockClock)
+    /// in tests
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Sets the configuration file that is reloaded when the process receives SIGHUP
+    pub fn with_config_path(mut self, config_path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(expand_tilde(&config_path.into()));
+        self
+    }
+
+    /// Sets the path of the state database
+    pub fn with_state_path(mut self, state_path: impl Into<PathBuf>) -> Self {
+        self.state_path = expand_tilde(&state_path.into());
+        self
+    }
+
+    /// Sets the minimum number of seconds between command executions
+    pub fn with_min_interval(mut self, min_interval_seconds: u64) -> Self {
+        self.min_interval_seconds = min_interval_seconds;
+        self
+    }
+
+    /// Sets how many immediate commands may run on startup
+    pub fn with_max_immediate_executions(mut self, max_immediate_executions: usize) -> Self {
+        self.max_immediate_executions = max_immediate_executions;
+        self
+    }
+
+    /// Limits how many commands may execute at the same time
+    pub fn with_max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = Some(max_concurrent);
+        self
+    }
+
+    /// Sets how many days of execution history to keep; 0 keeps it forever
+    pub fn with_history_retention_days(mut self, days: u32) -> Self {
+        self.history_retention_days = days;
+        self
+    }
+
+    /// Sets how many seconds running commands get to finish when the scheduler stops
+    pub fn with_shutdown_timeout(mut self, seconds: u64) -> Self {
+        self.shutdown_timeout_seconds = seconds;
+        self
+    }
+
+    /// Sets the upper bound of the random delay before the first dispatch after startup
+    pub fn with_startup_splay(mut self, seconds: u64) -> Self {
+        self.startup_splay_seconds = seconds;
+        self
+    }
+
+    /// Sets how the scheduler notices that the system slept through scheduled runs
+    pub fn with_sleep_detection(mut self, sleep_detection: SleepDetection) -> Self {
+        self.sleep_detection = sleep_detection;
+        self
+    }
+
+    /// Sets how many minutes the wall clock has to get ahead of the scheduler's own clock
+    /// to count as a sleep
+    pub fn with_sleep_detection_threshold(mut self, minutes: u64) -> Self {
+        self.sleep_detection_threshold_minutes = minutes;
+        self
+    }
+
+    /// Seeds the random number generator behind jitter and startup splay, so tests get the
+    /// same delays on every run
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    /// Records Prometheus metrics for every execution in `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Fails `build` when a command can't be scheduled, instead of reporting and skipping it
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Keeps the saved state of commands that aren't passed to the builder, instead of
+    /// deleting it
+    pub fn with_keep_orphaned_state(mut self, keep: bool) -> Self {
+        self.keep_orphaned_state = keep;
+        self
+    }
+
+    /// Opens the state database, prunes old execution history and schedules the enabled
+    /// commands
+    pub fn build(self) -> Result<Scheduler> {
+        let rng = match self.rng_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut scheduler = Scheduler::create(
+            self.commands,
+            self.state_path,
+            self.max_immediate_executions,
+            self.min_interval_seconds,
+            self.max_concurrent,
+            rng,
+            self.clock
+                .map_or_else(|| Arc::new(SystemClock) as Arc<dyn Clock>, Arc::from),
+        )?;
+        if !self.keep_orphaned_state {
+            scheduler.prune_orphaned_state();
+        }
+        scheduler.schedule_configured(self.strict)?;
+        if let Some(executor) = self.executor {
+            scheduler.executor = Arc::from(executor);
+        }
+        if let Some(notifier) = self.notifier {
+            scheduler.desktop = DesktopNotifier::new(Arc::from(notifier));
+        }
+        scheduler.config_path = self.config_path;
+        scheduler.metrics = self.metrics;
+        scheduler.shutdown_timeout = StdDuration::from_secs(self.shutdown_timeout_seconds);
+        scheduler.startup_splay = StdDuration::from_secs(self.startup_splay_seconds);
+        scheduler.sleep_detection = self.sleep_detection;
+        scheduler.sleep_threshold =
+            Duration::minutes(self.sleep_detection_threshold_minutes as i64);
+        scheduler.history_retention_days = self.history_retention_days;
+        Ok(scheduler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::executor::CommandOutput;
+    use crate::core::testing::{MockClock, RecordingExecutor, RecordingNotifier};
+    use chrono_tz::Tz;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use std::sync::Mutex;
+    use tempfile::NamedTempFile;
+    use tokio::time::sleep;
+
+    /// Executor that sleeps instead of spawning a process and tracks how many
+    /// executions are in flight at once
+    #[derive(Default)]
+    struct SlowExecutor {
+        delay: StdDuration,
+        delays: HashMap<String, StdDuration>,
+        runs: Arc<AtomicUsize>,
+        active: Arc<AtomicUsize>,
+        max_active: Arc<AtomicUsize>,
+        finished: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl CommandExecutor for SlowExecutor {
+        async fn execute(&self, command: &CommandConfig) -> std::io::Result<CommandOutput> {
+            self.runs.fetch_add(1, AtomicOrdering::SeqCst);
+            let active = self.active.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+            self.max_active.fetch_max(active, AtomicOrdering::SeqCst);
+            sleep(*self.delays.get(&command.name).unwrap_or(&self.delay)).await;
+            self.active.fetch_sub(1, AtomicOrdering::SeqCst);
+            self.finished.lock().unwrap().push(command.name.clone());
+            Ok(CommandOutput {
+                status: 0,
+                ..Default::default()
+            })
+        }
+    }
+
+    /// Executor whose commands always exit with a failing status
+    struct FailingExecutor {
+        runs: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl CommandExecutor for FailingExecutor {
+        async fn execute(&self, _command: &CommandConfig) -> std::io::Result<CommandOutput> {
+            self.runs.fetch_add(1, AtomicOrdering::SeqCst);
+            Ok(CommandOutput {
+                status: 1,
+                ..Default::default()
+            })
+        }
+    }
+
+    /// Puts `scheduler` on a mock clock, which in tests with `start_paused` moves on with
+    /// tokio's clock, so the scheduler's waits finish straight away
+    fn use_mock_clock(scheduler: &mut Scheduler) -> MockClock {
+        let clock = MockClock::new(Utc::now());
+        scheduler.clock = Arc::new(clock.clone());
+        clock
+    }
+
+    fn parsed(command: CommandConfig) -> ParsedCommand {
+        ParsedCommand::new(command).unwrap()
+    }
+
+    fn create_test_command(name: &str, interval_minutes: f64) -> CommandConfig {
+        CommandConfig {
+            name: name.to_string(),
+            command: "echo test".to_string(),
+            interval_minutes: Some(interval_minutes),
+            max_runtime_minutes: Some(5),
+            ..Default::default()
+        }
+    }
+
+    fn create_test_cron_command(name: &str, cron: &str) -> CommandConfig {
+        CommandConfig {
+            name: name.to_string(),
+            command: "echo test".to_string(),
+            cron: Some(cron.to_string()),
+            max_runtime_minutes: Some(5),
+            ..Default::default()
+        }
+    }
+
+    fn create_temp_state_path() -> PathBuf {
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file.path().to_path_buf()
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_initialization() {
+        let commands = vec![
+            create_test_command("test1", 1.0),
+            create_test_command("test2", 2.0),
+        ];
+        let scheduler = Scheduler::new(commands.clone(), create_temp_state_path()).unwrap();
+
+        assert_eq!(scheduler.commands.len(), 2);
+        assert!(scheduler.last_execution_time.is_none());
+    }
+
+    #[test]
+    fn test_new_with_config_stores_limits() {
+        let scheduler = Scheduler::new_with_config(
+            vec![create_test_command("test1", 1.0)],
+            create_temp_state_path(),
+            3,
+            45,
+            Some(2),
+        )
+        .unwrap();
+        assert_eq!(scheduler.max_immediate_executions, 3);
+        assert_eq!(scheduler.min_interval_seconds, 45);
+        assert_eq!(
+            scheduler
+                .concurrency_limit
+                .as_ref()
+                .map(|limit| limit.available_permits()),
+            Some(2)
+        );
+
+        // `new` uses the defaults of the general settings
+        let general = GeneralConfig::default();
+        let scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        assert_eq!(
+            scheduler.max_immediate_executions,
+            general.max_immediate_executions
+        );
+        assert_eq!(scheduler.min_interval_seconds, general.min_interval_seconds);
+        assert!(scheduler.concurrency_limit.is_none());
+    }
+
+    #[test]
+    fn test_initial_schedule_is_saved_before_any_run() {
+        let state_path = create_temp_state_path();
+        let build = || {
+            SchedulerBuilder::new(vec![
+                create_test_command("daily", 24.0 * 60.0),
+                create_test_cron_command("hourly", "0 0 * * * *"),
+            ])
+            .with_state_path(&state_path)
+            .build()
+            .unwrap()
+        };
+        let next_runs = |scheduler: &Scheduler| {
+            let mut next_runs = scheduler
+                .commands
+                .iter()
+                .map(|scheduled| (scheduled.command.name.clone(), scheduled.next_run))
+                .collect::<Vec<_>>();
+            next_runs.sort();
+            next_runs
+        };
+
+        let scheduler = build();
+        let mut saved = scheduler
+            .state_manager
+            .load_command_states()
+            .unwrap()
+            .into_iter()
+            .map(|state| (state.name, state.next_scheduled))
+            .collect::<Vec<_>>();
+        saved.sort();
+        assert_eq!(saved, next_runs(&scheduler));
+        let first = next_runs(&scheduler);
+        drop(scheduler);
+
+        // Restarting before the daily run doesn't push it back by a day
+        assert_eq!(next_runs(&build()), first);
+    }
+
+    #[test]
+    fn test_changed_schedule_recalculates_saved_next_run() {
+        let state_path = create_temp_state_path();
+        let next_run = |command: CommandConfig| {
+            let scheduler = SchedulerBuilder::new(vec![command])
+                .with_state_path(&state_path)
+                .build()
+                .unwrap();
+            scheduler.commands.peek().unwrap().next_run
+        };
+        let save = |command: &CommandConfig, next_run: DateTime<Utc>| {
+            StateManager::new(&state_path)
+                .unwrap()
+                .save_next_scheduled(command, next_run)
+                .unwrap();
+        };
+        let daily = create_test_command("report", 24.0 * 60.0);
+        let saved = Utc::now() + Duration::hours(20);
+
+        // Unchanged, the saved run is kept
+        save(&daily, saved);
+        assert_eq!(next_run(daily.clone()), saved);
+
+        // A shorter interval takes effect now rather than after the saved run
+        save(&daily, saved);
+        let hourly = create_test_command("report", 60.0);
+        assert!(next_run(hourly.clone()) <= Utc::now() + Duration::hours(1));
+
+        // So does switching to a cron schedule, and back
+        let saved = Utc::now() + Duration::hours(20);
+        save(&hourly, saved);
+        let cron = create_test_cron_command("report", "0 */15 * * * *");
+        assert!(next_run(cron.clone()) <= Utc::now() + Duration::minutes(15));
+        save(&cron, saved);
+        assert!(next_run(hourly) <= Utc::now() + Duration::hours(1));
+    }
+
+    #[test]
+    fn test_state_of_removed_commands_is_pruned_unless_kept() {
+        let state_path = create_temp_state_path();
+        let build = |names: &[&str], keep: bool| {
+            SchedulerBuilder::new(
+                names
+                    .iter()
+                    .map(|name| create_test_command(name, 60.0))
+                    .collect(),
+            )
+            .with_state_path(&state_path)
+            .with_keep_orphaned_state(keep)
+            .build()
+            .unwrap()
+        };
+        let saved_next_run = |scheduler: &Scheduler, name: &str| {
+            scheduler
+                .state_manager
+                .get_command_state(name)
+                .unwrap()
+                .map(|state| state.next_scheduled)
+        };
+
+        let scheduler = build(&["backup", "report"], false);
+        let report = create_test_command("report", 60.0);
+        let next_run = Utc::now() + Duration::minutes(42);
+        scheduler
+            .state_manager
+            .save_next_scheduled(&report, next_run)
+            .unwrap();
+        drop(scheduler);
+
+        // A command taken out of the configuration for a while keeps its schedule
+        let scheduler = build(&["backup"], true);
+        assert!(saved_next_run(&scheduler, "report").is_some());
+        drop(scheduler);
+        let scheduler = build(&["backup", "report"], false);
+        let scheduled = scheduler
+            .commands
+            .iter()
+            .find(|scheduled| scheduled.command.name == "report")
+            .unwrap();
+        assert_eq!(scheduled.next_run.timestamp(), next_run.timestamp());
+        drop(scheduler);
+
+        // Renamed, the old name's state is removed
+        let scheduler = build(&["backup", "weekly-report"], false);
+        assert_eq!(saved_next_run(&scheduler, "report"), None);
+    }
+
+    #[test]
+    fn test_unschedulable_command_is_skipped_unless_strict() {
+        let commands = vec![
+            create_test_cron_command("broken", "not a cron expression"),
+            create_test_command("healthy", 1.0),
+        ];
+        let scheduler = SchedulerBuilder::new(commands.clone())
+            .with_state_path(create_temp_state_path())
+            .build()
+            .unwrap();
+        let scheduled = scheduler
+            .commands
+            .iter()
+            .map(|scheduled| scheduled.command.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(scheduled, vec!["healthy"]);
+
+        let error = SchedulerBuilder::new(commands)
+            .with_state_path(create_temp_state_path())
+            .with_strict(true)
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "Failed to schedule command 'broken'");
+    }
+
+    #[tokio::test]
+    async fn test_cron_scheduler_initialization() {
+        let commands = vec![
+            create_test_cron_command("test1", "0 0 * * * *"), // Every hour
+            create_test_cron_command("test2", "0 0 0 * * *"), // Daily at midnight
+        ];
+        let scheduler = Scheduler::new(commands.clone(), create_temp_state_path()).unwrap();
+
+        assert_eq!(scheduler.commands.len(), 2);
+        assert!(scheduler.last_execution_time.is_none());
+    }
+
+    /// Executor that reports which command each run is of and when it starts by `clock`
+    struct TimingExecutor {
+        started: mpsc::UnboundedSender<(String, DateTime<Utc>)>,
+        clock: MockClock,
+    }
+
+    #[async_trait::async_trait]
+    impl CommandExecutor for TimingExecutor {
+        async fn execute(&self, command: &CommandConfig) -> std::io::Result<CommandOutput> {
+            let _ = self.started.send((command.name.clone(), self.clock.now()));
+            Ok(CommandOutput::default())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sub_second_schedule_fires_on_time() {
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+        let mut scheduler =
+            Scheduler::new_with_config(vec![], create_temp_state_path(), 10, 0, None).unwrap();
+        let clock = use_mock_clock(&mut scheduler);
+        scheduler.executor = Arc::new(TimingExecutor {
+            started: started_tx,
+            clock: clock.clone(),
+        });
+        let due = clock.now() + Duration::milliseconds(300);
+        scheduler.commands.push(ScheduledCommand {
+            command: parsed(create_test_command("precise", 60.0)),
+            next_run: due,
+        });
+
+        let _ = timeout(StdDuration::from_millis(800), scheduler.run()).await;
+
+        let (_, started) = started_rx.try_recv().expect("the command never ran");
+        let late = started - due;
+        assert!(late >= Duration::zero(), "ran {} early", -late);
+        assert!(late < Duration::milliseconds(150), "ran {} late", late);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_due_commands_are_spaced_by_min_interval() {
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+        let mut scheduler =
+            Scheduler::new_with_config(vec![], create_temp_state_path(), 10, 1, None).unwrap();
+        let clock = use_mock_clock(&mut scheduler);
+        scheduler.executor = Arc::new(TimingExecutor {
+            started: started_tx,
+            clock: clock.clone(),
+        });
+        for name in ["first", "second"] {
+            scheduler.commands.push(ScheduledCommand {
+                command: parsed(create_test_command(name, 60.0)),
+                next_run: clock.now(),
+            });
+        }
+
+        let _ = timeout(StdDuration::from_millis(1600), scheduler.run()).await;
+
+        let (_, first) = started_rx.try_recv().unwrap();
+        let (_, second) = started_rx.try_recv().expect("the second command never ran");
+        let spacing = second - first;
+        assert!(spacing >= Duration::seconds(1), "spaced by {}", spacing);
+        assert!(
+            spacing < Duration::milliseconds(1150),
+            "spaced by {}",
+            spacing
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst_is_dispatched_at_min_interval_spacing() {
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+        let mut scheduler =
+            Scheduler::new_with_config(vec![], create_temp_state_path(), 10, 1, None).unwrap();
+        let clock = use_mock_clock(&mut scheduler);
+        scheduler.executor = Arc::new(TimingExecutor {
+            started: started_tx,
+            clock: clock.clone(),
+        });
+        let due = clock.now();
+        for name in ["a", "b", "c"] {
+            scheduler.commands.push(ScheduledCommand {
+                command: parsed(create_test_command(name, 60.0)),
+                next_run: due,
+            });
+        }
+        scheduler.commands.push(ScheduledCommand {
+            command: parsed(CommandConfig {
+                ignore_min_interval: true,
+                ..create_test_command("urgent", 60.0)
+            }),
+            next_run: due + Duration::milliseconds(200),
+        });
+
+        let _ = timeout(StdDuration::from_millis(2600), scheduler.run()).await;
+
+        let mut started = HashMap::new();
+        while let Ok((name, time)) = started_rx.try_recv() {
+            started.insert(name, time - due);
+        }
+        let at = |name: &str| *started.get(name).expect("a command never ran");
+        // The exempt command starts on time, without waiting for the throttle
+        assert!(
+            at("urgent") < Duration::milliseconds(350),
+            "{}",
+            at("urgent")
+        );
+        // and without pushing back the others, which start a second apart
+        let mut throttled = [at("a"), at("b"), at("c")];
+        throttled.sort();
+        assert!(throttled[0] < Duration::milliseconds(150));
+        for pair in throttled.windows(2) {
+            let spacing = pair[1] - pair[0];
+            assert!(spacing >= Duration::seconds(1), "spaced by {}", spacing);
+            assert!(
+                spacing < Duration::milliseconds(1150),
+                "spaced by {}",
+                spacing
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_schedule_next_run() {
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        let command = create_test_command("test", 1.0);
+
+        scheduler
+            .schedule_next_run(parsed(command.clone()))
+            .unwrap();
+        assert_eq!(scheduler.commands.len(), 1);
+
+        let scheduled = scheduler.commands.peek().unwrap();
+        assert_eq!(scheduled.command.name, "test");
+        assert!(scheduled.next_run > Utc::now());
+    }
+
+    #[tokio::test]
+    async fn test_cron_schedule_next_run() {
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        let command = create_test_cron_command("test", "0 0 * * * *"); // Every hour
+
+        scheduler
+            .schedule_next_run(parsed(command.clone()))
+            .unwrap();
+        assert_eq!(scheduler.commands.len(), 1);
+
+        let scheduled = scheduler.commands.peek().unwrap();
+        assert_eq!(scheduled.command.name, "test");
+        assert!(scheduled.next_run > Utc::now());
+
+        let time_str = scheduled.next_run.format("%H:%M:%S").to_string();
+        assert_eq!(time_str.split(':').nth(1).unwrap(), "00");
+        assert_eq!(time_str.split(':').nth(2).unwrap(), "00");
+    }
+
+    #[tokio::test]
+    async fn test_command_ordering() {
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        let command1 = create_test_command("test1", 1.0);
+        let command2 = create_test_command("test2", 2.0);
+
+        scheduler.schedule_next_run(parsed(command1)).unwrap();
+        scheduler.schedule_next_run(parsed(command2)).unwrap();
+
+        let first = scheduler.commands.pop().unwrap();
+        let second = scheduler.commands.pop().unwrap();
+
+        assert!(first.next_run < second.next_run);
+    }
+
+    #[test]
+    fn test_next_in_business_hours_window() {
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        let window = "09:00-17:00".parse::<RunWindow>().unwrap();
+        let next =
+            |time| Scheduler::next_permitted(Some(&window), None, at(time), &Tz::UTC).unwrap();
+
+        assert_eq!(next("2026-10-15T12:00:00Z"), at("2026-10-15T12:00:00Z"));
+        assert_eq!(next("2026-10-15T09:00:00Z"), at("2026-10-15T09:00:00Z"));
+        assert_eq!(next("2026-10-15T06:30:00Z"), at("2026-10-15T09:00:00Z"));
+        // An hourly job due at 17:00 or later waits for the next morning
+        assert_eq!(next("2026-10-15T17:00:00Z"), at("2026-10-16T09:00:00Z"));
+        assert_eq!(next("2026-10-15T23:59:00Z"), at("2026-10-16T09:00:00Z"));
+    }
+
+    #[test]
+    fn test_next_in_overnight_window() {
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        let window = "22:00-06:00".parse::<RunWindow>().unwrap();
+        let next =
+            |time| Scheduler::next_permitted(Some(&window), None, at(time), &Tz::UTC).unwrap();
+
+        assert_eq!(next("2026-10-15T23:30:00Z"), at("2026-10-15T23:30:00Z"));
+        assert_eq!(next("2026-10-16T03:00:00Z"), at("2026-10-16T03:00:00Z"));
+        // A job whose interval comes due in the middle of the day waits for the evening
+        assert_eq!(next("2026-10-16T06:00:00Z"), at("2026-10-16T22:00:00Z"));
+        assert_eq!(next("2026-10-16T13:15:00Z"), at("2026-10-16T22:00:00Z"));
+    }
+
+    #[test]
+    fn test_next_in_window_uses_timezone() {
+        let window = "09:00-17:00".parse::<RunWindow>().unwrap();
+        let timezone: Tz = "America/New_York".parse().unwrap();
+        // 12:00 UTC is 08:00 in New York (EDT), so the window opens at 13:00 UTC
+        let next = Scheduler::next_permitted(
+            Some(&window),
+            None,
+            "2026-10-15T12:00:00Z".parse().unwrap(),
+            &timezone,
+        )
+        .unwrap();
+        assert_eq!(
+            next,
+            "2026-10-15T13:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_blocked_window_defers_interval_runs() {
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        let mut command = create_test_command("offhours", 120.0);
+        command.timezone = Some("UTC".to_string());
+        command.blocked_window = Some("09:00-17:00".to_string());
+
+        // Every natural slot from 09:30 on falls into the blackout until it lifts at 17:00
+        let command = parsed(command);
+        let mut run = at("2026-10-15T07:30:00Z");
+        let mut runs = Vec::new();
+        for _ in 0..4 {
+            run = Scheduler::scheduled_run(&command, run).unwrap();
+            run = Scheduler::within_run_window(&command, run).unwrap();
+            runs.push(run);
+        }
+        assert_eq!(
+            runs,
+            [
+                at("2026-10-15T17:00:00Z"),
+                at("2026-10-15T19:00:00Z"),
+                at("2026-10-15T21:00:00Z"),
+                at("2026-10-15T23:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blocked_window_across_midnight_in_timezone() {
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        let mut command = create_test_command("daytime", 60.0);
+        command.timezone = Some("America/New_York".to_string());
+        command.blocked_window = Some("22:00-06:00".to_string());
+
+        // 03:00 UTC is 23:00 EDT the evening before; the blackout lifts at 06:00 EDT
+        assert_eq!(
+            Scheduler::within_run_window(&command, at("2026-10-16T03:00:00Z")).unwrap(),
+            at("2026-10-16T10:00:00Z")
+        );
+        assert_eq!(
+            Scheduler::within_run_window(&command, at("2026-10-16T15:00:00Z")).unwrap(),
+            at("2026-10-16T15:00:00Z")
+        );
+    }
+
+    #[test]
+    fn test_allowed_and_blocked_windows_with_days() {
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        let mut command = create_test_command("weekend", 60.0);
+        command.timezone = Some("UTC".to_string());
+        command.run_window = Some("Sat,Sun 08:00-20:00".to_string());
+        command.blocked_window = Some("Sat 08:00-12:00".to_string());
+
+        // Thursday waits for Saturday, and on Saturday for the morning blackout to end
+        assert_eq!(
+            Scheduler::within_run_window(&command, at("2026-10-15T10:00:00Z")).unwrap(),
+            at("2026-10-17T12:00:00Z")
+        );
+        assert_eq!(
+            Scheduler::within_run_window(&command, at("2026-10-17T21:00:00Z")).unwrap(),
+            at("2026-10-18T08:00:00Z")
+        );
+
+        command.run_window = Some("09:00-17:00".to_string());
+        command.blocked_window = Some("08:00-18:00".to_string());
+        assert!(
+            Scheduler::within_run_window(&command, at("2026-10-15T10:00:00Z"))
+                .unwrap_err()
+                .to_string()
+                .contains("no time inside its run_window")
+        );
+    }
+
+    #[test]
+    fn test_calculate_next_run_within_window() {
+        let mut command = create_test_command("windowed", 60.0);
+        command.timezone = Some("UTC".to_string());
+        let now = Utc::now();
+        let start = (now + Duration::hours(3)).format("%H:%M").to_string();
+        let end = (now + Duration::hours(4)).format("%H:%M").to_string();
+        command.run_window = Some(format!("{}-{}", start, end));
+
+        let next_run = Scheduler::calculate_next_run(&parsed(command), Utc::now()).unwrap();
+        assert!(next_run > now + Duration::hours(2));
+        assert!(next_run <= now + Duration::hours(3));
+    }
+
+    #[test]
+    fn test_calculate_next_run_aligned() {
+        let mut command = create_test_command("hourly", 60.0);
+        command.align_to_interval = true;
+        let next_run = Scheduler::calculate_next_run(&parsed(command), Utc::now()).unwrap();
+        assert_eq!(next_run.timestamp() % 3600, 0);
+        assert!(next_run > Utc::now());
+        assert!(next_run <= Utc::now() + Duration::hours(1));
+    }
+
+    #[tokio::test]
+    async fn test_cron_command_with_timezone() {
+        let mut command = create_test_cron_command("zoned", "0 0 8 * * *");
+        command.timezone = Some("Europe/Berlin".to_string());
+
+        let next_run = Scheduler::calculate_next_run(&parsed(command), Utc::now()).unwrap();
+        let local = next_run.with_timezone(&Tz::Europe__Berlin);
+        assert_eq!(local.format("%H:%M:%S").to_string(), "08:00:00");
+        assert!(next_run > Utc::now());
+        assert!(next_run <= Utc::now() + Duration::days(1));
+    }
+
+    #[tokio::test]
+    async fn test_cron_command_ordering() {
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        let command1 = create_test_cron_command("test1", "0 0 * * * *"); // Every hour
+        let command2 = create_test_cron_command("test2", "0 0 0 * * *"); // Daily at midnight
+
+        scheduler.schedule_next_run(parsed(command1)).unwrap();
+        scheduler.schedule_next_run(parsed(command2)).unwrap();
+
+        let first = scheduler.commands.pop().unwrap();
+        let second = scheduler.commands.pop().unwrap();
+
+        assert!(first.next_run < second.next_run);
+    }
+
+    #[tokio::test]
+    async fn test_mixed_command_ordering() {
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        let command1 = create_test_command("test1", 1.0);
+        let command2 = create_test_cron_command("test2", "0 0 * * * *"); // Every hour
+
+        scheduler.schedule_next_run(parsed(command1)).unwrap();
+        scheduler.schedule_next_run(parsed(command2)).unwrap();
+
+        let first = scheduler.commands.pop().unwrap();
+        let second = scheduler.commands.pop().unwrap();
+
+        assert!(first.next_run < second.next_run);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_commands() {
+        let mut commands = vec![
+            create_test_command("enabled", 1.0),
+            create_test_command("disabled", 1.0),
+        ];
+        commands[1].enabled = false;
+
+        let scheduler = Scheduler::new(commands, create_temp_state_path()).unwrap();
+        assert_eq!(scheduler.commands.len(), 1);
+        assert_eq!(scheduler.commands.peek().unwrap().command.name, "enabled");
+    }
+
+    #[tokio::test]
+    async fn test_immediate_execution() {
+        let mut commands = vec![
+            create_test_command("normal", 1.0),
+            create_test_command("immediate", 1.0),
+        ];
+        commands[1].immediate = true;
+
+        let scheduler = Scheduler::new(commands, create_temp_state_path()).unwrap();
+        assert_eq!(scheduler.commands.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_skips_command_with_run_in_flight() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        scheduler.executor = Arc::new(SlowExecutor {
+            runs: runs.clone(),
+            ..Default::default()
+        });
+
+        let command = create_test_command("slow", 1.0);
+        scheduler.running.insert(command.name.clone(), 1);
+        scheduler
+            .dispatch_command(ScheduledCommand {
+                command: parsed(command),
+                next_run: Utc::now(),
+            })
+            .await;
+
+        assert_eq!(runs.load(AtomicOrdering::SeqCst), 0);
+        assert_eq!(scheduler.commands.len(), 1);
+        let next_run = scheduler.commands.peek().unwrap().next_run;
+        assert!(next_run > Utc::now());
+
+        let state = scheduler.state_manager.get_command_state("slow").unwrap();
+        assert_eq!(
+            state.unwrap().next_scheduled.timestamp(),
+            next_run.timestamp()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_allow_policy_runs_despite_run_in_flight() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        scheduler.executor = Arc::new(SlowExecutor {
+            runs: runs.clone(),
+            ..Default::default()
+        });
+
+        let mut command = create_test_command("overlapping", 1.0);
+        command.overlap_policy = OverlapPolicy::Allow;
+        scheduler.running.insert(command.name.clone(), 1);
+        scheduler
+            .dispatch_command(ScheduledCommand {
+                command: parsed(command),
+                next_run: Utc::now(),
+            })
+            .await;
+        let completed = scheduler.completions_rx.recv().await.unwrap();
+        scheduler.handle_completion(completed).await;
+
+        assert_eq!(runs.load(AtomicOrdering::SeqCst), 1);
+        assert_eq!(scheduler.running.get("overlapping"), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_queue_policy_runs_after_active_run_finishes() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        scheduler.executor = Arc::new(SlowExecutor {
+            runs: runs.clone(),
+            ..Default::default()
+        });
+
+        let mut command = create_test_command("queued", 1.0);
+        command.overlap_policy = OverlapPolicy::Queue;
+        scheduler
+            .dispatch_command(ScheduledCommand {
+                command: parsed(command.clone()),
+                next_run: Utc::now(),
+            })
+            .await;
+        scheduler
+            .dispatch_command(ScheduledCommand {
+                command: parsed(command),
+                next_run: Utc::now(),
+            })
+            .await;
+        assert!(scheduler.queued.contains_key("queued"));
+
+        let first = scheduler.completions_rx.recv().await.unwrap();
+        scheduler.handle_completion(first).await;
+        assert!(scheduler.queued.is_empty());
+        assert!(scheduler.commands.is_empty());
+
+        let second = scheduler.completions_rx.recv().await.unwrap();
+        scheduler.handle_completion(second).await;
+
+        assert_eq!(runs.load(AtomicOrdering::SeqCst), 2);
+        assert!(scheduler.running.is_empty());
+        assert_eq!(scheduler.commands.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_long_running_command_never_overlaps() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        // Interval of ~60ms against a 500ms execution
+        let mut command = create_test_command("slow", 0.001);
+        command.immediate = true;
+
+        let mut scheduler =
+            Scheduler::new_with_config(vec![command], create_temp_state_path(), 10, 1, None)
+                .unwrap();
+        scheduler.executor = Arc::new(SlowExecutor {
+            delay: StdDuration::from_millis(500),
+            runs: runs.clone(),
+            max_active: max_active.clone(),
+            ..Default::default()
+        });
+
+        use_mock_clock(&mut scheduler);
+        let _ = timeout(StdDuration::from_secs(3), scheduler.run()).await;
+
+        assert!(runs.load(AtomicOrdering::SeqCst) >= 2);
+        assert_eq!(max_active.load(AtomicOrdering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_due_commands_run_concurrently() {
+        let finished = Arc::new(Mutex::new(Vec::new()));
+        let mut slow = create_test_command("slow", 60.0);
+        slow.immediate = true;
+        let mut fast = create_test_command("fast", 60.0);
+        fast.immediate = true;
+
+        let mut scheduler =
+            Scheduler::new_with_config(vec![slow, fast], create_temp_state_path(), 10, 1, None)
+                .unwrap();
+        scheduler.executor = Arc::new(SlowExecutor {
+            delays: HashMap::from([("slow".to_string(), StdDuration::from_secs(2))]),
+            finished: finished.clone(),
+            ..Default::default()
+        });
+
+        use_mock_clock(&mut scheduler);
+        let _ = timeout(StdDuration::from_millis(1000), scheduler.run()).await;
+
+        assert_eq!(*finished.lock().unwrap(), vec!["fast".to_string()]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_concurrent_limits_parallel_executions() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let commands = (0..3)
+            .map(|i| {
+                let mut command = create_test_command(&format!("cmd{}", i), 60.0);
+                command.immediate = true;
+                command
+            })
+            .collect();
+
+        let mut scheduler =
+            Scheduler::new_with_config(commands, create_temp_state_path(), 10, 1, Some(1)).unwrap();
+        scheduler.executor = Arc::new(SlowExecutor {
+            delay: StdDuration::from_millis(200),
+            runs: runs.clone(),
+            max_active: max_active.clone(),
+            ..Default::default()
+        });
+
+        use_mock_clock(&mut scheduler);
+        let _ = timeout(StdDuration::from_millis(1000), scheduler.run()).await;
+
+        assert_eq!(runs.load(AtomicOrdering::SeqCst), 3);
+        assert_eq!(max_active.load(AtomicOrdering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_delay_backoff() {
+        let mut command = create_test_command("flaky", 1.0);
+        assert_eq!(
+            Scheduler::retry_delay(&command, 1),
+            StdDuration::from_secs(60)
+        );
+
+        command.retry_delay_seconds = Some(10);
+        command.retry_backoff_multiplier = Some(2.0);
+        assert_eq!(
+            Scheduler::retry_delay(&command, 1),
+            StdDuration::from_secs(10)
+        );
+        assert_eq!(
+            Scheduler::retry_delay(&command, 3),
+            StdDuration::from_secs(40)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failing_command_is_retried_until_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("attempts");
+        let mut command = create_test_command("flaky", 60.0);
+        command.immediate = true;
+        command.max_retries = Some(3);
+        command.retry_delay_seconds = Some(0);
+        // Fails on the first two attempts, succeeds on the third
+        command.command = format!(
+            "n=$(cat {0} 2>/dev/null || echo 0); n=$((n + 1)); echo $n > {0}; [ $n -ge 3 ]",
+            counter.display()
+        );
+
+        let mut scheduler =
+            Scheduler::new_with_config(vec![command], create_temp_state_path(), 10, 1, None)
+                .unwrap();
+        let _ = timeout(StdDuration::from_secs(4), scheduler.run()).await;
+
+        let attempts = std::fs::read_to_string(&counter).unwrap();
+        assert_eq!(attempts.trim(), "3");
+        assert!(scheduler.retry_attempts.is_empty());
+        let scheduled = scheduler.commands.peek().unwrap();
+        assert!(scheduled.next_run > Utc::now() + Duration::minutes(59));
+    }
+
+    #[tokio::test]
+    async fn test_retries_exhausted_falls_back_to_schedule() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut command = create_test_command("broken", 60.0);
+        command.max_retries = Some(1);
+        command.retry_delay_seconds = Some(0);
+
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        scheduler.executor = Arc::new(FailingExecutor { runs: runs.clone() });
+
+        for _ in 0..2 {
+            scheduler
+                .dispatch_command(ScheduledCommand {
+                    command: parsed(command.clone()),
+                    next_run: Utc::now(),
+                })
+                .await;
+            let completed = scheduler.completions_rx.recv().await.unwrap();
+            scheduler.handle_completion(completed).await;
+            scheduler.commands.pop();
+        }
+
+        assert_eq!(runs.load(AtomicOrdering::SeqCst), 2);
+        assert!(scheduler.retry_attempts.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_builder_with_custom_executor() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut command = create_test_command("built", 60.0);
+        command.immediate = true;
+
+        let mut scheduler = SchedulerBuilder::new(vec![command])
+            .with_executor(Box::new(SlowExecutor {
+                runs: runs.clone(),
+                ..Default::default()
+            }))
+            .with_state_path(create_temp_state_path())
+            .with_min_interval(5)
+            .with_max_immediate_executions(3)
+            .with_max_concurrent(2)
+            .with_clock(Box::new(MockClock::new(Utc::now())))
+            .build()
+            .unwrap();
+
+        assert_eq!(scheduler.min_interval_seconds, 5);
+        assert_eq!(scheduler.max_immediate_executions, 3);
+        assert_eq!(
+            scheduler
+                .concurrency_limit
+                .as_ref()
+                .unwrap()
+                .available_permits(),
+            2
+        );
+
+        scheduler
+            .run_until(sleep(StdDuration::from_millis(200)))
+            .await;
+        assert_eq!(runs.load(AtomicOrdering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_mock_clock_runs_hours_of_schedule_straight_away() {
+        let executor = RecordingExecutor::new();
+        let mut scheduler = SchedulerBuilder::new(vec![create_test_command("hourly", 60.0)])
+            .with_clock(Box::new(MockClock::new(Utc::now())))
+            .with_executor(Box::new(executor.clone()))
+            .with_state_path(create_temp_state_path())
+            .build()
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        scheduler
+            .run_until(sleep(StdDuration::from_secs(5 * 3600 + 60)))
+            .await;
+        assert_eq!(executor.count("hourly"), 5);
+        assert!(started.elapsed() < StdDuration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_mock_clock_sleep_catches_up_missed_runs() {
+        let executor = RecordingExecutor::new();
+        let clock = MockClock::new(Utc::now());
+        let command = CommandConfig {
+            catch_up: CatchUpPolicy::RunAll,
+            ..create_test_command("backup", 10.0)
+        };
+        let mut scheduler = SchedulerBuilder::new(vec![command])
+            .with_clock(Box::new(clock.clone()))
+            .with_executor(Box::new(executor.clone()))
+            .with_state_path(create_temp_state_path())
+            .with_min_interval(0)
+            .build()
+            .unwrap();
+
+        // A minute in, the system sleeps for 35 minutes: the wall clock jumps while tokio's
+        // clock stands still, so the runs due at 10, 20, 30 and 40 minutes were missed
+        scheduler
+            .run_until(async {
+                sleep(StdDuration::from_secs(60)).await;
+                clock.advance(Duration::minutes(35));
+                sleep(StdDuration::from_secs(14 * 60)).await;
+            })
+            .await;
+        assert_eq!(executor.count("backup"), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_long_idle_wait_is_not_taken_for_a_sleep() {
+        let build = |clock: &MockClock, executor: &RecordingExecutor| {
+            let command = CommandConfig {
+                catch_up: CatchUpPolicy::RunAll,
+                ..create_test_command("twice-daily", 12.0 * 60.0)
+            };
+            SchedulerBuilder::new(vec![command])
+                .with_clock(Box::new(clock.clone()))
+                .with_executor(Box::new(executor.clone()))
+                .with_state_path(create_temp_state_path())
+                .build()
+                .unwrap()
+        };
+
+        // Idling between runs moves both clocks alike: one run after 12 hours, on time
+        let executor = RecordingExecutor::new();
+        let clock = MockClock::new(Utc::now());
+        let mut scheduler = build(&clock, &executor);
+        let started = clock.now();
+        scheduler
+            .run_until(sleep(StdDuration::from_secs(23 * 3600)))
+            .await;
+        assert_eq!(executor.count("twice-daily"), 1);
+        let next_run = scheduler.commands.peek().unwrap().next_run;
+        assert!(
+            (next_run - (started + Duration::hours(24)))
+                .num_seconds()
+                .abs()
+                <= 1
+        );
+
+        // A day passing on the wall clock alone is a sleep, which missed both runs
+        let executor = RecordingExecutor::new();
+        let clock = MockClock::new(Utc::now());
+        let mut scheduler = build(&clock, &executor);
+        scheduler
+            .run_until(async {
+                sleep(StdDuration::from_secs(60)).await;
+                clock.advance(Duration::days(1));
+                // Noticed when the loop next wakes, at most `MAX_SLEEP` later
+                sleep(MAX_SLEEP).await;
+            })
+            .await;
+        assert_eq!(executor.count("twice-daily"), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_mock_clock_times_out_long_runs() {
+        let command = CommandConfig {
+            immediate: true,
+            max_runtime_minutes: Some(30),
+            ..create_test_command("stuck", 60.0)
+        };
+        let mut scheduler = SchedulerBuilder::new(vec![command])
+            .with_clock(Box::new(MockClock::new(Utc::now())))
+            .with_executor(Box::new(SlowExecutor {
+                delay: StdDuration::from_secs(2 * 3600),
+                ..Default::default()
+            }))
+            .with_state_path(create_temp_state_path())
+            .build()
+            .unwrap();
+
+        scheduler
+            .run_until(sleep(StdDuration::from_secs(31 * 60)))
+            .await;
+        let history = scheduler.state_manager.get_history("stuck", 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(history[0].timed_out);
+        assert_eq!(
+            history[0].finished_at - history[0].started_at,
+            Duration::minutes(30)
+        );
+    }
+
+    fn scheduled_names(scheduler: &Scheduler) -> Vec<String> {
+        let mut names = scheduler
+            .commands
+            .iter()
+            .map(|scheduled| scheduled.command.name.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn test_reload_config_updates_commands() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let config_path = config_dir.path().join("scheduler.toml");
+        let state_path = config_dir.path().join("state.db");
+        std::fs::write(
+            &config_path,
+            r#"
+[[commands]]
+name = "unchanged"
+command = "echo unchanged"
+interval_minutes = 10.0
+
+[[commands]]
+name = "changed"
+command = "echo changed"
+interval_minutes = 10.0
+
+[[commands]]
+name = "removed"
+command = "echo removed"
+interval_minutes = 10.0
+
+[[commands]]
+name = "disabled"
+command = "echo disabled"
+interval_minutes = 10.0
+"#,
+        )
+        .unwrap();
+
+        let config = Config::load(&config_path).unwrap();
+        let mut scheduler = SchedulerBuilder::from_config(config)
+            .with_config_path(&config_path)
+            .with_state_path(&state_path)
+            .build()
+            .unwrap();
+        let unchanged_next_run = scheduler
+            .commands
+            .iter()
+            .find(|scheduled| scheduled.command.name == "unchanged")
+            .unwrap()
+            .next_run;
+
+        std::fs::write(
+            &config_path,
+            r#"
+[[commands]]
+name = "unchanged"
+command = "echo still unchanged"
+interval_minutes = 10.0
+
+[[commands]]
+name = "changed"
+command = "echo changed"
+interval_minutes = 120.0
+
+[[commands]]
+name = "disabled"
+command = "echo disabled"
+interval_minutes = 10.0
+enabled = false
+
+[[commands]]
+name = "added"
+command = "echo added"
+cron = "0 0 * * * *"
+"#,
+        )
+        .unwrap();
+        scheduler.reload_config().await;
+
+        assert_eq!(
+            scheduled_names(&scheduler),
+            vec!["added", "changed", "unchanged"]
+        );
+        let unchanged = scheduler
+            .commands
+            .iter()
+            .find(|scheduled| scheduled.command.name == "unchanged")
+            .unwrap();
+        assert_eq!(unchanged.next_run, unchanged_next_run);
+        assert_eq!(unchanged.command.command, "echo still unchanged");
+        let changed = scheduler
+            .commands
+            .iter()
+            .find(|scheduled| scheduled.command.name == "changed")
+            .unwrap();
+        assert!(changed.next_run > Utc::now() + Duration::minutes(110));
+    }
+
+    #[tokio::test]
+    async fn test_reload_with_invalid_config_keeps_commands() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let config_path = config_dir.path().join("scheduler.toml");
+        std::fs::write(
+            &config_path,
+            "[[commands]]\nname = \"kept\"\ncommand = \"echo\"\ninterval_minutes = 5.0\n",
+        )
+        .unwrap();
+
+        let config = Config::load(&config_path).unwrap();
+        let mut scheduler = SchedulerBuilder::from_config(config)
+            .with_config_path(&config_path)
+            .with_state_path(create_temp_state_path())
+            .build()
+            .unwrap();
+
+        std::fs::write(&config_path, "[[commands]]\nname = \"broken\"\n").unwrap();
+        scheduler.reload_config().await;
+
+        assert_eq!(scheduled_names(&scheduler), vec!["kept"]);
+    }
+
+    #[tokio::test]
+    async fn test_reload_applies_min_interval() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let config_path = config_dir.path().join("scheduler.toml");
+        let command = "[[commands]]\nname = \"kept\"\ncommand = \"echo\"\ninterval_minutes = 5.0\n";
+        std::fs::write(&config_path, command).unwrap();
+
+        let config = Config::load(&config_path).unwrap();
+        let mut scheduler = SchedulerBuilder::from_config(config)
+            .with_config_path(&config_path)
+            .with_state_path(create_temp_state_path())
+            .build()
+            .unwrap();
+        assert_eq!(scheduler.min_interval_seconds, 30);
+
+        std::fs::write(
+            &config_path,
+            format!("[general]\nmin_interval_seconds = 5\n\n{}", command),
+        )
+        .unwrap();
+        scheduler.reload_config().await;
+
+        assert_eq!(scheduler.min_interval_seconds, 5);
+        let last = Utc::now();
+        scheduler.last_execution_time = Some(last);
+        assert_eq!(
+            scheduler.min_interval_elapsed_at(),
+            Some(last + Duration::seconds(5))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reload_removing_running_command_drops_it_after_completion() {
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        scheduler.executor = Arc::new(SlowExecutor::default());
+
+        scheduler
+            .dispatch_command(ScheduledCommand {
+                command: parsed(create_test_command("running", 1.0)),
+                next_run: Utc::now(),
+            })
+            .await;
+        scheduler.reload_commands(vec![]).await;
+
+        let completed = scheduler.completions_rx.recv().await.unwrap();
+        scheduler.handle_completion(completed).await;
+
+        assert!(scheduler.commands.is_empty());
+        assert!(scheduler.running.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reload_during_run_keeps_its_success() {
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        scheduler.executor = Arc::new(FailingExecutor {
+            runs: Arc::new(AtomicUsize::new(0)),
+        });
+        let command = CommandConfig {
+            max_retries: Some(1),
+            ..create_test_command("flaky", 60.0)
+        };
+
+        scheduler
+            .dispatch_command(ScheduledCommand {
+                command: parsed(command.clone()),
+                next_run: Utc::now(),
+            })
+            .await;
+        // Exit status 1 only counts as a success for runs started after the reload
+        scheduler
+            .reload_commands(vec![CommandConfig {
+                success_exit_codes: Some(vec![0, 1]),
+                ..command
+            }])
+            .await;
+
+        let completed = scheduler.completions_rx.recv().await.unwrap();
+        scheduler.handle_completion(completed).await;
+
+        let history = scheduler.state_manager.get_history("flaky", 1).unwrap();
+        assert!(!history[0].succeeded);
+        assert_eq!(scheduler.retry_attempts.get("flaky"), Some(&1));
+    }
+
+    /// Moves every command's next run `missed_by` into the past and makes the last wake
+    /// look like it happened before a long sleep
+    fn simulate_sleep(scheduler: &mut Scheduler, missed_by: Duration) {
+        for mut scheduled in std::mem::take(&mut scheduler.commands).into_vec() {
+            scheduled.next_run = Utc::now() - missed_by;
+            scheduler.commands.push(scheduled);
+        }
+        scheduler.last_wake_time = Some(Utc::now() - Duration::hours(1));
+    }
+
+    async fn catch_up_runs_after_sleep(catch_up: CatchUpPolicy) -> (usize, Scheduler) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut command = create_test_command("backup", 10.0);
+        command.catch_up = catch_up;
+        let mut scheduler = Scheduler::new(vec![command], create_temp_state_path()).unwrap();
+        scheduler.executor = Arc::new(SlowExecutor {
+            runs: runs.clone(),
+            ..Default::default()
+        });
+
+        // Four runs were due: 35, 25, 15 and 5 minutes ago
+        simulate_sleep(&mut scheduler, Duration::minutes(35));
+        scheduler.handle_sleep_resume().await;
+        while !scheduler.running.is_empty() {
+            let completed = scheduler.completions_rx.recv().await.unwrap();
+            scheduler.handle_completion(completed).await;
+        }
+
+        (runs.load(AtomicOrdering::SeqCst), scheduler)
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_run_all_executes_every_missed_run() {
+        let (runs, scheduler) = catch_up_runs_after_sleep(CatchUpPolicy::RunAll).await;
+        assert_eq!(runs, 4);
+        assert!(scheduler.catch_up_runs.is_empty());
+        assert!(scheduler.commands.peek().unwrap().next_run > Utc::now());
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_run_once_executes_single_run() {
+        let (runs, scheduler) = catch_up_runs_after_sleep(CatchUpPolicy::RunOnce).await;
+        assert_eq!(runs, 1);
+        assert!(scheduler.commands.peek().unwrap().next_run > Utc::now());
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_skip_only_reschedules() {
+        let (runs, scheduler) = catch_up_runs_after_sleep(CatchUpPolicy::Skip).await;
+        assert_eq!(runs, 0);
+        assert_eq!(scheduler.commands.len(), 1);
+        assert!(scheduler.commands.peek().unwrap().next_run > Utc::now());
+    }
+
+    #[test]
+    fn test_clock_jump() {
+        let threshold = Duration::minutes(5);
+        let wall = Utc::now();
+        let instant = Instant::now();
+        let jump = |wall_elapsed: Duration, monotonic_elapsed: StdDuration| {
+            Scheduler::clock_jump(
+                wall,
+                wall + wall_elapsed,
+                instant,
+                instant + monotonic_elapsed,
+                threshold,
+            )
+        };
+
+        // A long wait for the next command isn't a sleep
+        assert_eq!(
+            jump(Duration::hours(1), StdDuration::from_secs(3600)),
+            ClockJump::None
+        );
+        // Neither is a little drift in either direction
+        assert_eq!(
+            jump(Duration::seconds(61), StdDuration::from_secs(60)),
+            ClockJump::None
+        );
+        assert_eq!(
+            jump(Duration::seconds(30), StdDuration::from_secs(60)),
+            ClockJump::None
+        );
+        // Time the monotonic clock didn't see is a sleep, once it reaches the threshold
+        assert_eq!(
+            jump(Duration::minutes(64), StdDuration::from_secs(240)),
+            ClockJump::Ahead(Duration::minutes(60))
+        );
+        assert_eq!(
+            jump(Duration::minutes(4), StdDuration::ZERO),
+            ClockJump::None
+        );
+        assert_eq!(
+            jump(Duration::minutes(5), StdDuration::ZERO),
+            ClockJump::Ahead(Duration::minutes(5))
+        );
+        // A wall clock that went backwards was set back
+        assert_eq!(
+            jump(Duration::minutes(-30), StdDuration::from_secs(60)),
+            ClockJump::Back(Duration::minutes(31))
+        );
+        // A longer `sleep_detection_threshold_minutes` moves the boundary with it
+        let jump_past = |threshold: i64, gap: i64| {
+            Scheduler::clock_jump(
+                wall,
+                wall + Duration::minutes(gap),
+                instant,
+                instant,
+                Duration::minutes(threshold),
+            )
+        };
+        assert_eq!(jump_past(30, 29), ClockJump::None);
+        assert_eq!(jump_past(30, 30), ClockJump::Ahead(Duration::minutes(30)));
+        assert_eq!(jump_past(1, 1), ClockJump::Ahead(Duration::minutes(1)));
+    }
+
+    #[tokio::test]
+    async fn test_clock_set_back_recalculates_next_runs() {
+        let mut scheduler = Scheduler::new(
+            vec![
+                create_test_command("backup", 10.0),
+                create_test_cron_command("hourly", "0 0 * * * *"),
+            ],
+            create_temp_state_path(),
+        )
+        .unwrap();
+        // Runs calculated a day ago by a clock that is now set back
+        for mut scheduled in std::mem::take(&mut scheduler.commands).into_vec() {
+            scheduled.next_run = Utc::now() + Duration::days(1);
+            scheduler.commands.push(scheduled);
+        }
+        scheduler.last_wake_time = Some(Utc::now() + Duration::days(1));
+
+        scheduler.handle_sleep_resume().await;
+        assert!(scheduler.running.is_empty());
+        for scheduled in scheduler.commands.iter() {
+            assert!(scheduled.next_run <= Utc::now() + Duration::hours(1));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sleep_threshold_is_configurable() {
+        let mut scheduler = SchedulerBuilder::new(vec![create_test_command("backup", 10.0)])
+            .with_state_path(create_temp_state_path())
+            .with_sleep_detection_threshold(30)
+            .build()
+            .unwrap();
+        simulate_sleep(&mut scheduler, Duration::minutes(15));
+        scheduler.last_wake_time = Some(Utc::now() - Duration::minutes(20));
+        scheduler.handle_sleep_resume().await;
+        assert!(scheduler.running.is_empty());
+
+        simulate_sleep(&mut scheduler, Duration::minutes(15));
+        scheduler.handle_sleep_resume().await;
+        assert!(scheduler.running.contains_key("backup"));
+    }
+
+    #[tokio::test]
+    async fn test_sleep_detection_setting() {
+        let mut scheduler = Scheduler::new(
+            vec![create_test_command("backup", 10.0)],
+            create_temp_state_path(),
+        )
+        .unwrap();
+
+        scheduler.sleep_detection = SleepDetection::Off;
+        simulate_sleep(&mut scheduler, Duration::minutes(15));
+        scheduler.handle_sleep_resume().await;
+        assert!(scheduler.running.is_empty());
+
+        // Resume notifications from the system take the place of the time gap
+        scheduler.sleep_detection = SleepDetection::System;
+        let (_resumed, power_events) = PowerEvents::channel();
+        scheduler.power_events = Some(power_events);
+        simulate_sleep(&mut scheduler, Duration::minutes(15));
+        scheduler.handle_sleep_resume().await;
+        assert!(scheduler.running.is_empty());
+
+        // Without them the time gap is still noticed
+        scheduler.power_events = None;
+        simulate_sleep(&mut scheduler, Duration::minutes(15));
+        scheduler.handle_sleep_resume().await;
+        assert!(scheduler.running.contains_key("backup"));
+    }
+
+    #[tokio::test]
+    async fn test_resume_notification_catches_up_and_refreshes_cron_runs() {
+        let executor = RecordingExecutor::new();
+        let mut scheduler = SchedulerBuilder::new(vec![
+            create_test_command("backup", 10.0),
+            create_test_cron_command("hourly", "0 0 * * * *"),
+        ])
+        .with_executor(Box::new(executor.clone()))
+        .with_state_path(create_temp_state_path())
+        .with_sleep_detection(SleepDetection::System)
+        .build()
+        .unwrap();
+        let (resumed, power_events) = PowerEvents::channel();
+        scheduler.power_events = Some(power_events);
+        let stale = Utc::now() + Duration::days(2);
+        for mut scheduled in std::mem::take(&mut scheduler.commands).into_vec() {
+            scheduled.next_run = match scheduled.command.name.as_str() {
+                "backup" => Utc::now() - Duration::minutes(5),
+                _ => stale,
+            };
+            scheduler.commands.push(scheduled);
+        }
+
+        resumed.send(()).unwrap();
+        scheduler.wait_for(StdDuration::from_secs(5)).await;
+        assert!(scheduler.running.contains_key("backup"));
+        let hourly = scheduler.commands.peek().unwrap();
+        assert_eq!(hourly.command.name, "hourly");
+        assert!(hourly.next_run <= Utc::now() + Duration::hours(1));
+
+        // A watcher that stops hands sleep detection back to the time gap
+        drop(resumed);
+        scheduler.wait_for(StdDuration::from_secs(5)).await;
+        assert!(scheduler.power_events.is_none());
+        assert!(scheduler.detects_sleep_by_time_gap());
+    }
+
+    #[test]
+    fn test_catch_up_applies_to_runs_missed_while_stopped() {
+        let start = |catch_up| {
+            let state_path = create_temp_state_path();
+            let command = CommandConfig {
+                catch_up,
+                ..create_test_command("backup", 10.0)
+            };
+            // Four runs came due while the scheduler was stopped
+            StateManager::new(&state_path)
+                .unwrap()
+                .save_next_scheduled(&command, Utc::now() - Duration::minutes(35))
+                .unwrap();
+            Scheduler::new(vec![command], state_path).unwrap()
+        };
+
+        let scheduler = start(CatchUpPolicy::RunOnce);
+        assert!(scheduler.commands.peek().unwrap().next_run < Utc::now());
+        assert!(scheduler.catch_up_runs.is_empty());
+
+        let scheduler = start(CatchUpPolicy::RunAll);
+        assert!(scheduler.commands.peek().unwrap().next_run < Utc::now());
+        assert_eq!(scheduler.catch_up_runs.get("backup"), Some(&3));
+
+        let scheduler = start(CatchUpPolicy::Skip);
+        assert!(scheduler.commands.peek().unwrap().next_run > Utc::now());
+        assert!(scheduler.catch_up_runs.is_empty());
+    }
+
+    #[test]
+    fn test_missed_runs_counts_interval_and_cron_runs() {
+        let now = Utc::now();
+        let command = parsed(create_test_command("interval", 10.0));
+        assert_eq!(
+            Scheduler::missed_runs(&command, now - Duration::minutes(35), now, 10),
+            4
+        );
+        assert_eq!(
+            Scheduler::missed_runs(&command, now - Duration::minutes(35), now, 2),
+            2
+        );
+        assert_eq!(
+            Scheduler::missed_runs(&command, now + Duration::minutes(1), now, 10),
+            0
+        );
+
+        let hourly = parsed(create_test_cron_command("hourly", "0 0 * * * *"));
+        let next_run = DateTime::parse_from_rfc3339("2024-01-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            Scheduler::missed_runs(&hourly, next_run, next_run + Duration::minutes(150), 10),
+            3
+        );
+    }
+
+    #[test]
+    fn test_backoff_run_delays_each_schedule_type() {
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        let now = at("2026-10-15T10:07:31Z");
+        let mut interval = create_test_command("interval", 10.0);
+        interval.failure_backoff = true;
+        interval.failure_backoff_max_minutes = Some(60);
+        let run = |command: &CommandConfig, failures| {
+            Scheduler::backoff_run(&parsed(command.clone()), now, failures).unwrap()
+        };
+
+        assert_eq!(run(&interval, 0), now + Duration::minutes(10));
+        assert_eq!(run(&interval, 1), now + Duration::minutes(20));
+        assert_eq!(run(&interval, 3), now + Duration::minutes(60));
+
+        let mut hourly = create_test_cron_command("hourly", "0 0 * * * *");
+        hourly.failure_backoff = true;
+        assert_eq!(run(&hourly, 0), at("2026-10-15T11:00:00Z"));
+        // Two hours from now falls between slots, so the run waits for the next one
+        assert_eq!(run(&hourly, 1), at("2026-10-15T13:00:00Z"));
+
+        let mut aligned = create_test_command("aligned", 15.0);
+        aligned.align_to_interval = true;
+        aligned.failure_backoff = true;
+        assert_eq!(run(&aligned, 1), at("2026-10-15T10:45:00Z"));
+
+        aligned.failure_backoff = false;
+        assert_eq!(run(&aligned, 5), at("2026-10-15T10:15:00Z"));
+    }
+
+    #[test]
+    fn test_cron_next_run_counts_from_scheduled_time() {
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        let next_run = |catch_up, scheduled_for: &str, started: &str, now: &str| {
+            let command = CommandConfig {
+                catch_up,
+                ..create_test_cron_command("every5", "0 */5 * * * *")
+            };
+            let previous = PreviousRun {
+                scheduled_for: at(scheduled_for),
+                started: at(started),
+            };
+            Scheduler::anchored_run(&parsed(command), Some(previous), None, at(now)).unwrap()
+        };
+        let slot = "2026-10-15T10:05:00Z";
+
+        // Finishing within the second of its slot doesn't run it again
+        assert_eq!(
+            next_run(
+                CatchUpPolicy::RunOnce,
+                slot,
+                slot,
+                "2026-10-15T10:05:00.400Z"
+            ),
+            at("2026-10-15T10:10:00Z")
+        );
+        // nor does a start held back by the minimum interval move the schedule
+        assert_eq!(
+            next_run(
+                CatchUpPolicy::RunOnce,
+                slot,
+                "2026-10-15T10:05:03Z",
+                "2026-10-15T10:06:00Z"
+            ),
+            at("2026-10-15T10:10:00Z")
+        );
+
+        // Occurrences at 10:10 and 10:15 passed during a long run
+        let long_run = |catch_up| next_run(catch_up, slot, slot, "2026-10-15T10:17:30Z");
+        assert_eq!(long_run(CatchUpPolicy::RunAll), at("2026-10-15T10:10:00Z"));
+        assert_eq!(long_run(CatchUpPolicy::RunOnce), at("2026-10-15T10:17:30Z"));
+        assert_eq!(long_run(CatchUpPolicy::Skip), at("2026-10-15T10:20:00Z"));
+        // Running all of them works through them in turn
+        assert_eq!(
+            next_run(
+                CatchUpPolicy::RunAll,
+                "2026-10-15T10:10:00Z",
+                "2026-10-15T10:17:30Z",
+                "2026-10-15T10:17:31Z"
+            ),
+            at("2026-10-15T10:15:00Z")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cron_occurrences_missed_during_long_runs_follow_catch_up() {
+        let starts = |catch_up| async move {
+            let runs = Arc::new(AtomicUsize::new(0));
+            let command = CommandConfig {
+                catch_up,
+                max_runtime_minutes: Some(60),
+                ..create_test_cron_command("every5", "0 */5 * * * *")
+            };
+            let start = "2026-10-15T10:00:30Z".parse::<DateTime<Utc>>().unwrap();
+            let mut scheduler = SchedulerBuilder::new(vec![command])
+                .with_clock(Box::new(MockClock::new(start)))
+                .with_executor(Box::new(SlowExecutor {
+                    delay: StdDuration::from_secs(12 * 60),
+                    runs: runs.clone(),
+                    ..Default::default()
+                }))
+                .with_state_path(create_temp_state_path())
+                .with_shutdown_timeout(0)
+                .build()
+                .unwrap();
+            // Until 10:41:30, with each run taking 12 minutes
+            scheduler
+                .run_until(sleep(StdDuration::from_secs(41 * 60)))
+                .await;
+            runs.load(AtomicOrdering::SeqCst)
+        };
+
+        // The runs due at 10:05, 10:10, 10:15 and 10:20 start at 10:05, 10:17, 10:29 and
+        // 10:41
+        assert_eq!(starts(CatchUpPolicy::RunAll).await, 4);
+        // Those due at 10:05, 10:20 and 10:35 start on time
+        assert_eq!(starts(CatchUpPolicy::Skip).await, 3);
+    }
+
+    /// A previous run that started on time at `started`
+    fn ran_at(started: DateTime<Utc>) -> PreviousRun {
+        PreviousRun {
+            scheduled_for: started,
+            started,
+        }
+    }
+
+    #[test]
+    fn test_interval_anchor_drift() {
+        let first = "2026-10-15T10:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        // Starts of ten runs of a 10 minute interval command, each taking `runtime`
+        let starts = |anchor, runtime| {
+            let mut command = create_test_command("anchored", 10.0);
+            command.interval_anchor = anchor;
+            let command = parsed(command);
+            let mut starts = vec![first];
+            for _ in 1..10 {
+                let started = *starts.last().unwrap();
+                let finished = started + runtime;
+                starts.push(
+                    Scheduler::anchored_run(&command, Some(ran_at(started)), Some(first), finished)
+                        .unwrap(),
+                );
+            }
+            starts
+                .iter()
+                .map(|start| (*start - first).num_minutes())
+                .collect::<Vec<_>>()
+        };
+
+        // Each 3 minute run pushes the ones after it back when counting from its end
+        assert_eq!(
+            starts(IntervalAnchor::Finish, Duration::minutes(3)),
+            [0, 13, 26, 39, 52, 65, 78, 91, 104, 117]
+        );
+        let on_time = [0, 10, 20, 30, 40, 50, 60, 70, 80, 90];
+        assert_eq!(starts(IntervalAnchor::Start, Duration::minutes(3)), on_time);
+        assert_eq!(starts(IntervalAnchor::Fixed, Duration::minutes(3)), on_time);
+
+        // A run longer than the interval is followed straight away when counting from its
+        // start, while the fixed grid skips the slot it overran and keeps its phase
+        assert_eq!(
+            starts(IntervalAnchor::Start, Duration::minutes(12)),
+            [0, 12, 24, 36, 48, 60, 72, 84, 96, 108]
+        );
+        assert_eq!(
+            starts(IntervalAnchor::Fixed, Duration::minutes(12)),
+            [0, 20, 40, 60, 80, 100, 120, 140, 160, 180]
+        );
+    }
+
+    #[test]
+    fn test_fixed_rate_keeps_cadence_of_slow_command() {
+        let first = "2026-10-15T10:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        // Minutes after the first run at which a command taking 3 minutes on a 5 minute
+        // interval starts
+        let cadence = |fixed_rate| {
+            let mut command = create_test_command("slow", 5.0);
+            command.fixed_rate = fixed_rate;
+            let command = parsed(command);
+            let mut started = first;
+            let mut starts = vec![0];
+            for _ in 0..5 {
+                let finished = started + Duration::minutes(3);
+                started =
+                    Scheduler::anchored_run(&command, Some(ran_at(started)), Some(first), finished)
+                        .unwrap();
+                starts.push((started - first).num_minutes());
             }
-            Err(e) => {
-                error!("Failed to execute command '{}': {}", command.name, e);
+            starts
+        };
+        assert_eq!(cadence(false), [0, 8, 16, 24, 32, 40]);
+        assert_eq!(cadence(true), [0, 5, 10, 15, 20, 25]);
+    }
+
+    #[tokio::test]
+    async fn test_interval_anchor_applies_after_timeout_and_catch_up() {
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        let mut command = create_test_command("anchored", 10.0);
+        command.interval_anchor = IntervalAnchor::Start;
+
+        // A run that timed out after 4 minutes is counted from its start
+        let started = Utc::now() - Duration::minutes(4);
+        scheduler.running.insert(command.name.clone(), 1);
+        scheduler
+            .handle_completion(CompletedRun {
+                command: parsed(command.clone()),
+                scheduled_for: started,
+                started,
+                finished: Utc::now(),
+                outcome: RunOutcome::TimedOut(StdDuration::from_secs(240)),
+            })
+            .await;
+        let next_run = scheduler.commands.pop().unwrap().next_run;
+        assert_eq!(next_run, started + Duration::minutes(10));
+
+        // A fixed command whose slot was missed during sleep resumes on its grid
+        command.interval_anchor = IntervalAnchor::Fixed;
+        command.catch_up = CatchUpPolicy::Skip;
+        let now = Utc::now();
+        let missed = now - Duration::minutes(25);
+        scheduler.commands.push(ScheduledCommand {
+            command: parsed(command),
+            next_run: missed,
+        });
+        scheduler.catch_up_after_sleep(now).await;
+        let next_run = scheduler.commands.pop().unwrap().next_run;
+        assert_eq!(next_run, missed + Duration::minutes(30));
+        assert_eq!(scheduler.interval_origins.get("anchored"), Some(&missed));
+    }
+
+    #[tokio::test]
+    async fn test_failure_backoff_until_success() {
+        let state_path = create_temp_state_path();
+        let mut scheduler = Scheduler::new(vec![], state_path.clone()).unwrap();
+        scheduler.executor = Arc::new(FailingExecutor {
+            runs: Arc::new(AtomicUsize::new(0)),
+        });
+        let mut command = create_test_command("flaky", 60.0);
+        command.failure_backoff = true;
+
+        let mut next_run = Utc::now();
+        for expected in [120, 240] {
+            scheduler
+                .dispatch_command(ScheduledCommand {
+                    command: parsed(command.clone()),
+                    next_run,
+                })
+                .await;
+            let completed = scheduler.completions_rx.recv().await.unwrap();
+            scheduler.handle_completion(completed).await;
+            next_run = scheduler.commands.pop().unwrap().next_run;
+            let delay = next_run - Utc::now();
+            assert!(delay > Duration::minutes(expected - 1), "{}", delay);
+            assert!(delay <= Duration::minutes(expected), "{}", delay);
+        }
+
+        // The backed-off run is what a restart picks up
+        let state = scheduler
+            .state_manager
+            .get_command_state("flaky")
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.consecutive_failures, 2);
+        assert_eq!(state.next_scheduled.timestamp(), next_run.timestamp());
+
+        // The first success returns to the normal interval
+        scheduler.executor = Arc::new(RecordingExecutor::new());
+        scheduler
+            .dispatch_command(ScheduledCommand {
+                command: parsed(command),
+                next_run,
+            })
+            .await;
+        let completed = scheduler.completions_rx.recv().await.unwrap();
+        scheduler.handle_completion(completed).await;
+        let delay = scheduler.commands.pop().unwrap().next_run - Utc::now();
+        assert!(delay > Duration::minutes(59));
+        assert!(delay <= Duration::minutes(60));
+    }
+
+    #[tokio::test]
+    async fn test_completed_runs_are_recorded_in_history() {
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        scheduler.executor = Arc::new(FailingExecutor {
+            runs: Arc::new(AtomicUsize::new(0)),
+        });
+
+        scheduler
+            .dispatch_command(ScheduledCommand {
+                command: parsed(create_test_command("flaky", 60.0)),
+                next_run: Utc::now(),
+            })
+            .await;
+        let completed = scheduler.completions_rx.recv().await.unwrap();
+        scheduler.handle_completion(completed).await;
+
+        let history = scheduler.state_manager.get_history("flaky", 10).unwrap();
+        assert_eq!(history.len(), 1);
+        let state = scheduler
+            .state_manager
+            .get_command_state("flaky")
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.last_status, Some(1));
+        assert!(state.last_duration_ms.is_some());
+        assert_eq!(history[0].exit_code, Some(1));
+        assert!(!history[0].timed_out);
+        assert!(history[0].finished_at >= history[0].started_at);
+    }
+
+    #[tokio::test]
+    async fn test_old_history_is_pruned_on_start_and_daily() {
+        let state_path = create_temp_state_path();
+        let state_manager = StateManager::new(&state_path).unwrap();
+        let run = |name: &str, days_ago| {
+            let started_at = Utc::now() - Duration::days(days_ago);
+            ExecutionRecord {
+                name: name.to_string(),
+                started_at,
+                finished_at: started_at,
+                exit_code: Some(0),
+                timed_out: false,
+                succeeded: true,
+                skipped: false,
+                stdout: String::new(),
+                stderr: String::new(),
+                stdout_len: 0,
+                stderr_len: 0,
             }
+        };
+        state_manager.record_execution(&run("old", 10)).unwrap();
+        drop(state_manager);
+
+        let mut scheduler = SchedulerBuilder::new(vec![])
+            .with_state_path(&state_path)
+            .with_history_retention_days(7)
+            .build()
+            .unwrap();
+        // The first iteration of the loop prunes
+        scheduler.prune_history_if_due().await;
+        assert!(scheduler
+            .state_manager
+            .get_history("old", 10)
+            .unwrap()
+            .is_empty());
+
+        // History that ages past the cutoff is pruned again once a day
+        scheduler
+            .state_manager
+            .record_execution(&run("aging", 8))
+            .unwrap();
+        scheduler.prune_history_if_due().await;
+        assert_eq!(
+            scheduler
+                .state_manager
+                .get_history("aging", 10)
+                .unwrap()
+                .len(),
+            1
+        );
+        scheduler.last_history_prune = Some(Utc::now() - Duration::days(1));
+        scheduler.prune_history_if_due().await;
+        assert!(scheduler
+            .state_manager
+            .get_history("aging", 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_startup_with_corrupted_state_rows() {
+        let state_path = create_temp_state_path();
+        drop(StateManager::new(&state_path).unwrap());
+        let stored_next_run = Utc::now() + Duration::minutes(3);
+        crate::state::tests::insert_raw_state(&state_path, "corrupt", "garbage", "garbage");
+        crate::state::tests::insert_raw_state(
+            &state_path,
+            "intact",
+            "garbage",
+            &stored_next_run.to_rfc3339(),
+        );
+
+        let scheduler = Scheduler::new(
+            vec![
+                create_test_command("corrupt", 60.0),
+                create_test_command("intact", 60.0),
+            ],
+            state_path,
+        )
+        .unwrap();
+
+        let next_run = |name: &str| {
+            scheduler
+                .commands
+                .iter()
+                .find(|scheduled| scheduled.command.name == name)
+                .unwrap()
+                .next_run
+        };
+        assert!(next_run("corrupt") > Utc::now() + Duration::minutes(50));
+        assert_eq!(next_run("intact").timestamp(), stored_next_run.timestamp());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_command_disabled_after_consecutive_failures() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let state_path = create_temp_state_path();
+        let mut command = create_test_command("broken", 0.001);
+        command.immediate = true;
+        command.disable_after_failures = Some(3);
+
+        let mut scheduler =
+            Scheduler::new_with_config(vec![command.clone()], state_path.clone(), 10, 1, None)
+                .unwrap();
+        scheduler.executor = Arc::new(FailingExecutor { runs: runs.clone() });
+
+        use_mock_clock(&mut scheduler);
+        let _ = timeout(StdDuration::from_secs(3), scheduler.run()).await;
+
+        assert_eq!(runs.load(AtomicOrdering::SeqCst), 3);
+        assert!(scheduler.commands.is_empty());
+
+        // The failure count survives a restart, so the command stays disabled
+        let scheduler = Scheduler::new(vec![command.clone()], state_path.clone()).unwrap();
+        assert!(scheduler.commands.is_empty());
+
+        // Raising the limit lets it run again
+        command.disable_after_failures = Some(5);
+        let scheduler = Scheduler::new(vec![command], state_path).unwrap();
+        assert_eq!(scheduler.commands.len(), 1);
+    }
+
+    #[test]
+    fn test_start_delay_and_jitter_offset_first_run() {
+        let mut first = create_test_command("first", 60.0);
+        first.start_delay_seconds = Some(30);
+        first.jitter_seconds = Some(600);
+        let mut second = first.clone();
+        second.name = "second".to_string();
+
+        let before = Utc::now();
+        let scheduler =
+            Scheduler::new(vec![first.clone(), second], create_temp_state_path()).unwrap();
+        let next_runs = scheduler
+            .commands
+            .iter()
+            .map(|scheduled| scheduled.next_run)
+            .collect::<Vec<_>>();
+
+        assert_eq!(next_runs.len(), 2);
+        assert_ne!(next_runs[0], next_runs[1]);
+        for next_run in next_runs {
+            let offset = next_run - (before + Duration::minutes(60));
+            assert!(offset >= Duration::seconds(30));
+            assert!(offset <= Duration::seconds(631));
         }
+    }
 
-        let execution_duration = Utc::now().signed_duration_since(execution_start);
-        info!(
-            "Command '{}' execution took {} milliseconds",
-            command.name,
-            execution_duration.num_milliseconds()
+    #[test]
+    fn test_jitter_keeps_saved_schedule() {
+        let state_path = create_temp_state_path();
+        let mut command = create_test_command("saved", 60.0);
+        let saved = Utc::now() + Duration::minutes(5);
+        StateManager::new(&state_path)
+            .unwrap()
+            .save_next_scheduled(&command, saved)
+            .unwrap();
+
+        command.start_delay_seconds = Some(60);
+        command.jitter_seconds = Some(600);
+        let scheduler = Scheduler::new(vec![command], state_path).unwrap();
+        let scheduled = scheduler.commands.peek().unwrap();
+        assert_eq!(scheduled.next_run.timestamp(), saved.timestamp());
+    }
+
+    #[test]
+    fn test_jitter_never_passes_the_following_run() {
+        let run = "2026-10-15T10:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut every_minute = create_test_cron_command("every-minute", "0 * * * * *");
+        every_minute.jitter_seconds = Some(600);
+        let mut hourly = create_test_cron_command("hourly", "0 0 * * * *");
+        hourly.jitter_seconds = Some(600);
+        let every_minute = parsed(every_minute);
+        let hourly = parsed(hourly);
+
+        let offsets = (0..50)
+            .map(|_| Scheduler::jitter(&every_minute, run, &mut rng) - run)
+            .collect::<Vec<_>>();
+        assert!(offsets
+            .iter()
+            .all(|offset| *offset >= Duration::zero() && *offset < Duration::minutes(1)));
+        assert!(offsets.iter().any(|offset| *offset > Duration::seconds(30)));
+
+        let offsets = (0..50)
+            .map(|_| Scheduler::jitter(&hourly, run, &mut rng) - run)
+            .collect::<Vec<_>>();
+        assert!(offsets
+            .iter()
+            .all(|offset| *offset >= Duration::zero() && *offset <= Duration::minutes(10)));
+        assert!(offsets.iter().any(|offset| *offset > Duration::minutes(1)));
+
+        let mut one_shot = create_one_shot_command("once", run);
+        one_shot.jitter_seconds = Some(600);
+        assert_eq!(Scheduler::jitter(&parsed(one_shot), run, &mut rng), run);
+    }
+
+    #[test]
+    fn test_seeded_jitter_applies_to_every_run() {
+        let mut command = create_test_command("jittered", 60.0);
+        command.jitter_seconds = Some(600);
+        let offsets = |seed| {
+            let mut scheduler = SchedulerBuilder::new(vec![])
+                .with_state_path(create_temp_state_path())
+                .with_rng_seed(seed)
+                .build()
+                .unwrap();
+            (0..5)
+                .map(|_| {
+                    let before = Utc::now();
+                    let next_run = scheduler
+                        .schedule_next_run(parsed(command.clone()))
+                        .unwrap();
+                    let offset = next_run - (before + Duration::minutes(60));
+                    assert!(offset >= Duration::zero(), "{}", offset);
+                    assert!(offset <= Duration::seconds(601), "{}", offset);
+                    offset.num_seconds()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let first = offsets(42);
+        assert!(first.iter().any(|offset| *offset != first[0]));
+        // Offsets are drawn from the seed, give or take the time the loop takes
+        let second = offsets(42);
+        for (a, b) in first.iter().zip(&second) {
+            assert!((a - b).abs() <= 1, "{:?} vs {:?}", first, second);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_startup_splay_delays_first_dispatch() {
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+        let clock = MockClock::new(Utc::now());
+        let mut command = create_test_command("immediate", 60.0);
+        command.immediate = true;
+        let mut scheduler = SchedulerBuilder::new(vec![command])
+            .with_state_path(create_temp_state_path())
+            .with_startup_splay(1)
+            .with_rng_seed(7)
+            .with_clock(Box::new(clock.clone()))
+            .with_executor(Box::new(TimingExecutor {
+                started: started_tx,
+                clock: clock.clone(),
+            }))
+            .build()
+            .unwrap();
+        let splay = Duration::milliseconds(StdRng::seed_from_u64(7).gen_range(0..=1000));
+
+        let start = clock.now();
+        let _ = timeout(StdDuration::from_millis(1500), scheduler.run()).await;
+
+        let late = started_rx.try_recv().unwrap().1 - (start + splay);
+        assert!(late >= Duration::zero(), "ran {} early", -late);
+        assert!(late < Duration::milliseconds(150), "ran {} late", late);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_waits_for_running_commands_and_saves_schedule() {
+        let finished = Arc::new(Mutex::new(Vec::new()));
+        let state_path = create_temp_state_path();
+        let mut slow = create_test_command("slow", 60.0);
+        slow.immediate = true;
+        let later = create_test_command("later", 60.0);
+
+        let mut scheduler = SchedulerBuilder::new(vec![slow, later])
+            .with_executor(Box::new(SlowExecutor {
+                delay: StdDuration::from_millis(300),
+                finished: finished.clone(),
+                ..Default::default()
+            }))
+            .with_state_path(&state_path)
+            .with_shutdown_timeout(5)
+            .with_clock(Box::new(MockClock::new(Utc::now())))
+            .build()
+            .unwrap();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            sleep(StdDuration::from_millis(100)).await;
+            let _ = shutdown_tx.send(());
+        });
+
+        let started = std::time::Instant::now();
+        scheduler
+            .run_until(async {
+                let _ = shutdown_rx.await;
+            })
+            .await;
+
+        // The running command finished and nothing new was started
+        assert!(started.elapsed() < StdDuration::from_secs(5));
+        assert_eq!(*finished.lock().unwrap(), vec!["slow".to_string()]);
+        assert!(scheduler.running.is_empty());
+
+        let state = StateManager::new(&state_path).unwrap();
+        let slow = state.get_command_state("slow").unwrap().unwrap();
+        assert!(slow.last_execution.is_some());
+        let later = state.get_command_state("later").unwrap().unwrap();
+        let scheduled = scheduler
+            .commands
+            .iter()
+            .find(|scheduled| scheduled.command.name == "later")
+            .unwrap();
+        assert_eq!(
+            later.next_scheduled.timestamp(),
+            scheduled.next_run.timestamp()
         );
+    }
 
-        // Save state after execution
-        match self.schedule_next_run(command.clone()) {
-            Ok(next_run) => {
-                if let Err(e) =
-                    self.state_manager
-                        .save_command_state(&command, Some(execution_start), next_run)
-                {
-                    error!("Failed to save state for command '{}': {}", command.name, e);
-                }
-            }
-            Err(e) => {
-                error!(
-                    "Failed to calculate next run for command '{}': {}",
-                    command.name, e
-                );
-            }
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_notifies_systemd_and_pings_watchdog_while_sleeping() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("notify");
+        let receiver = std::os::unix::net::UnixDatagram::bind(&socket_path).unwrap();
+        receiver.set_nonblocking(true).unwrap();
+
+        let mut scheduler = SchedulerBuilder::new(vec![create_test_command("later", 60.0)])
+            .with_executor(Box::new(RecordingExecutor::new()))
+            .with_state_path(create_temp_state_path())
+            .build()
+            .unwrap();
+        // A 200ms watchdog has to be pinged every 100ms, well within the hour-long sleep
+        scheduler.sd_notify = SdNotify::new(Some(socket_path.as_os_str()), Some("200000"), None);
+        scheduler
+            .run_until(sleep(StdDuration::from_millis(450)))
+            .await;
+
+        let mut messages = Vec::new();
+        let mut buffer = [0; 256];
+        while let Ok(len) = receiver.recv(&mut buffer) {
+            messages.push(String::from_utf8_lossy(&buffer[..len]).to_string());
         }
+        assert_eq!(messages[0], "READY=1");
+        assert!(messages[1].starts_with("STATUS=Next: later at "));
+        let pings = messages.iter().filter(|m| *m == "WATCHDOG=1").count();
+        assert!(
+            pings >= 4,
+            "expected regular watchdog pings: {:?}",
+            messages
+        );
+        assert_eq!(messages.last().unwrap(), "STOPPING=1");
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::path::PathBuf;
-    use tempfile::NamedTempFile;
+    #[tokio::test(start_paused = true)]
+    async fn test_publishes_staleness_checks() {
+        let watched = CommandConfig {
+            immediate: true,
+            max_staleness_minutes: Some(90),
+            ..create_test_command("watched", 60.0)
+        };
+        let mut scheduler =
+            SchedulerBuilder::new(vec![watched, create_test_command("unwatched", 60.0)])
+                .with_executor(Box::new(RecordingExecutor::new()))
+                .with_state_path(create_temp_state_path())
+                .with_clock(Box::new(MockClock::new(Utc::now())))
+                .build()
+                .unwrap();
+        let checks = scheduler.staleness_tx.subscribe();
+        scheduler
+            .run_until(sleep(StdDuration::from_millis(300)))
+            .await;
 
-    fn create_test_command(name: &str, interval_minutes: f64) -> CommandConfig {
-        CommandConfig {
-            name: name.to_string(),
-            command: "echo test".to_string(),
-            interval_minutes: Some(interval_minutes),
-            cron: None,
-            max_runtime_minutes: Some(5),
-            enabled: true,
-            working_dir: None,
-            environment: None,
-            immediate: false,
+        let checks = checks.borrow();
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].command.name, "watched");
+        assert!(checks[0].last_execution.is_some());
+        assert!(checks[0].active);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_paused_command_skips_scheduled_runs() {
+        let executor = RecordingExecutor::new();
+        let mut scheduler = SchedulerBuilder::new(vec![
+            create_test_command("paused", 60.0),
+            create_test_command("other", 60.0),
+        ])
+        .with_executor(Box::new(executor.clone()))
+        .with_state_path(create_temp_state_path())
+        .with_clock(Box::new(MockClock::new(Utc::now())))
+        .build()
+        .unwrap();
+        let mut due = std::mem::take(&mut scheduler.commands).into_vec();
+        for scheduled in &mut due {
+            scheduled.next_run = match scheduled.command.name.as_str() {
+                "paused" => Utc::now() - Duration::seconds(2),
+                _ => Utc::now() - Duration::seconds(1),
+            };
         }
+        scheduler.commands = due.into();
+
+        let pause = |name: &str| ControlRequest::Pause {
+            name: name.to_string(),
+        };
+        assert!(scheduler.handle_control(pause("paused")).await.ok);
+        assert!(!scheduler.handle_control(pause("missing")).await.ok);
+        scheduler
+            .run_until(sleep(StdDuration::from_millis(300)))
+            .await;
+
+        assert_eq!(executor.invocations(), vec!["other".to_string()]);
+        let paused = scheduler
+            .commands
+            .iter()
+            .find(|scheduled| scheduled.command.name == "paused")
+            .unwrap();
+        assert!(paused.next_run > Utc::now() + Duration::minutes(59));
+
+        let resume = || ControlRequest::Resume {
+            name: "paused".to_string(),
+        };
+        assert!(scheduler.handle_control(resume()).await.ok);
+        let response = scheduler.handle_control(resume()).await;
+        assert_eq!(
+            response.error.as_deref(),
+            Some("Command 'paused' is not paused")
+        );
     }
 
-    fn create_test_cron_command(name: &str, cron: &str) -> CommandConfig {
+    #[tokio::test(start_paused = true)]
+    async fn test_failure_notifies_commands_with_notify_on_failure() {
+        let command = |name: &str, notify: bool| CommandConfig {
+            immediate: true,
+            notify_on_failure: notify.then_some(true),
+            ..create_test_command(name, 60.0)
+        };
+        let notifier = RecordingNotifier::new();
+        let mut scheduler = SchedulerBuilder::new(vec![
+            command("backup", true),
+            command("report", false),
+            command("cleanup", true),
+        ])
+        .with_executor(Box::new(
+            RecordingExecutor::new()
+                .with_status("backup", 3)
+                .with_status("report", 1),
+        ))
+        .with_notifier(Box::new(notifier.clone()))
+        .with_clock(Box::new(MockClock::new(Utc::now())))
+        .with_state_path(create_temp_state_path())
+        .build()
+        .unwrap();
+        scheduler
+            .run_until(sleep(StdDuration::from_secs(5 * 60)))
+            .await;
+
+        assert_eq!(
+            notifier.notifications(),
+            vec![(
+                "Zephyr: backup failed".to_string(),
+                "Exited with status 3".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pause_all_skips_every_command_until_resumed() {
+        let executor = RecordingExecutor::new();
+        let clock = MockClock::new(Utc::now());
+        let mut scheduler = SchedulerBuilder::new(vec![
+            create_test_command("backup", 60.0),
+            create_test_command("report", 60.0),
+        ])
+        .with_executor(Box::new(executor.clone()))
+        .with_state_path(create_temp_state_path())
+        .with_clock(Box::new(clock.clone()))
+        .build()
+        .unwrap();
+
+        assert!(!scheduler.handle_control(ControlRequest::ResumeAll).await.ok);
+        assert!(scheduler.handle_control(ControlRequest::PauseAll).await.ok);
+        assert!(scheduler.statuses().await.unwrap().iter().all(|s| s.paused));
+
+        // Paused through the first runs, an hour in, and resumed before the second ones
+        let handle = scheduler.control_handle();
+        let invocations = executor.clone();
+        let control = tokio::spawn(async move {
+            sleep(StdDuration::from_secs(90 * 60)).await;
+            let while_paused = invocations.invocations();
+            let status = handle.request(ControlRequest::Status).await;
+            let resumed = handle.request(ControlRequest::ResumeAll).await;
+            (while_paused, status, resumed)
+        });
+        scheduler
+            .run_until(sleep(StdDuration::from_secs(150 * 60)))
+            .await;
+        let (while_paused, status, resumed) = control.await.unwrap();
+
+        assert!(while_paused.is_empty());
+        assert!(status.commands.unwrap().iter().all(|s| s.paused));
+        assert_eq!(resumed.message.as_deref(), Some("Resumed all commands"));
+        let mut invocations = executor.invocations();
+        invocations.sort();
+        assert_eq!(invocations, vec!["backup", "report"]);
+        assert!(scheduler
+            .statuses()
+            .await
+            .unwrap()
+            .iter()
+            .all(|s| !s.paused));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_timeout_stops_waiting() {
+        let mut slow = create_test_command("slow", 60.0);
+        slow.immediate = true;
+
+        let mut scheduler = SchedulerBuilder::new(vec![slow])
+            .with_executor(Box::new(SlowExecutor {
+                delay: StdDuration::from_secs(30),
+                ..Default::default()
+            }))
+            .with_state_path(create_temp_state_path())
+            .with_shutdown_timeout(0)
+            .with_clock(Box::new(MockClock::new(Utc::now())))
+            .build()
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        scheduler
+            .run_until(sleep(StdDuration::from_millis(100)))
+            .await;
+        assert!(started.elapsed() < StdDuration::from_secs(5));
+        assert!(scheduler.running.contains_key("slow"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_allowed_exit_code_counts_as_success() {
+        let recorder = RecordingExecutor::new().with_status("sync", 1);
+        let mut command = create_test_command("sync", 60.0);
+        command.immediate = true;
+        command.success_exit_codes = Some(vec![0, 1]);
+        command.max_retries = Some(2);
+        command.retry_delay_seconds = Some(0);
+        command.disable_after_failures = Some(1);
+
+        let mut scheduler = Scheduler::new_with_executor(
+            vec![command],
+            create_temp_state_path(),
+            Box::new(recorder.clone()),
+        )
+        .unwrap();
+        use_mock_clock(&mut scheduler);
+        scheduler
+            .run_until(sleep(StdDuration::from_millis(300)))
+            .await;
+
+        // Exit status 1 is allowed, so the run is neither retried nor counted as a failure
+        assert_eq!(recorder.count("sync"), 1);
+        assert!(scheduler.retry_attempts.is_empty());
+        assert_eq!(scheduler.commands.len(), 1);
+        let state = scheduler
+            .state_manager
+            .get_command_state("sync")
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.last_status, Some(1));
+        assert_eq!(state.consecutive_failures, 0);
+        let history = scheduler.state_manager.get_history("sync", 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(!history[0].failed());
+        assert!(scheduler
+            .state_manager
+            .query_history("sync", 10, true)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_loop_runs_only_due_commands_with_recording_executor() {
+        let recorder = RecordingExecutor::new().with_status("report", 3);
+        let mut due = create_test_command("report", 60.0);
+        due.immediate = true;
+        let later = create_test_command("backup", 60.0);
+
+        let mut scheduler = Scheduler::new_with_executor(
+            vec![due, later],
+            create_temp_state_path(),
+            Box::new(recorder.clone()),
+        )
+        .unwrap();
+        use_mock_clock(&mut scheduler);
+        scheduler
+            .run_until(sleep(StdDuration::from_millis(300)))
+            .await;
+
+        assert_eq!(recorder.invocations(), vec!["report".to_string()]);
+        assert_eq!(recorder.count("backup"), 0);
+        let state = scheduler
+            .state_manager
+            .get_command_state("report")
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.last_status, Some(3));
+    }
+
+    fn create_dependent_command(name: &str, depends_on: &[&str]) -> CommandConfig {
         CommandConfig {
             name: name.to_string(),
-            command: "echo test".to_string(),
-            interval_minutes: None,
-            cron: Some(cron.to_string()),
-            max_runtime_minutes: Some(5),
-            enabled: true,
-            working_dir: None,
-            environment: None,
-            immediate: false,
+            command: format!("echo {}", name),
+            depends_on: Some(depends_on.iter().map(|dep| dep.to_string()).collect()),
+            ..Default::default()
         }
     }
 
-    fn create_temp_state_path() -> PathBuf {
-        let temp_file = NamedTempFile::new().unwrap();
-        temp_file.path().to_path_buf()
+    /// Starts `name` and handles completions until nothing is running anymore
+    async fn run_dependency_graph(scheduler: &mut Scheduler, name: &str) {
+        assert!(scheduler.trigger(name).await.ok);
+        while !scheduler.running.is_empty() {
+            let completed = scheduler.completions_rx.recv().await.unwrap();
+            scheduler.handle_completion(completed).await;
+        }
     }
 
     #[tokio::test]
-    async fn test_scheduler_initialization() {
-        let commands = vec![
-            create_test_command("test1", 1.0),
-            create_test_command("test2", 2.0),
-        ];
-        let scheduler = Scheduler::new(commands.clone(), create_temp_state_path()).unwrap();
+    async fn test_dependency_chain_runs_in_order() {
+        let executor = RecordingExecutor::new();
+        let mut scheduler = SchedulerBuilder::new(vec![
+            create_dependent_command("report", &["process"]),
+            create_test_command("fetch", 60.0),
+            create_dependent_command("process", &["fetch"]),
+            create_dependent_command("upload", &["report"]),
+        ])
+        .with_executor(Box::new(executor.clone()))
+        .with_state_path(create_temp_state_path())
+        .build()
+        .unwrap();
+        // Only the command with a schedule of its own is queued
+        assert_eq!(scheduled_names(&scheduler), vec!["fetch".to_string()]);
 
-        assert_eq!(scheduler.commands.len(), 2);
-        assert!(scheduler.last_execution_time.is_none());
+        run_dependency_graph(&mut scheduler, "fetch").await;
+
+        assert_eq!(
+            executor.invocations(),
+            vec!["fetch", "process", "report", "upload"]
+        );
+        assert_eq!(scheduled_names(&scheduler), vec!["fetch".to_string()]);
+        let statuses = scheduler.statuses().await.unwrap();
+        let upload = statuses.iter().find(|s| s.name == "upload").unwrap();
+        assert!(upload.last_execution.is_some());
+        assert_eq!(upload.next_run, None);
     }
 
     #[tokio::test]
-    async fn test_cron_scheduler_initialization() {
-        let commands = vec![
-            create_test_cron_command("test1", "0 0 * * * *"), // Every hour
-            create_test_cron_command("test2", "0 0 0 * * *"), // Daily at midnight
-        ];
-        let scheduler = Scheduler::new(commands.clone(), create_temp_state_path()).unwrap();
+    async fn test_diamond_dependency_waits_for_every_dependency() {
+        let executor = RecordingExecutor::new();
+        let mut scheduler = SchedulerBuilder::new(vec![
+            create_test_command("fetch", 60.0),
+            create_dependent_command("left", &["fetch"]),
+            create_dependent_command("right", &["fetch"]),
+            create_dependent_command("merge", &["left", "right"]),
+        ])
+        .with_executor(Box::new(executor.clone()))
+        .with_state_path(create_temp_state_path())
+        .build()
+        .unwrap();
 
-        assert_eq!(scheduler.commands.len(), 2);
-        assert!(scheduler.last_execution_time.is_none());
+        run_dependency_graph(&mut scheduler, "fetch").await;
+        assert_eq!(executor.count("left"), 1);
+        assert_eq!(executor.count("right"), 1);
+        assert_eq!(executor.count("merge"), 1);
+        assert_eq!(executor.invocations().last().unwrap(), "merge");
+        assert!(scheduler.dependency_progress.is_empty());
+
+        run_dependency_graph(&mut scheduler, "fetch").await;
+        assert_eq!(executor.count("merge"), 2);
     }
 
     #[tokio::test]
-    async fn test_schedule_next_run() {
-        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
-        let command = create_test_command("test", 1.0);
+    async fn test_failed_dependency_skips_dependents() {
+        let executor = RecordingExecutor::new().with_status("process", 1);
+        let mut scheduler = SchedulerBuilder::new(vec![
+            create_test_command("fetch", 60.0),
+            create_dependent_command("process", &["fetch"]),
+            create_dependent_command("report", &["process"]),
+            create_dependent_command("upload", &["report"]),
+        ])
+        .with_executor(Box::new(executor.clone()))
+        .with_state_path(create_temp_state_path())
+        .build()
+        .unwrap();
 
-        scheduler.schedule_next_run(command.clone()).unwrap();
-        assert_eq!(scheduler.commands.len(), 1);
+        run_dependency_graph(&mut scheduler, "fetch").await;
 
-        let scheduled = scheduler.commands.peek().unwrap();
-        assert_eq!(scheduled.command.name, "test");
-        assert!(scheduled.next_run > Utc::now());
+        assert_eq!(executor.invocations(), vec!["fetch", "process"]);
+        let history = scheduler.state_manager.get_history("report", 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(history[0].skipped);
+        assert!(!history[0].failed());
+        assert_eq!(
+            history[0].stderr,
+            "Skipped because dependency 'process' failed"
+        );
+        let history = scheduler.state_manager.get_history("upload", 10).unwrap();
+        assert_eq!(
+            history[0].stderr,
+            "Skipped because dependency 'report' failed"
+        );
+        // Skipped runs don't count as failures of the skipped command
+        assert!(scheduler
+            .state_manager
+            .get_command_state("report")
+            .unwrap()
+            .is_none());
     }
 
     #[tokio::test]
-    async fn test_cron_schedule_next_run() {
-        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
-        let command = create_test_cron_command("test", "0 0 * * * *"); // Every hour
+    async fn test_dependent_is_retried_before_its_dependents_are_skipped() {
+        let executor = RecordingExecutor::new().with_status("process", 1);
+        let mut process = create_dependent_command("process", &["fetch"]);
+        process.max_retries = Some(1);
+        process.retry_delay_seconds = Some(0);
+        let mut scheduler = SchedulerBuilder::new(vec![
+            create_test_command("fetch", 60.0),
+            process,
+            create_dependent_command("report", &["process"]),
+        ])
+        .with_executor(Box::new(executor.clone()))
+        .with_state_path(create_temp_state_path())
+        .build()
+        .unwrap();
 
-        scheduler.schedule_next_run(command.clone()).unwrap();
-        assert_eq!(scheduler.commands.len(), 1);
+        run_dependency_graph(&mut scheduler, "fetch").await;
+        assert_eq!(executor.count("process"), 1);
+        assert!(scheduler
+            .state_manager
+            .get_history("report", 10)
+            .unwrap()
+            .is_empty());
 
-        let scheduled = scheduler.commands.peek().unwrap();
-        assert_eq!(scheduled.command.name, "test");
-        assert!(scheduled.next_run > Utc::now());
+        // The retry is queued like any other run; once it fails too, report is skipped
+        let retry = scheduler.commands.pop().unwrap();
+        assert_eq!(retry.command.name, "process");
+        scheduler.dispatch_command(retry).await;
+        let completed = scheduler.completions_rx.recv().await.unwrap();
+        scheduler.handle_completion(completed).await;
+        assert_eq!(executor.count("process"), 2);
+        assert_eq!(executor.count("report"), 0);
+        let history = scheduler.state_manager.get_history("report", 10).unwrap();
+        assert!(history[0].skipped);
+        assert_eq!(scheduled_names(&scheduler), vec!["fetch".to_string()]);
+    }
 
-        let time_str = scheduled.next_run.format("%H:%M:%S").to_string();
-        assert_eq!(time_str.split(':').nth(1).unwrap(), "00");
-        assert_eq!(time_str.split(':').nth(2).unwrap(), "00");
+    /// Starts the due run of the named command as the loop would, and handles its
+    /// completion if it was started
+    async fn start_scheduled(scheduler: &mut Scheduler, name: &str) {
+        let mut scheduled = std::mem::take(&mut scheduler.commands).into_vec();
+        let index = scheduled
+            .iter()
+            .position(|scheduled| scheduled.command.name == name)
+            .unwrap();
+        let due = scheduled.swap_remove(index);
+        scheduler.commands = scheduled.into();
+        scheduler.start_due(due).await;
+        if !scheduler.running.is_empty() {
+            let completed = scheduler.completions_rx.recv().await.unwrap();
+            scheduler.handle_completion(completed).await;
+        }
     }
 
-    #[tokio::test]
-    async fn test_command_ordering() {
-        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
-        let command1 = create_test_command("test1", 1.0);
-        let command2 = create_test_command("test2", 2.0);
+    fn skipped_runs(scheduler: &Scheduler, name: &str) -> usize {
+        scheduler
+            .state_manager
+            .get_history(name, 10)
+            .unwrap()
+            .iter()
+            .filter(|record| record.skipped)
+            .count()
+    }
 
-        scheduler.schedule_next_run(command1).unwrap();
-        scheduler.schedule_next_run(command2).unwrap();
+    #[tokio::test]
+    async fn test_scheduled_dependent_skips_runs_until_dependency_succeeds() {
+        let executor = RecordingExecutor::new();
+        let sync = create_test_command("sync", 60.0);
+        let mut report = create_test_command("report", 60.0);
+        report.depends_on = Some(vec!["sync".to_string()]);
+        let mut scheduler = SchedulerBuilder::new(vec![sync.clone(), report])
+            .with_executor(Box::new(executor.clone()))
+            .with_state_path(create_temp_state_path())
+            .build()
+            .unwrap();
+        assert_eq!(scheduled_names(&scheduler), vec!["report", "sync"]);
 
-        let first = scheduler.commands.pop().unwrap();
-        let second = scheduler.commands.pop().unwrap();
+        // sync has never run, so report skips this run and waits for its next one
+        start_scheduled(&mut scheduler, "report").await;
+        assert_eq!(executor.count("report"), 0);
+        let history = scheduler.state_manager.get_history("report", 10).unwrap();
+        assert!(history[0].skipped);
+        assert_eq!(
+            history[0].stderr,
+            "Skipped because dependency 'sync' has not succeeded since the last run"
+        );
+        let next = scheduler
+            .commands
+            .iter()
+            .find(|scheduled| scheduled.command.name == "report")
+            .unwrap();
+        assert!(next.next_run > Utc::now() + Duration::minutes(59));
 
-        assert!(first.next_run < second.next_run);
+        // A failed run of sync doesn't satisfy it either
+        scheduler
+            .state_manager
+            .save_last_run(&sync, Utc::now(), Some(1), 10)
+            .unwrap();
+        start_scheduled(&mut scheduler, "report").await;
+        assert_eq!(executor.count("report"), 0);
+        assert_eq!(skipped_runs(&scheduler, "report"), 2);
     }
 
     #[tokio::test]
-    async fn test_cron_command_ordering() {
-        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
-        let command1 = create_test_cron_command("test1", "0 0 * * * *"); // Every hour
-        let command2 = create_test_cron_command("test2", "0 0 0 * * *"); // Daily at midnight
+    async fn test_scheduled_dependent_runs_once_per_dependency_success() {
+        let executor = RecordingExecutor::new();
+        let mut report = create_test_command("report", 60.0);
+        report.depends_on = Some(vec!["sync".to_string()]);
+        let mut scheduler = SchedulerBuilder::new(vec![create_test_command("sync", 60.0), report])
+            .with_executor(Box::new(executor.clone()))
+            .with_state_path(create_temp_state_path())
+            .build()
+            .unwrap();
 
-        scheduler.schedule_next_run(command1).unwrap();
-        scheduler.schedule_next_run(command2).unwrap();
+        // report isn't started by sync finishing, only by its own schedule
+        start_scheduled(&mut scheduler, "sync").await;
+        assert_eq!(executor.invocations(), vec!["sync"]);
+        start_scheduled(&mut scheduler, "report").await;
+        assert_eq!(executor.invocations(), vec!["sync", "report"]);
+        assert_eq!(skipped_runs(&scheduler, "report"), 0);
 
-        let first = scheduler.commands.pop().unwrap();
-        let second = scheduler.commands.pop().unwrap();
+        // Its last run is newer than the last run of sync, until sync succeeds again
+        start_scheduled(&mut scheduler, "report").await;
+        assert_eq!(executor.count("report"), 1);
+        assert_eq!(skipped_runs(&scheduler, "report"), 1);
+        start_scheduled(&mut scheduler, "sync").await;
+        start_scheduled(&mut scheduler, "report").await;
+        assert_eq!(executor.count("report"), 2);
+    }
 
-        assert!(first.next_run < second.next_run);
+    fn create_one_shot_command(name: &str, at: DateTime<Utc>) -> CommandConfig {
+        CommandConfig {
+            name: name.to_string(),
+            command: format!("echo {}", name),
+            at: Some(at.to_rfc3339()),
+            ..Default::default()
+        }
     }
 
-    #[tokio::test]
-    async fn test_mixed_command_ordering() {
-        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
-        let command1 = create_test_command("test1", 1.0);
-        let command2 = create_test_cron_command("test2", "0 0 * * * *"); // Every hour
+    #[tokio::test(start_paused = true)]
+    async fn test_past_one_shot_runs_once_right_away() {
+        let executor = RecordingExecutor::new();
+        let state_path = create_temp_state_path();
+        let migrate = create_one_shot_command("migrate", Utc::now() - Duration::hours(1));
+        let mut scheduler = SchedulerBuilder::new(vec![migrate.clone()])
+            .with_executor(Box::new(executor.clone()))
+            .with_state_path(&state_path)
+            .with_clock(Box::new(MockClock::new(Utc::now())))
+            .build()
+            .unwrap();
+        assert!(scheduler.commands.peek().unwrap().next_run <= Utc::now());
 
-        scheduler.schedule_next_run(command1).unwrap();
-        scheduler.schedule_next_run(command2).unwrap();
+        scheduler
+            .run_until(sleep(StdDuration::from_millis(300)))
+            .await;
+        assert_eq!(executor.count("migrate"), 1);
+        assert!(scheduler.commands.is_empty());
+        let state = scheduler
+            .state_manager
+            .get_command_state("migrate")
+            .unwrap()
+            .unwrap();
+        assert!(state.is_complete(migrate.schedule().unwrap().as_ref()));
+        let statuses = scheduler.statuses().await.unwrap();
+        assert!(statuses[0].completed);
+        assert_eq!(statuses[0].next_run, None);
 
-        let first = scheduler.commands.pop().unwrap();
-        let second = scheduler.commands.pop().unwrap();
+        // A restart doesn't run it again, but moving `at` later does
+        drop(scheduler);
+        let scheduler = SchedulerBuilder::new(vec![migrate])
+            .with_executor(Box::new(executor.clone()))
+            .with_state_path(&state_path)
+            .build()
+            .unwrap();
+        assert!(scheduler.commands.is_empty());
+        drop(scheduler);
+        let moved = create_one_shot_command("migrate", Utc::now() + Duration::hours(1));
+        let scheduler = SchedulerBuilder::new(vec![moved])
+            .with_executor(Box::new(executor.clone()))
+            .with_state_path(&state_path)
+            .build()
+            .unwrap();
+        assert_eq!(scheduled_names(&scheduler), vec!["migrate"]);
+    }
 
-        assert!(first.next_run < second.next_run);
+    #[tokio::test(start_paused = true)]
+    async fn test_future_one_shot_runs_once_then_leaves_the_queue() {
+        let executor = RecordingExecutor::new();
+        let at = Utc::now() + Duration::milliseconds(500);
+        let mut scheduler = SchedulerBuilder::new(vec![
+            create_one_shot_command("migrate", at),
+            create_test_command("backup", 60.0),
+        ])
+        .with_executor(Box::new(executor.clone()))
+        .with_state_path(create_temp_state_path())
+        .with_clock(Box::new(MockClock::new(Utc::now())))
+        .build()
+        .unwrap();
+        assert_eq!(scheduled_names(&scheduler), vec!["backup", "migrate"]);
+        let scheduled = scheduler
+            .commands
+            .iter()
+            .find(|scheduled| scheduled.command.name == "migrate")
+            .unwrap();
+        assert_eq!(scheduled.next_run, at);
+
+        scheduler.run_until(sleep(StdDuration::from_secs(2))).await;
+        assert_eq!(executor.invocations(), vec!["migrate"]);
+        assert_eq!(scheduled_names(&scheduler), vec!["backup"]);
     }
 
     #[tokio::test]
-    async fn test_disabled_commands() {
-        let mut commands = vec![
-            create_test_command("enabled", 1.0),
-            create_test_command("disabled", 1.0),
-        ];
-        commands[1].enabled = false;
+    async fn test_missed_one_shot_follows_catch_up_policy() {
+        let executor = RecordingExecutor::new();
+        let state_path = create_temp_state_path();
+        let missed = CommandConfig {
+            catch_up: CatchUpPolicy::Skip,
+            ..create_one_shot_command("migrate", Utc::now() - Duration::hours(1))
+        };
+        let scheduler = SchedulerBuilder::new(vec![missed.clone()])
+            .with_executor(Box::new(executor.clone()))
+            .with_state_path(&state_path)
+            .build()
+            .unwrap();
+        assert!(scheduler.commands.is_empty());
+        let state = scheduler
+            .state_manager
+            .get_command_state("migrate")
+            .unwrap()
+            .unwrap();
+        assert!(state.is_complete(missed.schedule().unwrap().as_ref()));
+        assert_eq!(executor.count("migrate"), 0);
 
-        let scheduler = Scheduler::new(commands, create_temp_state_path()).unwrap();
-        assert_eq!(scheduler.commands.len(), 1);
-        assert_eq!(scheduler.commands.peek().unwrap().command.name, "enabled");
+        // Once its time is moved later it is scheduled as usual
+        drop(scheduler);
+        let moved = CommandConfig {
+            catch_up: CatchUpPolicy::Skip,
+            ..create_one_shot_command("migrate", Utc::now() + Duration::hours(1))
+        };
+        let scheduler = SchedulerBuilder::new(vec![moved])
+            .with_state_path(&state_path)
+            .build()
+            .unwrap();
+        assert_eq!(scheduled_names(&scheduler), vec!["migrate"]);
+
+        // A missed one-shot added by a reload is skipped the same way
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        scheduler.add_reloaded_command(missed).await;
+        assert!(scheduler.commands.is_empty());
+        let state = scheduler
+            .state_manager
+            .get_command_state("migrate")
+            .unwrap();
+        assert!(state.unwrap().completed_at.is_some());
+
+        // Other policies run it right away
+        let run_once = CommandConfig {
+            catch_up: CatchUpPolicy::RunOnce,
+            ..create_one_shot_command("migrate", Utc::now() - Duration::hours(1))
+        };
+        let scheduler = SchedulerBuilder::new(vec![run_once])
+            .with_state_path(create_temp_state_path())
+            .build()
+            .unwrap();
+        assert!(scheduler.commands.peek().unwrap().next_run <= Utc::now());
+    }
+
+    /// Waits up to five seconds for a file to be written
+    #[cfg(unix)]
+    async fn wait_for_file(path: &std::path::Path) -> String {
+        for _ in 0..100 {
+            if let Ok(contents) = std::fs::read_to_string(path) {
+                if contents.ends_with('\n') {
+                    return contents;
+                }
+            }
+            sleep(StdDuration::from_millis(50)).await;
+        }
+        panic!("{:?} was never written", path);
     }
 
+    #[cfg(unix)]
     #[tokio::test]
-    async fn test_immediate_execution() {
-        let mut commands = vec![
-            create_test_command("normal", 1.0),
-            create_test_command("immediate", 1.0),
-        ];
-        commands[1].immediate = true;
+    async fn test_hooks_get_run_details_in_their_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut scheduler = Scheduler::new(vec![], create_temp_state_path()).unwrap();
+        let hook = "printenv ZEPHYR_COMMAND ZEPHYR_EXIT_CODE ZEPHYR_DURATION_MS TARGET";
+        let command = |name: &str, status: i32| CommandConfig {
+            name: name.to_string(),
+            command: format!("exit {}", status),
+            interval_minutes: Some(60.0),
+            working_dir: Some(dir.path().to_path_buf()),
+            environment: Some(vec![("TARGET".to_string(), name.to_string())]),
+            on_success: Some(format!("{} > success.txt", hook)),
+            on_failure: Some(format!("{} > failure.txt", hook)),
+            ..Default::default()
+        };
 
-        let scheduler = Scheduler::new(commands, create_temp_state_path()).unwrap();
-        assert_eq!(scheduler.commands.len(), 2);
+        for command in [command("backup", 3), command("report", 0)] {
+            scheduler
+                .dispatch_command(ScheduledCommand {
+                    command: parsed(command),
+                    next_run: Utc::now(),
+                })
+                .await;
+            let completed = scheduler.completions_rx.recv().await.unwrap();
+            scheduler.handle_completion(completed).await;
+        }
+
+        let failure = wait_for_file(&dir.path().join("failure.txt")).await;
+        let lines = failure.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "backup");
+        assert_eq!(lines[1], "3");
+        assert!(lines[2].parse::<i64>().unwrap() >= 0);
+        assert_eq!(lines[3], "backup");
+        let success = wait_for_file(&dir.path().join("success.txt")).await;
+        assert!(success.starts_with("report\n0\n"));
+
+        // Hooks leave no trace in the state database
+        let states = scheduler.state_manager.load_command_states().unwrap();
+        let mut names = states
+            .iter()
+            .map(|state| state.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["backup", "report"]);
+        assert!(scheduler
+            .state_manager
+            .get_history("backup (on_failure)", 10)
+            .unwrap()
+            .is_empty());
     }
 }
@@ -0,0 +1,201 @@
+//! Readiness, status and watchdog notifications for systemd (`sd_notify`)
+//!
+//! Everything here is a no-op unless systemd started the process with `NOTIFY_SOCKET` set,
+//! so running in the foreground or under another service manager is unaffected.
+
+use std::ffi::OsStr;
+use std::time::Duration as StdDuration;
+#[cfg(unix)]
+use std::{ffi::OsString, os::unix::net::UnixDatagram};
+#[cfg(unix)]
+use tracing::debug;
+
+/// Connection to the systemd notification socket
+#[derive(Default)]
+pub(crate) struct SdNotify {
+    #[cfg(unix)]
+    socket: Option<(UnixDatagram, OsString)>,
+    watchdog_interval: Option<StdDuration>,
+    last_status: Option<String>,
+}
+
+impl SdNotify {
+    /// Connects to the socket systemd passed in `NOTIFY_SOCKET`, if any
+    pub(crate) fn from_env() -> Self {
+        Self::new(
+            std::env::var_os("NOTIFY_SOCKET").as_deref(),
+            std::env::var("WATCHDOG_USEC").ok().as_deref(),
+            std::env::var("WATCHDOG_PID").ok().as_deref(),
+        )
+    }
+
+    /// Connects to `socket_path`; `watchdog_usec` and `watchdog_pid` are the values of the
+    /// variables systemd sets when the unit has `WatchdogSec=`
+    pub(crate) fn new(
+        socket_path: Option<&OsStr>,
+        watchdog_usec: Option<&str>,
+        watchdog_pid: Option<&str>,
+    ) -> Self {
+        let Some(socket_path) = socket_path.filter(|path| !path.is_empty()) else {
+            return Self::default();
+        };
+        #[cfg(unix)]
+        {
+            let socket = match UnixDatagram::unbound() {
+                Ok(socket) => socket,
+                Err(e) => {
+                    tracing::warn!("Failed to create systemd notification socket: {}", e);
+                    return Self::default();
+                }
+            };
+            Self {
+                socket: Some((socket, socket_path.to_os_string())),
+                watchdog_interval: watchdog_interval(watchdog_usec, watchdog_pid),
+                last_status: None,
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (socket_path, watchdog_usec, watchdog_pid);
+            Self::default()
+        }
+    }
+
+    /// How often the watchdog has to be pinged: half the interval systemd asked for, or
+    /// `None` when the unit has no watchdog
+    pub(crate) fn watchdog_interval(&self) -> Option<StdDuration> {
+        self.watchdog_interval
+    }
+
+    /// Tells systemd the scheduler has started
+    pub(crate) fn ready(&self) {
+        self.send("READY=1");
+    }
+
+    /// Tells systemd the scheduler is shutting down
+    pub(crate) fn stopping(&self) {
+        self.send("STOPPING=1");
+    }
+
+    /// Updates the status line shown by `systemctl status`, if it changed
+    pub(crate) fn status(&mut self, status: &str) {
+        if self.last_status.as_deref() == Some(status) {
+            return;
+        }
+        self.send(&format!("STATUS={}", status));
+        self.last_status = Some(status.to_string());
+    }
+
+    /// Tells systemd the scheduler loop is still alive
+    pub(crate) fn watchdog(&self) {
+        if self.watchdog_interval.is_some() {
+            self.send("WATCHDOG=1");
+        }
+    }
+
+    #[cfg(unix)]
+    fn send(&self, message: &str) {
+        let Some((socket, path)) = &self.socket else {
+            return;
+        };
+        if let Err(e) = send_to(socket, path, message.as_bytes()) {
+            debug!("Failed to notify systemd ({}): {}", message, e);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn send(&self, _message: &str) {}
+}
+
+/// Sends to a socket path, where a leading `@` means Linux's abstract namespace
+#[cfg(unix)]
+fn send_to(socket: &UnixDatagram, path: &OsStr, message: &[u8]) -> std::io::Result<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::net::SocketAddr;
+
+        if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+            return socket.send_to_addr(message, &SocketAddr::from_abstract_name(name)?);
+        }
+    }
+    socket.send_to(message, path)
+}
+
+/// Returns half of `WATCHDOG_USEC`, unless the watchdog is meant for another process
+#[cfg(unix)]
+fn watchdog_interval(
+    watchdog_usec: Option<&str>,
+    watchdog_pid: Option<&str>,
+) -> Option<StdDuration> {
+    if let Some(pid) = watchdog_pid {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    let usec = watchdog_usec?
+        .parse::<u64>()
+        .ok()
+        .filter(|usec| *usec > 0)?;
+    Some(StdDuration::from_micros(usec / 2))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(
+            watchdog_interval(Some("60000000"), None),
+            Some(StdDuration::from_secs(30))
+        );
+        let pid = std::process::id().to_string();
+        assert_eq!(
+            watchdog_interval(Some("10000000"), Some(&pid)),
+            Some(StdDuration::from_secs(5))
+        );
+        assert_eq!(watchdog_interval(Some("10000000"), Some("1")), None);
+        assert_eq!(watchdog_interval(Some("0"), None), None);
+        assert_eq!(watchdog_interval(None, None), None);
+    }
+
+    #[test]
+    fn test_without_socket_is_noop() {
+        let notify = SdNotify::new(None, Some("60000000"), None);
+        assert_eq!(notify.watchdog_interval(), None);
+        notify.ready();
+        notify.watchdog();
+    }
+
+    #[test]
+    fn test_sends_notifications() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        receiver
+            .set_read_timeout(Some(StdDuration::from_secs(1)))
+            .unwrap();
+        let recv = || {
+            let mut buffer = [0; 256];
+            let len = receiver.recv(&mut buffer).unwrap();
+            String::from_utf8_lossy(&buffer[..len]).to_string()
+        };
+
+        let mut notify = SdNotify::new(Some(path.as_os_str()), Some("4000000"), None);
+        assert_eq!(notify.watchdog_interval(), Some(StdDuration::from_secs(2)));
+        notify.ready();
+        notify.status("Next: backup");
+        notify.status("Next: backup");
+        notify.status("Next: cleanup");
+        notify.watchdog();
+        notify.stopping();
+
+        assert_eq!(recv(), "READY=1");
+        assert_eq!(recv(), "STATUS=Next: backup");
+        assert_eq!(recv(), "STATUS=Next: cleanup");
+        assert_eq!(recv(), "WATCHDOG=1");
+        assert_eq!(recv(), "STOPPING=1");
+    }
+}
@@ -0,0 +1,82 @@
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+#[cfg(unix)]
+use tracing::warn;
+
+/// A process signal the scheduler loop reacts to
+pub(crate) enum SignalEvent {
+    /// SIGHUP, which requests a configuration reload
+    Reload,
+    /// SIGTERM or SIGINT (Ctrl-C), which request a graceful shutdown; holds the signal name
+    Shutdown(&'static str),
+    /// SIGUSR1, which pauses every command
+    Pause,
+    /// SIGUSR2, which undoes a pause of every command
+    Resume,
+}
+
+/// Process signals the scheduler loop reacts to
+///
+/// On platforms without Unix signals only Ctrl-C is delivered.
+pub(crate) struct Signals {
+    #[cfg(unix)]
+    hangup: Option<Signal>,
+    #[cfg(unix)]
+    terminate: Option<Signal>,
+    #[cfg(unix)]
+    user1: Option<Signal>,
+    #[cfg(unix)]
+    user2: Option<Signal>,
+}
+
+impl Signals {
+    /// Installs the signal handlers; must be called from within a tokio runtime
+    pub(crate) fn new() -> Self {
+        Self {
+            #[cfg(unix)]
+            hangup: install(SignalKind::hangup(), "SIGHUP"),
+            #[cfg(unix)]
+            terminate: install(SignalKind::terminate(), "SIGTERM"),
+            #[cfg(unix)]
+            user1: install(SignalKind::user_defined1(), "SIGUSR1"),
+            #[cfg(unix)]
+            user2: install(SignalKind::user_defined2(), "SIGUSR2"),
+        }
+    }
+
+    /// Waits for the next signal
+    pub(crate) async fn recv(&mut self) -> SignalEvent {
+        #[cfg(unix)]
+        let (hangup, terminate) = (wait(&mut self.hangup), wait(&mut self.terminate));
+        #[cfg(unix)]
+        let (user1, user2) = (wait(&mut self.user1), wait(&mut self.user2));
+        #[cfg(not(unix))]
+        let (hangup, terminate) = (std::future::pending::<()>(), std::future::pending::<()>());
+        #[cfg(not(unix))]
+        let (user1, user2) = (std::future::pending::<()>(), std::future::pending::<()>());
+        tokio::select! {
+            _ = hangup => SignalEvent::Reload,
+            _ = terminate => SignalEvent::Shutdown("SIGTERM"),
+            _ = user1 => SignalEvent::Pause,
+            _ = user2 => SignalEvent::Resume,
+            Ok(()) = tokio::signal::ctrl_c() => SignalEvent::Shutdown("SIGINT"),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn install(kind: SignalKind, name: &str) -> Option<Signal> {
+    signal(kind)
+        .map_err(|e| warn!("Failed to install {} handler: {}", name, e))
+        .ok()
+}
+
+#[cfg(unix)]
+async fn wait(signal: &mut Option<Signal>) {
+    match signal {
+        Some(signal) => {
+            signal.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,289 @@
+//! Dead man's switch for commands with `max_staleness_minutes`
+//!
+//! The check runs on its own task from snapshots the scheduler loop publishes, so commands
+//! are still reported when the loop itself has stopped making progress.
+
+use crate::config::CommandConfig;
+use crate::core::desktop::DesktopNotifier;
+use crate::core::status::CommandStatus;
+use crate::core::webhook::{self, FailureEvent, FailurePayload};
+use crate::util::format_duration;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::time::Duration as StdDuration;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+/// How often commands are checked when the scheduler publishes nothing new
+pub(crate) const CHECK_INTERVAL: StdDuration = StdDuration::from_secs(60);
+
+/// A command with `max_staleness_minutes` as the scheduler last saw it
+#[derive(Debug, Clone)]
+pub(crate) struct StalenessCheck {
+    pub(crate) command: CommandConfig,
+    pub(crate) last_execution: Option<DateTime<Utc>>,
+    /// Whether the command is expected to run: enabled, not paused, not disabled after
+    /// failures, not a one-shot command that already ran and not running right now
+    pub(crate) active: bool,
+}
+
+impl StalenessCheck {
+    /// Returns the checks for the commands among `statuses` that have a staleness limit
+    pub(crate) fn from_statuses(
+        commands: &[CommandConfig],
+        statuses: &[CommandStatus],
+    ) -> Vec<Self> {
+        commands
+            .iter()
+            .filter(|command| command.max_staleness_minutes.is_some())
+            .filter_map(|command| {
+                let status = statuses.iter().find(|status| status.name == command.name)?;
+                Some(Self {
+                    command: command.clone(),
+                    last_execution: status.last_execution,
+                    active: status.enabled
+                        && !status.auto_disabled
+                        && !status.completed
+                        && !status.paused
+                        && !status.running,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Checks the commands published on `checks` every `interval` and whenever they change,
+/// until the scheduler drops the sender
+pub(crate) async fn watch(mut checks: watch::Receiver<Vec<StalenessCheck>>, interval: StdDuration) {
+    let mut watchdog = Watchdog::default();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            changed = checks.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+        }
+        let overdue = watchdog.check(&checks.borrow_and_update(), Utc::now());
+        for overdue in overdue {
+            watchdog.report(overdue);
+        }
+    }
+}
+
+/// A command that went longer than its `max_staleness_minutes` without running
+#[derive(Debug)]
+struct Overdue {
+    command: CommandConfig,
+    /// How long the command has gone without running
+    stale_for: Duration,
+    at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct Watchdog {
+    /// When each command that has never run was first checked
+    first_seen: HashMap<String, DateTime<Utc>>,
+    /// When each command was last seen not expected to run, which restarts its allowance
+    last_inactive: HashMap<String, DateTime<Utc>>,
+    /// Commands reported as overdue, with the last run they were overdue since
+    reported: HashMap<String, Option<DateTime<Utc>>>,
+    desktop: DesktopNotifier,
+}
+
+impl Watchdog {
+    /// Returns the commands that became overdue since the last check
+    ///
+    /// A command is reported once per overdue period; running again clears it.
+    fn check(&mut self, checks: &[StalenessCheck], now: DateTime<Utc>) -> Vec<Overdue> {
+        let mut overdue = Vec::new();
+        for check in checks {
+            let name = &check.command.name;
+            let Some(max_staleness) = check.command.max_staleness_minutes else {
+                continue;
+            };
+            if let Some(reported) = self.reported.get(name) {
+                if *reported == check.last_execution {
+                    continue;
+                }
+                info!("Command '{}' ran again and is no longer overdue", name);
+                self.reported.remove(name);
+            }
+            if !check.active {
+                self.last_inactive.insert(name.clone(), now);
+                continue;
+            }
+            let last_run = match check.last_execution {
+                Some(last_execution) => last_execution,
+                None => *self.first_seen.entry(name.clone()).or_insert(now),
+            };
+            let since = match self.last_inactive.get(name) {
+                Some(inactive) => last_run.max(*inactive),
+                None => last_run,
+            };
+            let stale_for = now - since;
+            if stale_for > Duration::minutes(max_staleness as i64) {
+                self.reported.insert(name.clone(), check.last_execution);
+                overdue.push(Overdue {
+                    command: check.command.clone(),
+                    stale_for,
+                    at: now,
+                });
+            }
+        }
+        overdue
+    }
+
+    /// Logs an overdue command and sends its notifications
+    fn report(&mut self, overdue: Overdue) {
+        let Overdue {
+            command,
+            stale_for,
+            at,
+        } = overdue;
+        let reason = format!("Has not run for {}", format_duration(stale_for));
+        error!(
+            command = %command.name,
+            event = "overdue",
+            "Command '{}' is overdue: it has not run for {} (max_staleness_minutes is {})",
+            command.name,
+            format_duration(stale_for),
+            command.max_staleness_minutes.unwrap_or_default()
+        );
+        if command.notify_on_failure == Some(true) {
+            self.desktop.notify_overdue(&command.name, &reason);
+        }
+        if let Some(url) = command.on_failure_webhook {
+            webhook::notify_failure(
+                url,
+                FailurePayload {
+                    name: command.name,
+                    event: FailureEvent::Overdue,
+                    exit_status: None,
+                    stderr: reason,
+                    timestamp: at,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    fn check(last_execution: Option<DateTime<Utc>>, active: bool) -> StalenessCheck {
+        StalenessCheck {
+            command: CommandConfig {
+                name: "backup".to_string(),
+                command: "true".to_string(),
+                interval_minutes: Some(60.0),
+                max_staleness_minutes: Some(90),
+                ..Default::default()
+            },
+            last_execution,
+            active,
+        }
+    }
+
+    fn names(overdue: Vec<Overdue>) -> Vec<String> {
+        overdue.into_iter().map(|o| o.command.name).collect()
+    }
+
+    #[test]
+    fn test_reports_once_per_overdue_period() {
+        let mut watchdog = Watchdog::default();
+        let now = Utc::now();
+        let last_run = now - Duration::minutes(100);
+
+        assert_eq!(
+            names(watchdog.check(&[check(Some(last_run), true)], now)),
+            ["backup"]
+        );
+        let later = now + Duration::minutes(30);
+        assert!(watchdog
+            .check(&[check(Some(last_run), true)], later)
+            .is_empty());
+
+        // Running again clears the alert, and the allowance starts over
+        let ran = later + Duration::minutes(1);
+        assert!(watchdog.check(&[check(Some(ran), true)], ran).is_empty());
+        assert!(watchdog.reported.is_empty());
+        let overdue = watchdog.check(&[check(Some(ran), true)], ran + Duration::minutes(91));
+        assert_eq!(overdue[0].stale_for, Duration::minutes(91));
+    }
+
+    #[test]
+    fn test_inactive_commands_are_not_reported() {
+        let mut watchdog = Watchdog::default();
+        let now = Utc::now();
+        let last_run = Some(now - Duration::minutes(100));
+        assert!(watchdog.check(&[check(last_run, false)], now).is_empty());
+
+        // After a pause the command gets its full allowance again
+        let resumed = now + Duration::minutes(1);
+        assert!(watchdog.check(&[check(last_run, true)], resumed).is_empty());
+        assert_eq!(
+            names(watchdog.check(&[check(last_run, true)], now + Duration::minutes(91))),
+            ["backup"]
+        );
+    }
+
+    #[test]
+    fn test_never_run_command_counts_from_first_check() {
+        let mut watchdog = Watchdog::default();
+        let now = Utc::now();
+        assert!(watchdog.check(&[check(None, true)], now).is_empty());
+        assert!(watchdog
+            .check(&[check(None, true)], now + Duration::minutes(90))
+            .is_empty());
+        assert_eq!(
+            names(watchdog.check(&[check(None, true)], now + Duration::minutes(91))),
+            ["backup"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_overdue_command_posts_webhook_once() {
+        let (payloads_tx, mut payloads_rx) = mpsc::unbounded_channel();
+        let router = Router::new().route(
+            "/hook",
+            post(move |Json(payload): Json<serde_json::Value>| async move {
+                payloads_tx.send(payload).unwrap();
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let mut overdue = check(Some(Utc::now() - Duration::minutes(120)), true);
+        overdue.command.on_failure_webhook = Some(format!("http://{}/hook", addr));
+        let (checks_tx, checks_rx) = watch::channel(vec![overdue.clone()]);
+        let task = tokio::spawn(watch(checks_rx, StdDuration::from_millis(20)));
+
+        let payload = tokio::time::timeout(StdDuration::from_secs(5), payloads_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(payload["name"], "backup");
+        assert_eq!(payload["event"], "overdue");
+        assert_eq!(payload["exit_status"], serde_json::Value::Null);
+        assert_eq!(payload["stderr"], "Has not run for 2h");
+
+        checks_tx.send_replace(vec![overdue]);
+        tokio::time::sleep(StdDuration::from_millis(200)).await;
+        assert!(payloads_rx.try_recv().is_err());
+
+        drop(checks_tx);
+        tokio::time::timeout(StdDuration::from_secs(1), task)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
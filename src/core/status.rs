@@ -0,0 +1,214 @@
+use crate::config::{CommandConfig, ParsedCommand};
+use crate::core::scheduler::Scheduler;
+use crate::state::{CommandState, StateManager};
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A configured command joined with its persisted state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandStatus {
+    pub name: String,
+    pub enabled: bool,
+    /// What the command runs, as a shell command line
+    pub command: String,
+    pub schedule: String,
+    pub last_execution: Option<DateTime<Utc>>,
+    /// Exit status of the last run; `None` if it timed out, failed to start or never ran
+    pub last_status: Option<i32>,
+    /// When the command will run next; `None` for disabled commands, one-shot commands that
+    /// already ran and commands that run after their `depends_on` commands
+    pub next_run: Option<DateTime<Utc>>,
+    /// Whether the command stopped being scheduled after too many failures in a row
+    pub auto_disabled: bool,
+    /// Whether an execution of the command is in progress; only known to a running scheduler
+    pub running: bool,
+    /// Whether scheduled runs are skipped until the command is resumed; only known to a
+    /// running scheduler
+    #[serde(default)]
+    pub paused: bool,
+    /// Whether a one-shot command already had its run at its `at` time
+    #[serde(default)]
+    pub completed: bool,
+    /// Whether the next run is pushed back by `failure_backoff` after failed runs
+    #[serde(default)]
+    pub backing_off: bool,
+}
+
+impl CommandStatus {
+    /// Returns how long until the next run, negative if it is overdue
+    pub fn time_until_next(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.next_run.map(|next_run| next_run - now)
+    }
+}
+
+/// Returns the status of every configured command, in configuration order
+///
+/// Enabled commands without a stored next run are given the time they would be scheduled
+/// for if the scheduler started now.
+pub fn command_statuses(
+    commands: &[CommandConfig],
+    state_manager: &StateManager,
+) -> Result<Vec<CommandStatus>> {
+    statuses_from_states(commands, state_manager.load_command_states()?)
+}
+
+/// Returns the status of every configured command from states already loaded from the
+/// state database, in configuration order
+pub fn statuses_from_states(
+    commands: &[CommandConfig],
+    states: Vec<CommandState>,
+) -> Result<Vec<CommandStatus>> {
+    let mut states = states
+        .into_iter()
+        .map(|state| (state.name.clone(), state))
+        .collect::<HashMap<_, _>>();
+
+    commands
+        .iter()
+        .map(|command| {
+            let state = states.remove(&command.name);
+            let last_execution = state.as_ref().and_then(|state| state.last_execution);
+            let last_status = state.as_ref().and_then(|state| state.last_status);
+            let auto_disabled = command.enabled
+                && state
+                    .as_ref()
+                    .is_some_and(|state| command.exceeds_failure_limit(state.consecutive_failures));
+            let completed = match &state {
+                Some(state) if state.completed_at.is_some() => {
+                    state.is_complete(command.schedule()?.as_ref())
+                }
+                _ => false,
+            };
+            let backing_off = command.failure_backoff
+                && !command.runs_after_dependencies()
+                && state
+                    .as_ref()
+                    .is_some_and(|state| state.consecutive_failures > 0);
+            let next_run = match state {
+                _ if !command.enabled || auto_disabled || completed => None,
+                _ if command.runs_after_dependencies() => None,
+                Some(state) => Some(state.next_scheduled),
+                None => Some(Scheduler::calculate_next_run(
+                    &ParsedCommand::new(command.clone())?,
+                    Utc::now(),
+                )?),
+            };
+            Ok(CommandStatus {
+                name: command.name.clone(),
+                enabled: command.enabled,
+                command: command.command_line(),
+                schedule: command.schedule_description(),
+                last_execution,
+                last_status,
+                next_run,
+                auto_disabled,
+                running: false,
+                paused: false,
+                completed,
+                backing_off: backing_off && next_run.is_some(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_command_statuses_joins_config_and_state() {
+        let dir = tempdir().unwrap();
+        let state_manager = StateManager::new(dir.path().join("state.db")).unwrap();
+        let stored = CommandConfig {
+            name: "stored".to_string(),
+            interval_minutes: Some(10.0),
+            ..Default::default()
+        };
+        let fresh = CommandConfig {
+            name: "fresh".to_string(),
+            cron: Some("0 0 * * * *".to_string()),
+            ..Default::default()
+        };
+        let disabled = CommandConfig {
+            name: "disabled".to_string(),
+            interval_minutes: Some(10.0),
+            enabled: false,
+            ..Default::default()
+        };
+        let next_scheduled = Utc::now() + Duration::minutes(3);
+        state_manager
+            .save_next_scheduled(&stored, next_scheduled)
+            .unwrap();
+        state_manager
+            .save_next_scheduled(&disabled, next_scheduled)
+            .unwrap();
+
+        let statuses =
+            command_statuses(&[stored, fresh, disabled.clone()], &state_manager).unwrap();
+
+        let names = statuses.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["stored", "fresh", "disabled"]);
+        assert_eq!(
+            statuses[0].next_run.unwrap().timestamp(),
+            next_scheduled.timestamp()
+        );
+        assert!(statuses[1].next_run.unwrap() > Utc::now());
+        assert_eq!(statuses[1].schedule, "cron: 0 0 * * * *");
+        assert!(!statuses[0].auto_disabled);
+        assert!(!statuses[2].enabled);
+        assert_eq!(statuses[2].next_run, None);
+        assert_eq!(statuses[2].time_until_next(Utc::now()), None);
+    }
+
+    #[test]
+    fn test_command_statuses_flags_auto_disabled_commands() {
+        let dir = tempdir().unwrap();
+        let state_manager = StateManager::new(dir.path().join("state.db")).unwrap();
+        let command = CommandConfig {
+            name: "broken".to_string(),
+            interval_minutes: Some(10.0),
+            disable_after_failures: Some(1),
+            ..Default::default()
+        };
+        state_manager
+            .save_last_run(&command, Utc::now(), Some(1), 10)
+            .unwrap();
+
+        let statuses = command_statuses(&[command], &state_manager).unwrap();
+        assert!(statuses[0].enabled);
+        assert!(statuses[0].auto_disabled);
+        assert_eq!(statuses[0].last_status, Some(1));
+        assert_eq!(statuses[0].next_run, None);
+    }
+
+    #[test]
+    fn test_command_statuses_flags_commands_backing_off() {
+        let dir = tempdir().unwrap();
+        let state_manager = StateManager::new(dir.path().join("state.db")).unwrap();
+        let command = CommandConfig {
+            name: "flaky".to_string(),
+            interval_minutes: Some(10.0),
+            failure_backoff: true,
+            ..Default::default()
+        };
+        state_manager
+            .save_last_run(&command, Utc::now(), Some(1), 10)
+            .unwrap();
+        state_manager
+            .save_next_scheduled(&command, Utc::now() + Duration::minutes(20))
+            .unwrap();
+
+        let statuses = command_statuses(std::slice::from_ref(&command), &state_manager).unwrap();
+        assert!(statuses[0].backing_off);
+        assert!(statuses[0].next_run.is_some());
+
+        state_manager
+            .save_last_run(&command, Utc::now(), Some(0), 10)
+            .unwrap();
+        let statuses = command_statuses(&[command], &state_manager).unwrap();
+        assert!(!statuses[0].backing_off);
+    }
+}
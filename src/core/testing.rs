@@ -0,0 +1,142 @@
+//! Executors, a clock and a notifier for testing code that embeds the scheduler, enabled
+//! by the `testing` feature
+
+use crate::config::CommandConfig;
+use crate::core::clock::Clock;
+use crate::core::desktop::Notifier;
+use crate::core::executor::{CommandExecutor, CommandOutput};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+
+/// Executor that records which commands it was asked to run instead of running them
+///
+/// Clones share their records, so keep a clone to inspect after handing one to the
+/// scheduler. Every command exits with status 0 unless given another with
+/// [`RecordingExecutor::with_status`].
+#[derive(Clone, Default)]
+pub struct RecordingExecutor {
+    invocations: Arc<Mutex<Vec<String>>>,
+    statuses: Arc<Mutex<HashMap<String, i32>>>,
+}
+
+impl RecordingExecutor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes runs of the named command exit with `status`
+    pub fn with_status(self, name: &str, status: i32) -> Self {
+        self.statuses
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), status);
+        self
+    }
+
+    /// Returns the names of the commands run so far, in the order they started
+    pub fn invocations(&self) -> Vec<String> {
+        self.invocations.lock().unwrap().clone()
+    }
+
+    /// Returns how many times the named command has been run
+    pub fn count(&self, name: &str) -> usize {
+        self.invocations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|invoked| *invoked == name)
+            .count()
+    }
+}
+
+#[async_trait::async_trait]
+impl CommandExecutor for RecordingExecutor {
+    async fn execute(&self, command: &CommandConfig) -> io::Result<CommandOutput> {
+        self.invocations.lock().unwrap().push(command.name.clone());
+        let status = self
+            .statuses
+            .lock()
+            .unwrap()
+            .get(&command.name)
+            .copied()
+            .unwrap_or(0);
+        Ok(CommandOutput {
+            status,
+            ..Default::default()
+        })
+    }
+}
+
+/// Notifier that records the notifications it was asked to show instead of showing them
+///
+/// Clones share their records.
+#[derive(Clone, Default)]
+pub struct RecordingNotifier {
+    notifications: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl RecordingNotifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the title and body of each notification so far, in the order they were sent
+    pub fn notifications(&self) -> Vec<(String, String)> {
+        self.notifications.lock().unwrap().clone()
+    }
+}
+
+impl Notifier for RecordingNotifier {
+    fn show(&self, title: &str, body: &str) {
+        self.notifications
+            .lock()
+            .unwrap()
+            .push((title.to_string(), body.to_string()));
+    }
+}
+
+/// Clock whose wall-clock time tests set and move by hand
+///
+/// Time also passes along with tokio's clock, so under `tokio::time::pause` the scheduler's
+/// waits finish straight away and the wall clock moves on by as much, making hours of
+/// schedule quick to test. [`MockClock::advance`] moves only the wall clock, as a system
+/// sleep does. Clones share their time.
+#[derive(Clone)]
+pub struct MockClock {
+    /// Wall-clock time at the given point of tokio's clock
+    reading: Arc<Mutex<(DateTime<Utc>, Instant)>>,
+}
+
+impl MockClock {
+    /// Starts the clock at `now`
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            reading: Arc::new(Mutex::new((now, Instant::now()))),
+        }
+    }
+
+    /// Sets the wall clock to `now`
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.reading.lock().unwrap() = (now, Instant::now());
+    }
+
+    /// Moves the wall clock by `by`, which may be negative, without tokio's clock moving
+    pub fn advance(&self, by: Duration) {
+        self.set(self.now() + by);
+    }
+}
+
+#[async_trait::async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        let (now, at) = *self.reading.lock().unwrap();
+        now + Duration::from_std(at.elapsed()).unwrap_or_default()
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        tokio::time::sleep_until(deadline).await;
+    }
+}
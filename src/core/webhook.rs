@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How long to wait for a webhook to respond before giving up
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a webhook notification is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureEvent {
+    /// A run failed, timed out or couldn't be started
+    Failed,
+    /// The command hasn't run for longer than its `max_staleness_minutes`
+    Overdue,
+}
+
+/// JSON body posted to a command's `on_failure_webhook`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FailurePayload {
+    pub name: String,
+    pub event: FailureEvent,
+    /// Exit status of the run; `None` if it timed out or failed to start
+    pub exit_status: Option<i32>,
+    /// Error output of the run, the error that stopped it from finishing, or for
+    /// `Overdue` how long ago the command last ran
+    pub stderr: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Posts a failure notification on its own task so a slow endpoint never holds up the
+/// scheduler; problems reaching the endpoint are only logged
+pub fn notify_failure(url: String, payload: FailurePayload) {
+    tokio::spawn(async move {
+        if let Err(e) = post(&url, &payload).await {
+            warn!(
+                "Failed to send failure webhook for command '{}' to {}: {}",
+                payload.name, url, e
+            );
+        }
+    });
+}
+
+async fn post(url: &str, payload: &FailurePayload) -> reqwest::Result<()> {
+    client()
+        .post(url)
+        .json(payload)
+        .send()
+        .await?
+        .error_for_status()?;
+    info!("Sent failure webhook for command '{}'", payload.name);
+    Ok(())
+}
+
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .expect("webhook HTTP client")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::CommandConfig;
+    use crate::core::testing::RecordingExecutor;
+    use crate::SchedulerBuilder;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_failing_command_posts_webhook() {
+        let (payloads_tx, mut payloads_rx) = mpsc::unbounded_channel();
+        let router = Router::new().route(
+            "/hook",
+            post(move |Json(payload): Json<serde_json::Value>| async move {
+                payloads_tx.send(payload).unwrap();
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let dir = tempfile::tempdir().unwrap();
+        let command = |name: &str| CommandConfig {
+            name: name.to_string(),
+            command: "true".to_string(),
+            interval_minutes: Some(60.0),
+            immediate: true,
+            on_failure_webhook: Some(format!("http://{}/hook", addr)),
+            ..Default::default()
+        };
+        let executor = RecordingExecutor::new().with_status("broken", 3);
+        let mut scheduler = SchedulerBuilder::new(vec![command("broken"), command("fine")])
+            .with_executor(Box::new(executor))
+            .with_state_path(dir.path().join("state.db"))
+            .with_min_interval(1)
+            .build()
+            .unwrap();
+        scheduler
+            .run_until(tokio::time::sleep(std::time::Duration::from_millis(300)))
+            .await;
+
+        let payload = tokio::time::timeout(std::time::Duration::from_secs(5), payloads_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(payload["name"], "broken");
+        assert_eq!(payload["event"], "failed");
+        assert_eq!(payload["exit_status"], 3);
+        assert_eq!(payload["stderr"], "");
+        assert!(payload["timestamp"]
+            .as_str()
+            .unwrap()
+            .parse::<chrono::DateTime<chrono::Utc>>()
+            .is_ok());
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(payloads_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! HTTP servers exposing the scheduler's status and metrics for monitoring
+
+use crate::core::status::CommandStatus;
+use crate::metrics::Metrics;
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+/// Binds a server's listening socket
+pub async fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to bind HTTP server to {}: {}", addr, e))?;
+    info!("HTTP server listening on {}", addr);
+    Ok(listener)
+}
+
+/// Serves the latest statuses published by the scheduler: every command at `GET /status`
+/// and `GET /commands`, a single one at `GET /commands/<name>`, and a liveness check at
+/// `GET /health`
+///
+/// Runs until the listener fails; errors are logged rather than returned.
+pub async fn serve(listener: TcpListener, status: watch::Receiver<Vec<CommandStatus>>) {
+    if let Err(e) = axum::serve(listener, router(status)).await {
+        error!("HTTP status server stopped: {}", e);
+    }
+}
+
+/// Serves `GET /metrics` with the scheduler's metrics in the Prometheus text format
+///
+/// Runs until the listener fails; errors are logged rather than returned.
+pub async fn serve_metrics(listener: TcpListener, metrics: Arc<Metrics>) {
+    let router = Router::new()
+        .route("/metrics", get(get_metrics))
+        .with_state(metrics);
+    if let Err(e) = axum::serve(listener, router).await {
+        error!("HTTP metrics server stopped: {}", e);
+    }
+}
+
+fn router(status: watch::Receiver<Vec<CommandStatus>>) -> Router {
+    Router::new()
+        .route("/status", get(get_status))
+        .route("/commands", get(get_status))
+        .route("/commands/:name", get(get_command))
+        .route("/health", get(get_health))
+        .with_state(status)
+}
+
+async fn get_status(
+    State(status): State<watch::Receiver<Vec<CommandStatus>>>,
+) -> Json<Vec<CommandStatus>> {
+    Json(status.borrow().clone())
+}
+
+async fn get_command(
+    State(status): State<watch::Receiver<Vec<CommandStatus>>>,
+    Path(name): Path<String>,
+) -> Response {
+    let command = status
+        .borrow()
+        .iter()
+        .find(|command| command.name == name)
+        .cloned();
+    match command {
+        Some(command) => Json(command).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("No command named '{}'", name) })),
+        )
+            .into_response(),
+    }
+}
+
+async fn get_health(
+    State(status): State<watch::Receiver<Vec<CommandStatus>>>,
+) -> Json<serde_json::Value> {
+    let status = status.borrow();
+    Json(json!({
+        "status": "ok",
+        "commands": status.len(),
+        "running": status.iter().filter(|command| command.running).count(),
+    }))
+}
+
+async fn get_metrics(State(metrics): State<Arc<Metrics>>) -> String {
+    metrics.render()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::CommandConfig;
+    use crate::core::scheduler::Scheduler;
+    use crate::core::testing::RecordingExecutor;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// Sends a GET request to the server and returns the response status line and body
+    pub(crate) async fn get(addr: SocketAddr, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, addr
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status_line = head.lines().next().unwrap().to_string();
+        (status_line, body.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_get_status_returns_scheduler_statuses() {
+        let dir = tempfile::tempdir().unwrap();
+        let commands = vec![
+            CommandConfig {
+                name: "ran".to_string(),
+                command: "true".to_string(),
+                interval_minutes: Some(60.0),
+                immediate: true,
+                ..Default::default()
+            },
+            CommandConfig {
+                name: "off".to_string(),
+                command: "true".to_string(),
+                interval_minutes: Some(60.0),
+                enabled: false,
+                ..Default::default()
+            },
+        ];
+        let mut scheduler = Scheduler::new_with_executor(
+            commands,
+            dir.path().join("state.db"),
+            Box::new(RecordingExecutor::new()),
+        )
+        .unwrap();
+
+        let listener = bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, scheduler.subscribe_status()));
+        scheduler
+            .run_until(tokio::time::sleep(std::time::Duration::from_millis(300)))
+            .await;
+
+        let (status_line, body) = get(addr, "/status").await;
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+        let statuses: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let statuses = statuses.as_array().unwrap();
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses[0]["name"], "ran");
+        assert_eq!(statuses[0]["last_status"], 0);
+        assert_eq!(statuses[0]["running"], false);
+        assert!(statuses[0]["last_execution"].is_string());
+        assert!(statuses[0]["next_run"].is_string());
+        assert_eq!(statuses[1]["name"], "off");
+        assert!(statuses[1]["next_run"].is_null());
+
+        let (status_line, _) = get(addr, "/missing").await;
+        assert_eq!(status_line, "HTTP/1.1 404 Not Found");
+
+        let (status_line, commands) = get(addr, "/commands").await;
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+        assert_eq!(commands, body);
+
+        let (status_line, body) = get(addr, "/commands/ran").await;
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+        let command: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(command["name"], "ran");
+        assert_eq!(command["last_status"], 0);
+
+        let (status_line, body) = get(addr, "/commands/unknown").await;
+        assert_eq!(status_line, "HTTP/1.1 404 Not Found");
+        let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(error["error"], "No command named 'unknown'");
+
+        let (status_line, body) = get(addr, "/health").await;
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+        let health: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["commands"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_metrics_counts_executions() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = CommandConfig {
+            name: "ran".to_string(),
+            command: "true".to_string(),
+            interval_minutes: Some(60.0),
+            immediate: true,
+            ..Default::default()
+        };
+        let metrics = Arc::new(Metrics::new());
+        let mut scheduler = crate::SchedulerBuilder::new(vec![command])
+            .with_executor(Box::new(RecordingExecutor::new()))
+            .with_state_path(dir.path().join("state.db"))
+            .with_metrics(Arc::clone(&metrics))
+            .build()
+            .unwrap();
+
+        let listener = bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_metrics(listener, metrics));
+        scheduler
+            .run_until(tokio::time::sleep(std::time::Duration::from_millis(300)))
+            .await;
+
+        let (status_line, body) = get(addr, "/metrics").await;
+        assert_eq!(status_line, "HTTP/1.1 200 OK");
+        assert!(body.contains("zephyr_command_executions_total{name=\"ran\",status=\"success\"} 1"));
+        assert!(body.contains("zephyr_command_duration_seconds_count{name=\"ran\"} 1"));
+        assert!(body.contains("zephyr_commands_scheduled 1"));
+    }
+}
@@ -1,5 +1,65 @@
+//! Zephyr is a lightweight task scheduler with interval and CRON scheduling, sleep
+//! handling and persistent state.
+//!
+//! Besides the `zephyr` binary, the scheduler can be embedded in another tokio
+//! application. Commands are run through a [`CommandExecutor`], which can be replaced to
+//! change how they are executed:
+//!
+//! ```
+//! use std::time::Duration;
+//! use zephyr_scheduler::{CommandConfig, CommandExecutor, CommandOutput, SchedulerBuilder};
+//!
+//! /// Pretends to run every command and reports success
+//! struct LoggingExecutor;
+//!
+//! #[async_trait::async_trait]
+//! impl CommandExecutor for LoggingExecutor {
+//!     async fn execute(&self, command: &CommandConfig) -> std::io::Result<CommandOutput> {
+//!         println!("running {}", command.name);
+//!         Ok(CommandOutput {
+//!             status: 0,
+//!             ..Default::default()
+//!         })
+//!     }
+//! }
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! # let state_dir = tempfile::tempdir()?;
+//! let command = CommandConfig {
+//!     name: "report".to_string(),
+//!     command: "generate-report".to_string(),
+//!     interval_minutes: Some(60.0),
+//!     immediate: true,
+//!     ..Default::default()
+//! };
+//!
+//! let mut scheduler = SchedulerBuilder::new(vec![command])
+//!     .with_executor(Box::new(LoggingExecutor))
+//!     .with_state_path(state_dir.path().join("state.db"))
+//!     .build()?;
+//!
+//! // Runs until the shutdown future completes, e.g. `tokio::signal::ctrl_c()`
+//! scheduler
+//!     .run_until(tokio::time::sleep(Duration::from_millis(100)))
+//!     .await;
+//! # Ok(())
+//! # }
+//! ```
+
 pub mod config;
+#[cfg(unix)]
+pub mod control;
 pub mod core;
+pub mod http;
+pub mod metrics;
 pub mod service;
 pub mod state;
 pub mod util;
+
+pub use config::{CommandConfig, Config};
+pub use core::clock::{Clock, SystemClock};
+pub use core::desktop::{Notifier, SystemNotifier};
+pub use core::executor::{CommandExecutor, CommandOutput};
+pub use core::scheduler::{Scheduler, SchedulerBuilder};
+pub use state::{ExecutionRecord, StateManager};
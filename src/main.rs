@@ -1,107 +1,1186 @@
-use clap::Parser;
-use std::path::PathBuf;
-use tracing::{error, info, warn, Level};
-use tracing_subscriber::FmtSubscriber;
-use zephyr_scheduler::util::{expand_tilde, log_level_from_str};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::error::ErrorKind;
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use tracing_subscriber::fmt::format::debug_fn;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+use zephyr_scheduler::config::{CommandConfig, Config, LogFormat, ParsedCommand};
+use zephyr_scheduler::core::control::ControlRequest;
+use zephyr_scheduler::core::executor::{DefaultExecutor, DryRunExecutor};
+use zephyr_scheduler::core::scheduler::{Scheduler, SchedulerBuilder};
+use zephyr_scheduler::core::status::{command_statuses, CommandStatus};
+use zephyr_scheduler::http;
+use zephyr_scheduler::metrics::Metrics;
+use zephyr_scheduler::service::{InstallOptions, ServiceScope};
+use zephyr_scheduler::state::{ExecutionRecord, StateManager, StateSnapshot};
+use zephyr_scheduler::util::{
+    expand_tilde, format_duration, humanize_duration, log_level_from_str,
+};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(name = "zephyr", author, version, about, long_about = None)]
+#[command(group(
+    ArgGroup::new("legacy")
+        .args([
+            "install_service",
+            "uninstall_service",
+            "start_service",
+            "stop_service",
+            "reset_state",
+            "list",
+            "run_now",
+            "run_tag",
+            "next",
+            "validate",
+        ])
+))]
 struct Args {
-    #[arg(short, long, default_value = "~/.config/zephyr/scheduler.toml")]
+    /// Path to the configuration file
+    #[arg(
+        short,
+        long,
+        global = true,
+        default_value = "~/.config/zephyr/scheduler.toml"
+    )]
     config: PathBuf,
 
-    #[arg(short = 'i', long)]
+    /// Path to the state database (defaults to `state_path` from the configuration)
+    #[arg(short = 's', long, global = true)]
+    state_path: Option<PathBuf>,
+
+    #[command(flatten)]
+    log: LogOptions,
+
+    /// Output format of `list`, `history` and `next`, `text` or `json`
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = OutputFormat::Text
+    )]
+    output: OutputFormat,
+
+    #[command(flatten)]
+    run: RunArgs,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    // Flags from before subcommands existed, kept as hidden aliases
+    #[arg(short = 'i', long, hide = true)]
     install_service: bool,
 
-    #[arg(short = 'u', long)]
+    #[arg(short = 'u', long, hide = true)]
     uninstall_service: bool,
 
-    #[arg(short = 'S', long)]
+    #[arg(short = 'S', long, hide = true)]
     start_service: bool,
 
-    #[arg(short = 'X', long)]
+    #[arg(short = 'X', long, hide = true)]
     stop_service: bool,
 
-    #[arg(short = 's', long, default_value = "~/.local/state/zephyr/state.db")]
-    state_path: Option<PathBuf>,
-
-    #[arg(short = 'r', long)]
+    #[arg(short = 'r', long, hide = true)]
     reset_state: bool,
+
+    #[arg(short = 'l', long, hide = true)]
+    list: bool,
+
+    #[arg(long, value_name = "NAME", hide = true)]
+    run_now: Option<String>,
+
+    #[arg(long, value_name = "TAG", hide = true)]
+    run_tag: Option<String>,
+
+    /// Filter of `--list`
+    #[arg(long, value_name = "TAG", hide = true, requires = "list")]
+    tag: Option<String>,
+
+    #[arg(long, value_name = "NAME", hide = true)]
+    next: Option<String>,
+
+    /// Number of runs `--next` prints
+    #[arg(long, value_name = "COUNT", hide = true, requires = "next")]
+    count: Option<usize>,
+
+    #[arg(long, hide = true)]
+    validate: bool,
+}
+
+impl Args {
+    /// Returns the subcommand to run, translating the legacy flags
+    fn subcommand(&self) -> Result<Commands, clap::Error> {
+        match (&self.command, self.legacy_command()) {
+            (Some(_), Some(_)) => Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                "legacy flags cannot be combined with a subcommand",
+            )),
+            (Some(Commands::Run(run)), None) => Ok(Commands::Run(RunArgs {
+                dry_run: run.dry_run || self.run.dry_run,
+            })),
+            (None, None) => Ok(Commands::Run(self.run.clone())),
+            (_, _) if self.run.dry_run => Err(Args::command().error(
+                ErrorKind::ArgumentConflict,
+                "--dry-run can only be used when running the scheduler",
+            )),
+            (Some(command), None) => Ok(command.clone()),
+            (None, Some(command)) => Ok(command),
+        }
+    }
+
+    fn legacy_command(&self) -> Option<Commands> {
+        let command = if self.install_service {
+            Commands::Service {
+                user: false,
+                action: ServiceAction::Install {
+                    exec_path: None,
+                    log_path: None,
+                },
+            }
+        } else if self.uninstall_service {
+            Commands::Service {
+                user: false,
+                action: ServiceAction::Uninstall,
+            }
+        } else if self.start_service {
+            Commands::Service {
+                user: false,
+                action: ServiceAction::Start,
+            }
+        } else if self.stop_service {
+            Commands::Service {
+                user: false,
+                action: ServiceAction::Stop,
+            }
+        } else if self.reset_state {
+            // Scripts use `-r` without expecting a prompt
+            Commands::State {
+                action: StateAction::Reset {
+                    command: None,
+                    yes: true,
+                },
+            }
+        } else if self.list {
+            Commands::List(ListArgs {
+                tag: self.tag.clone(),
+                ..Default::default()
+            })
+        } else if let Some(name) = &self.run_now {
+            Commands::RunNow(RunNowArgs {
+                name: Some(name.clone()),
+                tag: None,
+                no_state: false,
+            })
+        } else if let Some(tag) = &self.run_tag {
+            Commands::RunNow(RunNowArgs {
+                name: None,
+                tag: Some(tag.clone()),
+                no_state: false,
+            })
+        } else if let Some(name) = &self.next {
+            Commands::Next(NextArgs {
+                name: name.clone(),
+                count: self.count.unwrap_or(DEFAULT_NEXT_RUNS),
+            })
+        } else if self.validate {
+            Commands::Validate
+        } else {
+            return None;
+        };
+        Some(command)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct LogOptions {
+    /// Log level, overriding RUST_LOG and `log_level` from the configuration
+    #[arg(
+        long = "log-level",
+        global = true,
+        value_name = "LEVEL",
+        value_parser = ["trace", "debug", "info", "warn", "error"]
+    )]
+    level: Option<String>,
+
+    /// Log format, `text` or `json`, overriding `log_format` from the configuration
+    #[arg(long = "log-format", global = true, value_name = "FORMAT")]
+    format: Option<LogFormat>,
+}
+
+/// How `list` and `history` print their results
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum OutputFormat {
+    /// Tables for reading in a terminal
+    #[default]
+    Text,
+    /// A JSON array for scripts
+    Json,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+enum Commands {
+    /// Run the scheduler in the foreground (the default)
+    Run(RunArgs),
+    /// Manage the Zephyr system service
+    Service {
+        /// Manage a systemd user service instead of a system service
+        #[arg(long, global = true)]
+        user: bool,
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+    /// Manage the state database
+    State {
+        #[command(subcommand)]
+        action: StateAction,
+    },
+    /// Check the configuration file and exit
+    Validate,
+    /// List configured commands with their schedules and next run times
+    List(ListArgs),
+    /// Execute a single command immediately and exit with its status
+    RunNow(RunNowArgs),
+    /// Show the most recent runs of a command
+    History(HistoryArgs),
+    /// Print the upcoming run times of a command, without touching the state database
+    Next(NextArgs),
+    /// Control the running scheduler through its control socket
+    Ctl {
+        /// Path to the control socket (defaults to `control_socket` from the configuration)
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+        #[command(subcommand)]
+        action: CtlAction,
+    },
+    /// Run the scheduler as a Windows service; started by the Service Control Manager
+    #[cfg(windows)]
+    #[command(name = "windows-service", hide = true)]
+    WindowsService,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+enum ServiceAction {
+    /// Install Zephyr as a system service
+    Install {
+        /// Zephyr executable the service runs (defaults to this one)
+        #[arg(long, value_name = "PATH")]
+        exec_path: Option<PathBuf>,
+        /// File the launchd agent logs to (macOS; defaults to ~/Library/Logs/zephyr.log)
+        #[arg(long, value_name = "PATH")]
+        log_path: Option<PathBuf>,
+    },
+    /// Remove the Zephyr service
+    Uninstall,
+    /// Start the Zephyr service
+    Start,
+    /// Stop the Zephyr service
+    Stop,
+    /// Show whether the service is installed and running; exits with 0 when running, 1 when
+    /// stopped and 2 when not installed
+    Status,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+enum CtlAction {
+    /// Print the status of every command as JSON
+    Status,
+    /// Run a command now
+    Trigger { name: String },
+    /// Skip the scheduled runs of a command until it is resumed
+    Pause { name: String },
+    /// Resume a paused command
+    Resume { name: String },
+    /// Skip the scheduled runs of every command until the scheduler is resumed
+    PauseAll,
+    /// Resume the scheduler after `pause-all`
+    ResumeAll,
+    /// Reload the configuration file
+    Reload,
+}
+
+impl From<CtlAction> for ControlRequest {
+    fn from(action: CtlAction) -> Self {
+        match action {
+            CtlAction::Status => ControlRequest::Status,
+            CtlAction::Trigger { name } => ControlRequest::Trigger { name },
+            CtlAction::Pause { name } => ControlRequest::Pause { name },
+            CtlAction::Resume { name } => ControlRequest::Resume { name },
+            CtlAction::PauseAll => ControlRequest::PauseAll,
+            CtlAction::ResumeAll => ControlRequest::ResumeAll,
+            CtlAction::Reload => ControlRequest::Reload,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+enum StateAction {
+    /// Clear all command state and history from the state database, or only that of one
+    /// command
+    Reset {
+        /// Only forget the state and history of the command NAME
+        #[arg(long, value_name = "NAME")]
+        command: Option<String>,
+        /// Reset the whole database without asking for confirmation
+        #[arg(short = 'y', long)]
+        yes: bool,
+    },
+    /// Write every command's state and the execution history as JSON
+    Export {
+        /// File to write to instead of stdout
+        #[arg(short = 'o', long = "file", value_name = "PATH")]
+        file: Option<PathBuf>,
+    },
+    /// Load command state and execution history written by `state export`
+    Import {
+        /// File written by `state export`
+        file: PathBuf,
+        /// Keep commands that aren't in the file (the default)
+        #[arg(long, conflicts_with = "replace")]
+        merge: bool,
+        /// Delete all stored state and history before importing
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+#[derive(clap::Args, Debug, Clone, PartialEq, Default)]
+struct RunArgs {
+    /// Log what would be executed instead of running commands; state is kept in a
+    /// temporary copy of the state database
+    #[arg(long)]
+    dry_run: bool,
 }
 
-fn init_tracing(level: Level) {
-    FmtSubscriber::builder()
-        .with_max_level(level)
+#[derive(clap::Args, Debug, Clone, PartialEq, Default)]
+struct ListArgs {
+    /// Print the commands as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Only list the commands tagged with TAG
+    #[arg(long, value_name = "TAG")]
+    tag: Option<String>,
+}
+
+#[derive(clap::Args, Debug, Clone, PartialEq)]
+struct HistoryArgs {
+    /// Name of the command
+    name: String,
+
+    /// Maximum number of runs to show
+    #[arg(short = 'n', long, default_value_t = 20)]
+    limit: usize,
+
+    /// Only show runs that failed or timed out
+    #[arg(long)]
+    failed: bool,
+
+    /// Print the runs as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// Number of runs `next` prints unless told otherwise
+const DEFAULT_NEXT_RUNS: usize = 5;
+
+#[derive(clap::Args, Debug, Clone, PartialEq)]
+struct NextArgs {
+    /// Name of the command
+    name: String,
+
+    /// Number of runs to print
+    #[arg(default_value_t = DEFAULT_NEXT_RUNS)]
+    count: usize,
+}
+
+#[derive(clap::Args, Debug, Clone, PartialEq)]
+struct RunNowArgs {
+    /// Name of the command to run
+    #[arg(required_unless_present = "tag")]
+    name: Option<String>,
+
+    /// Run every enabled command tagged with TAG instead, one after another
+    #[arg(long, value_name = "TAG", conflicts_with = "name")]
+    tag: Option<String>,
+
+    /// Don't record the run in the state database
+    #[arg(long)]
+    no_state: bool,
+}
+
+/// A command as printed by `list --output json`
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct CommandView {
+    name: String,
+    enabled: bool,
+    command: String,
+    schedule: String,
+    last_execution: Option<DateTime<Utc>>,
+    /// Exit status of the last run; `null` if it timed out, failed to start or never ran
+    last_status: Option<i32>,
+    next_scheduled: Option<DateTime<Utc>>,
+    /// Seconds until the next run, negative if it is overdue
+    seconds_until_next: Option<i64>,
+    auto_disabled: bool,
+    completed: bool,
+    backing_off: bool,
+}
+
+impl CommandView {
+    fn new(status: &CommandStatus, now: DateTime<Utc>) -> Self {
+        Self {
+            name: status.name.clone(),
+            enabled: status.enabled,
+            command: status.command.clone(),
+            schedule: status.schedule.clone(),
+            last_execution: status.last_execution,
+            last_status: status.last_status,
+            next_scheduled: status.next_run,
+            seconds_until_next: status.time_until_next(now).map(|d| d.num_seconds()),
+            auto_disabled: status.auto_disabled,
+            completed: status.completed,
+            backing_off: status.backing_off,
+        }
+    }
+}
+
+/// Renders command statuses as the JSON array printed by `list --output json`
+fn list_json(statuses: &[CommandStatus], now: DateTime<Utc>) -> serde_json::Result<String> {
+    let views = statuses
+        .iter()
+        .map(|status| CommandView::new(status, now))
+        .collect::<Vec<_>>();
+    serde_json::to_string_pretty(&views)
+}
+
+type FilteredRegistry = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+type FormatLayer = Box<dyn Layer<FilteredRegistry> + Send + Sync>;
+
+/// Handles to change the log filter and format once the configuration has been loaded
+struct LogHandles {
+    filter: reload::Handle<EnvFilter, Registry>,
+    format: reload::Handle<FormatLayer, FilteredRegistry>,
+}
+
+impl LogHandles {
+    fn apply(&self, filter: &str, format: LogFormat) {
+        if let Err(e) = self.filter.reload(EnvFilter::new(filter)) {
+            warn!("Failed to apply log level from configuration: {}", e);
+        }
+        if let Err(e) = self.format.reload(format_layer(format, std::io::stdout)) {
+            warn!("Failed to apply log format from configuration: {}", e);
+        }
+    }
+}
+
+/// Installs the global subscriber
+fn init_tracing(filter: &str, format: LogFormat) -> LogHandles {
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::new(filter));
+    let (format, format_handle) = reload::Layer::new(format_layer(format, std::io::stdout));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(format)
+        .init();
+    LogHandles {
+        filter: filter_handle,
+        format: format_handle,
+    }
+}
+
+/// Builds the layer that writes events in the given format
+///
+/// Text output shows only each event's message; JSON output includes its fields as well.
+fn format_layer<W>(format: LogFormat, writer: W) -> FormatLayer
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
         .with_target(false)
         .with_thread_ids(false)
         .with_file(false)
         .with_line_number(false)
-        .with_thread_names(false)
-        .with_ansi(true)
-        .init();
+        .with_thread_names(false);
+    match format {
+        LogFormat::Text => layer
+            .with_ansi(true)
+            .fmt_fields(debug_fn(|writer, field, value| {
+                if field.name() == "message" {
+                    write!(writer, "{:?}", value)
+                } else {
+                    Ok(())
+                }
+            }))
+            .boxed(),
+        LogFormat::Json => layer
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(false)
+            .boxed(),
+    }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-    let config_path = expand_tilde(&args.config);
+/// Builds the log filter directive; `--log-level` wins over `RUST_LOG`, which wins over
+/// `log_level` from the configuration
+fn log_filter(
+    config_level: Option<&str>,
+    cli_level: Option<&str>,
+    rust_log: Option<&str>,
+) -> String {
+    let level = |level: &str| log_level_from_str(level).to_string().to_lowercase();
+    match (
+        cli_level,
+        rust_log.filter(|rust_log| !rust_log.trim().is_empty()),
+    ) {
+        (Some(cli_level), _) => level(cli_level),
+        (None, Some(rust_log)) => rust_log.to_string(),
+        (None, None) => level(config_level.unwrap_or("info")),
+    }
+}
 
-    if args.reset_state {
-        init_tracing(Level::INFO);
-        let state_path = if let Some(ref cli_path) = args.state_path {
-            cli_path.clone()
-        } else if config_path.exists() {
-            match zephyr_scheduler::config::Config::load(&config_path) {
-                Ok(config) => config.general.state_path,
-                Err(e) => {
-                    error!("Failed to load config for state path: {}", e);
-                    return Err(e);
-                }
-            }
-        } else {
-            PathBuf::from("~/.local/state/zephyr/state.db")
-        };
+/// Prints every configured command with its schedule, last execution and next run
+///
+/// Disabled commands are listed too, dimmed when writing to a terminal.
+fn list_commands(
+    config_path: &Path,
+    state_path: Option<PathBuf>,
+    json: bool,
+    tag: Option<&str>,
+) -> anyhow::Result<()> {
+    let config = Config::load(config_path)?;
+    let commands = match tag {
+        Some(tag) => config.commands_tagged(tag).into_iter().cloned().collect(),
+        None => config.commands.clone(),
+    };
+    let state_path = expand_tilde(&state_path.unwrap_or(config.general.state_path));
+    let state_manager = StateManager::new(&state_path)?;
+    let statuses = command_statuses(&commands, &state_manager)?;
+    let now = Utc::now();
 
-        info!("Resetting state database at {:?}", state_path);
-        let state_path = expand_tilde(&state_path);
-        let state_manager = zephyr_scheduler::state::StateManager::new(&state_path)?;
-        state_manager.reset_state()?;
-        info!("State database reset successfully");
+    if json {
+        println!("{}", list_json(&statuses, now)?);
         return Ok(());
     }
 
-    if args.install_service {
-        init_tracing(Level::INFO);
-        info!("Installing service...");
-        zephyr_scheduler::service::install_service()?;
+    let rows = statuses
+        .iter()
+        .map(|status| {
+            (
+                status.enabled && !status.auto_disabled,
+                [
+                    status.name.clone(),
+                    if status.enabled { "yes" } else { "no" }.to_string(),
+                    status.schedule.clone(),
+                    status
+                        .last_execution
+                        .map(format_time)
+                        .unwrap_or_else(|| "never".to_string()),
+                    status
+                        .next_run
+                        .map(format_time)
+                        .unwrap_or_else(|| "-".to_string()),
+                    match status.time_until_next(now) {
+                        Some(until) if status.backing_off => {
+                            format!("{} (backing off)", humanize_duration(until))
+                        }
+                        Some(until) => humanize_duration(until),
+                        None if status.auto_disabled => "disabled after failures".to_string(),
+                        None if status.completed => "completed".to_string(),
+                        None if status.enabled => "after dependencies".to_string(),
+                        None => "disabled".to_string(),
+                    },
+                ],
+            )
+        })
+        .collect();
+    print_table(
+        [
+            "NAME",
+            "ENABLED",
+            "SCHEDULE",
+            "LAST EXECUTION",
+            "NEXT RUN",
+            "DUE",
+        ],
+        rows,
+    );
+
+    Ok(())
+}
+
+/// Prints the most recent runs of a command from the execution history
+fn show_history(
+    config_path: &Path,
+    state_path: Option<PathBuf>,
+    args: &HistoryArgs,
+) -> anyhow::Result<()> {
+    let config = Config::load(config_path)?;
+    let state_path = expand_tilde(&state_path.unwrap_or(config.general.state_path.clone()));
+    let state_manager = StateManager::new(&state_path)?;
+
+    let known = config.commands.iter().any(|c| c.name == args.name)
+        || state_manager.get_command_state(&args.name)?.is_some()
+        || !state_manager.get_history(&args.name, 1)?.is_empty();
+    if !known {
+        find_command(&config, &args.name)?;
+    }
+    let records = state_manager.query_history(&args.name, args.limit, args.failed)?;
+
+    if args.json {
+        #[derive(Serialize)]
+        struct Entry<'a> {
+            #[serde(flatten)]
+            record: &'a ExecutionRecord,
+            duration_ms: i64,
+        }
+        let entries = records
+            .iter()
+            .map(|record| Entry {
+                record,
+                duration_ms: record.duration().num_milliseconds(),
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
         return Ok(());
     }
 
-    if args.uninstall_service {
-        init_tracing(Level::INFO);
-        info!("Uninstalling service...");
-        zephyr_scheduler::service::uninstall_service()?;
+    if records.is_empty() {
+        let kind = if args.failed { "failed runs" } else { "runs" };
+        println!("No recorded {} of '{}'", kind, args.name);
         return Ok(());
     }
+    let rows = records
+        .iter()
+        .map(|record| {
+            (
+                !record.skipped,
+                [
+                    format_time(record.started_at),
+                    format_duration(record.duration()),
+                    match record.exit_code {
+                        Some(code) => code.to_string(),
+                        None if record.skipped => "skipped".to_string(),
+                        None => "-".to_string(),
+                    },
+                    if record.timed_out { "yes" } else { "no" }.to_string(),
+                ],
+            )
+        })
+        .collect();
+    print_table(["STARTED", "DURATION", "EXIT STATUS", "TIMED OUT"], rows);
+
+    Ok(())
+}
 
-    if args.start_service {
-        init_tracing(Level::INFO);
-        info!("Starting service...");
-        zephyr_scheduler::service::start_service()?;
+/// Prints the next runs of a command as its schedule computes them
+///
+/// Cron runs are shown in the command's `timezone` as well when it has one. `run_window`,
+/// `blocked_window` and `jitter_seconds` aren't applied.
+fn print_next_runs(config_path: &Path, args: &NextArgs, json: bool) -> anyhow::Result<()> {
+    let config = Config::load(config_path)?;
+    let command = find_command(&config, &args.name)?;
+    let runs = next_runs(command, Utc::now(), args.count)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&runs)?);
         return Ok(());
     }
+    let timezone = command.timezone()?;
+    for run in runs {
+        match &command.timezone {
+            Some(name) => println!(
+                "{} {} ({})",
+                run.with_timezone(&timezone).format("%Y-%m-%d %H:%M:%S"),
+                name,
+                format_time(run)
+            ),
+            None => println!("{}", format_time(run)),
+        }
+    }
+    Ok(())
+}
 
-    if args.stop_service {
-        init_tracing(Level::INFO);
-        info!("Stopping service...");
-        zephyr_scheduler::service::stop_service()?;
+/// Returns up to `count` runs of a command after `now`
+fn next_runs(
+    command: &CommandConfig,
+    now: DateTime<Utc>,
+    count: usize,
+) -> anyhow::Result<Vec<DateTime<Utc>>> {
+    match command.schedule()? {
+        Some(schedule) => Ok(schedule.upcoming(now, count)),
+        None => anyhow::bail!(
+            "Command '{}' has no schedule of its own; it runs after {}",
+            command.name,
+            command.depends_on.as_deref().unwrap_or_default().join(", ")
+        ),
+    }
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+/// Prints rows as aligned columns under a header; rows flagged `false` are dimmed when
+/// writing to a terminal
+fn print_table<const N: usize>(header: [&str; N], rows: Vec<(bool, [String; N])>) {
+    let header = (true, header.map(str::to_string));
+    let rows = std::iter::once(header).chain(rows).collect::<Vec<_>>();
+    let mut widths = [0; N];
+    for (_, row) in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let dim = std::io::stdout().is_terminal();
+    for (highlighted, row) in &rows {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell))
+            .collect::<Vec<_>>()
+            .join("  ");
+        if !highlighted && dim {
+            println!("\x1b[2m{}\x1b[0m", line.trim_end());
+        } else {
+            println!("{}", line.trim_end());
+        }
+    }
+}
+
+/// Checks the configuration file and reports every problem found
+///
+/// Returns whether the configuration is valid.
+fn validate_config(config_path: &Path) -> anyhow::Result<bool> {
+    let config = Config::parse(config_path)?;
+    let errors = config.validate();
+    for error in &errors {
+        eprintln!("error: {}", error);
+    }
+
+    let valid_commands = config
+        .commands
+        .iter()
+        .filter(|command| command.validate().is_ok())
+        .count();
+    println!(
+        "{} of {} commands are valid",
+        valid_commands,
+        config.commands.len()
+    );
+    if errors.is_empty() {
+        println!("Configuration at {:?} is valid", config_path);
+    } else {
+        println!(
+            "Configuration at {:?} has {} error(s)",
+            config_path,
+            errors.len()
+        );
+    }
+
+    Ok(errors.is_empty())
+}
+
+/// Executes a single configured command right away, streaming its output to the terminal
+///
+/// Unless `record_state` is false the run is recorded in the state database so a running
+/// scheduler doesn't immediately run it again. Returns the exit status of the command.
+async fn run_command_now(
+    config_path: &Path,
+    state_path: Option<PathBuf>,
+    name: &str,
+    record_state: bool,
+) -> anyhow::Result<i32> {
+    let config = Config::load(config_path)?;
+    let command = find_command(&config, name)?;
+    run_attached(&config, state_path, command, record_state).await
+}
+
+/// Runs every enabled command tagged with `tag` once, one after another
+///
+/// Returns the exit status of the first command that failed, or 0 if all succeeded.
+async fn run_tagged_now(
+    config_path: &Path,
+    state_path: Option<PathBuf>,
+    tag: &str,
+    record_state: bool,
+) -> anyhow::Result<i32> {
+    let config = Config::load(config_path)?;
+    let commands = config
+        .commands_tagged(tag)
+        .into_iter()
+        .filter(|command| command.enabled)
+        .collect::<Vec<_>>();
+    if commands.is_empty() {
+        anyhow::bail!("No enabled commands tagged '{}' in configuration", tag);
+    }
+
+    let mut first_failure = 0;
+    for command in commands {
+        let status = match run_attached(&config, state_path.clone(), command, record_state).await {
+            Ok(status) if command.is_success(status) => continue,
+            Ok(status) => status,
+            Err(e) => {
+                error!("Command '{}' failed: {:#}", command.name, e);
+                1
+            }
+        };
+        if first_failure == 0 {
+            first_failure = if status > 0 { status } else { 1 };
+        }
+    }
+    Ok(first_failure)
+}
+
+/// Runs a command with its output going to the terminal, recording the run in the state
+/// database if `record_state`
+async fn run_attached(
+    config: &Config,
+    state_path: Option<PathBuf>,
+    command: &CommandConfig,
+    record_state: bool,
+) -> anyhow::Result<i32> {
+    info!("Running command '{}' now", command.name);
+    let execution_start = Utc::now();
+    let result = DefaultExecutor.run_attached(command).await;
+
+    if record_state {
+        let state_path = expand_tilde(&state_path.unwrap_or(config.general.state_path.clone()));
+        let state_manager = StateManager::new(&state_path)?;
+        if !command.runs_after_dependencies() {
+            let parsed = ParsedCommand::new(command.clone())?;
+            let next_run = Scheduler::calculate_next_run(&parsed, Utc::now())?;
+            state_manager.save_command_state(command, Some(execution_start), next_run)?;
+        }
+        let duration_ms = (Utc::now() - execution_start).num_milliseconds();
+        state_manager.save_last_run(
+            command,
+            execution_start,
+            result.as_ref().ok().copied(),
+            duration_ms,
+        )?;
+        // The output went straight to the terminal, so only errors are kept
+        let stderr = match &result {
+            Err(e) => e.to_string(),
+            Ok(_) => String::new(),
+        };
+        state_manager.record_execution(&ExecutionRecord {
+            name: command.name.clone(),
+            started_at: execution_start,
+            finished_at: Utc::now(),
+            exit_code: result.as_ref().ok().copied(),
+            timed_out: matches!(&result, Err(e) if e.kind() == std::io::ErrorKind::TimedOut),
+            succeeded: result
+                .as_ref()
+                .is_ok_and(|status| command.is_success(*status)),
+            skipped: false,
+            stdout: String::new(),
+            stdout_len: 0,
+            stderr_len: stderr.len(),
+            stderr,
+        })?;
+    }
+
+    let status = result?;
+    info!("Command '{}' exited with status {}", command.name, status);
+    Ok(status)
+}
+
+/// Looks up a command by name, listing the valid names if there is no such command
+fn find_command<'a>(config: &'a Config, name: &str) -> anyhow::Result<&'a CommandConfig> {
+    config
+        .commands
+        .iter()
+        .find(|c| c.name == name)
+        .ok_or_else(|| {
+            let names = config
+                .commands
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>();
+            anyhow::anyhow!(
+                "No command named '{}' in configuration. Valid commands: {}",
+                name,
+                names.join(", ")
+            )
+        })
+}
+
+/// Returns the path of the state database from the CLI or the configuration
+fn resolve_state_path(config_path: &Path, state_path: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    let state_path = if let Some(cli_path) = state_path {
+        cli_path
+    } else if config_path.exists() {
+        match Config::load(config_path) {
+            Ok(config) => config.general.state_path,
+            Err(e) => {
+                error!("Failed to load config for state path: {}", e);
+                return Err(e);
+            }
+        }
+    } else {
+        PathBuf::from("~/.local/state/zephyr/state.db")
+    };
+    Ok(expand_tilde(&state_path))
+}
+
+/// Resets the state database, resolving its path from the CLI or the configuration
+///
+/// Unless `yes`, asks for confirmation first, and refuses when there is no terminal to ask
+/// on.
+fn reset_state(config_path: &Path, state_path: Option<PathBuf>, yes: bool) -> anyhow::Result<()> {
+    let state_path = resolve_state_path(config_path, state_path)?;
+    if !yes
+        && !confirm(&format!(
+            "Delete the state and history of every command in {:?}?",
+            state_path
+        ))?
+    {
+        println!("State database left unchanged");
         return Ok(());
     }
+    info!("Resetting state database at {:?}", state_path);
+    let state_manager = StateManager::new(&state_path)?;
+    state_manager.reset_state()?;
+    info!("State database reset successfully");
+    Ok(())
+}
+
+/// Asks a yes/no question on the terminal, defaulting to no
+fn confirm(question: &str) -> anyhow::Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("Not resetting without confirmation; pass --yes to reset the state database");
+    }
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
 
-    let config = match zephyr_scheduler::config::Config::load(&config_path) {
+/// Deletes the stored state and execution history of one command, so the scheduler treats
+/// it as new
+///
+/// Fails, listing the commands the database knows, if it has nothing stored for `name`.
+fn reset_command_state(state_manager: &StateManager, name: &str) -> anyhow::Result<()> {
+    let names = state_manager.stored_names()?;
+    if !names.iter().any(|stored| stored == name) {
+        anyhow::bail!(
+            "No state stored for command '{}'. Commands in the state database: {}",
+            name,
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        );
+    }
+    state_manager.delete_command_state(name)?;
+    let runs = state_manager.delete_history(name)?;
+    info!(
+        "Reset the state of command '{}' and deleted {} recorded runs",
+        name, runs
+    );
+    Ok(())
+}
+
+/// Writes the state database as JSON to `file`, or to stdout
+fn export_state(
+    config_path: &Path,
+    state_path: Option<PathBuf>,
+    file: Option<&Path>,
+) -> anyhow::Result<()> {
+    let state_manager = StateManager::new(resolve_state_path(config_path, state_path)?)?;
+    let snapshot = state_manager.export()?;
+    let json = serde_json::to_string_pretty(&snapshot)?;
+    match file {
+        Some(file) => {
+            std::fs::write(file, json + "\n")
+                .with_context(|| format!("Failed to write state export {:?}", file))?;
+            info!(
+                "Exported the state of {} commands and {} runs to {:?}",
+                snapshot.commands.len(),
+                snapshot.executions.len(),
+                file
+            );
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+/// Loads a file written by `state export` into the state database
+fn import_state(
+    config_path: &Path,
+    state_path: Option<PathBuf>,
+    file: &Path,
+    replace: bool,
+) -> anyhow::Result<()> {
+    let json = std::fs::read_to_string(file)
+        .with_context(|| format!("Failed to read state export {:?}", file))?;
+    let snapshot: StateSnapshot =
+        serde_json::from_str(&json).with_context(|| format!("Invalid state export {:?}", file))?;
+    let state_manager = StateManager::new(resolve_state_path(config_path, state_path)?)?;
+    state_manager.import(&snapshot, replace)?;
+    info!(
+        "Imported the state of {} commands and {} runs from {:?}",
+        snapshot.commands.len(),
+        snapshot.executions.len(),
+        file
+    );
+    Ok(())
+}
+
+/// Sends a request to the running scheduler and prints its answer
+///
+/// Returns whether the scheduler accepted the request.
+#[cfg(unix)]
+async fn control(
+    config_path: &Path,
+    socket: Option<PathBuf>,
+    request: ControlRequest,
+) -> anyhow::Result<bool> {
+    let socket = socket.unwrap_or_else(|| match Config::parse(config_path) {
+        Ok(config) => config.general.control_socket,
+        Err(_) => zephyr_scheduler::config::default_control_socket(),
+    });
+    let response = zephyr_scheduler::control::send(&expand_tilde(&socket), &request).await?;
+    if let Some(error) = &response.error {
+        eprintln!("error: {}", error);
+    }
+    if let Some(message) = &response.message {
+        println!("{}", message);
+    }
+    if let Some(commands) = &response.commands {
+        println!("{}", serde_json::to_string_pretty(commands)?);
+    }
+    Ok(response.ok)
+}
+
+#[cfg(not(unix))]
+async fn control(
+    _config_path: &Path,
+    _socket: Option<PathBuf>,
+    _request: ControlRequest,
+) -> anyhow::Result<bool> {
+    anyhow::bail!("zephyr ctl is only available on Unix")
+}
+
+/// Installs, removes, starts or stops the system service
+fn manage_service(
+    action: ServiceAction,
+    user: bool,
+    config_path: &Path,
+    state_path: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let scope = if user {
+        ServiceScope::User
+    } else {
+        ServiceScope::System
+    };
+    match action {
+        ServiceAction::Install {
+            exec_path,
+            log_path,
+        } => {
+            info!("Installing service...");
+            zephyr_scheduler::service::install_service(&InstallOptions {
+                scope,
+                config_path: config_path.to_path_buf(),
+                state_path,
+                exec_path,
+                log_path,
+            })
+        }
+        ServiceAction::Uninstall => {
+            info!("Uninstalling service...");
+            zephyr_scheduler::service::uninstall_service(scope)
+        }
+        ServiceAction::Start => {
+            info!("Starting service...");
+            zephyr_scheduler::service::start_service(scope)
+        }
+        ServiceAction::Stop => {
+            info!("Stopping service...");
+            zephyr_scheduler::service::stop_service(scope)
+        }
+        ServiceAction::Status => {
+            let status = zephyr_scheduler::service::service_status(scope)?;
+            println!("{}", status.describe(Utc::now()));
+            let code = status.exit_code();
+            if code != 0 {
+                std::process::exit(code);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Runs the scheduler under the Windows Service Control Manager, shutting it down
+/// gracefully when the service is stopped
+#[cfg(windows)]
+fn run_windows_service(
+    config_path: PathBuf,
+    state_path: Option<PathBuf>,
+    log_options: LogOptions,
+    log_handles: LogHandles,
+) -> anyhow::Result<()> {
+    zephyr_scheduler::service::windows::run(move |stop| {
+        tokio::runtime::Runtime::new()?.block_on(run_scheduler(
+            &config_path,
+            state_path,
+            false,
+            &log_options,
+            log_handles,
+            stop,
+        ))
+    })
+}
+
+/// Copies the state database to a temporary file for a dry run to use, so it starts from
+/// the real schedule without changing it
+fn dry_run_state_copy(state_path: &Path) -> anyhow::Result<PathBuf> {
+    let copy = std::env::temp_dir().join(format!("zephyr-dry-run-{}.db", std::process::id()));
+    if state_path.exists() {
+        StateManager::new(state_path)
+            .and_then(|state_manager| state_manager.copy_to(&copy))
+            .with_context(|| {
+                format!("Failed to copy state database {:?} for dry run", state_path)
+            })?;
+    } else if copy.exists() {
+        std::fs::remove_file(&copy)?;
+    }
+    info!(
+        "Dry run: commands are only logged, and state is kept in {:?}",
+        copy
+    );
+    Ok(copy)
+}
+
+/// Loads the configuration and runs the scheduler loop until it receives a shutdown
+/// signal or `shutdown` completes
+///
+/// A dry run logs commands instead of executing them and records their runs in a temporary
+/// copy of the state database, leaving the real one untouched.
+async fn run_scheduler(
+    config_path: &Path,
+    state_path: Option<PathBuf>,
+    dry_run: bool,
+    log_options: &LogOptions,
+    log_handles: LogHandles,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let config = match Config::load(config_path) {
         Ok(c) => c,
         Err(e) => {
-            init_tracing(Level::INFO);
             if !config_path.exists() {
                 warn!("Configuration file not found at {:?}", config_path);
             } else {
@@ -116,7 +1195,15 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
-    init_tracing(log_level_from_str(&config.general.log_level));
+    let filter = log_filter(
+        Some(&config.general.log_level),
+        log_options.level.as_deref(),
+        std::env::var("RUST_LOG").ok().as_deref(),
+    );
+    log_handles.apply(
+        &filter,
+        log_options.format.unwrap_or(config.general.log_format),
+    );
     info!("Starting Zephyr with config: {:?}", config_path);
     info!("Loading configuration from {:?}", config_path);
     info!(
@@ -124,25 +1211,843 @@ async fn main() -> anyhow::Result<()> {
         config.commands.len()
     );
 
-    let state_path = args.state_path.unwrap_or(config.general.state_path);
-    let state_path = expand_tilde(&state_path);
-
     info!(
         "Initializing scheduler with {} commands (min_interval_seconds: {}, max_immediate_executions: {})",
         config.commands.len(),
         config.general.min_interval_seconds,
         config.general.max_immediate_executions
     );
-    let mut scheduler = zephyr_scheduler::core::scheduler::Scheduler::new_with_config(
-        config.commands,
-        state_path,
-        config.general.max_immediate_executions,
-        config.general.min_interval_seconds,
-    )?;
+    let http = config.http.clone();
+    let metrics_server = config.metrics.clone();
+    #[cfg(unix)]
+    let control_socket = expand_tilde(&config.general.control_socket);
+    let dry_run_state = if dry_run {
+        let state_path = expand_tilde(state_path.as_ref().unwrap_or(&config.general.state_path));
+        Some(dry_run_state_copy(&state_path)?)
+    } else {
+        None
+    };
+    let mut builder = SchedulerBuilder::from_config(config).with_config_path(config_path);
+    if let Some(state_path) = dry_run_state.clone().or(state_path) {
+        builder = builder.with_state_path(state_path);
+    }
+    if dry_run {
+        builder = builder.with_executor(Box::new(DryRunExecutor));
+    }
+    let metrics = metrics_server.as_ref().map(|_| Arc::new(Metrics::new()));
+    if let Some(metrics) = &metrics {
+        builder = builder.with_metrics(Arc::clone(metrics));
+    }
+    let mut scheduler = builder.build()?;
+
+    if let Some(http) = http {
+        let listener = http::bind(http.socket_addr()?).await?;
+        tokio::spawn(http::serve(listener, scheduler.subscribe_status()));
+    }
+    if let (Some(server), Some(metrics)) = (metrics_server, metrics) {
+        let listener = http::bind(server.socket_addr()?).await?;
+        tokio::spawn(http::serve_metrics(listener, metrics));
+    }
+
+    #[cfg(unix)]
+    let control_listener = match zephyr_scheduler::control::bind(&control_socket).await {
+        Ok(listener) => {
+            tokio::spawn(zephyr_scheduler::control::serve(
+                listener,
+                scheduler.control_handle(),
+            ));
+            true
+        }
+        Err(e) => {
+            warn!("Control socket disabled: {:#}", e);
+            false
+        }
+    };
 
     info!("Starting Zephyr task scheduler");
 
-    scheduler.run().await;
+    scheduler.run_until(shutdown).await;
+
+    #[cfg(unix)]
+    if control_listener {
+        if let Err(e) = std::fs::remove_file(&control_socket) {
+            warn!(
+                "Failed to remove control socket {:?}: {}",
+                control_socket, e
+            );
+        }
+    }
+    if let Some(path) = dry_run_state {
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Failed to remove dry run state database {:?}: {}", path, e);
+        }
+    }
 
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let config_path = expand_tilde(&args.config);
+
+    let command = args.subcommand().unwrap_or_else(|e| e.exit());
+    let log_handles = init_tracing(
+        &log_filter(
+            None,
+            args.log.level.as_deref(),
+            std::env::var("RUST_LOG").ok().as_deref(),
+        ),
+        args.log.format.unwrap_or_default(),
+    );
+    let json = args.output == OutputFormat::Json;
+
+    match command {
+        Commands::Run(run) => {
+            run_scheduler(
+                &config_path,
+                args.state_path,
+                run.dry_run,
+                &args.log,
+                log_handles,
+                std::future::pending(),
+            )
+            .await
+        }
+        #[cfg(windows)]
+        Commands::WindowsService => tokio::task::block_in_place(|| {
+            run_windows_service(config_path, args.state_path, args.log, log_handles)
+        }),
+        Commands::Service { user, action } => {
+            manage_service(action, user, &config_path, args.state_path)
+        }
+        Commands::State { action } => match action {
+            StateAction::Reset {
+                command: Some(name),
+                ..
+            } => StateManager::new(resolve_state_path(&config_path, args.state_path)?)
+                .and_then(|state_manager| reset_command_state(&state_manager, &name)),
+            StateAction::Reset { command: None, yes } => {
+                reset_state(&config_path, args.state_path, yes)
+            }
+            StateAction::Export { file } => {
+                export_state(&config_path, args.state_path, file.as_deref())
+            }
+            StateAction::Import { file, replace, .. } => {
+                import_state(&config_path, args.state_path, &file, replace)
+            }
+        },
+        Commands::Validate => {
+            if !validate_config(&config_path)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::List(list) => list_commands(
+            &config_path,
+            args.state_path,
+            list.json || json,
+            list.tag.as_deref(),
+        ),
+        Commands::History(mut history) => {
+            history.json |= json;
+            show_history(&config_path, args.state_path, &history)
+        }
+        Commands::Next(next) => print_next_runs(&config_path, &next, json),
+        Commands::Ctl { socket, action } => {
+            if !control(&config_path, socket, action.into()).await? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Commands::RunNow(run_now) => {
+            let record_state = !run_now.no_state;
+            let status = match (&run_now.name, &run_now.tag) {
+                (_, Some(tag)) => {
+                    run_tagged_now(&config_path, args.state_path, tag, record_state).await?
+                }
+                (Some(name), None) => {
+                    run_command_now(&config_path, args.state_path, name, record_state).await?
+                }
+                (None, None) => unreachable!("clap requires a name or a tag"),
+            };
+            if status != 0 {
+                std::process::exit(if status > 0 { status } else { 1 });
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn parse(args: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once("zephyr").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_no_subcommand_runs_scheduler() {
+        let args = parse(&["--config", "/etc/zephyr.toml"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::Run(RunArgs::default())
+        );
+        assert_eq!(args.config, PathBuf::from("/etc/zephyr.toml"));
+        assert_eq!(args.state_path, None);
+    }
+
+    #[test]
+    fn test_service_user_flag() {
+        for args in [
+            &["service", "--user", "start"][..],
+            &["service", "start", "--user"][..],
+        ] {
+            assert_eq!(
+                parse(args).unwrap().subcommand().unwrap(),
+                Commands::Service {
+                    user: true,
+                    action: ServiceAction::Start
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_service_status() {
+        assert_eq!(
+            parse(&["service", "status"]).unwrap().subcommand().unwrap(),
+            Commands::Service {
+                user: false,
+                action: ServiceAction::Status
+            }
+        );
+    }
+
+    #[test]
+    fn test_service_install_keeps_config() {
+        for args in [
+            &[
+                "--config",
+                "/etc/zephyr/scheduler.toml",
+                "service",
+                "install",
+            ][..],
+            &[
+                "service",
+                "install",
+                "--config",
+                "/etc/zephyr/scheduler.toml",
+            ][..],
+            &["--install-service", "-c", "/etc/zephyr/scheduler.toml"][..],
+        ] {
+            let args = parse(args).unwrap();
+            assert_eq!(args.config, PathBuf::from("/etc/zephyr/scheduler.toml"));
+            assert!(matches!(
+                args.subcommand().unwrap(),
+                Commands::Service {
+                    action: ServiceAction::Install { .. },
+                    ..
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_service_install_exec_path() {
+        let args = parse(&[
+            "--config",
+            "~/zephyr.toml",
+            "service",
+            "install",
+            "--exec-path",
+            "/opt/zephyr/bin/zephyr",
+            "--log-path",
+            "~/logs/zephyr.log",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::Service {
+                user: false,
+                action: ServiceAction::Install {
+                    exec_path: Some(PathBuf::from("/opt/zephyr/bin/zephyr")),
+                    log_path: Some(PathBuf::from("~/logs/zephyr.log")),
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_dry_run_flag() {
+        let dry_run = Commands::Run(RunArgs { dry_run: true });
+        for args in [
+            &["--dry-run"][..],
+            &["run", "--dry-run"][..],
+            &["--dry-run", "run"][..],
+        ] {
+            assert_eq!(parse(args).unwrap().subcommand().unwrap(), dry_run);
+        }
+        assert!(parse(&["--dry-run", "list"]).unwrap().subcommand().is_err());
+        assert!(parse(&["list", "--dry-run"]).is_err());
+    }
+
+    #[test]
+    fn test_ctl_parse() {
+        let args = parse(&["ctl", "trigger", "backup"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::Ctl {
+                socket: None,
+                action: CtlAction::Trigger {
+                    name: "backup".to_string()
+                }
+            }
+        );
+
+        let args = parse(&["ctl", "--socket", "/run/zephyr.sock", "status"]).unwrap();
+        let Commands::Ctl { socket, action } = args.subcommand().unwrap() else {
+            panic!("expected ctl");
+        };
+        assert_eq!(socket, Some(PathBuf::from("/run/zephyr.sock")));
+        assert_eq!(ControlRequest::from(action), ControlRequest::Status);
+
+        assert!(parse(&["ctl", "pause"]).is_err());
+        let Commands::Ctl { action, .. } =
+            parse(&["ctl", "pause-all"]).unwrap().subcommand().unwrap()
+        else {
+            panic!("expected ctl");
+        };
+        assert_eq!(ControlRequest::from(action), ControlRequest::PauseAll);
+    }
+
+    #[test]
+    fn test_subcommands_parse() {
+        let args = parse(&["service", "install"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::Service {
+                user: false,
+                action: ServiceAction::Install {
+                    exec_path: None,
+                    log_path: None,
+                }
+            }
+        );
+
+        let args = parse(&["state", "reset", "-s", "/tmp/state.db"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::State {
+                action: StateAction::Reset {
+                    command: None,
+                    yes: false,
+                }
+            }
+        );
+        assert_eq!(args.state_path, Some(PathBuf::from("/tmp/state.db")));
+
+        let args = parse(&["state", "reset", "--command", "backup"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::State {
+                action: StateAction::Reset {
+                    command: Some("backup".to_string()),
+                    yes: false,
+                }
+            }
+        );
+
+        let args = parse(&["state", "export", "-o", "state.json"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::State {
+                action: StateAction::Export {
+                    file: Some(PathBuf::from("state.json"))
+                }
+            }
+        );
+        let args = parse(&["state", "import", "state.json", "--replace"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::State {
+                action: StateAction::Import {
+                    file: PathBuf::from("state.json"),
+                    merge: false,
+                    replace: true,
+                }
+            }
+        );
+        assert!(parse(&["state", "import", "state.json", "--merge", "--replace"]).is_err());
+
+        let args = parse(&["list", "-c", "/tmp/scheduler.toml"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::List(ListArgs::default())
+        );
+        assert_eq!(args.config, PathBuf::from("/tmp/scheduler.toml"));
+
+        let args = parse(&["-c", "/tmp/scheduler.toml", "list"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::List(ListArgs::default())
+        );
+        assert_eq!(args.config, PathBuf::from("/tmp/scheduler.toml"));
+
+        let args = parse(&["list", "--json"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::List(ListArgs {
+                json: true,
+                ..Default::default()
+            })
+        );
+
+        let args = parse(&["list", "--tag", "reports"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::List(ListArgs {
+                tag: Some("reports".to_string()),
+                ..Default::default()
+            })
+        );
+
+        let args = parse(&["--output", "json", "list"]).unwrap();
+        assert_eq!(args.output, OutputFormat::Json);
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::List(ListArgs::default())
+        );
+        assert_eq!(
+            parse(&["history", "backup", "--output", "json"])
+                .unwrap()
+                .output,
+            OutputFormat::Json
+        );
+        assert_eq!(parse(&["list"]).unwrap().output, OutputFormat::Text);
+        assert!(parse(&["list", "--output", "yaml"]).is_err());
+
+        let args = parse(&["run-now", "backup"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::RunNow(RunNowArgs {
+                name: Some("backup".to_string()),
+                tag: None,
+                no_state: false,
+            })
+        );
+
+        let args = parse(&["run-now", "--tag", "backups"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::RunNow(RunNowArgs {
+                name: None,
+                tag: Some("backups".to_string()),
+                no_state: false,
+            })
+        );
+        assert!(parse(&["run-now"]).is_err());
+        assert!(parse(&["run-now", "backup", "--tag", "backups"]).is_err());
+
+        let args = parse(&["run-now", "backup", "--no-state"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::RunNow(RunNowArgs {
+                name: Some("backup".to_string()),
+                tag: None,
+                no_state: true,
+            })
+        );
+
+        let args = parse(&["history", "backup", "--limit", "5", "--failed"]).unwrap();
+        assert_eq!(
+            args.subcommand().unwrap(),
+            Commands::History(HistoryArgs {
+                name: "backup".to_string(),
+                limit: 5,
+                failed: true,
+                json: false,
+            })
+        );
+
+        assert_eq!(
+            parse(&["validate"]).unwrap().subcommand().unwrap(),
+            Commands::Validate
+        );
+        assert_eq!(
+            parse(&["run"]).unwrap().subcommand().unwrap(),
+            Commands::Run(RunArgs::default())
+        );
+        assert!(parse(&["service", "restart"]).is_err());
+    }
+
+    #[test]
+    fn test_legacy_flags_map_to_subcommands() {
+        assert_eq!(
+            parse(&["-i"]).unwrap().subcommand().unwrap(),
+            Commands::Service {
+                user: false,
+                action: ServiceAction::Install {
+                    exec_path: None,
+                    log_path: None,
+                }
+            }
+        );
+        assert_eq!(
+            parse(&["--stop-service"]).unwrap().subcommand().unwrap(),
+            Commands::Service {
+                user: false,
+                action: ServiceAction::Stop
+            }
+        );
+        assert_eq!(
+            parse(&["-r"]).unwrap().subcommand().unwrap(),
+            Commands::State {
+                action: StateAction::Reset {
+                    command: None,
+                    yes: true,
+                }
+            }
+        );
+        assert_eq!(
+            parse(&["-r"]).unwrap().subcommand().unwrap(),
+            parse(&["state", "reset", "--yes"])
+                .unwrap()
+                .subcommand()
+                .unwrap()
+        );
+        assert_eq!(
+            parse(&["--list"]).unwrap().subcommand().unwrap(),
+            Commands::List(ListArgs::default())
+        );
+        assert_eq!(
+            parse(&["--list", "--tag", "reports"])
+                .unwrap()
+                .subcommand()
+                .unwrap(),
+            Commands::List(ListArgs {
+                tag: Some("reports".to_string()),
+                ..Default::default()
+            })
+        );
+        assert!(parse(&["--tag", "reports"]).is_err());
+        assert_eq!(
+            parse(&["--next", "backup", "--count", "3"])
+                .unwrap()
+                .subcommand()
+                .unwrap(),
+            Commands::Next(NextArgs {
+                name: "backup".to_string(),
+                count: 3,
+            })
+        );
+        assert_eq!(
+            parse(&["next", "backup"]).unwrap().subcommand().unwrap(),
+            parse(&["--next", "backup"]).unwrap().subcommand().unwrap()
+        );
+        assert_eq!(
+            parse(&["--run-tag", "backups"])
+                .unwrap()
+                .subcommand()
+                .unwrap(),
+            Commands::RunNow(RunNowArgs {
+                name: None,
+                tag: Some("backups".to_string()),
+                no_state: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_log_filter() {
+        for (config_level, expected) in [
+            ("trace", "trace"),
+            ("debug", "debug"),
+            ("info", "info"),
+            ("warn", "warn"),
+            ("Warning", "warn"),
+            ("ERROR", "error"),
+        ] {
+            assert_eq!(log_filter(Some(config_level), None, None), expected);
+        }
+        assert_eq!(log_filter(None, None, None), "info");
+        assert_eq!(
+            log_filter(Some("info"), None, Some("zephyr_scheduler=trace")),
+            "zephyr_scheduler=trace"
+        );
+        assert_eq!(log_filter(Some("info"), None, Some("  ")), "info");
+        assert_eq!(
+            log_filter(Some("info"), Some("error"), Some("debug")),
+            "error"
+        );
+
+        let args = parse(&["run", "--log-level", "debug"]).unwrap();
+        assert_eq!(args.log.level.as_deref(), Some("debug"));
+        assert!(parse(&["--log-level", "loud"]).is_err());
+    }
+
+    /// Writer that collects log output in memory
+    #[derive(Clone, Default)]
+    struct Buffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log_completed_event(format: LogFormat) -> String {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(reload::Layer::new(EnvFilter::new("info")).0)
+            .with(format_layer(format, move || writer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            info!(
+                command = "backup",
+                event = "completed",
+                exit_code = 0,
+                duration_ms = 12,
+                "Command 'backup' completed successfully"
+            );
+        });
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_next_runs_of_cron_command() {
+        let command = CommandConfig {
+            name: "report".to_string(),
+            command: "report.sh".to_string(),
+            cron: Some("0 30 9 * * Mon-Fri".to_string()),
+            timezone: Some("America/New_York".to_string()),
+            ..Default::default()
+        };
+        let now = Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap();
+
+        let expected = "0 30 9 * * Mon-Fri"
+            .parse::<cron::Schedule>()
+            .unwrap()
+            .after(&now.with_timezone(&chrono_tz::America::New_York))
+            .take(5)
+            .map(|run| run.with_timezone(&Utc))
+            .collect::<Vec<_>>();
+        let runs = next_runs(&command, now, 5).unwrap();
+        assert_eq!(runs, expected);
+        // 09:30 in New York, on the next five weekdays
+        assert_eq!(
+            runs[0],
+            Utc.with_ymd_and_hms(2026, 10, 16, 13, 30, 0).unwrap()
+        );
+        assert_eq!(
+            runs[1],
+            Utc.with_ymd_and_hms(2026, 10, 19, 13, 30, 0).unwrap()
+        );
+
+        let dependent = CommandConfig {
+            name: "publish".to_string(),
+            cron: None,
+            depends_on: Some(vec!["report".to_string()]),
+            ..command
+        };
+        assert_eq!(
+            next_runs(&dependent, now, 5).unwrap_err().to_string(),
+            "Command 'publish' has no schedule of its own; it runs after report"
+        );
+    }
+
+    #[test]
+    fn test_list_json_round_trips() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 16, 12, 0, 0).unwrap();
+        let status = CommandStatus {
+            name: "backup".to_string(),
+            enabled: true,
+            command: "backup.sh".to_string(),
+            schedule: "every 60 minutes".to_string(),
+            last_execution: Some(now - chrono::Duration::minutes(30)),
+            last_status: Some(0),
+            next_run: Some(now + chrono::Duration::minutes(30)),
+            auto_disabled: false,
+            running: false,
+            paused: false,
+            completed: false,
+            backing_off: false,
+        };
+        let never_ran = CommandStatus {
+            name: "report".to_string(),
+            last_execution: None,
+            last_status: None,
+            next_run: None,
+            enabled: false,
+            ..status.clone()
+        };
+
+        let output = list_json(&[status, never_ran], now).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(value[0]["last_execution"], "2026-10-16T11:30:00Z");
+        assert_eq!(value[0]["next_scheduled"], "2026-10-16T12:30:00Z");
+        assert_eq!(value[1]["last_status"], serde_json::Value::Null);
+
+        let views: Vec<CommandView> = serde_json::from_str(&output).unwrap();
+        assert_eq!(
+            views[0],
+            CommandView {
+                name: "backup".to_string(),
+                enabled: true,
+                command: "backup.sh".to_string(),
+                schedule: "every 60 minutes".to_string(),
+                last_execution: Some(now - chrono::Duration::minutes(30)),
+                last_status: Some(0),
+                next_scheduled: Some(now + chrono::Duration::minutes(30)),
+                seconds_until_next: Some(1800),
+                auto_disabled: false,
+                completed: false,
+                backing_off: false,
+            }
+        );
+        assert_eq!(views[1].name, "report");
+        assert_eq!(views[1].next_scheduled, None);
+        assert_eq!(views[1].seconds_until_next, None);
+    }
+
+    #[test]
+    fn test_json_log_format_includes_event_fields() {
+        let output = log_completed_event(LogFormat::Json);
+        let event: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(event["command"], "backup");
+        assert_eq!(event["event"], "completed");
+        assert_eq!(event["exit_code"], 0);
+        assert_eq!(event["duration_ms"], 12);
+        assert_eq!(event["message"], "Command 'backup' completed successfully");
+        assert_eq!(event["level"], "INFO");
+
+        let args = parse(&["--log-format", "json"]).unwrap();
+        assert_eq!(args.log.format, Some(LogFormat::Json));
+        assert!(parse(&["--log-format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_text_log_format_shows_only_message() {
+        let output = log_completed_event(LogFormat::Text);
+        assert!(output.contains(" INFO"));
+        assert!(output
+            .trim_end()
+            .ends_with("Command 'backup' completed successfully"));
+        assert!(!output.contains("event="));
+    }
+
+    #[test]
+    fn test_conflicting_flags_are_rejected() {
+        assert!(parse(&["-i", "-u"]).is_err());
+        assert!(parse(&["--start-service", "--stop-service"]).is_err());
+        assert!(parse(&["-r", "--list"]).is_err());
+        assert!(parse(&["--list", "--validate"]).is_err());
+        assert!(parse(&["service", "install", "uninstall"]).is_err());
+        assert!(parse(&["-i", "service", "stop"])
+            .unwrap()
+            .subcommand()
+            .is_err());
+    }
+
+    #[test]
+    fn test_find_command_lists_valid_names() {
+        let command = |name: &str| CommandConfig {
+            name: name.to_string(),
+            interval_minutes: Some(1.0),
+            ..Default::default()
+        };
+        let config = Config {
+            general: Default::default(),
+            http: None,
+            metrics: None,
+            commands: vec![command("backup"), command("cleanup")],
+        };
+
+        assert_eq!(find_command(&config, "cleanup").unwrap().name, "cleanup");
+        let error = find_command(&config, "backpu").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "No command named 'backpu' in configuration. Valid commands: backup, cleanup"
+        );
+    }
+
+    #[test]
+    fn test_reset_command_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_manager = StateManager::new(dir.path().join("state.db")).unwrap();
+        let command = |name: &str| CommandConfig {
+            name: name.to_string(),
+            interval_minutes: Some(1.0),
+            ..Default::default()
+        };
+        let now = Utc::now();
+        for name in ["backup", "cleanup"] {
+            state_manager
+                .save_command_state(&command(name), Some(now), now)
+                .unwrap();
+        }
+
+        reset_command_state(&state_manager, "backup").unwrap();
+        assert!(state_manager.get_command_state("backup").unwrap().is_none());
+        assert!(state_manager
+            .get_command_state("cleanup")
+            .unwrap()
+            .is_some());
+
+        let error = reset_command_state(&state_manager, "backup")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "No state stored for command 'backup'. Commands in the state database: cleanup"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_tagged_now_runs_each_enabled_tagged_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("ran.log");
+        let config_path = dir.path().join("config.toml");
+        let command = |name: &str, tags: &str, enabled: bool| {
+            format!(
+                "[[commands]]\nname = \"{name}\"\ncommand = \"echo {name} >> {}\"\n\
+                 interval_minutes = 60\ntags = [{tags}]\nenabled = {enabled}\n\n",
+                log.display()
+            )
+        };
+        std::fs::write(
+            &config_path,
+            [
+                command("db", "\"backups\"", true),
+                command("report", "\"reports\"", true),
+                command("photos", "\"backups\", \"reports\"", true),
+                command("old", "\"backups\"", false),
+            ]
+            .concat(),
+        )
+        .unwrap();
+
+        let status = run_tagged_now(&config_path, None, "backups", false)
+            .await
+            .unwrap();
+        assert_eq!(status, 0);
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "db\nphotos\n");
+
+        let error = run_tagged_now(&config_path, None, "nightly", false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No enabled commands tagged 'nightly' in configuration"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Prometheus metrics for command executions
+
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry};
+use std::time::Duration;
+
+/// Collectors updated by the scheduler and rendered for the `/metrics` endpoint
+pub struct Metrics {
+    registry: Registry,
+    executions: IntCounterVec,
+    duration: HistogramVec,
+    scheduled: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let executions = IntCounterVec::new(
+            Opts::new(
+                "zephyr_command_executions_total",
+                "Number of finished command executions",
+            ),
+            &["name", "status"],
+        )
+        .expect("valid executions metric");
+        let duration = HistogramVec::new(
+            HistogramOpts::new(
+                "zephyr_command_duration_seconds",
+                "How long command executions took",
+            )
+            .buckets(vec![
+                0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0,
+            ]),
+            &["name"],
+        )
+        .expect("valid duration metric");
+        let scheduled = IntGauge::new(
+            "zephyr_commands_scheduled",
+            "Number of commands the scheduler is running or waiting to run",
+        )
+        .expect("valid scheduled metric");
+
+        let registry = Registry::new();
+        registry
+            .register(Box::new(executions.clone()))
+            .expect("executions metric registered once");
+        registry
+            .register(Box::new(duration.clone()))
+            .expect("duration metric registered once");
+        registry
+            .register(Box::new(scheduled.clone()))
+            .expect("scheduled metric registered once");
+
+        Self {
+            registry,
+            executions,
+            duration,
+            scheduled,
+        }
+    }
+
+    /// Counts a finished execution of a command and observes how long it took
+    pub fn record_execution(&self, name: &str, success: bool, duration: Duration) {
+        let status = if success { "success" } else { "failure" };
+        self.executions.with_label_values(&[name, status]).inc();
+        self.duration
+            .with_label_values(&[name])
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Sets how many commands are currently scheduled
+    pub fn set_scheduled(&self, count: usize) {
+        self.scheduled.set(count as i64);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        prometheus::TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding of gathered metrics");
+        String::from_utf8(buffer).expect("metrics are valid UTF-8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_includes_recorded_executions() {
+        let metrics = Metrics::new();
+        metrics.record_execution("backup", true, Duration::from_millis(1500));
+        metrics.record_execution("backup", false, Duration::from_secs(2));
+        metrics.set_scheduled(3);
+
+        let rendered = metrics.render();
+        assert!(rendered
+            .contains("zephyr_command_executions_total{name=\"backup\",status=\"success\"} 1"));
+        assert!(rendered
+            .contains("zephyr_command_executions_total{name=\"backup\",status=\"failure\"} 1"));
+        assert!(rendered.contains("zephyr_command_duration_seconds_sum{name=\"backup\"} 3.5"));
+        assert!(rendered.contains("zephyr_commands_scheduled 3"));
+    }
+}
@@ -1,66 +1,164 @@
+use crate::util::{expand_tilde, format_duration};
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+#[cfg(any(target_os = "linux", test))]
+use std::collections::HashMap;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
 use std::fs;
+use std::path::{Path, PathBuf};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
 use std::process::{Command, ExitStatus};
+#[cfg(target_os = "linux")]
 use users::get_current_username;
 
-fn check_status(status: std::io::Result<ExitStatus>, operation: &'static str) -> Result<()> {
-    let status = status.context(operation)?;
-    if !status.success() {
-        anyhow::bail!("{} failed with exit code: {:?}", operation, status.code());
+#[cfg(windows)]
+pub mod windows;
+
+/// Whether the service is installed for the whole system or only for the current user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ServiceScope {
+    /// A system service, which needs root to install
+    #[default]
+    System,
+    /// A systemd user service, run by the user's own service manager
+    User,
+}
+
+impl ServiceScope {
+    /// Returns where the systemd unit file is written
+    pub fn systemd_unit_path(self) -> Result<PathBuf> {
+        match self {
+            Self::System => Ok(PathBuf::from("/etc/systemd/system/zephyr.service")),
+            Self::User => Ok(dirs::config_dir()
+                .context("Failed to find the user configuration directory")?
+                .join("systemd/user/zephyr.service")),
+        }
     }
-    Ok(())
 }
 
-#[cfg(target_os = "linux")]
-pub fn install_service() -> Result<()> {
-    let username = get_current_username()
-        .context("Failed to get current username")?
-        .to_string_lossy()
-        .to_string();
+/// Settings the installed service runs Zephyr with
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    /// Whether to install a system or a user service
+    pub scope: ServiceScope,
+    /// Configuration file the service loads
+    pub config_path: PathBuf,
+    /// State database the service uses, instead of `state_path` from the configuration
+    pub state_path: Option<PathBuf>,
+    /// Zephyr executable the service runs, instead of the one doing the install
+    pub exec_path: Option<PathBuf>,
+    /// File the launchd agent writes the scheduler's output to, instead of
+    /// `~/Library/Logs/zephyr.log`; systemd and Windows services log elsewhere
+    pub log_path: Option<PathBuf>,
+}
+
+impl InstallOptions {
+    /// Returns the executable and arguments the service runs, with every path expanded and
+    /// made absolute
+    pub fn command_line(&self) -> Result<Vec<String>> {
+        let executable = match &self.exec_path {
+            Some(exec_path) => absolute(exec_path)?,
+            None => installed_executable(std::env::current_exe())?,
+        };
+        let mut command_line = vec![
+            executable.to_string_lossy().to_string(),
+            "--config".to_string(),
+            absolute(&self.config_path)?.to_string_lossy().to_string(),
+        ];
+        if let Some(state_path) = &self.state_path {
+            command_line.push("--state-path".to_string());
+            command_line.push(absolute(state_path)?.to_string_lossy().to_string());
+        }
+        Ok(command_line)
+    }
+}
 
-    let service_content = format!(
+/// Where the executable is assumed to be installed when the running one can't be resolved
+#[cfg(unix)]
+const FALLBACK_EXEC_PATH: &str = "/usr/local/bin/zephyr";
+
+/// Returns the executable the service should run: the running one, or on Unix the usual
+/// install location when its path can't be resolved
+fn installed_executable(current_exe: std::io::Result<PathBuf>) -> Result<PathBuf> {
+    match current_exe {
+        Ok(path) => Ok(path),
+        #[cfg(unix)]
+        Err(e) => {
+            tracing::warn!(
+                "Failed to resolve the path of the zephyr executable ({}), using {}; pass \
+                 --exec-path to choose another",
+                e,
+                FALLBACK_EXEC_PATH
+            );
+            Ok(PathBuf::from(FALLBACK_EXEC_PATH))
+        }
+        #[cfg(not(unix))]
+        Err(e) => Err(e).context("Failed to resolve the path of the zephyr executable"),
+    }
+}
+
+fn absolute(path: &Path) -> Result<PathBuf> {
+    std::path::absolute(expand_tilde(path))
+        .with_context(|| format!("Failed to resolve path {:?}", path))
+}
+
+/// Renders the systemd unit that runs `command_line`
+///
+/// A system unit runs as `username`; a user unit (`username` of `None`) runs as whoever
+/// owns the service manager and is started with the user's session. The scheduler reports
+/// readiness and pings the watchdog through `sd_notify`, so systemd restarts it if its loop
+/// stops responding.
+pub fn systemd_unit(username: Option<&str>, command_line: &[String]) -> String {
+    let exec_start = command_line
+        .iter()
+        .map(|arg| systemd_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let (user, wanted_by) = match username {
+        Some(username) => (format!("User={}\n", username), "multi-user.target"),
+        None => (String::new(), "default.target"),
+    };
+    format!(
         "[Unit]
 Description=Zephyr Task Scheduler
 After=network.target
 
 [Service]
-Type=simple
-User={}
-ExecStart=/usr/local/bin/zephyr
+Type=notify
+{}ExecStart={}
+WatchdogSec=120
 Restart=always
 RestartSec=60
 
 [Install]
-WantedBy=multi-user.target",
-        username
-    );
-
-    let service_path = "/etc/systemd/system/zephyr.service";
-    fs::write(service_path, service_content).context("Failed to write systemd service file")?;
-
-    check_status(
-        Command::new("systemctl").args(["daemon-reload"]).status(),
-        "Failed to reload systemd daemon",
-    )?;
-
-    check_status(
-        Command::new("systemctl")
-            .args(["enable", "zephyr.service"])
-            .status(),
-        "Failed to enable zephyr service",
-    )?;
-
-    Ok(())
+WantedBy={}",
+        user, exec_start, wanted_by
+    )
 }
 
-#[cfg(target_os = "macos")]
-pub fn install_service() -> Result<()> {
-    let username = get_current_username()
-        .context("Failed to get current username")?
-        .to_string_lossy()
-        .to_string();
+/// Escapes an argument for an `ExecStart=` line, doubling `%` and `$` so systemd doesn't
+/// expand them and quoting it when it contains whitespace, quotes or backslashes
+fn systemd_quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+    if !arg.is_empty()
+        && !arg
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\\' | '\''))
+    {
+        return arg;
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
 
-    let plist_content = format!(
+/// Renders the launchd property list that runs `command_line`, appending its output to
+/// `log_path`
+pub fn launchd_plist(command_line: &[String], log_path: &Path) -> String {
+    let program_arguments = command_line
+        .iter()
+        .map(|arg| format!("        <string>{}</string>", xml_escape(arg)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
         "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
 <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">
 <plist version=\"1.0\">
@@ -69,92 +167,230 @@ pub fn install_service() -> Result<()> {
     <string>com.zephyr.scheduler</string>
     <key>ProgramArguments</key>
     <array>
-        <string>/usr/local/bin/zephyr</string>
+{}
     </array>
     <key>RunAtLoad</key>
     <true/>
     <key>KeepAlive</key>
     <true/>
     <key>StandardErrorPath</key>
-    <string>/Users/{}/Library/Logs/zephyr.log</string>
+    <string>{log_path}</string>
     <key>StandardOutPath</key>
-    <string>/Users/{}/Library/Logs/zephyr.log</string>
+    <string>{log_path}</string>
 </dict>
 </plist>",
-        username, username
-    );
+        program_arguments,
+        log_path = xml_escape(&log_path.to_string_lossy())
+    )
+}
 
-    let plist_dir = format!("/Users/{}/Library/LaunchAgents", username);
-    let plist_path = format!("{}/com.zephyr.scheduler.plist", plist_dir);
-    let logs_dir = format!("/Users/{}/Library/Logs", username);
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
 
-    fs::create_dir_all(&plist_dir).context("Failed to create LaunchAgents directory")?;
-    fs::create_dir_all(&logs_dir).context("Failed to create Logs directory")?;
-    fs::write(&plist_path, plist_content).context("Failed to write launchd plist file")?;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn check_status(status: std::io::Result<ExitStatus>, operation: &'static str) -> Result<()> {
+    let status = status.context(operation)?;
+    if !status.success() {
+        anyhow::bail!("{} failed with exit code: {:?}", operation, status.code());
+    }
+    Ok(())
+}
+
+/// Runs `systemctl`, against the user's service manager for a user service
+#[cfg(target_os = "linux")]
+fn systemctl(scope: ServiceScope, args: &[&str]) -> std::io::Result<ExitStatus> {
+    systemctl_command(scope).args(args).status()
+}
+
+#[cfg(target_os = "linux")]
+fn systemctl_command(scope: ServiceScope) -> Command {
+    let mut command = Command::new("systemctl");
+    if scope == ServiceScope::User {
+        command.arg("--user");
+    }
+    command
+}
+
+#[cfg(target_os = "linux")]
+pub fn install_service(options: &InstallOptions) -> Result<()> {
+    let username = get_current_username()
+        .context("Failed to get current username")?
+        .to_string_lossy()
+        .to_string();
+    let scope = options.scope;
+    if options.log_path.is_some() {
+        tracing::warn!("--log-path only applies to launchd; systemd sends output to the journal");
+    }
+
+    let unit_user = (scope == ServiceScope::System).then_some(username.as_str());
+    let service_content = systemd_unit(unit_user, &options.command_line()?);
+
+    let service_path = scope.systemd_unit_path()?;
+    if let Some(parent) = service_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create systemd unit directory")?;
+    }
+    fs::write(&service_path, service_content).context("Failed to write systemd service file")?;
 
     check_status(
-        Command::new("launchctl")
-            .args(["load", &plist_path])
-            .status(),
-        "Failed to load launchd service",
+        systemctl(scope, &["daemon-reload"]),
+        "Failed to reload systemd daemon",
     )?;
 
+    check_status(
+        systemctl(scope, &["enable", "zephyr.service"]),
+        "Failed to enable zephyr service",
+    )?;
+
+    if scope == ServiceScope::User {
+        tracing::info!(
+            "User services only run while you are logged in; run `loginctl enable-linger {}` \
+             to keep Zephyr running after you log out",
+            username
+        );
+    }
+
+    Ok(())
+}
+
+/// Label of the launchd agent
+#[cfg(target_os = "macos")]
+const LAUNCHD_LABEL: &str = "com.zephyr.scheduler";
+
+/// Returns where the launchd agent's property list is written
+#[cfg(target_os = "macos")]
+fn launch_agent_path() -> Result<PathBuf> {
+    Ok(home_dir()?.join("Library/LaunchAgents/com.zephyr.scheduler.plist"))
+}
+
+#[cfg(target_os = "macos")]
+fn home_dir() -> Result<PathBuf> {
+    dirs::home_dir().context("Failed to find the home directory")
+}
+
+/// Returns the launchd domain of the current user's GUI session
+#[cfg(target_os = "macos")]
+fn launchd_domain() -> String {
+    format!("gui/{}", users::get_current_uid())
+}
+
+/// Runs `launchctl` with `args`, falling back to the deprecated `legacy_args` for systems
+/// without `bootstrap`/`bootout`
+#[cfg(target_os = "macos")]
+fn launchctl(args: &[&str], legacy_args: &[&str], operation: &'static str) -> Result<()> {
+    match Command::new("launchctl").args(args).status() {
+        Ok(status) if status.success() => Ok(()),
+        result => {
+            tracing::debug!(
+                "launchctl {} failed ({:?}), falling back to launchctl {}",
+                args[0],
+                result,
+                legacy_args[0]
+            );
+            check_status(
+                Command::new("launchctl").args(legacy_args).status(),
+                operation,
+            )
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn install_service(options: &InstallOptions) -> Result<()> {
+    let log_path = match &options.log_path {
+        Some(log_path) => absolute(log_path)?,
+        None => home_dir()?.join("Library/Logs/zephyr.log"),
+    };
+    let plist_content = launchd_plist(&options.command_line()?, &log_path);
+
+    let plist_path = launch_agent_path()?;
+    if let Some(parent) = plist_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create LaunchAgents directory")?;
+    }
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create log directory")?;
+    }
+    fs::write(&plist_path, plist_content).context("Failed to write launchd plist file")?;
+
+    let plist_path = plist_path.to_string_lossy();
+    launchctl(
+        &["bootstrap", &launchd_domain(), &plist_path],
+        &["load", &plist_path],
+        "Failed to load launchd service",
+    )
+}
+
+#[cfg(windows)]
+pub fn install_service(options: &InstallOptions) -> Result<()> {
+    system_scope_only(options.scope)?;
+    if options.log_path.is_some() {
+        tracing::warn!(
+            "--log-path only applies to launchd; the Windows service logs to `log_dir` from \
+             the configuration"
+        );
+    }
+    windows::install(options)
+}
+
+#[cfg(windows)]
+pub fn uninstall_service(scope: ServiceScope) -> Result<()> {
+    system_scope_only(scope)?;
+    windows::uninstall()
+}
+
+#[cfg(windows)]
+fn system_scope_only(scope: ServiceScope) -> Result<()> {
+    if scope == ServiceScope::User {
+        anyhow::bail!("User services (--user) are only supported with systemd");
+    }
     Ok(())
 }
 
-#[cfg(not(any(target_os = "linux", target_os = "macos")))]
-pub fn install_service() -> Result<()> {
-    anyhow::bail!("Service installation is not supported on this platform (only Linux and macOS are supported)");
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn install_service(_options: &InstallOptions) -> Result<()> {
+    anyhow::bail!("Service installation is not supported on this platform (only Linux, macOS and Windows are supported)");
 }
 
-#[cfg(not(any(target_os = "linux", target_os = "macos")))]
-pub fn uninstall_service() -> Result<()> {
-    anyhow::bail!("Service uninstallation is not supported on this platform (only Linux and macOS are supported)");
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn uninstall_service(_scope: ServiceScope) -> Result<()> {
+    anyhow::bail!("Service uninstallation is not supported on this platform (only Linux, macOS and Windows are supported)");
 }
 
 #[cfg(target_os = "linux")]
-pub fn uninstall_service() -> Result<()> {
+pub fn uninstall_service(scope: ServiceScope) -> Result<()> {
     check_status(
-        Command::new("systemctl")
-            .args(["stop", "zephyr.service"])
-            .status(),
+        systemctl(scope, &["stop", "zephyr.service"]),
         "Failed to stop zephyr service",
     )?;
 
     check_status(
-        Command::new("systemctl")
-            .args(["disable", "zephyr.service"])
-            .status(),
+        systemctl(scope, &["disable", "zephyr.service"]),
         "Failed to disable zephyr service",
     )?;
 
-    fs::remove_file("/etc/systemd/system/zephyr.service")
-        .context("Failed to remove systemd service file")?;
+    fs::remove_file(scope.systemd_unit_path()?).context("Failed to remove systemd service file")?;
 
     check_status(
-        Command::new("systemctl").args(["daemon-reload"]).status(),
+        systemctl(scope, &["daemon-reload"]),
         "Failed to reload systemd daemon",
     )?;
 
     Ok(())
 }
 
+/// Removes the launchd agent; agents always belong to the current user, so `scope` makes no
+/// difference
 #[cfg(target_os = "macos")]
-pub fn uninstall_service() -> Result<()> {
-    let username = get_current_username()
-        .context("Failed to get current username")?
-        .to_string_lossy()
-        .to_string();
-
-    let plist_path = format!(
-        "/Users/{}/Library/LaunchAgents/com.zephyr.scheduler.plist",
-        username
-    );
-
-    check_status(
-        Command::new("launchctl")
-            .args(["unload", &plist_path])
-            .status(),
+pub fn uninstall_service(_scope: ServiceScope) -> Result<()> {
+    let plist_path = launch_agent_path()?;
+    let service_target = format!("{}/{}", launchd_domain(), LAUNCHD_LABEL);
+    launchctl(
+        &["bootout", &service_target],
+        &["unload", &plist_path.to_string_lossy()],
         "Failed to unload launchd service",
     )?;
 
@@ -163,13 +399,11 @@ pub fn uninstall_service() -> Result<()> {
     Ok(())
 }
 
-pub fn start_service() -> Result<()> {
+pub fn start_service(scope: ServiceScope) -> Result<()> {
     #[cfg(target_os = "linux")]
     {
         check_status(
-            Command::new("systemctl")
-                .args(["start", "zephyr.service"])
-                .status(),
+            systemctl(scope, &["start", "zephyr.service"]),
             "Failed to start zephyr service",
         )?;
         Ok(())
@@ -177,28 +411,34 @@ pub fn start_service() -> Result<()> {
 
     #[cfg(target_os = "macos")]
     {
+        let _ = scope;
         check_status(
             Command::new("launchctl")
-                .args(["start", "com.zephyr.scheduler"])
+                .args(["start", LAUNCHD_LABEL])
                 .status(),
             "Failed to start zephyr service",
         )?;
         Ok(())
     }
 
-    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    #[cfg(windows)]
     {
-        anyhow::bail!("Service management is not supported on this platform (only Linux and macOS are supported)");
+        system_scope_only(scope)?;
+        windows::start()
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        let _ = scope;
+        anyhow::bail!("Service management is not supported on this platform (only Linux, macOS and Windows are supported)");
     }
 }
 
-pub fn stop_service() -> Result<()> {
+pub fn stop_service(scope: ServiceScope) -> Result<()> {
     #[cfg(target_os = "linux")]
     {
         check_status(
-            Command::new("systemctl")
-                .args(["stop", "zephyr.service"])
-                .status(),
+            systemctl(scope, &["stop", "zephyr.service"]),
             "Failed to stop zephyr service",
         )?;
         Ok(())
@@ -206,17 +446,406 @@ pub fn stop_service() -> Result<()> {
 
     #[cfg(target_os = "macos")]
     {
+        let _ = scope;
         check_status(
             Command::new("launchctl")
-                .args(["stop", "com.zephyr.scheduler"])
+                .args(["stop", LAUNCHD_LABEL])
                 .status(),
             "Failed to stop zephyr service",
         )?;
         Ok(())
     }
 
-    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    #[cfg(windows)]
     {
-        anyhow::bail!("Service management is not supported on this platform (only Linux and macOS are supported)");
+        system_scope_only(scope)?;
+        windows::stop()
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        let _ = scope;
+        anyhow::bail!("Service management is not supported on this platform (only Linux, macOS and Windows are supported)");
+    }
+}
+
+/// What the service manager reports about the Zephyr service
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServiceStatus {
+    pub installed: bool,
+    pub running: bool,
+    pub pid: Option<u32>,
+    /// When the service last started, if the service manager reports it
+    pub started_at: Option<DateTime<Utc>>,
+}
+
+impl ServiceStatus {
+    /// Returns the exit code for `zephyr service status`: 0 when running, 1 when installed
+    /// but stopped and 2 when not installed
+    pub fn exit_code(&self) -> i32 {
+        match (self.installed, self.running) {
+            (_, true) => 0,
+            (true, false) => 1,
+            (false, false) => 2,
+        }
+    }
+
+    /// Renders the status as one `Name: value` line per known field
+    pub fn describe(&self, now: DateTime<Utc>) -> String {
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        let mut lines = vec![
+            format!("Installed: {}", yes_no(self.installed)),
+            format!("Running:   {}", yes_no(self.running)),
+        ];
+        if let Some(pid) = self.pid {
+            lines.push(format!("PID:       {}", pid));
+        }
+        if let Some(started_at) = self.started_at {
+            lines.push(format!("Uptime:    {}", format_duration(now - started_at)));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Parses the output of `systemctl show -p LoadState,ActiveState,MainPID,ActiveEnterTimestamp`
+#[cfg(any(target_os = "linux", test))]
+fn parse_systemctl_show(output: &str) -> ServiceStatus {
+    let properties: HashMap<&str, &str> = output
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect();
+    let installed = properties
+        .get("LoadState")
+        .is_some_and(|state| *state != "not-found");
+    let running = matches!(
+        properties.get("ActiveState").copied(),
+        Some("active" | "reloading")
+    );
+    let pid = properties
+        .get("MainPID")
+        .and_then(|pid| pid.parse().ok())
+        .filter(|pid| *pid != 0);
+    let started_at = properties
+        .get("ActiveEnterTimestamp")
+        .filter(|_| running)
+        .and_then(|timestamp| parse_systemd_timestamp(timestamp));
+    ServiceStatus {
+        installed,
+        running,
+        pid,
+        started_at,
+    }
+}
+
+/// Parses a timestamp as systemctl prints it, e.g. `Thu 2026-10-15 10:00:00 CEST`
+///
+/// systemctl prints the time in the local time zone, so anything but `UTC` is read as local
+/// time; the zone abbreviation itself is ambiguous and ignored.
+#[cfg(any(target_os = "linux", test))]
+fn parse_systemd_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    use chrono::{Local, NaiveDateTime, TimeZone};
+
+    let mut fields = timestamp.split_whitespace().skip(1);
+    let date_time = format!("{} {}", fields.next()?, fields.next()?);
+    let naive = NaiveDateTime::parse_from_str(&date_time, "%Y-%m-%d %H:%M:%S").ok()?;
+    match fields.next() {
+        Some("UTC") => Some(naive.and_utc()),
+        _ => Local
+            .from_local_datetime(&naive)
+            .single()
+            .map(|time| time.with_timezone(&Utc)),
+    }
+}
+
+/// Parses the output of `launchctl list com.zephyr.scheduler` for a loaded agent, which has
+/// a `"PID" = 123;` entry only while it is running
+#[cfg(any(target_os = "macos", test))]
+fn parse_launchctl_list(output: &str) -> ServiceStatus {
+    let pid = output.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        if key.trim().trim_matches('"') != "PID" {
+            return None;
+        }
+        value.trim().trim_end_matches(';').trim().parse().ok()
+    });
+    ServiceStatus {
+        installed: true,
+        running: pid.is_some(),
+        pid,
+        started_at: None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub fn service_status(scope: ServiceScope) -> Result<ServiceStatus> {
+    let output = systemctl_command(scope)
+        .args([
+            "show",
+            "zephyr.service",
+            "--property=LoadState,ActiveState,MainPID,ActiveEnterTimestamp",
+        ])
+        .output()
+        .context("Failed to run systemctl")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to query zephyr service: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse_systemctl_show(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Queries the launchd agent; an agent that isn't loaded counts as installed as long as its
+/// property list exists
+#[cfg(target_os = "macos")]
+pub fn service_status(_scope: ServiceScope) -> Result<ServiceStatus> {
+    let output = Command::new("launchctl")
+        .args(["list", LAUNCHD_LABEL])
+        .output()
+        .context("Failed to run launchctl")?;
+    if !output.status.success() {
+        return Ok(ServiceStatus {
+            installed: launch_agent_path()?.exists(),
+            ..Default::default()
+        });
+    }
+    Ok(parse_launchctl_list(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+#[cfg(windows)]
+pub fn service_status(scope: ServiceScope) -> Result<ServiceStatus> {
+    system_scope_only(scope)?;
+    windows::query()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn service_status(_scope: ServiceScope) -> Result<ServiceStatus> {
+    anyhow::bail!("Service management is not supported on this platform (only Linux, macOS and Windows are supported)");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(config_path: &str) -> InstallOptions {
+        InstallOptions {
+            config_path: PathBuf::from(config_path),
+            exec_path: Some(PathBuf::from("/opt/zephyr/bin/zephyr")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_command_line_uses_current_exe_and_absolute_paths() {
+        let options = InstallOptions {
+            config_path: PathBuf::from("configs/scheduler.toml"),
+            state_path: Some(PathBuf::from("~/zephyr/state.db")),
+            exec_path: None,
+            ..Default::default()
+        };
+        let command_line = options.command_line().unwrap();
+        let home = dirs::home_dir().unwrap();
+        let current_dir = std::env::current_dir().unwrap();
+        assert_eq!(
+            command_line,
+            vec![
+                std::env::current_exe()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string(),
+                "--config".to_string(),
+                current_dir
+                    .join("configs/scheduler.toml")
+                    .to_string_lossy()
+                    .to_string(),
+                "--state-path".to_string(),
+                home.join("zephyr/state.db").to_string_lossy().to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_service_files_run_current_exe() {
+        let options = InstallOptions {
+            config_path: PathBuf::from("/etc/zephyr/scheduler.toml"),
+            ..Default::default()
+        };
+        let command_line = options.command_line().unwrap();
+        let current_exe = std::env::current_exe().unwrap();
+        let current_exe = current_exe.to_string_lossy();
+
+        let unit = systemd_unit(Some("alice"), &command_line);
+        assert!(unit.contains(&format!(
+            "\nExecStart={} --config",
+            systemd_quote(&current_exe)
+        )));
+        let plist = launchd_plist(
+            &command_line,
+            Path::new("/Users/alice/Library/Logs/zephyr.log"),
+        );
+        assert!(plist.contains(&format!(
+            "<array>\n        <string>{}</string>",
+            xml_escape(&current_exe)
+        )));
+        assert!(!unit.contains("/usr/local/bin/zephyr"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_installed_executable_fallback() {
+        let resolved = PathBuf::from("/home/alice/.cargo/bin/zephyr");
+        assert_eq!(
+            installed_executable(Ok(resolved.clone())).unwrap(),
+            resolved
+        );
+        let error = std::io::Error::new(std::io::ErrorKind::NotFound, "no /proc");
+        assert_eq!(
+            installed_executable(Err(error)).unwrap(),
+            PathBuf::from("/usr/local/bin/zephyr")
+        );
+    }
+
+    #[test]
+    fn test_systemd_unit_exec_start() {
+        let command_line = options("/etc/zephyr/scheduler.toml")
+            .command_line()
+            .unwrap();
+        let unit = systemd_unit(Some("alice"), &command_line);
+        assert!(unit.contains("\nUser=alice\n"));
+        assert!(unit.contains("\nType=notify\n"));
+        assert!(unit.contains("\nWatchdogSec=120\n"));
+        assert!(unit.ends_with("\nWantedBy=multi-user.target"));
+        assert!(unit
+            .contains("\nExecStart=/opt/zephyr/bin/zephyr --config /etc/zephyr/scheduler.toml\n"));
+
+        let command_line = options("/home/alice/My Config/100%.toml")
+            .command_line()
+            .unwrap();
+        let unit = systemd_unit(Some("alice"), &command_line);
+        assert!(unit.contains(
+            "\nExecStart=/opt/zephyr/bin/zephyr --config \"/home/alice/My Config/100%%.toml\"\n"
+        ));
+    }
+
+    #[test]
+    fn test_systemd_user_unit() {
+        let command_line = options("/home/alice/.config/zephyr/scheduler.toml")
+            .command_line()
+            .unwrap();
+        let unit = systemd_unit(None, &command_line);
+        assert!(!unit.contains("User="));
+        assert!(unit.contains(
+            "\nExecStart=/opt/zephyr/bin/zephyr --config /home/alice/.config/zephyr/scheduler.toml\n"
+        ));
+        assert!(unit.ends_with("\nWantedBy=default.target"));
+
+        assert_eq!(
+            ServiceScope::System.systemd_unit_path().unwrap(),
+            PathBuf::from("/etc/systemd/system/zephyr.service")
+        );
+        let user_path = ServiceScope::User.systemd_unit_path().unwrap();
+        assert!(user_path.starts_with(dirs::config_dir().unwrap()));
+        assert!(user_path.ends_with("systemd/user/zephyr.service"));
+    }
+
+    #[test]
+    fn test_launchd_plist_program_arguments() {
+        let mut options = options("/Users/alice/.config/zephyr/a&b.toml");
+        options.state_path = Some(PathBuf::from("/Users/alice/state.db"));
+        let plist = launchd_plist(
+            &options.command_line().unwrap(),
+            Path::new("/Volumes/Data/logs/zephyr <dev>.log"),
+        );
+        assert!(plist.contains(
+            "    <array>
+        <string>/opt/zephyr/bin/zephyr</string>
+        <string>--config</string>
+        <string>/Users/alice/.config/zephyr/a&amp;b.toml</string>
+        <string>--state-path</string>
+        <string>/Users/alice/state.db</string>
+    </array>"
+        ));
+        assert!(plist.contains(
+            "    <key>StandardErrorPath</key>
+    <string>/Volumes/Data/logs/zephyr &lt;dev&gt;.log</string>
+    <key>StandardOutPath</key>
+    <string>/Volumes/Data/logs/zephyr &lt;dev&gt;.log</string>"
+        ));
+    }
+
+    #[test]
+    fn test_parse_systemctl_show() {
+        let running = parse_systemctl_show(
+            "LoadState=loaded\nActiveState=active\nMainPID=4242\n\
+             ActiveEnterTimestamp=Thu 2026-10-15 10:00:00 UTC\n",
+        );
+        assert_eq!(
+            running,
+            ServiceStatus {
+                installed: true,
+                running: true,
+                pid: Some(4242),
+                started_at: Some("2026-10-15T10:00:00Z".parse().unwrap()),
+            }
+        );
+        assert_eq!(running.exit_code(), 0);
+
+        let stopped = parse_systemctl_show(
+            "LoadState=loaded\nActiveState=inactive\nMainPID=0\n\
+             ActiveEnterTimestamp=Thu 2026-10-15 10:00:00 UTC\n",
+        );
+        assert_eq!(
+            stopped,
+            ServiceStatus {
+                installed: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(stopped.exit_code(), 1);
+
+        let missing = parse_systemctl_show(
+            "LoadState=not-found\nActiveState=inactive\nMainPID=0\nActiveEnterTimestamp=\n",
+        );
+        assert_eq!(missing, ServiceStatus::default());
+        assert_eq!(missing.exit_code(), 2);
+    }
+
+    #[test]
+    fn test_parse_launchctl_list() {
+        let running = parse_launchctl_list(
+            "{\n\t\"LimitLoadToSessionType\" = \"Aqua\";\n\t\"Label\" = \"com.zephyr.scheduler\";\n\
+             \t\"LastExitStatus\" = 0;\n\t\"PID\" = 812;\n};\n",
+        );
+        assert!(running.installed && running.running);
+        assert_eq!(running.pid, Some(812));
+
+        let stopped = parse_launchctl_list(
+            "{\n\t\"Label\" = \"com.zephyr.scheduler\";\n\t\"LastExitStatus\" = 256;\n};\n",
+        );
+        assert_eq!(stopped.exit_code(), 1);
+        assert_eq!(stopped.pid, None);
+    }
+
+    #[test]
+    fn test_describe_status() {
+        let now = "2026-10-15T12:05:00Z".parse().unwrap();
+        let status = ServiceStatus {
+            installed: true,
+            running: true,
+            pid: Some(4242),
+            started_at: Some("2026-10-15T10:00:00Z".parse().unwrap()),
+        };
+        assert_eq!(
+            status.describe(now),
+            "Installed: yes\nRunning:   yes\nPID:       4242\nUptime:    2h 5m"
+        );
+        assert_eq!(
+            ServiceStatus::default().describe(now),
+            "Installed: no\nRunning:   no"
+        );
     }
 }
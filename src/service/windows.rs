@@ -0,0 +1,261 @@
+//! Windows service support through the Service Control Manager
+
+use super::InstallOptions;
+use anyhow::{Context, Result};
+use std::ffi::{OsStr, OsString};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::error;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+const SERVICE_NAME: &str = "zephyr";
+const DISPLAY_NAME: &str = "Zephyr Task Scheduler";
+const DESCRIPTION: &str = "Runs commands on interval and cron schedules";
+
+/// Hidden subcommand the Service Control Manager starts the executable with
+pub const SERVICE_SUBCOMMAND: &str = "windows-service";
+
+const ERROR_ACCESS_DENIED: i32 = 5;
+const ERROR_SERVICE_DOES_NOT_EXIST: i32 = 1060;
+const ERROR_SERVICE_EXISTS: i32 = 1073;
+
+/// How long uninstalling waits for a running service to stop before removing it
+const STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Future that completes when the Service Control Manager asks the service to stop
+pub type StopSignal = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+type Runner = Box<dyn FnOnce(StopSignal) -> Result<()> + Send>;
+
+static RUNNER: Mutex<Option<Runner>> = Mutex::new(None);
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// Registers an auto-start service that runs Zephyr with the given options
+pub fn install(options: &InstallOptions) -> Result<()> {
+    let mut command_line = options.command_line()?.into_iter().map(OsString::from);
+    let executable_path = command_line.next().unwrap_or_default().into();
+    let mut launch_arguments = command_line.collect::<Vec<_>>();
+    launch_arguments.push(SERVICE_SUBCOMMAND.into());
+
+    let manager = connect(ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: DISPLAY_NAME.into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path,
+        launch_arguments,
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .map_err(|e| explain(e, "Failed to create the zephyr service"))?;
+    service
+        .set_description(DESCRIPTION)
+        .map_err(|e| explain(e, "Failed to set the zephyr service description"))?;
+    Ok(())
+}
+
+/// Stops the service if it is running and removes it
+pub fn uninstall() -> Result<()> {
+    let service = open(ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)?;
+    let status = service
+        .query_status()
+        .map_err(|e| explain(e, "Failed to query the zephyr service"))?;
+    if status.current_state != ServiceState::Stopped {
+        service
+            .stop()
+            .map_err(|e| explain(e, "Failed to stop zephyr service"))?;
+        // The scheduler shuts down gracefully, so give it time before the service goes away
+        let deadline = std::time::Instant::now() + STOP_TIMEOUT;
+        while std::time::Instant::now() < deadline {
+            let status = service
+                .query_status()
+                .map_err(|e| explain(e, "Failed to query the zephyr service"))?;
+            if status.current_state == ServiceState::Stopped {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+    }
+    service
+        .delete()
+        .map_err(|e| explain(e, "Failed to remove the zephyr service"))
+}
+
+pub fn start() -> Result<()> {
+    open(ServiceAccess::START)?
+        .start::<&OsStr>(&[])
+        .map_err(|e| explain(e, "Failed to start zephyr service"))
+}
+
+pub fn stop() -> Result<()> {
+    open(ServiceAccess::STOP)?
+        .stop()
+        .map_err(|e| explain(e, "Failed to stop zephyr service"))?;
+    Ok(())
+}
+
+/// Reports whether the service is installed and running
+pub fn query() -> Result<super::ServiceStatus> {
+    let service = match connect(ServiceManagerAccess::CONNECT)?
+        .open_service(SERVICE_NAME, ServiceAccess::QUERY_STATUS)
+    {
+        Ok(service) => service,
+        Err(windows_service::Error::Winapi(e))
+            if e.raw_os_error() == Some(ERROR_SERVICE_DOES_NOT_EXIST) =>
+        {
+            return Ok(super::ServiceStatus::default());
+        }
+        Err(e) => return Err(explain(e, "Failed to open the zephyr service")),
+    };
+    let status = service
+        .query_status()
+        .map_err(|e| explain(e, "Failed to query the zephyr service"))?;
+    let running = status.current_state == ServiceState::Running;
+    Ok(super::ServiceStatus {
+        installed: true,
+        running,
+        pid: status.process_id.filter(|_| running),
+        started_at: None,
+    })
+}
+
+/// Runs `runner` as the body of the service, blocking until it returns
+///
+/// Must be called when the Service Control Manager starts the process. `runner` is given a
+/// future that completes when the service is asked to stop, and the service is reported as
+/// stopped once it returns.
+pub fn run<F>(runner: F) -> Result<()>
+where
+    F: FnOnce(StopSignal) -> Result<()> + Send + 'static,
+{
+    *RUNNER.lock().unwrap() = Some(Box::new(runner));
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main).context(
+        "Failed to connect to the Service Control Manager; this command only works when \
+         started as a Windows service",
+    )
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("Zephyr service failed: {:#}", e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let runner = RUNNER
+        .lock()
+        .unwrap()
+        .take()
+        .context("The service was started without a runner")?;
+
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel();
+    let stop_tx = Mutex::new(Some(stop_tx));
+    let status_handle =
+        service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                if let Some(stop_tx) = stop_tx.lock().unwrap().take() {
+                    let _ = stop_tx.send(());
+                }
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })
+        .context("Failed to register the service control handler")?;
+
+    status_handle.set_service_status(status(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        0,
+    ))?;
+    let result = runner(Box::pin(async move {
+        let _ = stop_rx.await;
+    }));
+    status_handle.set_service_status(status(
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        u32::from(result.is_err()),
+    ))?;
+    result
+}
+
+fn status(state: ServiceState, accepted: ServiceControlAccept, exit_code: u32) -> ServiceStatus {
+    ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: accepted,
+        exit_code: ServiceExitCode::Win32(exit_code),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    }
+}
+
+fn connect(access: ServiceManagerAccess) -> Result<ServiceManager> {
+    ServiceManager::local_computer(None::<&str>, access)
+        .map_err(|e| explain(e, "Failed to connect to the Service Control Manager"))
+}
+
+fn open(access: ServiceAccess) -> Result<windows_service::service::Service> {
+    connect(ServiceManagerAccess::CONNECT)?
+        .open_service(SERVICE_NAME, access)
+        .map_err(|e| explain(e, "Failed to open the zephyr service"))
+}
+
+/// Adds the operation to an error, explaining the errors users can fix themselves: missing
+/// Administrator rights and a service that is already, or not yet, installed
+fn explain(error: windows_service::Error, operation: &str) -> anyhow::Error {
+    if let windows_service::Error::Winapi(io_error) = &error {
+        let hint = match io_error.raw_os_error() {
+            Some(ERROR_ACCESS_DENIED) => Some(
+                "access denied. Managing the Windows service requires Administrator rights; \
+                 run zephyr from an elevated prompt",
+            ),
+            Some(ERROR_SERVICE_EXISTS) => Some(
+                "the service is already installed; run `zephyr service uninstall` first to \
+                 reinstall it",
+            ),
+            Some(ERROR_SERVICE_DOES_NOT_EXIST) => Some(
+                "the service is not installed; run `zephyr service install` from an elevated \
+                 prompt",
+            ),
+            _ => None,
+        };
+        if let Some(hint) = hint {
+            return anyhow::anyhow!("{}: {}", operation, hint);
+        }
+    }
+    anyhow::Error::new(error).context(operation.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn explained(code: i32) -> String {
+        let error = windows_service::Error::Winapi(std::io::Error::from_raw_os_error(code));
+        explain(error, "Failed to create the zephyr service").to_string()
+    }
+
+    #[test]
+    fn test_explain_known_errors() {
+        assert!(explained(ERROR_ACCESS_DENIED).contains("Administrator"));
+        assert!(explained(ERROR_SERVICE_EXISTS).contains("already installed"));
+        assert!(explained(ERROR_SERVICE_DOES_NOT_EXIST).contains("not installed"));
+        assert_eq!(explained(2), "Failed to create the zephyr service");
+    }
+}
@@ -1,21 +1,140 @@
-use crate::config::CommandConfig;
+use crate::config::{CommandConfig, ScheduleSpec};
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
 use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tracing::warn;
 
 /// Represents the last execution time and next scheduled time for a command
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CommandState {
     pub name: String,
-    #[allow(dead_code)]
     pub last_execution: Option<DateTime<Utc>>,
     pub next_scheduled: DateTime<Utc>,
+    /// Exit status of the last run; `None` if it timed out, failed to start or never ran
+    pub last_status: Option<i32>,
+    pub last_duration_ms: Option<i64>,
+    /// Number of runs in a row that failed, reset by a successful run
+    pub consecutive_failures: u32,
+    /// When a one-shot command finished its run and stopped being scheduled
+    pub completed_at: Option<DateTime<Utc>>,
+    /// The kind of schedule the command had when the state was saved, such as `interval`
+    pub schedule_type: String,
+    /// The schedule itself, such as the interval in minutes or the cron expression
+    pub schedule_data: String,
 }
 
+impl CommandState {
+    /// Returns whether a command with the parsed `schedule` is a one-shot command that had
+    /// its run at or after its current `at` time; moving `at` later schedules it again
+    pub fn is_complete(&self, schedule: Option<&ScheduleSpec>) -> bool {
+        match (schedule, self.completed_at) {
+            (Some(ScheduleSpec::At(at)), Some(completed_at)) => completed_at >= *at,
+            _ => false,
+        }
+    }
+
+    /// Returns the schedule `command` had when the state was saved if it has a different one
+    /// now, as `(type, data)`
+    pub fn changed_schedule(&self, command: &CommandConfig) -> Option<(&str, &str)> {
+        let (schedule_type, schedule_data) = StateManager::schedule_columns(command).ok()?;
+        (schedule_type != self.schedule_type || schedule_data != self.schedule_data)
+            .then_some((&self.schedule_type, &self.schedule_data))
+    }
+}
+
+/// A single finished run of a command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionRecord {
+    pub name: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// Exit code of the process; `None` if it couldn't be started or was killed
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    /// Whether the exit code was one of the command's `success_exit_codes`
+    pub succeeded: bool,
+    /// Whether the run never started because a command it depends on failed; `stderr`
+    /// says which
+    pub skipped: bool,
+    /// Output of the run, cut down to `HISTORY_OUTPUT_LIMIT` bytes when stored
+    pub stdout: String,
+    pub stderr: String,
+    /// Size in bytes of the complete output, before it was truncated
+    pub stdout_len: usize,
+    pub stderr_len: usize,
+}
+
+impl ExecutionRecord {
+    /// Returns how long the run took
+    pub fn duration(&self) -> chrono::Duration {
+        self.finished_at - self.started_at
+    }
+
+    /// Returns whether the run exited with a status that doesn't count as success, timed out
+    /// or failed to start
+    pub fn failed(&self) -> bool {
+        !self.succeeded && !self.skipped
+    }
+}
+
+/// Every command's state and the execution history, as written by `zephyr state export`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub commands: Vec<CommandState>,
+    /// Recorded runs, oldest first; may be left out of a snapshot to import only the state
+    #[serde(default)]
+    pub executions: Vec<ExecutionRecord>,
+}
+
+/// Maximum number of bytes of stdout and stderr kept for each recorded execution
+pub const HISTORY_OUTPUT_LIMIT: usize = 4096;
+
+/// Schema migrations, applied in order; `PRAGMA user_version` records how many have run
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS commands (
+        name TEXT PRIMARY KEY,
+        last_execution TEXT,
+        next_scheduled TEXT NOT NULL,
+        schedule_type TEXT NOT NULL,
+        schedule_data TEXT NOT NULL
+    );",
+    "CREATE TABLE executions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        started_at TEXT NOT NULL,
+        finished_at TEXT NOT NULL,
+        exit_code INTEGER,
+        timed_out INTEGER NOT NULL DEFAULT 0,
+        stdout TEXT NOT NULL DEFAULT '',
+        stderr TEXT NOT NULL DEFAULT ''
+    );
+    CREATE INDEX executions_name_started_at ON executions (name, started_at);",
+    "ALTER TABLE executions ADD COLUMN stdout_len INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE executions ADD COLUMN stderr_len INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE commands ADD COLUMN last_status INTEGER;
+    ALTER TABLE commands ADD COLUMN last_duration_ms INTEGER;",
+    "ALTER TABLE commands ADD COLUMN consecutive_failures INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE executions ADD COLUMN succeeded INTEGER NOT NULL DEFAULT 0;
+    UPDATE executions SET succeeded = 1 WHERE exit_code = 0;",
+    "ALTER TABLE executions ADD COLUMN skipped INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE commands ADD COLUMN completed_at TEXT;",
+];
+
+/// How long a statement waits for another connection to release its lock on the database
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
 /// Manages persistent state for the scheduler
+///
+/// Clones share the same connection. Each method holds it for as long as its statement
+/// runs, so the `_async` variants run the statement on a blocking thread instead of the
+/// async runtime's.
+#[derive(Clone)]
 pub struct StateManager {
-    conn: Connection,
+    conn: Arc<Mutex<Connection>>,
 }
 
 impl StateManager {
@@ -27,49 +146,95 @@ impl StateManager {
                 .map_err(|e| anyhow::anyhow!("Failed to create state directory: {}", e))?;
         }
         let conn = Connection::open(path)?;
+        // Readers such as `zephyr list` or the status endpoint then don't block the
+        // scheduler's writes, and either side waits for a lock instead of failing
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.query_row("PRAGMA journal_mode = WAL", [], |row| {
+            row.get::<_, String>(0)
+        })?;
         Self::init_db(&conn)?;
-        Ok(Self { conn })
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
     }
 
-    /// Initializes the database schema
+    /// Locks the connection, which a statement that panicked can't leave in a bad state
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Writes a consistent copy of the database to `path`, replacing any file there
+    ///
+    /// Unlike copying the file, this includes changes still in the write-ahead log.
+    pub fn copy_to(&self, path: &Path) -> Result<()> {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        self.conn()
+            .execute("VACUUM INTO ?1", [path.to_string_lossy()])?;
+        Ok(())
+    }
+
+    /// Initializes the database schema, migrating databases created by older versions
+    ///
+    /// A database migrated by a newer version of zephyr is refused rather than written to
+    /// in a layout it no longer has.
     fn init_db(conn: &Connection) -> Result<()> {
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS commands (
-                name TEXT PRIMARY KEY,
-                last_execution TEXT,
-                next_scheduled TEXT NOT NULL,
-                schedule_type TEXT NOT NULL,
-                schedule_data TEXT NOT NULL
-            )",
-            [],
-        )?;
+        let version: usize =
+            conn.query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))? as usize;
+        if version > MIGRATIONS.len() {
+            return Err(anyhow::anyhow!(
+                "State database has schema version {}, but this version of zephyr only \
+                supports up to version {}. It was last opened by a newer zephyr: upgrade \
+                zephyr, or set state_path to a new file to start over with an empty state",
+                version,
+                MIGRATIONS.len()
+            ));
+        }
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            conn.execute_batch(&format!(
+                "BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+                migration,
+                index + 1
+            ))
+            .map_err(|e| {
+                let _ = conn.execute_batch("ROLLBACK");
+                anyhow::anyhow!(
+                    "Failed to migrate state database to version {}: {}",
+                    index + 1,
+                    e
+                )
+            })?;
+        }
         Ok(())
     }
 
     /// Loads the state for all commands
     pub fn load_command_states(&self) -> Result<Vec<CommandState>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT name, last_execution, next_scheduled FROM commands")?;
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!("{} FROM commands", STATE_COLUMNS))?;
         let states = stmt
-            .query_map([], |row| {
-                Ok(CommandState {
-                    name: row.get(0)?,
-                    last_execution: row
-                        .get::<_, Option<String>>(1)?
-                        .map(|s| {
-                            s.parse()
-                                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
-                        })
-                        .transpose()?,
-                    next_scheduled: row
-                        .get::<_, String>(2)?
-                        .parse()
-                        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?,
-                })
-            })?
+            .query_map([], command_state_from_row)?
             .collect::<Result<Vec<_>, _>>()?;
-        Ok(states)
+        Ok(states.into_iter().flatten().collect())
+    }
+
+    /// Returns the schedule type and data stored alongside a command's state
+    fn schedule_columns(command: &CommandConfig) -> Result<(&'static str, String)> {
+        if let Some(interval) = command.interval_minutes {
+            Ok(("interval", interval.to_string()))
+        } else if let Some(cron) = &command.cron {
+            Ok(("cron", cron.clone()))
+        } else if let Some(at) = &command.at {
+            Ok(("at", at.clone()))
+        } else if let Some(depends_on) = &command.depends_on {
+            Ok(("depends_on", depends_on.join(",")))
+        } else {
+            Err(anyhow::anyhow!(
+                "Command '{}' has no schedule type",
+                command.name
+            ))
+        }
     }
 
     /// Saves the state for a command
@@ -79,21 +244,17 @@ impl StateManager {
         last_execution: Option<DateTime<Utc>>,
         next_scheduled: DateTime<Utc>,
     ) -> Result<()> {
-        let (schedule_type, schedule_data) = if let Some(interval) = command.interval_minutes {
-            ("interval", interval.to_string())
-        } else if let Some(cron) = &command.cron {
-            ("cron", cron.clone())
-        } else {
-            return Err(anyhow::anyhow!(
-                "Command '{}' has no schedule type",
-                command.name
-            ));
-        };
+        let (schedule_type, schedule_data) = Self::schedule_columns(command)?;
 
-        self.conn.execute(
-            "INSERT OR REPLACE INTO commands
+        self.conn().execute(
+            "INSERT INTO commands
             (name, last_execution, next_scheduled, schedule_type, schedule_data)
-            VALUES (?1, ?2, ?3, ?4, ?5)",
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT(name) DO UPDATE SET
+                last_execution = excluded.last_execution,
+                next_scheduled = excluded.next_scheduled,
+                schedule_type = excluded.schedule_type,
+                schedule_data = excluded.schedule_data",
             params![
                 command.name,
                 last_execution.map(|dt| dt.to_rfc3339()),
@@ -105,53 +266,505 @@ impl StateManager {
         Ok(())
     }
 
+    /// Updates the next scheduled time for a command, keeping its last execution time
+    pub fn save_next_scheduled(
+        &self,
+        command: &CommandConfig,
+        next_scheduled: DateTime<Utc>,
+    ) -> Result<()> {
+        let (schedule_type, schedule_data) = Self::schedule_columns(command)?;
+
+        self.conn().execute(
+            "INSERT INTO commands
+            (name, last_execution, next_scheduled, schedule_type, schedule_data)
+            VALUES (?1, NULL, ?2, ?3, ?4)
+            ON CONFLICT(name) DO UPDATE SET
+                next_scheduled = excluded.next_scheduled,
+                schedule_type = excluded.schedule_type,
+                schedule_data = excluded.schedule_data",
+            params![
+                command.name,
+                next_scheduled.to_rfc3339(),
+                schedule_type,
+                schedule_data,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stores the start time and result of a command's latest run, keeping its next scheduled
+    /// time, and returns how many runs in a row have now failed
+    ///
+    /// A run fails unless it exited with one of the command's success exit codes. A command without stored state gets
+    /// `started` as its next scheduled time until the caller saves the real one.
+    pub fn save_last_run(
+        &self,
+        command: &CommandConfig,
+        started: DateTime<Utc>,
+        status: Option<i32>,
+        duration_ms: i64,
+    ) -> Result<u32> {
+        let (schedule_type, schedule_data) = Self::schedule_columns(command)?;
+
+        let failures = self.conn().query_row(
+            "INSERT INTO commands
+            (name, last_execution, next_scheduled, schedule_type, schedule_data,
+             last_status, last_duration_ms, consecutive_failures)
+            VALUES (?1, ?2, ?2, ?3, ?4, ?5, ?6, ?7)
+            ON CONFLICT(name) DO UPDATE SET
+                last_execution = excluded.last_execution,
+                last_status = excluded.last_status,
+                last_duration_ms = excluded.last_duration_ms,
+                consecutive_failures = CASE WHEN excluded.consecutive_failures = 0
+                    THEN 0 ELSE consecutive_failures + 1 END
+            RETURNING consecutive_failures",
+            params![
+                command.name,
+                started.to_rfc3339(),
+                schedule_type,
+                schedule_data,
+                status,
+                duration_ms,
+                u32::from(!status.is_some_and(|status| command.is_success(status))),
+            ],
+            |row| row.get(0),
+        )?;
+        Ok(failures)
+    }
+
+    /// Records that a one-shot command finished its run at `completed_at` and won't be
+    /// scheduled again
+    pub fn mark_completed(
+        &self,
+        command: &CommandConfig,
+        completed_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let (schedule_type, schedule_data) = Self::schedule_columns(command)?;
+
+        self.conn().execute(
+            "INSERT INTO commands
+            (name, last_execution, next_scheduled, schedule_type, schedule_data, completed_at)
+            VALUES (?1, NULL, ?2, ?3, ?4, ?2)
+            ON CONFLICT(name) DO UPDATE SET
+                completed_at = excluded.completed_at,
+                schedule_type = excluded.schedule_type,
+                schedule_data = excluded.schedule_data",
+            params![
+                command.name,
+                completed_at.to_rfc3339(),
+                schedule_type,
+                schedule_data,
+            ],
+        )?;
+        Ok(())
+    }
+
     /// Gets the state for a specific command
-    #[allow(dead_code)]
     pub fn get_command_state(&self, name: &str) -> Result<Option<CommandState>> {
-        self.conn
+        self.conn()
             .query_row(
-                "SELECT name, last_execution, next_scheduled FROM commands WHERE name = ?1",
+                &format!("{} FROM commands WHERE name = ?1", STATE_COLUMNS),
                 [name],
-                |row| {
-                    Ok(CommandState {
-                        name: row.get(0)?,
-                        last_execution: row
-                            .get::<_, Option<String>>(1)?
-                            .map(|s| {
-                                s.parse().map_err(|e| {
-                                    rusqlite::Error::ToSqlConversionFailure(Box::new(e))
-                                })
-                            })
-                            .transpose()?,
-                        next_scheduled: row
-                            .get::<_, String>(2)?
-                            .parse()
-                            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?,
-                    })
-                },
+                command_state_from_row,
             )
             .optional()
+            .map(Option::flatten)
             .map_err(Into::into)
     }
 
     /// Deletes the state for a specific command
-    #[allow(dead_code)]
     pub fn delete_command_state(&self, name: &str) -> Result<()> {
-        self.conn
+        self.conn()
             .execute("DELETE FROM commands WHERE name = ?1", [name])?;
         Ok(())
     }
 
-    /// Resets the entire state database by dropping and recreating the table
+    /// Deletes every recorded run of a command, returning how many were removed
+    pub fn delete_history(&self, name: &str) -> Result<usize> {
+        let removed = self
+            .conn()
+            .execute("DELETE FROM executions WHERE name = ?1", [name])?;
+        Ok(removed)
+    }
+
+    /// Returns the names of all commands with stored state or recorded runs, sorted
+    pub fn stored_names(&self) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let names = conn
+            .prepare("SELECT name FROM commands UNION SELECT name FROM executions ORDER BY name")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(names)
+    }
+
+    /// Deletes the state of every command not in `names`, such as commands that were renamed
+    /// or removed from the configuration, returning the names removed
+    ///
+    /// Their execution history is kept until it ages out.
+    pub fn prune(&self, names: &[String]) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let orphaned = conn
+            .prepare("SELECT name FROM commands ORDER BY name")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|name| !names.contains(name))
+            .collect::<Vec<_>>();
+        let tx = conn.unchecked_transaction()?;
+        for name in &orphaned {
+            tx.execute("DELETE FROM commands WHERE name = ?1", [name])?;
+        }
+        tx.commit()?;
+        Ok(orphaned)
+    }
+
+    /// Records a finished run in the execution history, truncating its output
+    pub fn record_execution(&self, record: &ExecutionRecord) -> Result<()> {
+        insert_execution(&self.conn(), record, false)?;
+        Ok(())
+    }
+
+    /// Returns up to `limit` of the most recent runs of a command, newest first
+    pub fn get_history(&self, name: &str, limit: usize) -> Result<Vec<ExecutionRecord>> {
+        self.query_history(name, limit, false)
+    }
+
+    /// Returns up to `limit` of the most recent runs of a command, newest first, optionally
+    /// only those that failed (see `ExecutionRecord::failed`)
+    pub fn query_history(
+        &self,
+        name: &str,
+        limit: usize,
+        failed_only: bool,
+    ) -> Result<Vec<ExecutionRecord>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "{} FROM executions
+            WHERE name = ?1 AND (NOT ?3 OR NOT (succeeded OR skipped))
+            ORDER BY started_at DESC, id DESC LIMIT ?2",
+            EXECUTION_COLUMNS
+        ))?;
+        let records = stmt
+            .query_map(params![name, limit as i64, failed_only], execution_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(records)
+    }
+
+    /// Deletes recorded runs that started before `cutoff`, returning how many were removed
+    pub fn prune_history(&self, cutoff: DateTime<Utc>) -> Result<usize> {
+        let removed = self.conn().execute(
+            "DELETE FROM executions WHERE started_at < ?1",
+            [cutoff.to_rfc3339()],
+        )?;
+        Ok(removed)
+    }
+
+    /// Returns the state of every command and the whole execution history
+    pub fn export(&self) -> Result<StateSnapshot> {
+        let conn = self.conn();
+        let commands = conn
+            .prepare(&format!("{} FROM commands ORDER BY name", STATE_COLUMNS))?
+            .query_map([], command_state_from_row)?
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+        let executions = conn
+            .prepare(&format!(
+                "{} FROM executions ORDER BY id",
+                EXECUTION_COLUMNS
+            ))?
+            .query_map([], execution_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(StateSnapshot {
+            commands,
+            executions,
+        })
+    }
+
+    /// Writes the commands and runs of `snapshot` to the database in one transaction
+    ///
+    /// With `replace` every existing command state and run is deleted first. Otherwise the
+    /// state of each command in the snapshot overwrites the stored one, other commands are
+    /// kept, and runs already in the history aren't recorded a second time.
+    pub fn import(&self, snapshot: &StateSnapshot, replace: bool) -> Result<()> {
+        let conn = self.conn();
+        let tx = conn.unchecked_transaction()?;
+        if replace {
+            tx.execute_batch("DELETE FROM commands; DELETE FROM executions;")?;
+        }
+        for state in &snapshot.commands {
+            tx.execute(
+                "INSERT INTO commands
+                (name, last_execution, next_scheduled, schedule_type, schedule_data,
+                 last_status, last_duration_ms, consecutive_failures, completed_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                ON CONFLICT(name) DO UPDATE SET
+                    last_execution = excluded.last_execution,
+                    next_scheduled = excluded.next_scheduled,
+                    schedule_type = excluded.schedule_type,
+                    schedule_data = excluded.schedule_data,
+                    last_status = excluded.last_status,
+                    last_duration_ms = excluded.last_duration_ms,
+                    consecutive_failures = excluded.consecutive_failures,
+                    completed_at = excluded.completed_at",
+                params![
+                    state.name,
+                    state.last_execution.map(|dt| dt.to_rfc3339()),
+                    state.next_scheduled.to_rfc3339(),
+                    state.schedule_type,
+                    state.schedule_data,
+                    state.last_status,
+                    state.last_duration_ms,
+                    state.consecutive_failures,
+                    state.completed_at.map(|dt| dt.to_rfc3339()),
+                ],
+            )?;
+        }
+        for record in &snapshot.executions {
+            insert_execution(&tx, record, !replace)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Rebuilds the database file to give back the space left by deleted rows
+    pub fn vacuum(&self) -> Result<()> {
+        self.conn().execute_batch("VACUUM")?;
+        Ok(())
+    }
+
+    /// Resets the entire state database by dropping and recreating its tables
     pub fn reset_state(&self) -> Result<()> {
-        self.conn.execute("DROP TABLE IF EXISTS commands", [])?;
-        Self::init_db(&self.conn)?;
+        self.conn().execute_batch(
+            "DROP TABLE IF EXISTS commands;
+            DROP TABLE IF EXISTS executions;
+            PRAGMA user_version = 0;",
+        )?;
+        Self::init_db(&self.conn())?;
         Ok(())
     }
+
+    /// Runs `f` with a clone of the state manager on a blocking thread
+    ///
+    /// The returned future doesn't borrow `self`, so it can be awaited while whatever
+    /// holds the state manager is changed.
+    fn blocking<T, F>(&self, f: F) -> impl Future<Output = Result<T>> + Send + 'static
+    where
+        T: Send + 'static,
+        F: FnOnce(&StateManager) -> Result<T> + Send + 'static,
+    {
+        let state = self.clone();
+        async move { tokio::task::spawn_blocking(move || f(&state)).await? }
+    }
+
+    /// Async version of [`Self::load_command_states`]
+    pub fn load_command_states_async(
+        &self,
+    ) -> impl Future<Output = Result<Vec<CommandState>>> + Send + 'static {
+        self.blocking(|state| state.load_command_states())
+    }
+
+    /// Async version of [`Self::get_command_state`]
+    pub fn get_command_state_async(
+        &self,
+        name: &str,
+    ) -> impl Future<Output = Result<Option<CommandState>>> + Send + 'static {
+        let name = name.to_string();
+        self.blocking(move |state| state.get_command_state(&name))
+    }
+
+    /// Async version of [`Self::save_command_state`]
+    pub fn save_command_state_async(
+        &self,
+        command: &CommandConfig,
+        last_execution: Option<DateTime<Utc>>,
+        next_scheduled: DateTime<Utc>,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let command = command.clone();
+        self.blocking(move |state| {
+            state.save_command_state(&command, last_execution, next_scheduled)
+        })
+    }
+
+    /// Async version of [`Self::save_next_scheduled`]
+    pub fn save_next_scheduled_async(
+        &self,
+        command: &CommandConfig,
+        next_scheduled: DateTime<Utc>,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let command = command.clone();
+        self.blocking(move |state| state.save_next_scheduled(&command, next_scheduled))
+    }
+
+    /// Async version of [`Self::save_last_run`]
+    pub fn save_last_run_async(
+        &self,
+        command: &CommandConfig,
+        started: DateTime<Utc>,
+        status: Option<i32>,
+        duration_ms: i64,
+    ) -> impl Future<Output = Result<u32>> + Send + 'static {
+        let command = command.clone();
+        self.blocking(move |state| state.save_last_run(&command, started, status, duration_ms))
+    }
+
+    /// Async version of [`Self::mark_completed`]
+    pub fn mark_completed_async(
+        &self,
+        command: &CommandConfig,
+        completed_at: DateTime<Utc>,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let command = command.clone();
+        self.blocking(move |state| state.mark_completed(&command, completed_at))
+    }
+
+    /// Async version of [`Self::delete_command_state`]
+    pub fn delete_command_state_async(
+        &self,
+        name: &str,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let name = name.to_string();
+        self.blocking(move |state| state.delete_command_state(&name))
+    }
+
+    /// Async version of [`Self::record_execution`]
+    pub fn record_execution_async(
+        &self,
+        record: ExecutionRecord,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        self.blocking(move |state| state.record_execution(&record))
+    }
+
+    /// Async version of [`Self::prune_history`]
+    pub fn prune_history_async(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> impl Future<Output = Result<usize>> + Send + 'static {
+        self.blocking(move |state| state.prune_history(cutoff))
+    }
+
+    /// Async version of [`Self::vacuum`]
+    pub fn vacuum_async(&self) -> impl Future<Output = Result<()>> + Send + 'static {
+        self.blocking(|state| state.vacuum())
+    }
+}
+
+const STATE_COLUMNS: &str =
+    "SELECT name, last_execution, next_scheduled, last_status, last_duration_ms, consecutive_failures, completed_at, schedule_type, schedule_data";
+
+/// Reads a row of the `commands` table, tolerating corrupted timestamps
+///
+/// An unreadable last execution time is dropped; a row whose next scheduled time can't be
+/// read is skipped entirely so the command gets rescheduled from its configuration.
+fn command_state_from_row(row: &rusqlite::Row) -> rusqlite::Result<Option<CommandState>> {
+    let name: String = row.get(0)?;
+    let next_scheduled: String = row.get(2)?;
+    let Ok(next_scheduled) = DateTime::parse_from_rfc3339(&next_scheduled) else {
+        warn!(
+            "Ignoring stored state for command '{}': invalid next scheduled time '{}'",
+            name, next_scheduled
+        );
+        return Ok(None);
+    };
+    let last_execution = row.get::<_, Option<String>>(1)?.and_then(|value| {
+        match DateTime::parse_from_rfc3339(&value) {
+            Ok(last_execution) => Some(last_execution.with_timezone(&Utc)),
+            Err(_) => {
+                warn!(
+                    "Ignoring invalid last execution time '{}' for command '{}'",
+                    value, name
+                );
+                None
+            }
+        }
+    });
+    Ok(Some(CommandState {
+        name,
+        last_execution,
+        next_scheduled: next_scheduled.with_timezone(&Utc),
+        last_status: row.get(3)?,
+        last_duration_ms: row.get(4)?,
+        consecutive_failures: row.get(5)?,
+        completed_at: row
+            .get::<_, Option<String>>(6)?
+            .and_then(|value| DateTime::parse_from_rfc3339(&value).ok())
+            .map(|completed_at| completed_at.with_timezone(&Utc)),
+        schedule_type: row.get(7)?,
+        schedule_data: row.get(8)?,
+    }))
+}
+
+const EXECUTION_COLUMNS: &str =
+    "SELECT name, started_at, finished_at, exit_code, timed_out, stdout, stderr, stdout_len, stderr_len, succeeded, skipped";
+
+fn execution_from_row(row: &rusqlite::Row) -> rusqlite::Result<ExecutionRecord> {
+    Ok(ExecutionRecord {
+        name: row.get(0)?,
+        started_at: parse_timestamp(row.get(1)?)?,
+        finished_at: parse_timestamp(row.get(2)?)?,
+        exit_code: row.get(3)?,
+        timed_out: row.get(4)?,
+        stdout: row.get(5)?,
+        stderr: row.get(6)?,
+        stdout_len: row.get::<_, i64>(7)? as usize,
+        stderr_len: row.get::<_, i64>(8)? as usize,
+        succeeded: row.get(9)?,
+        skipped: row.get(10)?,
+    })
+}
+
+/// Inserts a run into the execution history, truncating its output; with `skip_existing`
+/// nothing is inserted if a run of the same command that started at the same time is there
+fn insert_execution(
+    conn: &Connection,
+    record: &ExecutionRecord,
+    skip_existing: bool,
+) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO executions
+        (name, started_at, finished_at, exit_code, timed_out, stdout, stderr,
+         stdout_len, stderr_len, succeeded, skipped)
+        SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11
+        WHERE NOT ?12 OR NOT EXISTS
+            (SELECT 1 FROM executions WHERE name = ?1 AND started_at = ?2)",
+        params![
+            record.name,
+            record.started_at.to_rfc3339(),
+            record.finished_at.to_rfc3339(),
+            record.exit_code,
+            record.timed_out,
+            truncate_output(&record.stdout),
+            truncate_output(&record.stderr),
+            record.stdout_len as i64,
+            record.stderr_len as i64,
+            record.succeeded,
+            record.skipped,
+            skip_existing,
+        ],
+    )
+}
+
+fn parse_timestamp(value: String) -> rusqlite::Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(&value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+/// Cuts output down to `HISTORY_OUTPUT_LIMIT` bytes without splitting a character
+fn truncate_output(output: &str) -> &str {
+    if output.len() <= HISTORY_OUTPUT_LIMIT {
+        return output;
+    }
+    let mut end = HISTORY_OUTPUT_LIMIT;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    &output[..end]
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use tempfile::NamedTempFile;
 
@@ -160,12 +773,8 @@ mod tests {
             name: name.to_string(),
             command: "echo test".to_string(),
             interval_minutes: Some(interval),
-            cron: None,
             max_runtime_minutes: Some(5),
-            enabled: true,
-            working_dir: None,
-            environment: None,
-            immediate: false,
+            ..Default::default()
         }
     }
 
@@ -195,28 +804,544 @@ mod tests {
     }
 
     #[test]
-    fn test_corrupted_datetime_returns_error() {
-        let temp_file = NamedTempFile::new().unwrap();
-        let conn = rusqlite::Connection::open(temp_file.path()).unwrap();
-        conn.execute(
-            "CREATE TABLE commands (
-                name TEXT PRIMARY KEY,
-                last_execution TEXT,
-                next_scheduled TEXT NOT NULL,
-                schedule_type TEXT NOT NULL,
-                schedule_data TEXT NOT NULL
-            )",
-            [],
-        )
-        .unwrap();
+    fn test_save_next_scheduled_keeps_last_execution() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let state = StateManager::new(temp_file.path())?;
+        let command = create_test_command("test", 5.0);
+        let now = Utc::now();
+
+        state.save_next_scheduled(&command, now)?;
+        assert!(state
+            .get_command_state("test")?
+            .unwrap()
+            .last_execution
+            .is_none());
+
+        state.save_command_state(&command, Some(now), now)?;
+        let later = now + chrono::Duration::minutes(10);
+        state.save_next_scheduled(&command, later)?;
+
+        let loaded = state.get_command_state("test")?.unwrap();
+        assert_eq!(loaded.last_execution.unwrap().timestamp(), now.timestamp());
+        assert_eq!(loaded.next_scheduled.timestamp(), later.timestamp());
+        Ok(())
+    }
+
+    #[test]
+    fn test_last_run_result_persists() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let command = create_test_command("failing", 5.0);
+        let started = Utc::now();
+        let next_run = started + chrono::Duration::minutes(5);
+        {
+            let state = StateManager::new(temp_file.path())?;
+            state.save_last_run(&command, started, Some(1), 1500)?;
+            state.save_command_state(&command, Some(started), next_run)?;
+        }
+
+        let state = StateManager::new(temp_file.path())?;
+        let loaded = state.get_command_state("failing")?.unwrap();
+        assert_eq!(loaded.last_status, Some(1));
+        assert_eq!(loaded.last_duration_ms, Some(1500));
+        assert_eq!(
+            loaded.last_execution.unwrap().timestamp(),
+            started.timestamp()
+        );
+        assert_eq!(loaded.next_scheduled.timestamp(), next_run.timestamp());
+
+        state.save_last_run(&command, next_run, None, 300_000)?;
+        let loaded = state.load_command_states()?.pop().unwrap();
+        assert_eq!(loaded.last_status, None);
+        assert_eq!(loaded.next_scheduled.timestamp(), next_run.timestamp());
+        Ok(())
+    }
+
+    /// Inserts a row straight into the `commands` table, bypassing timestamp formatting
+    ///
+    /// The row has the schedule of an hourly interval command.
+    pub(crate) fn insert_raw_state(
+        path: &Path,
+        name: &str,
+        last_execution: &str,
+        next_scheduled: &str,
+    ) {
+        let conn = rusqlite::Connection::open(path).unwrap();
         conn.execute(
             "INSERT INTO commands (name, last_execution, next_scheduled, schedule_type, schedule_data) VALUES (?1, ?2, ?3, ?4, ?5)",
-            rusqlite::params!["bad_cmd", "not-a-date", "also-not-a-date", "interval", "5.0"],
+            rusqlite::params![name, last_execution, next_scheduled, "interval", "60"],
         )
         .unwrap();
+    }
+
+    #[test]
+    fn test_consecutive_failures_persist_until_success() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let command = create_test_command("flaky", 5.0);
+        let now = Utc::now();
+        {
+            let state = StateManager::new(temp_file.path())?;
+            assert_eq!(state.save_last_run(&command, now, Some(1), 10)?, 1);
+            assert_eq!(state.save_last_run(&command, now, None, 10)?, 2);
+        }
+
+        let state = StateManager::new(temp_file.path())?;
+        let loaded = state.get_command_state("flaky")?.unwrap();
+        assert_eq!(loaded.consecutive_failures, 2);
+        assert_eq!(state.save_last_run(&command, now, Some(2), 10)?, 3);
+        assert_eq!(state.save_last_run(&command, now, Some(0), 10)?, 0);
+        assert_eq!(
+            state
+                .get_command_state("flaky")?
+                .unwrap()
+                .consecutive_failures,
+            0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_datetimes_are_tolerated() {
+        let temp_file = NamedTempFile::new().unwrap();
+        drop(StateManager::new(temp_file.path()).unwrap());
+        let next_run = Utc::now().to_rfc3339();
+        insert_raw_state(temp_file.path(), "bad_cmd", "not-a-date", "also-not-a-date");
+        insert_raw_state(
+            temp_file.path(),
+            "bad_last_run",
+            "2024-13-45T99:00:00Z",
+            &next_run,
+        );
+        insert_raw_state(temp_file.path(), "good_cmd", &next_run, &next_run);
 
         let state = StateManager::new(temp_file.path()).unwrap();
-        let result = state.load_command_states();
-        assert!(result.is_err());
+        let mut states = state.load_command_states().unwrap();
+        states.sort_by(|a, b| a.name.cmp(&b.name));
+        let names = states.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["bad_last_run", "good_cmd"]);
+        assert!(states[0].last_execution.is_none());
+        assert!(states[1].last_execution.is_some());
+
+        assert!(state.get_command_state("bad_cmd").unwrap().is_none());
+        let bad_last_run = state.get_command_state("bad_last_run").unwrap().unwrap();
+        assert!(bad_last_run.last_execution.is_none());
+    }
+
+    fn execution(name: &str, started_at: DateTime<Utc>, exit_code: i32) -> ExecutionRecord {
+        let stdout = format!("run at {}", started_at);
+        ExecutionRecord {
+            name: name.to_string(),
+            started_at,
+            finished_at: started_at + chrono::Duration::seconds(2),
+            exit_code: Some(exit_code),
+            timed_out: false,
+            succeeded: exit_code == 0,
+            skipped: false,
+            stdout_len: stdout.len(),
+            stdout,
+            stderr: String::new(),
+            stderr_len: 0,
+        }
+    }
+
+    #[test]
+    fn test_record_and_get_history() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let state = StateManager::new(temp_file.path())?;
+        let start = Utc::now() - chrono::Duration::hours(3);
+        let runs = (0..3)
+            .map(|i| execution("backup", start + chrono::Duration::hours(i), i as i32))
+            .collect::<Vec<_>>();
+        for run in &runs {
+            state.record_execution(run)?;
+        }
+        state.record_execution(&execution("other", start, 0))?;
+
+        let history = state.get_history("backup", 10)?;
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].exit_code, Some(2));
+        assert_eq!(history[2].exit_code, Some(0));
+        assert_eq!(history[0].stdout, runs[2].stdout);
+        assert_eq!(
+            history[0].started_at.timestamp(),
+            runs[2].started_at.timestamp()
+        );
+
+        let latest = state.get_history("backup", 1)?;
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].exit_code, Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_failed_history() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let state = StateManager::new(temp_file.path())?;
+        let start = Utc::now() - chrono::Duration::hours(3);
+        state.record_execution(&execution("backup", start, 0))?;
+        state.record_execution(&execution("backup", start + chrono::Duration::hours(1), 2))?;
+        let mut timed_out = execution("backup", start + chrono::Duration::hours(2), 0);
+        timed_out.exit_code = None;
+        timed_out.timed_out = true;
+        timed_out.succeeded = false;
+        state.record_execution(&timed_out)?;
+
+        let failed = state.query_history("backup", 10, true)?;
+        assert_eq!(failed.len(), 2);
+        assert!(failed.iter().all(ExecutionRecord::failed));
+        assert!(failed[0].timed_out);
+        assert_eq!(failed[1].exit_code, Some(2));
+        assert_eq!(state.query_history("backup", 10, false)?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_execution_truncates_output() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let state = StateManager::new(temp_file.path())?;
+        let mut run = execution("noisy", Utc::now(), 0);
+        run.stdout = "é".repeat(HISTORY_OUTPUT_LIMIT);
+        run.stdout_len = run.stdout.len();
+        state.record_execution(&run)?;
+
+        let stored = &state.get_history("noisy", 1)?[0];
+        assert!(stored.stdout.len() <= HISTORY_OUTPUT_LIMIT);
+        assert!(stored.stdout.chars().all(|c| c == 'é'));
+        assert_eq!(stored.stdout_len, 2 * HISTORY_OUTPUT_LIMIT);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_state_managers() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let scheduler = StateManager::new(temp_file.path())?;
+        let reader = StateManager::new(temp_file.path())?;
+        let journal_mode: String = reader
+            .conn()
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))?;
+        assert_eq!(journal_mode, "wal");
+        let command = create_test_command("backup", 5.0);
+        let now = Utc::now();
+
+        for minutes in 0..10 {
+            let next_run = now + chrono::Duration::minutes(minutes);
+            scheduler.save_command_state(&command, Some(now), next_run)?;
+            scheduler.record_execution(&execution("backup", now, 0))?;
+            let state = reader.get_command_state("backup")?.unwrap();
+            assert_eq!(state.next_scheduled, next_run);
+            assert_eq!(
+                reader.get_history("backup", 20)?.len(),
+                minutes as usize + 1
+            );
+            reader.prune_history(now - chrono::Duration::days(1))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_to_includes_uncheckpointed_changes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let state = StateManager::new(dir.path().join("state.db"))?;
+        let command = create_test_command("backup", 5.0);
+        let next_run = Utc::now();
+        state.save_command_state(&command, None, next_run)?;
+
+        let copy_path = dir.path().join("copy.db");
+        std::fs::write(&copy_path, "stale")?;
+        state.copy_to(&copy_path)?;
+        let copy = StateManager::new(&copy_path)?;
+        assert_eq!(
+            copy.get_command_state("backup")?.unwrap().next_scheduled,
+            next_run
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_removes_orphaned_states() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let state = StateManager::new(temp_file.path())?;
+        let now = Utc::now();
+        for name in ["backup", "old-report", "cleanup"] {
+            state.save_command_state(&create_test_command(name, 5.0), Some(now), now)?;
+        }
+        state.record_execution(&execution("old-report", now, 0))?;
+
+        // `old-report` was renamed to `report`, which has no state yet
+        let configured = ["backup", "report", "cleanup"].map(String::from);
+        assert_eq!(state.prune(&configured)?, vec!["old-report".to_string()]);
+        let names = state
+            .load_command_states()?
+            .into_iter()
+            .map(|state| state.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"backup".to_string()));
+        assert!(names.contains(&"cleanup".to_string()));
+        assert_eq!(state.get_history("old-report", 10)?.len(), 1);
+
+        assert!(state.prune(&configured)?.is_empty());
+        assert_eq!(state.prune(&[])?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_history_and_stored_names() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let state = StateManager::new(temp_file.path())?;
+        let now = Utc::now();
+        state.save_command_state(&create_test_command("backup", 5.0), Some(now), now)?;
+        state.record_execution(&execution("backup", now, 0))?;
+        state.record_execution(&execution("backup", now, 1))?;
+        // Only history is left of a command whose state was pruned
+        state.record_execution(&execution("old-report", now, 0))?;
+        assert_eq!(state.stored_names()?, vec!["backup", "old-report"]);
+
+        assert_eq!(state.delete_history("backup")?, 2);
+        assert!(state.get_history("backup", 10)?.is_empty());
+        assert_eq!(state.get_history("old-report", 10)?.len(), 1);
+        state.delete_command_state("backup")?;
+        assert_eq!(state.stored_names()?, vec!["old-report"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_schedule() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let state = StateManager::new(temp_file.path())?;
+        let command = create_test_command("backup", 1440.0);
+        state.save_next_scheduled(&command, Utc::now())?;
+        let saved = state.get_command_state("backup")?.unwrap();
+
+        assert_eq!(saved.changed_schedule(&command), None);
+        assert_eq!(
+            saved.changed_schedule(&create_test_command("backup", 60.0)),
+            Some(("interval", "1440"))
+        );
+        let cron = CommandConfig {
+            interval_minutes: None,
+            cron: Some("0 0 * * * *".to_string()),
+            ..command.clone()
+        };
+        assert_eq!(saved.changed_schedule(&cron), Some(("interval", "1440")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_history() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let state = StateManager::new(temp_file.path())?;
+        let now = Utc::now();
+        state.record_execution(&execution("backup", now - chrono::Duration::days(40), 0))?;
+        state.record_execution(&execution("backup", now - chrono::Duration::days(1), 0))?;
+
+        assert_eq!(state.prune_history(now - chrono::Duration::days(30))?, 1);
+        assert_eq!(state.get_history("backup", 10)?.len(), 1);
+        state.vacuum()?;
+        assert_eq!(state.get_history("backup", 10)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_existing_database_is_migrated() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        {
+            // A database from before the schema was versioned
+            let conn = Connection::open(temp_file.path())?;
+            conn.execute_batch(MIGRATIONS[0])?;
+        }
+        let command = create_test_command("kept", 5.0);
+        let next_run = Utc::now();
+        {
+            let conn = Connection::open(temp_file.path())?;
+            conn.execute(
+                "INSERT INTO commands VALUES (?1, NULL, ?2, 'interval', '5')",
+                params![command.name, next_run.to_rfc3339()],
+            )?;
+        }
+
+        let state = StateManager::new(temp_file.path())?;
+        let loaded = state.get_command_state("kept")?.unwrap();
+        assert_eq!(loaded.next_scheduled.timestamp(), next_run.timestamp());
+        state.record_execution(&execution("kept", next_run, 0))?;
+
+        let version: i64 = state
+            .conn()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        assert_eq!(version as usize, MIGRATIONS.len());
+
+        // Opening an up-to-date database again leaves it untouched
+        drop(state);
+        let state = StateManager::new(temp_file.path())?;
+        assert_eq!(state.get_history("kept", 10)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_newer_database_is_refused() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        StateManager::new(temp_file.path())?;
+        {
+            let conn = Connection::open(temp_file.path())?;
+            conn.execute_batch(&format!("PRAGMA user_version = {}", MIGRATIONS.len() + 1))?;
+        }
+
+        let err = StateManager::new(temp_file.path()).err().unwrap();
+        assert!(err.to_string().starts_with(&format!(
+            "State database has schema version {}, but this version of zephyr only supports \
+            up to version {}.",
+            MIGRATIONS.len() + 1,
+            MIGRATIONS.len()
+        )));
+
+        // The database is left as it was
+        let conn = Connection::open(temp_file.path())?;
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        assert_eq!(version as usize, MIGRATIONS.len() + 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_lengths_added_to_existing_history() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let started_at = Utc::now();
+        {
+            // A database with execution history from before output lengths were recorded
+            let conn = Connection::open(temp_file.path())?;
+            conn.execute_batch(&format!("{} {}", MIGRATIONS[0], MIGRATIONS[1]))?;
+            conn.execute_batch("PRAGMA user_version = 2")?;
+            conn.execute(
+                "INSERT INTO executions (name, started_at, finished_at, exit_code, stdout)
+                VALUES ('backup', ?1, ?1, 0, 'done')",
+                [started_at.to_rfc3339()],
+            )?;
+        }
+
+        let state = StateManager::new(temp_file.path())?;
+        state.record_execution(&execution(
+            "backup",
+            started_at + chrono::Duration::minutes(1),
+            0,
+        ))?;
+
+        let history = state.get_history("backup", 10)?;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].stdout_len, history[0].stdout.len());
+        assert_eq!(history[1].stdout, "done");
+        assert_eq!(history[1].stdout_len, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_import_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let state = StateManager::new(dir.path().join("old.db"))?;
+        let now = Utc::now();
+        let backup = create_test_command("backup", 60.0);
+        state.save_last_run(&backup, now, Some(3), 1500)?;
+        state.save_command_state(&backup, Some(now), now + chrono::Duration::hours(1))?;
+        // Never ran, so its last execution is `None`
+        state.save_next_scheduled(&create_test_command("report", 5.0), now)?;
+        state.record_execution(&execution("backup", now, 3))?;
+        state.record_execution(&execution("backup", now - chrono::Duration::hours(1), 0))?;
+
+        let exported = state.export()?;
+        assert_eq!(exported.commands.len(), 2);
+        assert_eq!(exported.commands[1].last_execution, None);
+        let json = serde_json::to_string_pretty(&exported)?;
+
+        let imported = StateManager::new(dir.path().join("new.db"))?;
+        imported.save_next_scheduled(&create_test_command("stale", 5.0), now)?;
+        imported.import(&serde_json::from_str(&json)?, true)?;
+        assert_eq!(imported.export()?, exported);
+        assert_eq!(serde_json::to_string_pretty(&imported.export()?)?, json);
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_merge() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let state = StateManager::new(temp_file.path())?;
+        let now = Utc::now();
+        let backup = create_test_command("backup", 60.0);
+        state.save_command_state(&backup, None, now)?;
+        state.save_next_scheduled(&create_test_command("local", 5.0), now)?;
+        state.record_execution(&execution("backup", now, 0))?;
+
+        let mut snapshot = state.export()?;
+        snapshot.commands.retain(|state| state.name == "backup");
+        snapshot.commands[0].last_execution = Some(now - chrono::Duration::days(1));
+        snapshot
+            .executions
+            .push(execution("backup", now - chrono::Duration::days(1), 1));
+        state.import(&snapshot, false)?;
+
+        let merged = state.export()?;
+        let names = merged
+            .commands
+            .iter()
+            .map(|state| state.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["backup", "local"]);
+        assert_eq!(
+            merged.commands[0].last_execution,
+            snapshot.commands[0].last_execution
+        );
+        // The run that was already recorded isn't duplicated
+        assert_eq!(merged.executions.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_timestamps() {
+        let json = r#"{"commands": [{"name": "backup", "last_execution": null,
+            "next_scheduled": "tomorrow", "last_status": null, "last_duration_ms": null,
+            "consecutive_failures": 0, "completed_at": null, "schedule_type": "interval",
+            "schedule_data": "60"}]}"#;
+        assert!(serde_json::from_str::<StateSnapshot>(json).is_err());
+        let snapshot: StateSnapshot =
+            serde_json::from_str(&json.replace("tomorrow", "2026-10-16T12:00:00Z")).unwrap();
+        assert!(snapshot.executions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_async_methods_run_off_the_runtime() -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let state = StateManager::new(temp_file.path())?;
+        let command = create_test_command("backup", 5.0);
+        let now = Utc::now();
+
+        state.save_command_state_async(&command, None, now).await?;
+        let loaded = state.get_command_state_async("backup").await?.unwrap();
+        assert_eq!(loaded.next_scheduled.timestamp(), now.timestamp());
+
+        // The futures don't borrow the state manager
+        let recorded = state.record_execution_async(execution("backup", now, 1));
+        let saved = state.save_last_run_async(&command, now, Some(1), 2000);
+        drop(state);
+        recorded.await?;
+        assert_eq!(saved.await?, 1);
+
+        let state = StateManager::new(temp_file.path())?;
+        assert_eq!(state.get_history("backup", 10)?.len(), 1);
+        state
+            .save_next_scheduled_async(&command, now + chrono::Duration::minutes(5))
+            .await?;
+        let states = state.load_command_states_async().await?;
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].last_status, Some(1));
+        assert_eq!(
+            states[0].next_scheduled.timestamp(),
+            (now + chrono::Duration::minutes(5)).timestamp()
+        );
+
+        state.mark_completed_async(&command, now).await?;
+        assert!(state
+            .get_command_state("backup")?
+            .unwrap()
+            .completed_at
+            .is_some());
+        state.delete_command_state_async("backup").await?;
+        assert!(state.get_command_state("backup")?.is_none());
+        Ok(())
     }
 }
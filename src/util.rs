@@ -1,37 +1,224 @@
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use tracing::Level;
 
 /// Maps a log level string (e.g. "info", "debug") to tracing::Level.
 /// Returns Level::INFO for unknown values.
 pub fn log_level_from_str(s: &str) -> Level {
+    parse_log_level(s).unwrap_or(Level::INFO)
+}
+
+/// Parses a log level string case-insensitively, returning `None` for unknown values
+pub fn parse_log_level(s: &str) -> Option<Level> {
     match s.to_lowercase().as_str() {
-        "trace" => Level::TRACE,
-        "debug" => Level::DEBUG,
-        "info" => Level::INFO,
-        "warn" | "warning" => Level::WARN,
-        "error" => Level::ERROR,
-        _ => Level::INFO,
+        "trace" => Some(Level::TRACE),
+        "debug" => Some(Level::DEBUG),
+        "info" => Some(Level::INFO),
+        "warn" | "warning" => Some(Level::WARN),
+        "error" => Some(Level::ERROR),
+        _ => None,
     }
 }
 
+/// Expands a leading `~` or `~/` to the home directory and, on Unix, `~user` to that user's
+/// home directory
+///
+/// Paths that don't start with a tilde, tildes naming unknown users (such as a file called
+/// `~notes`) and paths whose home directory can't be determined are returned unchanged.
 pub fn expand_tilde(path: &Path) -> PathBuf {
-    let s = path.to_string_lossy();
-    if s.starts_with('~') {
-        let home = std::env::var_os("HOME")
-            .map(|p| p.to_string_lossy().to_string())
-            .or_else(|| dirs::home_dir().map(|p| p.to_string_lossy().to_string()));
-        if let Some(home) = home {
-            return PathBuf::from(s.replacen("~", &home, 1));
+    expand_tilde_with(path, home_dir(), user_home_dir)
+}
+
+fn expand_tilde_with(
+    path: &Path,
+    home: Option<PathBuf>,
+    user_home: impl FnOnce(&str) -> Option<PathBuf>,
+) -> PathBuf {
+    let mut components = path.components();
+    let Some(Component::Normal(first)) = components.next() else {
+        return path.to_path_buf();
+    };
+    let Some(user) = first.to_str().and_then(|first| first.strip_prefix('~')) else {
+        return path.to_path_buf();
+    };
+    let home = if user.is_empty() {
+        home
+    } else {
+        user_home(user)
+    };
+    match home {
+        Some(home) if components.as_path().as_os_str().is_empty() => home,
+        Some(home) => home.join(components.as_path()),
+        None => path.to_path_buf(),
+    }
+}
+
+/// Returns the home directory from `HOME` (or `USERPROFILE` on Windows), falling back to
+/// the one the operating system reports
+fn home_dir() -> Option<PathBuf> {
+    let var = |name| {
+        std::env::var_os(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+    #[cfg(windows)]
+    let from_env = var("HOME").or_else(|| var("USERPROFILE"));
+    #[cfg(not(windows))]
+    let from_env = var("HOME");
+    from_env.or_else(dirs::home_dir)
+}
+
+#[cfg(unix)]
+fn user_home_dir(user: &str) -> Option<PathBuf> {
+    use users::os::unix::UserExt;
+
+    users::get_user_by_name(user).map(|user| user.home_dir().to_path_buf())
+}
+
+#[cfg(not(unix))]
+fn user_home_dir(_user: &str) -> Option<PathBuf> {
+    None
+}
+
+/// Expands `$VAR` and `${VAR}` in `input` with the values `lookup` returns
+///
+/// References to variables `lookup` doesn't know are left as written when `keep_unset` is
+/// true and removed otherwise. A `$` escaped with a backslash or not followed by a variable
+/// name, as in `$1` or `$(date)`, is left alone for the shell.
+pub fn expand_env_vars(
+    input: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+    keep_unset: bool,
+) -> String {
+    let mut expanded = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(dollar) = rest.find('$') {
+        let (before, reference) = rest.split_at(dollar);
+        expanded.push_str(before);
+        let after = &reference[1..];
+        let name = if before.ends_with('\\') {
+            None
+        } else if let Some(braced) = after.strip_prefix('{') {
+            braced
+                .find('}')
+                .map(|end| (&braced[..end], end + 2))
+                .filter(|(name, _)| is_env_var_name(name))
+        } else {
+            let len = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            Some((&after[..len], len)).filter(|(name, _)| is_env_var_name(name))
+        };
+        let Some((name, len)) = name else {
+            expanded.push('$');
+            rest = after;
+            continue;
+        };
+        match lookup(name) {
+            Some(value) => expanded.push_str(&value),
+            None if keep_unset => expanded.push_str(&reference[..1 + len]),
+            None => {}
+        }
+        rest = &after[len..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+fn is_env_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Finds an executable the way a shell would: a path containing a separator is checked as
+/// is, and a bare name is looked up in each directory of `PATH`
+///
+/// On Windows a name without an extension also matches the extensions listed in `PATHEXT`.
+pub fn find_executable(program: &str) -> Option<PathBuf> {
+    let program = expand_tilde(Path::new(program));
+    if program.components().count() > 1 {
+        return executable_candidates(&program).find(|candidate| is_executable(candidate));
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .flat_map(|dir| executable_candidates(&dir.join(&program)).collect::<Vec<_>>())
+        .find(|candidate| is_executable(candidate))
+}
+
+#[cfg(not(windows))]
+fn executable_candidates(path: &Path) -> impl Iterator<Item = PathBuf> {
+    std::iter::once(path.to_path_buf())
+}
+
+#[cfg(windows)]
+fn executable_candidates(path: &Path) -> impl Iterator<Item = PathBuf> {
+    let mut candidates = vec![path.to_path_buf()];
+    if path.extension().is_none() {
+        let extensions = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".into());
+        candidates.extend(
+            extensions
+                .split(';')
+                .filter(|extension| !extension.is_empty())
+                .map(|extension| {
+                    let mut candidate = path.as_os_str().to_owned();
+                    candidate.push(extension);
+                    PathBuf::from(candidate)
+                }),
+        );
+    }
+    candidates.into_iter()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Formats a duration using its two largest units, e.g. "2h 5m", or milliseconds when it
+/// is shorter than a second
+pub fn format_duration(duration: chrono::Duration) -> String {
+    let milliseconds = duration.num_milliseconds().unsigned_abs();
+    if milliseconds < 1000 {
+        return format!("{}ms", milliseconds);
+    }
+    let units = [("d", 86_400), ("h", 3_600), ("m", 60), ("s", 1)];
+    let mut remaining = milliseconds / 1000;
+    let mut parts = Vec::new();
+    for (suffix, size) in units {
+        if remaining >= size && parts.len() < 2 {
+            parts.push(format!("{}{}", remaining / size, suffix));
+            remaining %= size;
+        } else if !parts.is_empty() {
+            break;
         }
     }
-    path.to_path_buf()
+    parts.join(" ")
+}
+
+/// Formats the time until an event as e.g. "in 2h 5m", or "overdue by 30s" when it has
+/// already passed
+pub fn humanize_duration(duration: chrono::Duration) -> String {
+    match duration.num_seconds() {
+        0 => "now".to_string(),
+        seconds if seconds < 0 => format!("overdue by {}", format_duration(duration)),
+        _ => format!("in {}", format_duration(duration)),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[cfg(unix)]
     #[test]
     fn test_expand_tilde_with_home() {
         let home = std::env::var_os("HOME").expect("HOME must be set in test environment");
@@ -46,6 +233,62 @@ mod tests {
         assert_eq!(expanded.to_string_lossy(), format!("{}/foo/bar", home_str));
     }
 
+    #[test]
+    fn test_expand_env_vars() {
+        let lookup = |name: &str| match name {
+            "HOME" => Some("/home/zephyr".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let expand = |input| expand_env_vars(input, lookup, true);
+        assert_eq!(
+            expand("backup.sh $HOME/data"),
+            "backup.sh /home/zephyr/data"
+        );
+        assert_eq!(expand("${HOME}data"), "/home/zephyrdata");
+        assert_eq!(expand("[$EMPTY]"), "[]");
+        assert_eq!(expand("$MISSING ${MISSING}!"), "$MISSING ${MISSING}!");
+        assert_eq!(
+            expand(r"echo \$HOME $1 $(date) ${HOME $"),
+            r"echo \$HOME $1 $(date) ${HOME $"
+        );
+        assert_eq!(
+            expand_env_vars("a${MISSING}b $MISSING", lookup, false),
+            "ab "
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_executable() {
+        let found = find_executable("sh").unwrap();
+        assert!(found.is_absolute());
+        assert_eq!(find_executable(found.to_str().unwrap()), Some(found));
+        assert_eq!(find_executable("zephyr-no-such-program"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("script");
+        std::fs::write(&script, "#!/bin/sh\n").unwrap();
+        assert_eq!(find_executable(script.to_str().unwrap()), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_find_executable_uses_pathext() {
+        let found = find_executable("cmd").unwrap();
+        assert!(found.is_absolute());
+        assert!(found
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exe")));
+        assert_eq!(find_executable("zephyr-no-such-program"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("task.bat"), "@echo off\r\n").unwrap();
+        let bare = dir.path().join("task");
+        let found = find_executable(bare.to_str().unwrap()).unwrap();
+        assert!(found.to_string_lossy().to_lowercase().ends_with("task.bat"));
+    }
+
     #[test]
     fn test_expand_tilde_non_tilde_path() {
         let path = PathBuf::from("/foo/bar/baz");
@@ -53,6 +296,36 @@ mod tests {
         assert_eq!(expanded.to_string_lossy(), "/foo/bar/baz");
     }
 
+    #[test]
+    fn test_expand_tilde_with_given_home() {
+        let home = || Some(PathBuf::from("/home/alice"));
+        let users = |user: &str| (user == "bob").then(|| PathBuf::from("/srv/bob"));
+        let expand = |path: &str| expand_tilde_with(Path::new(path), home(), users);
+
+        assert_eq!(expand("~"), PathBuf::from("/home/alice"));
+        assert_eq!(expand("~/x"), PathBuf::from("/home/alice/x"));
+        assert_eq!(expand("~/x/y.toml"), PathBuf::from("/home/alice/x/y.toml"));
+        assert_eq!(expand("~bob/x"), PathBuf::from("/srv/bob/x"));
+        assert_eq!(expand("~bob"), PathBuf::from("/srv/bob"));
+        // A tilde that doesn't name a user is part of the file name
+        assert_eq!(expand("~notes"), PathBuf::from("~notes"));
+        assert_eq!(expand("/data/~/x"), PathBuf::from("/data/~/x"));
+        assert_eq!(expand("x/~"), PathBuf::from("x/~"));
+    }
+
+    #[test]
+    fn test_expand_tilde_without_home() {
+        let path = Path::new("~/x");
+        assert_eq!(
+            expand_tilde_with(path, None, |_| None),
+            PathBuf::from("~/x")
+        );
+        assert_eq!(
+            expand_tilde_with(Path::new("~"), None, |_| None),
+            PathBuf::from("~")
+        );
+    }
+
     #[test]
     fn test_expand_tilde_relative_path() {
         let path = PathBuf::from("foo/bar");
@@ -71,5 +344,31 @@ mod tests {
         assert_eq!(log_level_from_str("warning"), Level::WARN);
         assert_eq!(log_level_from_str("error"), Level::ERROR);
         assert_eq!(log_level_from_str("unknown"), Level::INFO);
+        assert_eq!(parse_log_level("DEBUG"), Some(Level::DEBUG));
+        assert_eq!(parse_log_level("verbose"), None);
+    }
+
+    #[test]
+    fn test_humanize_duration() {
+        use chrono::Duration;
+        assert_eq!(humanize_duration(Duration::zero()), "now");
+        assert_eq!(humanize_duration(Duration::seconds(45)), "in 45s");
+        assert_eq!(humanize_duration(Duration::seconds(125)), "in 2m 5s");
+        assert_eq!(humanize_duration(Duration::minutes(125)), "in 2h 5m");
+        assert_eq!(humanize_duration(Duration::hours(50)), "in 2d 2h");
+        assert_eq!(humanize_duration(Duration::hours(2)), "in 2h");
+        assert_eq!(
+            humanize_duration(Duration::hours(2) + Duration::seconds(5)),
+            "in 2h"
+        );
+        assert_eq!(humanize_duration(Duration::seconds(-30)), "overdue by 30s");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        use chrono::Duration;
+        assert_eq!(format_duration(Duration::milliseconds(350)), "350ms");
+        assert_eq!(format_duration(Duration::milliseconds(1500)), "1s");
+        assert_eq!(format_duration(Duration::seconds(3725)), "1h 2m");
     }
 }
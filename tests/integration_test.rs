@@ -41,6 +41,7 @@ async fn test_quick_run_executes_command() {
         state_path.clone(),
         config.general.max_immediate_executions,
         config.general.min_interval_seconds,
+        config.general.max_concurrent,
     )
     .unwrap();
 
@@ -92,6 +93,7 @@ immediate = false
         state_path_buf.clone(),
         config.general.max_immediate_executions,
         config.general.min_interval_seconds,
+        config.general.max_concurrent,
     )
     .unwrap();
 